@@ -0,0 +1,311 @@
+use std::{
+    fmt,
+    net::{Ipv4Addr, Ipv6Addr},
+};
+
+use litep2p::PeerId;
+use multiaddr::{Multiaddr, Protocol};
+
+/// Check whether a multiaddress points to a loopback, private or otherwise non-public host.
+pub fn is_private(address: &Multiaddr) -> bool {
+    match address.iter().next() {
+        Some(Protocol::Ip4(ip)) => is_private_ipv4(&ip),
+        Some(Protocol::Ip6(ip)) => is_private_ipv6(&ip),
+        Some(Protocol::Dns(host) | Protocol::Dns4(host) | Protocol::Dns6(host)) => {
+            host == "localhost" || host.ends_with(".localhost")
+        }
+        _ => false,
+    }
+}
+
+fn is_private_ipv4(ip: &Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+
+    ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        // Shared address space for carrier-grade NAT (100.64.0.0/10).
+        || (a == 100 && (64..128).contains(&b))
+}
+
+fn is_private_ipv6(ip: &Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+
+    ip.is_loopback()
+        || ip.is_unspecified()
+        // Unique local addresses (fc00::/7).
+        || (first & 0xfe00) == 0xfc00
+        // Link-local addresses (fe80::/10).
+        || (first & 0xffc0) == 0xfe80
+        || ip.to_ipv4_mapped().is_some_and(|ip| is_private_ipv4(&ip))
+}
+
+/// Filter for addresses learned from other peers.
+///
+/// The filter applies to the addresses the tool seeds the routing table with and to the addresses
+/// it reports. Addresses returned by peers during a query are still dialed by litep2p's Kademlia
+/// itself, which can't be filtered from the outside.
+pub struct AddressFilter {
+    allow_private: bool,
+    skipped: usize,
+}
+
+impl AddressFilter {
+    /// Create new [`AddressFilter`]. Private addresses are dropped unless `allow_private` is set.
+    pub fn new(allow_private: bool) -> Self {
+        Self {
+            allow_private,
+            skipped: 0,
+        }
+    }
+
+    /// Check whether the filter keeps `address`.
+    pub fn allows(&self, address: &Multiaddr) -> bool {
+        self.allow_private || !is_private(address)
+    }
+
+    /// Filter out private addresses, counting the skipped ones.
+    pub fn filter(&mut self, addresses: Vec<Multiaddr>) -> Vec<Multiaddr> {
+        if self.allow_private {
+            return addresses;
+        }
+
+        let total = addresses.len();
+        let addresses: Vec<_> = addresses
+            .into_iter()
+            .filter(|address| !is_private(address))
+            .collect();
+        self.skipped += total - addresses.len();
+
+        addresses
+    }
+
+    /// Filter out private addresses of `peers`, counting the skipped ones.
+    pub fn filter_peers(
+        &mut self,
+        peers: Vec<(PeerId, Vec<Multiaddr>)>,
+    ) -> Vec<(PeerId, Vec<Multiaddr>)> {
+        peers
+            .into_iter()
+            .map(|(peer, addresses)| (peer, self.filter(addresses)))
+            .collect()
+    }
+
+    /// Number of addresses skipped so far.
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+
+    /// Print the number of skipped addresses, if any.
+    pub fn print_skipped(&self) {
+        if self.skipped > 0 {
+            println!("Filtered private addresses: {}", self.skipped);
+        }
+    }
+}
+
+/// Strip the trailing `/p2p/<peer id>` component.
+fn without_peer_id(address: &Multiaddr) -> Multiaddr {
+    address
+        .iter()
+        .filter(|protocol| !matches!(protocol, Protocol::P2p(_)))
+        .collect()
+}
+
+/// Host component of a multiaddress.
+fn host(address: &Multiaddr) -> Option<Protocol<'_>> {
+    address.iter().next()
+}
+
+/// TCP port of a multiaddress.
+fn port(address: &Multiaddr) -> Option<u16> {
+    address.iter().find_map(|protocol| match protocol {
+        Protocol::Tcp(port) => Some(port),
+        _ => None,
+    })
+}
+
+/// Compare a configured address of a peer with the listen addresses it advertises via identify.
+///
+/// Returns a description of the mismatch, if any.
+pub fn check_advertised(configured: &Multiaddr, advertised: &[Multiaddr]) -> Option<String> {
+    let configured = without_peer_id(configured);
+    let advertised: Vec<_> = advertised.iter().map(without_peer_id).collect();
+
+    if advertised.contains(&configured) {
+        return None;
+    }
+
+    let is_dns = matches!(
+        host(&configured),
+        Some(Protocol::Dns(_) | Protocol::Dns4(_) | Protocol::Dns6(_))
+    );
+    let same_host: Vec<_> = advertised
+        .iter()
+        .filter(|address| host(address) == host(&configured))
+        .collect();
+    let same_port = advertised
+        .iter()
+        .any(|address| port(address) == port(&configured));
+
+    if !same_host.is_empty() {
+        let ports: Vec<_> = same_host
+            .iter()
+            .filter_map(|address| port(address))
+            .map(|port| port.to_string())
+            .collect();
+        Some(format!(
+            "port mismatch: configured {}, advertised {}",
+            port(&configured).map_or("none".to_string(), |port| port.to_string()),
+            ports.join(", "),
+        ))
+    } else if same_port && !is_dns {
+        Some("host mismatch: configured host is not among advertised addresses".to_string())
+    } else if same_port {
+        // DNS names are usually not advertised, we can only verify the port.
+        None
+    } else {
+        Some("configured address is not advertised".to_string())
+    }
+}
+
+/// Freshness of the addresses stored in a provider record compared to the addresses the provider
+/// currently advertises via identify.
+pub struct Freshness {
+    /// Stored addresses that are still advertised.
+    pub current: usize,
+    /// Stored addresses whose host is advertised with a different port.
+    pub port_changed: usize,
+    /// Stored addresses no longer advertised at all.
+    pub stale: usize,
+    /// Advertised addresses missing from the record.
+    pub missing: usize,
+}
+
+impl Freshness {
+    /// Compare `stored` provider record addresses with `advertised` listen addresses.
+    ///
+    /// Private advertised addresses are not counted as missing unless `allow_private` is set.
+    pub fn new(stored: &[Multiaddr], advertised: &[Multiaddr], allow_private: bool) -> Self {
+        let stored: Vec<_> = stored.iter().map(without_peer_id).collect();
+        let advertised: Vec<_> = advertised
+            .iter()
+            .filter(|address| allow_private || !is_private(address))
+            .map(without_peer_id)
+            .collect();
+
+        let mut freshness = Self {
+            current: 0,
+            port_changed: 0,
+            stale: 0,
+            missing: 0,
+        };
+        for address in &stored {
+            if advertised.contains(address) {
+                freshness.current += 1;
+            } else if advertised.iter().any(|other| host(other) == host(address)) {
+                freshness.port_changed += 1;
+            } else {
+                freshness.stale += 1;
+            }
+        }
+        freshness.missing = advertised
+            .iter()
+            .filter(|address| !stored.contains(address))
+            .count();
+
+        freshness
+    }
+
+    /// Share of stored addresses that are still advertised.
+    pub fn ratio(&self) -> f64 {
+        let total = self.current + self.port_changed + self.stale;
+        if total == 0 {
+            return 0.0;
+        }
+
+        self.current as f64 / total as f64
+    }
+}
+
+impl fmt::Display for Freshness {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.0}% fresh: {} current, {} port changed, {} stale, {} missing from record",
+            self.ratio() * 100.0,
+            self.current,
+            self.port_changed,
+            self.stale,
+            self.missing,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(address: &str) -> Multiaddr {
+        address.parse().unwrap()
+    }
+
+    #[test]
+    fn detects_private_addresses() {
+        for private in [
+            "/ip4/127.0.0.1/tcp/30333",
+            "/ip4/10.1.2.3/tcp/30333",
+            "/ip4/172.16.0.1/tcp/30333",
+            "/ip4/192.168.1.1/tcp/30333",
+            "/ip4/169.254.0.1/tcp/30333",
+            "/ip4/0.0.0.0/tcp/30333",
+            "/ip4/255.255.255.255/tcp/30333",
+            "/ip4/100.64.0.1/tcp/30333",
+            "/ip4/100.127.255.255/tcp/30333",
+            "/ip6/::1/tcp/30333",
+            "/ip6/::/tcp/30333",
+            "/ip6/fd00::1/tcp/30333",
+            "/ip6/fe80::1/tcp/30333",
+            "/ip6/::ffff:192.168.1.1/tcp/30333",
+            "/dns/localhost/tcp/30333",
+            "/dns4/node.localhost/tcp/30333",
+        ] {
+            assert!(is_private(&address(private)), "{private}");
+        }
+
+        for public in [
+            "/ip4/1.1.1.1/tcp/30333",
+            "/ip4/100.63.255.255/tcp/30333",
+            "/ip4/100.128.0.1/tcp/30333",
+            "/ip4/172.32.0.1/tcp/30333",
+            "/ip6/2001:db8::1/tcp/30333",
+            "/ip6/::ffff:1.1.1.1/tcp/30333",
+            "/dns/boot.polkadot.io/tcp/30333",
+            "/dns/localhost.example.com/tcp/30333",
+        ] {
+            assert!(!is_private(&address(public)), "{public}");
+        }
+    }
+
+    #[test]
+    fn filters_and_counts_private_addresses() {
+        let addresses = vec![
+            address("/ip4/1.1.1.1/tcp/30333"),
+            address("/ip4/192.168.1.1/tcp/30333"),
+            address("/ip6/::1/tcp/30333"),
+        ];
+
+        let mut filter = AddressFilter::new(false);
+        assert_eq!(filter.filter(addresses.clone()), addresses[..1]);
+        let peers = filter.filter_peers(vec![(PeerId::random(), addresses.clone())]);
+        assert_eq!(peers[0].1, addresses[..1]);
+        assert_eq!(filter.skipped(), 4);
+
+        let mut filter = AddressFilter::new(true);
+        assert_eq!(filter.filter(addresses.clone()), addresses);
+        assert!(filter.allows(&addresses[1]));
+        assert_eq!(filter.skipped(), 0);
+    }
+}
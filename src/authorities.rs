@@ -0,0 +1,459 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{anyhow, Context};
+use futures::StreamExt;
+use litep2p::{
+    protocol::libp2p::kademlia::{KademliaEvent, QueryId, Quorum, RecordKey as KademliaKey},
+    Litep2pEvent, PeerId,
+};
+use multiaddr::{Multiaddr, Protocol};
+use sha2::{Digest, Sha256};
+use tokio::net::TcpListener;
+
+use crate::{
+    json_output, kademlia_protocol, known_peers,
+    metrics::{self, Metrics},
+    namespace::authority_addresses,
+    preset::Settings,
+    print_protocol_hint, print_statistics, query_dht,
+    reachability::dial_peer,
+    read_list,
+    refresh::Refresh,
+    verify::record_identified,
+    NodeStalled, Query, QueryArgs, QueryRun, SocketOptions, STALL_TIMEOUT,
+};
+
+/// Most peers of authority discovery records dialed at the same time.
+const AUTHORITY_DIALS: usize = 64;
+
+/// Audit the authority discovery records of a validator set every `--interval` on the same node,
+/// and serve per-validator Prometheus metrics at `http://<ADDR>/metrics`.
+#[derive(clap::Args, Debug)]
+pub struct MonitorAuthoritiesArgs {
+    /// File with the authority discovery public keys of the validators, one per line: 32 hex
+    /// bytes, optionally prefixed with `0x` and followed by a name for the `name` label.
+    #[arg(long, value_name = "PATH")]
+    authorities: PathBuf,
+    /// Address to serve the metrics at.
+    #[arg(long, value_name = "IP:PORT")]
+    metrics_addr: SocketAddr,
+    /// Seconds between the audit rounds.
+    #[arg(long, value_name = "SECS", default_value_t = 300)]
+    interval: u64,
+    /// Number of GET_VALUE queries run concurrently.
+    #[arg(long, value_name = "N", default_value_t = 8)]
+    concurrency: usize,
+}
+
+/// Validator whose authority discovery record is audited.
+#[derive(Debug, PartialEq)]
+struct Authority {
+    /// Hex public key.
+    public_key: String,
+    name: String,
+    /// DHT key of the record: the SHA-256 hash of the public key, like Substrate derives it.
+    key: KademliaKey,
+}
+
+/// Parse a line of the `--authorities` file.
+fn parse_authority(line: &str) -> anyhow::Result<Authority> {
+    let (public_key, name) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let public_key = public_key.strip_prefix("0x").unwrap_or(public_key);
+    let bytes: [u8; 32] = hex::decode(public_key)?
+        .try_into()
+        .map_err(|_| anyhow!("expected a 32-byte public key"))?;
+
+    Ok(Authority {
+        public_key: hex::encode(bytes),
+        name: name.trim().to_string(),
+        key: KademliaKey::new(&Sha256::digest(bytes).to_vec()),
+    })
+}
+
+/// Outcome of the audit of an authority.
+#[derive(Debug, Default)]
+struct Audit {
+    /// Whether a record was found.
+    present: bool,
+    /// Creation time of the newest record found.
+    created: Option<SystemTime>,
+    /// Addresses that accepted a connection.
+    reachable: usize,
+}
+
+/// Fetch the authority discovery record of every authority, up to `concurrency` queries at a
+/// time, and dial the addresses of the newest record found for each.
+///
+/// Returns the audits in order, or [`NodeStalled`] if the node stops producing events.
+async fn audit(
+    run: &mut QueryRun,
+    authorities: &[Authority],
+    concurrency: usize,
+    settings: &Settings,
+    socket: &SocketOptions,
+) -> anyhow::Result<Vec<Audit>> {
+    let records = fetch_records(run, authorities, concurrency).await?;
+
+    let dials: Vec<_> = records
+        .iter()
+        .enumerate()
+        .filter_map(|(index, record)| Some((index, record.as_ref()?)))
+        .flat_map(|(index, (addresses, _))| {
+            by_peer(addresses)
+                .into_iter()
+                .map(move |(peer, addresses)| (index, peer, addresses))
+        })
+        .collect();
+    let reachable: Vec<_> = futures::stream::iter(dials)
+        .map(|(index, peer, addresses)| async move {
+            let reachability = dial_peer(peer, &addresses, settings, socket, false).await;
+            let reachable = reachability
+                .addresses
+                .iter()
+                .filter(|outcome| outcome.result.is_ok())
+                .count();
+            (index, reachable)
+        })
+        .buffer_unordered(AUTHORITY_DIALS)
+        .collect()
+        .await;
+
+    let mut audits: Vec<_> = records
+        .into_iter()
+        .map(|record| match record {
+            Some((_, created)) => Audit {
+                present: true,
+                created,
+                reachable: 0,
+            },
+            None => Audit::default(),
+        })
+        .collect();
+    for (index, reachable) in reachable {
+        audits[index].reachable += reachable;
+    }
+
+    Ok(audits)
+}
+
+/// Run GET_VALUE for the record of every authority on the node of `run`, up to `concurrency`
+/// queries at a time, and return the addresses, with the ones the address filter rejects
+/// dropped, and the creation time of the newest record found for each, or [`NodeStalled`] if the
+/// node stops producing events. Values that aren't authority discovery records are ignored.
+async fn fetch_records(
+    run: &mut QueryRun,
+    authorities: &[Authority],
+    concurrency: usize,
+) -> anyhow::Result<Vec<Option<(Vec<Multiaddr>, Option<SystemTime>)>>> {
+    let mut records: Vec<Option<(Vec<Multiaddr>, Option<SystemTime>)>> =
+        authorities.iter().map(|_| None).collect();
+    let mut pending: HashMap<QueryId, usize> = HashMap::new();
+    let mut next = 0;
+    let mut finished = 0;
+
+    while finished < authorities.len() {
+        while pending.len() < concurrency.max(1) && next < authorities.len() {
+            let query_id = run
+                .kademlia_handle
+                .get_record(authorities[next].key.clone(), Quorum::All)
+                .await;
+            pending.insert(query_id, next);
+            next += 1;
+        }
+
+        tokio::select! {
+            event = run.litep2p.next_event() => {
+                if let Some(Litep2pEvent::ConnectionEstablished { peer, .. }) = event {
+                    run.contacted_peers.insert(peer);
+                }
+            },
+            event = run.identify_events.next() => record_identified(&mut run.identified, event),
+            event = run.kademlia_handle.next() => match event {
+                Some(KademliaEvent::GetRecordPartialResult { query_id, record }) => {
+                    let Some(&index) = pending.get(&query_id) else {
+                        continue
+                    };
+                    let Some((addresses, created)) = authority_addresses(&record.record.value) else {
+                        continue
+                    };
+                    if records[index].as_ref().is_none_or(|(_, newest)| created > *newest) {
+                        records[index] = Some((run.address_filter.filter(addresses), created));
+                    }
+                },
+                Some(KademliaEvent::GetRecordSuccess { query_id })
+                | Some(KademliaEvent::QueryFailed { query_id }) => {
+                    if pending.remove(&query_id).is_some() {
+                        finished += 1;
+                    }
+                },
+                Some(KademliaEvent::RoutingTableUpdate { peers }) => {
+                    run.discovered_peers.extend(peers);
+                },
+                Some(_) => {},
+                None => return Err(anyhow!("libp2p Kademlia terminated")),
+            },
+            _ = tokio::time::sleep(STALL_TIMEOUT) => return Err(NodeStalled.into()),
+        }
+    }
+
+    Ok(records)
+}
+
+/// Group `addresses` by the peer ID they end with. Addresses without one can't be dialed and are
+/// skipped.
+fn by_peer(addresses: &[Multiaddr]) -> Vec<(PeerId, Vec<Multiaddr>)> {
+    let mut peers: Vec<(PeerId, Vec<Multiaddr>)> = Vec::new();
+    for address in addresses {
+        let Some(Protocol::P2p(multihash)) = address.iter().last() else {
+            continue;
+        };
+        let Ok(peer) = PeerId::from_multihash(multihash) else {
+            continue;
+        };
+        match peers.iter_mut().find(|(known, _)| *known == peer) {
+            Some((_, known)) => known.push(address.clone()),
+            None => peers.push((peer, vec![address.clone()])),
+        }
+    }
+
+    peers
+}
+
+/// Audit the authority discovery records every `--interval` on the same node and serve the
+/// outcomes as Prometheus metrics until interrupted.
+///
+/// A FIND_NODE query for a random peer populates the routing table before the first round. The
+/// routing table is refreshed between the rounds, and a node that stalls during a round is
+/// restarted and the round rerun.
+pub async fn run(args: MonitorAuthoritiesArgs, query: &QueryArgs) -> anyhow::Result<()> {
+    let authorities = read_list(&args.authorities, "authority", parse_authority)?;
+    let listener = TcpListener::bind(args.metrics_addr)
+        .await
+        .with_context(|| format!("failed to listen on {}", args.metrics_addr))?;
+    let metrics = Arc::new(Mutex::new(Metrics::default()));
+    tokio::spawn(metrics::serve(listener, metrics.clone()));
+    progress!("Serving metrics at http://{}/metrics", args.metrics_addr);
+
+    let kad_proto = kademlia_protocol(query);
+    let settings = query.preset.settings();
+    let mut run = query_dht(
+        query,
+        &Query::Peer(PeerId::random()),
+        &kad_proto,
+        known_peers(query).await?,
+        &settings,
+        None,
+    )
+    .await?;
+    if run.result.is_err() && run.fan_out.responses() == 0 {
+        if !json_output() {
+            print_statistics(&run, &settings);
+            print_protocol_hint(query, &run.fan_out, &kad_proto);
+        }
+        return Err(anyhow!("no peer responded to the first FIND_NODE query"));
+    }
+
+    let mut refresh = Refresh::new();
+    for round in 1usize.. {
+        let audits = loop {
+            match audit(
+                &mut run,
+                &authorities,
+                args.concurrency,
+                &settings,
+                &query.socket,
+            )
+            .await
+            {
+                Err(error) if error.is::<NodeStalled>() => {
+                    progress!("Round {round}: {error}");
+                    run.restart(query, &kad_proto).await?;
+                    refresh.restart();
+                }
+                audits => break audits?,
+            }
+        };
+        let present = audits.iter().filter(|audit| audit.present).count();
+        let reachable = audits.iter().filter(|audit| audit.reachable > 0).count();
+        progress!(
+            "Round {round}: {present}/{} records found, {reachable} authorities reachable",
+            authorities.len()
+        );
+        {
+            let mut metrics = metrics.lock().expect("metrics lock poisoned");
+            for (authority, audit) in authorities.iter().zip(&audits) {
+                metrics.on_authority(
+                    &authority.public_key,
+                    &authority.name,
+                    audit.present,
+                    audit.created,
+                    audit.reachable,
+                );
+            }
+            metrics.on_peers(run.discovered_peers.len(), run.contacted_peers.len());
+            metrics.on_refresh(refresh.started(), refresh.failed(), refresh.refreshed());
+        }
+
+        run.maintain_until(
+            tokio::time::Instant::now() + Duration::from_secs(args.interval),
+            &mut refresh,
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::UNIX_EPOCH;
+
+    use clap::Parser;
+    use prost::Message;
+
+    use super::*;
+    use crate::{
+        record,
+        test_utils::{spawn_node, KAD_PROTO},
+    };
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        query: QueryArgs,
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    struct SignedAuthorityRecord {
+        #[prost(bytes = "vec", tag = "1")]
+        record: Vec<u8>,
+        #[prost(bytes = "vec", tag = "2")]
+        auth_signature: Vec<u8>,
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    struct AuthorityRecord {
+        #[prost(bytes = "vec", repeated, tag = "1")]
+        addresses: Vec<Vec<u8>>,
+        #[prost(message, optional, tag = "2")]
+        creation_time: Option<TimestampInfo>,
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    struct TimestampInfo {
+        #[prost(bytes = "vec", tag = "1")]
+        timestamp: Vec<u8>,
+    }
+
+    #[test]
+    fn parses_authorities() {
+        let public_key = "d4".repeat(32);
+        let authority = parse_authority(&format!("0x{public_key} alice  stash")).unwrap();
+        assert_eq!(authority.public_key, public_key);
+        assert_eq!(authority.name, "alice  stash");
+        assert_eq!(
+            authority.key,
+            KademliaKey::new(&Sha256::digest([0xd4; 32]).to_vec())
+        );
+        assert_eq!(parse_authority(&public_key).unwrap().name, "");
+        assert!(parse_authority("d4d4").is_err());
+        assert!(parse_authority("alice").is_err());
+    }
+
+    #[test]
+    fn groups_addresses_by_peer() {
+        let (first, second) = (PeerId::random(), PeerId::random());
+        let addresses: Vec<Multiaddr> = [
+            format!("/ip4/1.1.1.1/tcp/30333/p2p/{first}"),
+            "/ip4/1.1.1.1/tcp/30334".to_string(),
+            format!("/ip4/2.2.2.2/tcp/30333/p2p/{second}"),
+            format!("/dns/validator.example/tcp/30333/p2p/{first}"),
+        ]
+        .iter()
+        .map(|address| address.parse().unwrap())
+        .collect();
+
+        assert_eq!(
+            by_peer(&addresses),
+            vec![
+                (first, vec![addresses[0].clone(), addresses[3].clone()]),
+                (second, vec![addresses[2].clone()]),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn audits_authority_records() {
+        let (holder, holder_address) = spawn_node(HashMap::new());
+        let query = Cli::parse_from([
+            "dht-inspect",
+            "--bootnode",
+            &format!("{holder_address}/p2p/{holder}"),
+            "--kad-proto",
+            KAD_PROTO,
+            "--allow-private-addresses",
+        ])
+        .query;
+        let settings = query.preset.settings();
+        let mut run = query_dht(
+            &query,
+            &Query::Peer(PeerId::random()),
+            KAD_PROTO,
+            known_peers(&query).await.unwrap(),
+            &settings,
+            None,
+        )
+        .await
+        .unwrap();
+
+        let authorities = [
+            parse_authority(&format!("{} published", "01".repeat(32))).unwrap(),
+            parse_authority(&"02".repeat(32)).unwrap(),
+        ];
+        let created = SystemTime::now() - Duration::from_secs(60);
+        let nanos = created.duration_since(UNIX_EPOCH).unwrap().as_nanos();
+        let address: Multiaddr = format!("{holder_address}/p2p/{holder}").parse().unwrap();
+        let value = SignedAuthorityRecord {
+            record: AuthorityRecord {
+                addresses: vec![address.to_vec()],
+                creation_time: Some(TimestampInfo {
+                    timestamp: nanos.to_le_bytes().to_vec(),
+                }),
+            }
+            .encode_to_vec(),
+            auth_signature: vec![0; 64],
+        }
+        .encode_to_vec();
+        record::publish(
+            &mut run,
+            authorities[0].key.clone(),
+            value,
+            Duration::from_secs(1),
+            None,
+        )
+        .await
+        .unwrap();
+
+        let audits = audit(&mut run, &authorities, 2, &settings, &query.socket)
+            .await
+            .unwrap();
+        assert!(audits[0].present);
+        assert_eq!(
+            audits[0]
+                .created
+                .unwrap()
+                .duration_since(UNIX_EPOCH)
+                .unwrap(),
+            Duration::from_nanos(nanos as u64)
+        );
+        assert_eq!(audits[0].reachable, 1);
+        assert!(!audits[1].present);
+        assert_eq!(audits[1].reachable, 0);
+    }
+}
@@ -0,0 +1,125 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Context};
+use litep2p::PeerId;
+use multiaddr::Multiaddr;
+
+use crate::{json::Json, parse_multiaddress};
+
+/// Network parameters from a Substrate chain spec.
+///
+/// Chain specs don't carry the genesis hash, which is computed from the genesis state by the
+/// runtime, so the current Kademlia protocol name can't be derived from a chain spec alone.
+#[derive(Debug, Clone)]
+pub struct ChainSpec {
+    /// Chain name.
+    pub name: Option<String>,
+    /// Bootnodes from `bootNodes`.
+    pub boot_nodes: Vec<(PeerId, Multiaddr)>,
+    /// Legacy protocol ID from `protocolId`.
+    pub protocol_id: Option<String>,
+    /// Fork ID from `forkId`, part of the protocol name derived from the genesis hash.
+    pub fork_id: Option<String>,
+}
+
+impl ChainSpec {
+    /// Read the chain spec JSON file at `path`.
+    pub fn read(path: &Path) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let spec = Json::parse(&content)
+            .map_err(|error| anyhow!("failed to parse chain spec {}: {error}", path.display()))?;
+
+        let boot_nodes = spec
+            .get("bootNodes")
+            .and_then(Json::as_array)
+            .unwrap_or_default()
+            .iter()
+            .map(|address| {
+                let address = address
+                    .as_str()
+                    .ok_or_else(|| anyhow!("bootnode is not a string"))?;
+                parse_multiaddress(address)
+                    .map_err(|error| anyhow!("invalid bootnode {address}: {error}"))
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self {
+            name: spec.get("name").and_then(Json::as_str).map(String::from),
+            boot_nodes,
+            protocol_id: spec
+                .get("protocolId")
+                .and_then(Json::as_str)
+                .map(String::from),
+            fork_id: spec.get("forkId").and_then(Json::as_str).map(String::from),
+        })
+    }
+}
+
+/// Read a chain spec given on the command line.
+pub fn parse_chain_spec(path: &str) -> anyhow::Result<ChainSpec> {
+    ChainSpec::read(Path::new(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::{kademlia_protocol, QueryArgs};
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        query: QueryArgs,
+    }
+
+    #[test]
+    fn reads_chain_spec() {
+        let path = std::env::temp_dir().join(format!(
+            "dht-inspect-test-{:016x}.json",
+            rand::random::<u64>()
+        ));
+        std::fs::write(
+            &path,
+            r#"{
+                "name": "Test",
+                "id": "test",
+                "bootNodes": [
+                    "/dns/boot.example.com/tcp/30333/p2p/12D3KooWEyoppNCUx8Yx66oV9fJnriXwCcXwDDUA2kj6vnc6iDEp"
+                ],
+                "protocolId": "tst",
+                "forkId": "fork1",
+                "genesis": {"raw": {"top": {}}}
+            }"#,
+        )
+        .unwrap();
+        let spec = ChainSpec::read(&path);
+        let genesis_hash = "0x".to_string() + &"ab".repeat(32);
+        let with_genesis_hash = Cli::try_parse_from([
+            "dht-inspect",
+            "--chainspec",
+            path.to_str().unwrap(),
+            "--genesis-hash",
+            &genesis_hash,
+        ]);
+        let without_genesis_hash =
+            Cli::try_parse_from(["dht-inspect", "--chainspec", path.to_str().unwrap()]);
+        std::fs::remove_file(&path).unwrap();
+        let spec = spec.unwrap();
+
+        assert_eq!(
+            kademlia_protocol(&with_genesis_hash.unwrap().query),
+            format!("/{}/fork1/kad", "ab".repeat(32))
+        );
+        assert_eq!(
+            kademlia_protocol(&without_genesis_hash.unwrap().query),
+            "/tst/kad"
+        );
+
+        assert_eq!(spec.name.as_deref(), Some("Test"));
+        assert_eq!(spec.boot_nodes.len(), 1);
+        assert_eq!(spec.protocol_id.as_deref(), Some("tst"));
+        assert_eq!(spec.fork_id.as_deref(), Some("fork1"));
+    }
+}
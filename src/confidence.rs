@@ -0,0 +1,57 @@
+use std::fmt;
+
+use litep2p::protocol::libp2p::kademlia::ContentProvider;
+
+use crate::verify::ProtocolSupport;
+
+/// Confidence that a provider returned by the query can actually serve the content.
+///
+/// The score is the mean of the known signals, each between 0 and 1: whether the record carries
+/// addresses, whether the provider was reached during the query, and, with `--verify-protocol`,
+/// whether it serves the protocol. litep2p merges the responses of all peers, so how many peers
+/// reported the provider and how close they are to the key are not known.
+pub struct Confidence {
+    pub score: f64,
+    /// Descriptions of the signals the score is based on.
+    pub signals: Vec<&'static str>,
+}
+
+impl Confidence {
+    /// Confidence in `provider`. `contacted` is whether a connection to it was established during
+    /// the query, `support` the verification result, if verified.
+    pub fn new(
+        provider: &ContentProvider,
+        contacted: bool,
+        support: Option<&ProtocolSupport>,
+    ) -> Self {
+        let mut signals = Vec::new();
+
+        signals.push(match provider.addresses.is_empty() {
+            false => (1.0, "has addresses"),
+            true => (0.0, "no addresses"),
+        });
+        if contacted {
+            signals.push((1.0, "contacted during the query"));
+        }
+        if let Some(support) = support {
+            signals.push(match support {
+                ProtocolSupport::Serving => (1.0, "serving"),
+                ProtocolSupport::Accepted => (0.75, "accepted stream"),
+                ProtocolSupport::NotServing => (0.25, "protocol not supported"),
+                ProtocolSupport::Timeout => (0.0, "timeout"),
+                ProtocolSupport::Unreachable | ProtocolSupport::Failed(_) => (0.0, "unreachable"),
+            });
+        }
+
+        Self {
+            score: signals.iter().map(|(value, _)| value).sum::<f64>() / signals.len() as f64,
+            signals: signals.into_iter().map(|(_, signal)| signal).collect(),
+        }
+    }
+}
+
+impl fmt::Display for Confidence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.2} ({})", self.score, self.signals.join(", "))
+    }
+}
@@ -0,0 +1,85 @@
+use std::{collections::HashMap, time::Instant};
+
+use litep2p::{transport::Endpoint, types::ConnectionId, BandwidthSink, PeerId};
+use multiaddr::Multiaddr;
+
+/// Open connection.
+struct Connection {
+    peer: PeerId,
+    address: Multiaddr,
+    outbound: bool,
+    established: Instant,
+}
+
+/// Table of currently open connections.
+#[derive(Default)]
+pub struct ConnectionTable {
+    connections: HashMap<ConnectionId, Connection>,
+    /// Most connections open at the same time.
+    peak: usize,
+}
+
+impl ConnectionTable {
+    /// Register established connection.
+    pub fn on_connection_established(&mut self, peer: PeerId, endpoint: Endpoint) {
+        let (address, connection_id, outbound) = match endpoint {
+            Endpoint::Dialer {
+                address,
+                connection_id,
+            } => (address, connection_id, true),
+            Endpoint::Listener {
+                address,
+                connection_id,
+            } => (address, connection_id, false),
+        };
+
+        self.connections.insert(
+            connection_id,
+            Connection {
+                peer,
+                address,
+                outbound,
+                established: Instant::now(),
+            },
+        );
+        self.peak = self.peak.max(self.connections.len());
+    }
+
+    /// Remove closed connection.
+    pub fn on_connection_closed(&mut self, connection_id: ConnectionId) {
+        self.connections.remove(&connection_id);
+    }
+
+    /// Number of open connections.
+    pub fn len(&self) -> usize {
+        self.connections.len()
+    }
+
+    /// Most connections open at the same time so far.
+    pub fn peak(&self) -> usize {
+        self.peak
+    }
+
+    /// Print the connection table.
+    pub fn print(&self, bandwidth: &BandwidthSink) {
+        let mut connections: Vec<_> = self.connections.values().collect();
+        connections.sort_by_key(|connection| connection.established);
+
+        progress!("Open connections: {}", connections.len());
+        for connection in connections {
+            progress!(
+                "{} {} {} {} s",
+                connection.peer,
+                if connection.outbound { "->" } else { "<-" },
+                connection.address,
+                connection.established.elapsed().as_secs(),
+            );
+        }
+        progress!(
+            "Bytes transferred: {} in, {} out",
+            bandwidth.inbound(),
+            bandwidth.outbound()
+        );
+        progress!();
+    }
+}
@@ -0,0 +1,1789 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    io::{self, BufWriter, Write},
+    net::IpAddr,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Context};
+use futures::StreamExt;
+use litep2p::{
+    error::DialError,
+    protocol::libp2p::{
+        identify::IdentifyEvent,
+        kademlia::{KademliaEvent, QueryId},
+    },
+    Litep2pEvent, PeerId,
+};
+use multiaddr::{Multiaddr, Protocol};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    add_dialed_peer,
+    address::{is_private, AddressFilter},
+    cancelled, emit_event,
+    find_node::distance,
+    json::Json,
+    json_output, kademlia_protocol, known_peers,
+    peer_store::{fingerprint, PeerStore},
+    preset::Settings,
+    print_document, print_protocol_hint, print_statistics, query_dht_with,
+    reachability::dial_peer,
+    retry::{parse_duration, sleep_until},
+    schema::SCHEMA_VERSION,
+    signing, topology, CrawlSummary, Partial, Query, QueryArgs, QueryCancelled, QueryControl,
+    QueryRun, SocketOptions, Statistics,
+};
+
+/// Maximum number of crawl targets, one per 12-bit prefix of the keyspace.
+const MAX_TARGETS: usize = 1 << 12;
+
+/// Number of keyspace slices explore picks targets from, one per first byte of the hash.
+const EXPLORE_SLICES: usize = 256;
+
+/// Number of keyspace buckets, one per first byte of the hash, the coverage report is based on.
+const COVERAGE_BUCKETS: usize = 256;
+
+/// Dial timeouts after which a peer counts as stalling. Crawls add peers to the routing table only
+/// once dialed successfully, and never stalling ones, so they don't seed later lookups.
+const STALL_THRESHOLD: usize = 2;
+
+/// Number of most used ports listed in the summary.
+const TOP_PORTS: usize = 10;
+
+/// Number of IPs hosting the most peers listed in the summary.
+const TOP_IPS: usize = 10;
+
+/// Number of peers the audit stage dials at a time.
+const AUDIT_DIALS: usize = 64;
+
+/// Walk the keyspace with FIND_NODE queries and summarize the peers found.
+#[derive(clap::Args, Debug)]
+pub struct CrawlArgs {
+    /// Number of FIND_NODE targets spread evenly over the keyspace. Rounded up to a power of two.
+    #[arg(long, value_name = "N", default_value_t = 64, value_parser = clap::value_parser!(u32).range(1..=MAX_TARGETS as i64))]
+    targets: u32,
+    /// Number of FIND_NODE queries run in parallel.
+    #[arg(long, value_name = "N", default_value_t = 4)]
+    parallelism: usize,
+    /// List every peer found after the summary.
+    #[arg(long)]
+    list_peers: bool,
+    /// Ask every peer found for its closest peers and write the graph of who reported whom to this
+    /// file in the GraphViz DOT format.
+    #[arg(long, value_name = "PATH", conflicts_with = "memory_cap")]
+    dot: Option<PathBuf>,
+    /// Move the peers found to a temporary file once they take about this many MiB of memory,
+    /// keeping only 64-bit fingerprints of their IDs for deduplication.
+    #[arg(long, value_name = "MIB")]
+    memory_cap: Option<usize>,
+    /// Split the targets into this many shards of the keyspace, crawled one after another on a
+    /// fresh node each. Rounded up to a power of two, at most the number of targets.
+    #[arg(long, value_name = "N", requires = "shard_dir", conflicts_with = "dot", value_parser = clap::value_parser!(u32).range(1..=MAX_TARGETS as i64))]
+    shards: Option<u32>,
+    /// Directory of the per-shard results, `shard-<i>-of-<n>.json` documents listing every peer
+    /// the shard found. Shards with a result from an earlier run are skipped, so rerunning the
+    /// command only crawls the failed ones.
+    #[arg(long, value_name = "DIR", requires = "shards")]
+    shard_dir: Option<PathBuf>,
+    /// Stages to run in this order on the peers of one crawl, sharing them in memory: `crawl`
+    /// walks the keyspace, `audit` dials every peer found with addresses and `export-json` writes
+    /// the result listing every peer, and whether the audit reached it, to --export.
+    #[arg(
+        long,
+        value_name = "STAGES",
+        value_enum,
+        value_delimiter = ',',
+        conflicts_with = "shards"
+    )]
+    pipeline: Vec<Stage>,
+    /// File the `export-json` stage writes the crawl result to.
+    #[arg(long, value_name = "PATH", requires = "pipeline")]
+    export: Option<PathBuf>,
+}
+
+/// Stage of a crawl pipeline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+enum Stage {
+    Crawl,
+    Audit,
+    ExportJson,
+}
+
+/// Check that `stages` start with the crawl and follow the order of [`Stage`], and that
+/// `export-json` gets the `export` file.
+fn check_pipeline(stages: &[Stage], export: Option<&Path>) -> anyhow::Result<()> {
+    if stages.first().is_some_and(|stage| *stage != Stage::Crawl) {
+        return Err(anyhow!("the pipeline must start with the crawl stage"));
+    }
+    if stages.windows(2).any(|pair| pair[0] >= pair[1]) {
+        return Err(anyhow!(
+            "pipeline stages must appear once each, in the order crawl,audit,export-json"
+        ));
+    }
+    match (stages.contains(&Stage::ExportJson), export) {
+        (true, None) => Err(anyhow!("the export-json stage requires --export")),
+        (false, Some(_)) => Err(anyhow!("--export requires the export-json stage")),
+        _ => Ok(()),
+    }
+}
+
+/// Discover as many peers as possible within a time budget and dump them.
+#[derive(clap::Args, Debug)]
+pub struct ExploreArgs {
+    /// Time budget, e.g. 2m.
+    #[arg(long, value_name = "DURATION", default_value = "2m", value_parser = parse_duration)]
+    duration: Duration,
+    /// Number of FIND_NODE queries run in parallel. Combine with --preset aggressive for shorter
+    /// timeouts and more parallel dials.
+    #[arg(long, value_name = "N", default_value_t = 16)]
+    parallelism: usize,
+    /// Ask every peer found for its closest peers and write the graph of who reported whom to this
+    /// file in the GraphViz DOT format.
+    #[arg(long, value_name = "PATH", conflicts_with = "memory_cap")]
+    dot: Option<PathBuf>,
+    /// Move the peers found to a temporary file once they take about this many MiB of memory,
+    /// keeping only 64-bit fingerprints of their IDs for deduplication.
+    #[arg(long, value_name = "MIB")]
+    memory_cap: Option<usize>,
+}
+
+/// Peers found by the crawl.
+///
+/// Only the [`PeerStore`] keeps peer IDs and addresses, the other sets track peers by their 64-bit
+/// fingerprints. With `--memory-cap`, the memory of the peers found is bounded by the cap plus a
+/// few fingerprints per peer.
+struct Crawl {
+    /// Every peer the node saw, with the addresses FIND_NODE responses returned and the ones it
+    /// advertised via identify.
+    peers: PeerStore,
+    /// Peers returned by FIND_NODE queries.
+    returned: HashSet<u64>,
+    /// Peers the node connected to.
+    contacted: HashSet<u64>,
+    /// Peers identified via identify.
+    identified: HashSet<u64>,
+    /// Network size estimated from the closest peers of every target.
+    size_estimates: Vec<f64>,
+    /// Targets of the successful queries.
+    queried: Vec<PeerId>,
+    queries: usize,
+    failed_queries: usize,
+    /// Dial timeouts per peer.
+    dial_timeouts: HashMap<u64, usize>,
+    /// Whether the peers the audit stage dialed accepted a connection, if it ran.
+    audit: Option<HashMap<u64, bool>>,
+}
+
+impl Crawl {
+    /// Create new [`Crawl`] keeping at most about `memory_cap` MiB of peers in memory.
+    fn new(memory_cap: Option<usize>) -> Self {
+        Self {
+            peers: PeerStore::new(memory_cap.map(|mib| mib << 20)),
+            returned: HashSet::new(),
+            contacted: HashSet::new(),
+            identified: HashSet::new(),
+            size_estimates: Vec::new(),
+            queried: Vec::new(),
+            queries: 0,
+            failed_queries: 0,
+            dial_timeouts: HashMap::new(),
+            audit: None,
+        }
+    }
+
+    /// Summary of the crawl for embedders, listing every peer found.
+    fn into_summary(mut self) -> anyhow::Result<CrawlSummary> {
+        let mut peers = Vec::with_capacity(self.peers.len());
+        self.peers
+            .for_each(|peer, addresses| peers.push((*peer, addresses.to_vec())))?;
+        peers.sort_by_key(|(peer, _)| *peer);
+
+        Ok(CrawlSummary {
+            queries: self.queries,
+            failed_queries: self.failed_queries,
+            network_size_estimate: median(&mut self.size_estimates).map(f64::round),
+            contacted_peers: self.contacted.len(),
+            peers,
+        })
+    }
+
+    /// Move the peers the first query of `run` saw into the crawl, so they aren't kept twice.
+    fn take_peers(&mut self, run: &mut QueryRun) -> anyhow::Result<()> {
+        for peer in std::mem::take(&mut run.discovered_peers) {
+            self.peers.insert(peer, [])?;
+        }
+        for peer in std::mem::take(&mut run.contacted_peers) {
+            self.on_contacted(peer)?;
+        }
+        for (peer, addresses) in std::mem::take(&mut run.identified) {
+            self.on_identified(&run.address_filter, peer, addresses)?;
+        }
+
+        Ok(())
+    }
+
+    /// Record the closest peers found for `target`. Returns the number of peers not seen before.
+    fn on_closest_peers(
+        &mut self,
+        target: &PeerId,
+        peers: Vec<(PeerId, Vec<Multiaddr>)>,
+    ) -> anyhow::Result<usize> {
+        self.queries += 1;
+        self.queried.push(*target);
+        if let Some(estimate) = size_estimate(target, peers.iter().map(|(peer, _)| peer)) {
+            self.size_estimates.push(estimate);
+        }
+
+        let mut new_peers = 0;
+        for (peer, addresses) in peers {
+            self.peers.insert(peer, addresses)?;
+            if self.returned.insert(fingerprint(&peer)) {
+                new_peers += 1;
+            }
+        }
+
+        Ok(new_peers)
+    }
+
+    /// Dial every peer found with addresses, [`AUDIT_DIALS`] at a time, and record which ones
+    /// accept a connection on any address.
+    ///
+    /// The peers to dial are collected in memory, also the ones spilled with `--memory-cap`.
+    async fn audit(&mut self, settings: &Settings, socket: &SocketOptions) -> anyhow::Result<()> {
+        let mut peers = Vec::new();
+        self.peers.for_each(|peer, addresses| {
+            if !addresses.is_empty() {
+                peers.push((*peer, addresses.to_vec()));
+            }
+        })?;
+        progress!("Auditing {} peers with addresses", peers.len());
+
+        let audit = futures::stream::iter(peers)
+            .map(|(peer, addresses)| async move {
+                let reachability = dial_peer(peer, &addresses, settings, socket, false).await;
+                (fingerprint(&peer), reachability.online())
+            })
+            .buffer_unordered(AUDIT_DIALS)
+            .collect()
+            .await;
+        self.audit = Some(audit);
+
+        Ok(())
+    }
+
+    fn on_contacted(&mut self, peer: PeerId) -> anyhow::Result<()> {
+        self.contacted.insert(fingerprint(&peer));
+        self.peers.insert(peer, [])?;
+
+        Ok(())
+    }
+
+    /// Record the `listen_addresses` `peer` advertised via identify, skipping the ones `filter`
+    /// rejects.
+    fn on_identified(
+        &mut self,
+        filter: &AddressFilter,
+        peer: PeerId,
+        listen_addresses: Vec<Multiaddr>,
+    ) -> anyhow::Result<()> {
+        self.identified.insert(fingerprint(&peer));
+        self.peers.insert(
+            peer,
+            listen_addresses
+                .into_iter()
+                .filter(|address| filter.allows(address)),
+        )?;
+
+        Ok(())
+    }
+
+    fn on_query_failed(&mut self) {
+        self.queries += 1;
+        self.failed_queries += 1;
+    }
+
+    /// Record a dial of `address` that timed out.
+    fn on_dial_timeout(&mut self, address: &Multiaddr) {
+        if let Some(Protocol::P2p(multihash)) = address.iter().last() {
+            if let Ok(peer) = PeerId::from_multihash(multihash) {
+                *self.dial_timeouts.entry(fingerprint(&peer)).or_default() += 1;
+            }
+        }
+    }
+
+    /// Whether dials of `peer` timed out at least [`STALL_THRESHOLD`] times.
+    fn is_stalling(&self, peer: &PeerId) -> bool {
+        self.dial_timeouts
+            .get(&fingerprint(peer))
+            .is_some_and(|timeouts| *timeouts >= STALL_THRESHOLD)
+    }
+
+    /// Number of peers whose dials timed out at least [`STALL_THRESHOLD`] times.
+    fn stalling_peers(&self) -> usize {
+        self.dial_timeouts
+            .values()
+            .filter(|timeouts| **timeouts >= STALL_THRESHOLD)
+            .count()
+    }
+}
+
+/// Source of FIND_NODE targets.
+trait Targets {
+    /// Next target to query, `None` if there are no more.
+    fn next_target(&mut self) -> Option<PeerId>;
+
+    /// Record that the query for `target` found `new_peers` peers not seen before.
+    fn on_result(&mut self, _target: &PeerId, _new_peers: usize) {}
+
+    /// Number of targets left, if known in advance.
+    fn remaining(&self) -> Option<usize> {
+        None
+    }
+
+    /// Time the walk stops at, leaving the running queries unfinished.
+    fn deadline(&self) -> Option<tokio::time::Instant> {
+        None
+    }
+}
+
+/// Fixed list of targets.
+impl Targets for VecDeque<PeerId> {
+    fn next_target(&mut self) -> Option<PeerId> {
+        self.pop_front()
+    }
+
+    fn remaining(&self) -> Option<usize> {
+        Some(self.len())
+    }
+}
+
+/// Targets picked from the keyspace slices that yielded the most new peers per query so far,
+/// until a deadline.
+struct AdaptiveTargets {
+    /// Queries and new peers found per slice.
+    slices: Vec<(usize, usize)>,
+    deadline: tokio::time::Instant,
+}
+
+impl AdaptiveTargets {
+    fn new(deadline: tokio::time::Instant) -> Self {
+        Self {
+            slices: vec![(0, 0); EXPLORE_SLICES],
+            deadline,
+        }
+    }
+}
+
+impl Targets for AdaptiveTargets {
+    fn next_target(&mut self) -> Option<PeerId> {
+        // Unexplored slices score 1, so they are tried before slices that stopped yielding peers.
+        let score =
+            |(queries, new_peers): &(usize, usize)| (new_peers + 1) as f64 / (queries + 1) as f64;
+        let slice = (0..EXPLORE_SLICES)
+            .max_by(|a, b| score(&self.slices[*a]).total_cmp(&score(&self.slices[*b])))
+            .expect("slices are not empty; qed");
+        self.slices[slice].0 += 1;
+
+        Some(target_in_slice(slice, EXPLORE_SLICES))
+    }
+
+    fn on_result(&mut self, target: &PeerId, new_peers: usize) {
+        self.slices[slice(target, EXPLORE_SLICES)].1 += new_peers;
+    }
+
+    fn deadline(&self) -> Option<tokio::time::Instant> {
+        Some(self.deadline)
+    }
+}
+
+/// Keyspace walk run by a [`DhtInspector`](crate::DhtInspector).
+pub(crate) enum Walk {
+    /// Crawl this many targets spread evenly over the keyspace, rounded up to a power of two.
+    Crawl { targets: u32 },
+    /// Explore the keyspace for this long.
+    Explore { duration: Duration },
+}
+
+/// Run `walk` under the `control` of an embedder, querying at most `parallelism` targets at a
+/// time and keeping all peers found in memory.
+pub(crate) async fn walk_with(
+    query: &QueryArgs,
+    kad_proto: &str,
+    known_peers: HashMap<PeerId, Vec<Multiaddr>>,
+    walk: Walk,
+    parallelism: usize,
+    control: QueryControl,
+) -> anyhow::Result<(Partial<CrawlSummary>, Statistics)> {
+    let start = Instant::now();
+    let (_, crawl) = match walk {
+        Walk::Crawl { targets } => {
+            let mut targets = fixed_targets(targets);
+            self::walk(
+                query,
+                kad_proto,
+                known_peers,
+                &mut targets,
+                Crawl::new(None),
+                parallelism,
+                control,
+            )
+            .await?
+        }
+        Walk::Explore { duration } => {
+            let mut targets = AdaptiveTargets::new(tokio::time::Instant::now() + duration);
+            self::walk(
+                query,
+                kad_proto,
+                known_peers,
+                &mut targets,
+                Crawl::new(None),
+                parallelism,
+                control,
+            )
+            .await?
+        }
+    };
+    let (Partial::Complete(found) | Partial::Cancelled(found)) = &crawl;
+    let statistics = Statistics {
+        discovered_peers: found.peers.len(),
+        contacted_peers: found.contacted.len(),
+        elapsed: start.elapsed(),
+    };
+    let summary = match crawl {
+        Partial::Complete(crawl) => Partial::Complete(crawl.into_summary()?),
+        Partial::Cancelled(crawl) => Partial::Cancelled(crawl.into_summary()?),
+    };
+
+    Ok((summary, statistics))
+}
+
+/// `count` targets spread evenly over the keyspace, rounded up to a power of two.
+fn fixed_targets(count: u32) -> VecDeque<PeerId> {
+    let count = count.next_power_of_two() as usize;
+    (0..count)
+        .map(|slice| target_in_slice(slice, count))
+        .collect()
+}
+
+/// Run the crawl.
+///
+/// The first target is queried after the routing table prepopulation, the remaining ones on the
+/// same node, `--parallelism` at a time.
+pub async fn run(args: CrawlArgs, query: &QueryArgs) -> anyhow::Result<()> {
+    if let (Some(shards), Some(dir)) = (args.shards, &args.shard_dir) {
+        return run_shards(&args, shards, dir, query).await;
+    }
+    check_pipeline(&args.pipeline, args.export.as_deref())?;
+    let mut targets = fixed_targets(args.targets);
+
+    let known_peers = known_peers(query).await?;
+    let kad_proto = kademlia_protocol(query);
+    let start = Instant::now();
+    let (run, crawl) = walk(
+        query,
+        &kad_proto,
+        known_peers,
+        &mut targets,
+        Crawl::new(args.memory_cap),
+        args.parallelism,
+        QueryControl::default(),
+    )
+    .await?;
+    let mut crawl = crawl.into_inner();
+    let elapsed = start.elapsed();
+    if args.pipeline.contains(&Stage::Audit) {
+        crawl.audit(&query.preset.settings(), &query.socket).await?;
+    }
+    if let Some(path) = &args.dot {
+        topology::export(path, crawl.peers.in_memory(), &kad_proto, query).await?;
+    }
+
+    let mut summary = Summary::new(&run, crawl)?;
+    if let Some(path) = &args.export {
+        let file = std::fs::File::create(path)
+            .with_context(|| format!("failed to create {}", path.display()))?;
+        let mut out = BufWriter::new(file);
+        summary.write_json(&mut out, "CRAWL", &kad_proto, elapsed, true)?;
+        out.flush()
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        progress!("Crawl result written to {}", path.display());
+    }
+    summary.report("CRAWL", &kad_proto, elapsed, args.list_peers)
+}
+
+/// Outcome of a sharded crawl.
+#[derive(Debug, Default, PartialEq)]
+struct Shards {
+    crawled: usize,
+    /// Shards with a result from an earlier run.
+    skipped: usize,
+    failed: usize,
+}
+
+impl Shards {
+    /// JSON document of the crawl of `shards` shards into `dir`.
+    fn json(&self, kad_proto: &str, shards: usize, dir: &Path, elapsed: Duration) -> Json {
+        Json::object()
+            .field("schema_version", SCHEMA_VERSION)
+            .field("query", "CRAWL")
+            .field("protocol", kad_proto)
+            .field("shards", shards)
+            .field("crawled_shards", self.crawled)
+            .field("skipped_shards", self.skipped)
+            .field("failed_shards", self.failed)
+            .field("shard_dir", dir.display().to_string())
+            .field("elapsed_ms", elapsed.as_millis() as u64)
+    }
+}
+
+/// Run the crawl in `shards` shards, writing their results to `dir`.
+async fn run_shards(
+    args: &CrawlArgs,
+    shards: u32,
+    dir: &Path,
+    query: &QueryArgs,
+) -> anyhow::Result<()> {
+    let count = args.targets.next_power_of_two() as usize;
+    let shards = (shards.next_power_of_two() as usize).min(count);
+    let kad_proto = kademlia_protocol(query);
+    let start = Instant::now();
+    let outcome = crawl_shards(query, &kad_proto, args, count, shards, dir).await?;
+
+    if json_output() {
+        print_document(outcome.json(&kad_proto, shards, dir, start.elapsed()));
+    } else {
+        println!();
+        println!(
+            "{shards} shards in {} s: {} crawled, {} from earlier runs, {} failed",
+            start.elapsed().as_secs(),
+            outcome.crawled,
+            outcome.skipped,
+            outcome.failed,
+        );
+        println!("Shard results in {}", dir.display());
+    }
+
+    match outcome.failed {
+        0 => Ok(()),
+        failed => Err(anyhow!(
+            "{failed} of {shards} shards failed, rerun the command to retry them"
+        )),
+    }
+}
+
+/// Crawl the shards of the `count` targets that have no result in `dir` yet, one after another.
+///
+/// A shard fails if its walk fails or none of its queries succeed. Failed shards get no result
+/// file, so the next run retries them.
+async fn crawl_shards(
+    query: &QueryArgs,
+    kad_proto: &str,
+    args: &CrawlArgs,
+    count: usize,
+    shards: usize,
+    dir: &Path,
+) -> anyhow::Result<Shards> {
+    std::fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    let known_peers = known_peers(query).await?;
+    let mut outcome = Shards::default();
+
+    for shard in 0..shards {
+        let path = dir.join(format!("shard-{shard}-of-{shards}.json"));
+        if path.exists() {
+            progress!("Shard {}/{shards}: done in an earlier run", shard + 1);
+            outcome.skipped += 1;
+            continue;
+        }
+
+        progress!("Shard {}/{shards}: crawling", shard + 1);
+        let mut targets = shard_targets(shard, shards, count);
+        let start = Instant::now();
+        let (run, crawl) = match walk(
+            query,
+            kad_proto,
+            known_peers.clone(),
+            &mut targets,
+            Crawl::new(args.memory_cap),
+            args.parallelism,
+            QueryControl::default(),
+        )
+        .await
+        {
+            Ok((run, crawl)) => (run, crawl.into_inner()),
+            Err(error) => {
+                progress!("Shard {}/{shards} failed: {error:#}", shard + 1);
+                outcome.failed += 1;
+                continue;
+            }
+        };
+        if crawl.failed_queries == crawl.queries {
+            progress!("Shard {}/{shards} failed: every query failed", shard + 1);
+            outcome.failed += 1;
+            continue;
+        }
+
+        write_shard(&path, kad_proto, &run, crawl, start.elapsed())?;
+        outcome.crawled += 1;
+    }
+
+    Ok(outcome)
+}
+
+/// Targets of `shard` out of `shards` equal shards of the keyspace: the `count` targets of
+/// [`fixed_targets`] falling into it.
+fn shard_targets(shard: usize, shards: usize, count: usize) -> VecDeque<PeerId> {
+    let per_shard = count / shards;
+    (shard * per_shard..(shard + 1) * per_shard)
+        .map(|slice| target_in_slice(slice, count))
+        .collect()
+}
+
+/// Write the result of a shard, listing every peer found, to `path`.
+///
+/// The result is written to `<path>.tmp` first and renamed over `path`, so an interrupted run
+/// leaves no result and the shard is crawled again.
+fn write_shard(
+    path: &Path,
+    kad_proto: &str,
+    run: &QueryRun,
+    crawl: Crawl,
+    elapsed: Duration,
+) -> anyhow::Result<()> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    let temporary = PathBuf::from(temporary);
+    let file = std::fs::File::create(&temporary)
+        .with_context(|| format!("failed to create {}", temporary.display()))?;
+    let mut out = BufWriter::new(file);
+    Summary::new(run, crawl)?.write_json(&mut out, "CRAWL", kad_proto, elapsed, true)?;
+    out.flush()
+        .with_context(|| format!("failed to write {}", temporary.display()))?;
+    std::fs::rename(&temporary, path)
+        .with_context(|| format!("failed to replace {}", path.display()))
+}
+
+/// Run the exploration.
+///
+/// Like the crawl, but targets are picked adaptively from the keyspace slices that yielded the
+/// most new peers until the time budget runs out, and all peers found are listed.
+pub async fn run_explore(args: ExploreArgs, query: &QueryArgs) -> anyhow::Result<()> {
+    let known_peers = known_peers(query).await?;
+    let kad_proto = kademlia_protocol(query);
+    let start = Instant::now();
+    let mut targets = AdaptiveTargets::new(tokio::time::Instant::now() + args.duration);
+    let (run, crawl) = walk(
+        query,
+        &kad_proto,
+        known_peers,
+        &mut targets,
+        Crawl::new(args.memory_cap),
+        args.parallelism,
+        QueryControl::default(),
+    )
+    .await?;
+    let crawl = crawl.into_inner();
+    let elapsed = start.elapsed();
+    if let Some(path) = &args.dot {
+        topology::export(path, crawl.peers.in_memory(), &kad_proto, query).await?;
+    }
+
+    Summary::new(&run, crawl)?.report("EXPLORE", &kad_proto, elapsed, true)
+}
+
+/// Query the first target after the routing table prepopulation, then the other targets on the
+/// same node, at most `parallelism` at a time, until they run out, their deadline is reached or
+/// the walk is cancelled through `control`.
+async fn walk(
+    query: &QueryArgs,
+    kad_proto: &str,
+    known_peers: HashMap<PeerId, Vec<Multiaddr>>,
+    targets: &mut dyn Targets,
+    mut crawl: Crawl,
+    parallelism: usize,
+    control: QueryControl,
+) -> anyhow::Result<(QueryRun, Partial<Crawl>)> {
+    let settings = query.preset.settings();
+    let total = targets.remaining();
+    let deadline = targets.deadline();
+    let first = targets
+        .next_target()
+        .ok_or_else(|| anyhow!("no crawl targets"))?;
+
+    let cancel = control.cancel.clone();
+    let control = QueryControl {
+        dialed_routing: true,
+        ..control
+    };
+    let mut run = query_dht_with(
+        query,
+        &Query::Peer(first),
+        kad_proto,
+        known_peers,
+        &settings,
+        None,
+        control,
+    )
+    .await?;
+    if run
+        .result
+        .as_ref()
+        .is_err_and(|error| error.is::<QueryCancelled>())
+    {
+        crawl.on_query_failed();
+        crawl.take_peers(&mut run)?;
+        return Ok((run, Partial::Cancelled(crawl)));
+    }
+    if run.result.is_err() && run.fan_out.responses() == 0 {
+        if !json_output() {
+            print_statistics(&run, &settings);
+            print_protocol_hint(query, &run.fan_out, kad_proto);
+        }
+        return Err(anyhow!("no peer responded to the first FIND_NODE query"));
+    }
+
+    match run.result {
+        Ok(()) => {
+            let new_peers =
+                crawl.on_closest_peers(&first, std::mem::take(&mut run.closest_peers))?;
+            targets.on_result(&first, new_peers);
+        }
+        Err(_) => crawl.on_query_failed(),
+    }
+    crawl.take_peers(&mut run)?;
+
+    let progress = |crawl: &Crawl| match total {
+        Some(total) => format!("Target {}/{total}", crawl.queries),
+        None => format!("Query {}", crawl.queries),
+    };
+    let mut pending: HashMap<QueryId, PeerId> = HashMap::new();
+    loop {
+        while pending.len() < parallelism.max(1) {
+            let Some(target) = targets.next_target() else {
+                break;
+            };
+            pending.insert(run.kademlia_handle.find_node(target).await, target);
+        }
+        if pending.is_empty() {
+            return Ok((run, Partial::Complete(crawl)));
+        }
+
+        tokio::select! {
+            _ = cancelled(cancel.as_ref()) => {
+                progress!("Cancelled, {} queries left running", pending.len());
+                return Ok((run, Partial::Cancelled(crawl)))
+            },
+            _ = sleep_until(deadline) => {
+                progress!("Time budget exhausted, {} queries left running", pending.len());
+                return Ok((run, Partial::Complete(crawl)))
+            },
+            event = run.litep2p.next_event() => match event {
+                Some(Litep2pEvent::ConnectionEstablished { peer, endpoint }) => {
+                    crawl.on_contacted(peer)?;
+                    if !crawl.is_stalling(&peer) {
+                        add_dialed_peer(&run.kademlia_handle, peer, &endpoint).await;
+                    }
+                    run.connections.on_connection_established(peer, endpoint);
+                },
+                Some(Litep2pEvent::ConnectionClosed { connection_id, .. }) => {
+                    run.connections.on_connection_closed(connection_id);
+                },
+                Some(Litep2pEvent::DialFailure { address, error: DialError::Timeout }) => {
+                    crawl.on_dial_timeout(&address);
+                },
+                Some(Litep2pEvent::ListDialFailures { errors }) => {
+                    for (address, error) in errors {
+                        if matches!(error, DialError::Timeout) {
+                            crawl.on_dial_timeout(&address);
+                        }
+                    }
+                },
+                _ => {},
+            },
+            event = run.identify_events.next() => {
+                if let Some(IdentifyEvent::PeerIdentified { peer, listen_addresses, .. }) = event {
+                    crawl.on_identified(&run.address_filter, peer, listen_addresses)?;
+                }
+            },
+            event = run.kademlia_handle.next() => match event {
+                Some(KademliaEvent::FindNodeSuccess { query_id, peers, .. }) => {
+                    let Some(target) = pending.remove(&query_id) else {
+                        continue
+                    };
+                    emit_event("crawl_target_finished", |line| {
+                        line.field("target", target.to_string())
+                            .field("peers", peers.len())
+                    });
+                    let peers = run.address_filter.filter_peers(peers);
+                    let new_peers = crawl.on_closest_peers(&target, peers)?;
+                    targets.on_result(&target, new_peers);
+                    progress!(
+                        "{}: {new_peers} new peers, {} peers found so far",
+                        progress(&crawl),
+                        crawl.peers.len()
+                    );
+                },
+                Some(KademliaEvent::QueryFailed { query_id }) => {
+                    if pending.remove(&query_id).is_some() {
+                        crawl.on_query_failed();
+                        progress!("{}: FIND_NODE query failed", progress(&crawl));
+                    }
+                },
+                Some(KademliaEvent::RoutingTableUpdate { peers }) => {
+                    for peer in peers {
+                        crawl.peers.insert(peer, [])?;
+                    }
+                },
+                Some(_) => {},
+                None => return Err(anyhow!("libp2p Kademlia terminated")),
+            },
+        }
+    }
+}
+
+/// Statistics of a finished crawl.
+///
+/// Peers spilled to disk are read back twice, once for the statistics and once for the listing.
+/// The listing is streamed rather than collected, and only sorted if no peers were spilled.
+struct Summary<'a> {
+    run: &'a QueryRun,
+    crawl: Crawl,
+    peers_with_addresses: usize,
+    size_estimate: Option<f64>,
+    coverage: Coverage,
+    distribution: AddressDistribution,
+    ips: IpDistribution,
+}
+
+impl<'a> Summary<'a> {
+    fn new(run: &'a QueryRun, mut crawl: Crawl) -> anyhow::Result<Self> {
+        let size_estimate = median(&mut crawl.size_estimates);
+        let mut coverage = Coverage::new(&crawl.queried, size_estimate);
+        let mut distribution = AddressDistribution::default();
+        let mut ips = IpDistribution::default();
+        let mut peers_with_addresses = 0;
+        crawl.peers.for_each(|peer, addresses| {
+            coverage.on_peer(peer);
+            addresses
+                .iter()
+                .for_each(|address| distribution.add(address));
+            ips.add_peer(addresses);
+            if !addresses.is_empty() {
+                peers_with_addresses += 1;
+            }
+        })?;
+
+        Ok(Self {
+            run,
+            crawl,
+            peers_with_addresses,
+            size_estimate,
+            coverage,
+            distribution,
+            ips,
+        })
+    }
+
+    /// Print the summary of the crawl named `name` and, if `list_peers` is set, every peer found.
+    fn report(
+        &mut self,
+        name: &str,
+        kad_proto: &str,
+        elapsed: Duration,
+        list_peers: bool,
+    ) -> anyhow::Result<()> {
+        if json_output() && signing() {
+            let mut document = Vec::new();
+            self.write_json(&mut document, name, kad_proto, elapsed, list_peers)?;
+            let document = String::from_utf8(document).expect("JSON is UTF-8; qed");
+            print_document(document.trim_end());
+            return Ok(());
+        }
+        if json_output() {
+            let mut out = BufWriter::new(io::stdout().lock());
+            self.write_json(&mut out, name, kad_proto, elapsed, list_peers)?;
+            return out.flush().context("failed to write the crawl result");
+        }
+
+        self.print(elapsed, list_peers)
+    }
+
+    /// Call `visit` for every peer found with its addresses, whether it was contacted and, if the
+    /// audit dialed it, whether it was reachable.
+    fn for_each_peer(
+        &mut self,
+        mut visit: impl FnMut(&PeerId, &[Multiaddr], bool, Option<bool>) -> io::Result<()>,
+    ) -> anyhow::Result<()> {
+        let Crawl {
+            peers,
+            contacted,
+            audit,
+            ..
+        } = &mut self.crawl;
+        let is_contacted = |peer: &PeerId| contacted.contains(&fingerprint(peer));
+        let reachable = |peer: &PeerId| audit.as_ref()?.get(&fingerprint(peer)).copied();
+        if peers.spilled() == 0 {
+            let mut sorted: Vec<_> = peers.in_memory().iter().collect();
+            sorted.sort_by_key(|(peer, _)| **peer);
+            for (peer, addresses) in sorted {
+                let addresses: Vec<_> = addresses.iter().cloned().collect();
+                visit(peer, &addresses, is_contacted(peer), reachable(peer))?;
+            }
+            return Ok(());
+        }
+
+        let mut result = Ok(());
+        peers.for_each(|peer, addresses| {
+            if result.is_ok() {
+                result = visit(peer, addresses, is_contacted(peer), reachable(peer));
+            }
+        })?;
+        Ok(result?)
+    }
+
+    /// JSON document of the crawl named `name`, without the list of peers.
+    fn json(&self, name: &str, kad_proto: &str, elapsed: Duration) -> Json {
+        let (run, crawl) = (self.run, &self.crawl);
+        Json::object()
+            .field("schema_version", SCHEMA_VERSION)
+            .field("query", name)
+            .field("protocol", kad_proto)
+            .field("queries", crawl.queries)
+            .field("failed_queries", crawl.failed_queries)
+            .field("dial_timeouts", crawl.dial_timeouts.values().sum::<usize>())
+            .field("stalling_peers", crawl.stalling_peers())
+            .field("elapsed_ms", elapsed.as_millis() as u64)
+            .field("peers", crawl.peers.len())
+            .field("peers_with_addresses", self.peers_with_addresses)
+            .field("spilled_peers", crawl.peers.spilled())
+            .field("contacted_peers", crawl.contacted.len())
+            .field("identified_peers", crawl.identified.len())
+            .field(
+                "network_size_estimate",
+                self.size_estimate.map(|size| size.round()),
+            )
+            .field("coverage", self.coverage.json())
+            .field("addresses", self.distribution.json())
+            .field("ips", self.ips.json())
+            .field("resources", run.usage().json())
+            .field(
+                "audit",
+                crawl.audit.as_ref().map(|audit| {
+                    Json::object().field("dialed_peers", audit.len()).field(
+                        "reachable_peers",
+                        audit.values().filter(|reachable| **reachable).count(),
+                    )
+                }),
+            )
+    }
+
+    /// Write the JSON document of the crawl named `name` to `out`, listing every peer found if
+    /// `list_peers` is set.
+    ///
+    /// The peers are written one at a time rather than collected into the document first.
+    fn write_json(
+        &mut self,
+        out: &mut impl Write,
+        name: &str,
+        kad_proto: &str,
+        elapsed: Duration,
+        list_peers: bool,
+    ) -> anyhow::Result<()> {
+        let document = self.json(name, kad_proto, elapsed).to_string();
+        if !list_peers {
+            writeln!(out, "{document}")?;
+            return Ok(());
+        }
+
+        let fields = document
+            .strip_suffix('}')
+            .expect("document is an object; qed");
+        write!(out, r#"{fields},"discovered":["#)?;
+        let mut first = true;
+        self.for_each_peer(|peer, addresses, contacted, reachable| {
+            let entry = Json::object()
+                .field("peer_id", peer.to_string())
+                .field(
+                    "addresses",
+                    addresses
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>(),
+                )
+                .field("contacted", contacted);
+            let entry = match reachable {
+                Some(reachable) => entry.field("reachable", reachable),
+                None => entry,
+            };
+            let separator = if std::mem::take(&mut first) { "" } else { "," };
+            write!(out, "{separator}{entry}")
+        })?;
+        writeln!(out, "]}}")?;
+
+        Ok(())
+    }
+
+    /// Print the statistics and, if `list_peers` is set, every peer found.
+    fn print(&mut self, elapsed: Duration, list_peers: bool) -> anyhow::Result<()> {
+        let run = self.run;
+        println!();
+        println!(
+            "{} queries in {} s, {} failed",
+            self.crawl.queries,
+            elapsed.as_secs(),
+            self.crawl.failed_queries
+        );
+        println!("Peers found: {}", self.crawl.peers.len());
+        println!("Peers with addresses: {}", self.peers_with_addresses);
+        run.address_filter.print_skipped();
+        if self.crawl.peers.spilled() > 0 {
+            println!("Peers spilled to disk: {}", self.crawl.peers.spilled());
+        }
+        println!("Contacted peers: {}", self.crawl.contacted.len());
+        println!("Identified peers: {}", self.crawl.identified.len());
+        println!(
+            "Dial timeouts: {} across {} peers, {} timed out at least {STALL_THRESHOLD} times",
+            self.crawl.dial_timeouts.values().sum::<usize>(),
+            self.crawl.dial_timeouts.len(),
+            self.crawl.stalling_peers()
+        );
+        match self.size_estimate {
+            Some(size) => println!("Estimated network size: {size:.0} peers"),
+            None => println!("Estimated network size: unknown"),
+        }
+        println!();
+        self.coverage.print();
+        println!();
+        self.distribution.print();
+        println!();
+        self.ips.print();
+        println!();
+        run.usage().print();
+        if let Some(audit) = &self.crawl.audit {
+            println!();
+            println!(
+                "Audit: {} of {} peers with addresses reachable",
+                audit.values().filter(|reachable| **reachable).count(),
+                audit.len()
+            );
+        }
+
+        if list_peers {
+            println!();
+            let mut out = io::stdout().lock();
+            self.for_each_peer(|peer, addresses, contacted, reachable| {
+                let contacted = if contacted { ", contacted" } else { "" };
+                let reachable = match reachable {
+                    Some(true) => ", reachable",
+                    Some(false) => ", unreachable",
+                    None => "",
+                };
+                writeln!(out, "{peer}{contacted}{reachable}")?;
+                for address in addresses {
+                    writeln!(out, "  {address}")?;
+                }
+                Ok(())
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Slice of the keyspace out of `count` equal ones that `peer` falls into.
+///
+/// `count` must be a power of two of at most [`MAX_TARGETS`].
+fn slice(peer: &PeerId, count: usize) -> usize {
+    let hash = Sha256::digest(peer.to_bytes());
+    let prefix = u16::from_be_bytes([hash[0], hash[1]]) as usize;
+
+    prefix >> (16 - count.trailing_zeros())
+}
+
+/// Random peer ID falling into `slice` out of `count` equal slices of the keyspace.
+///
+/// Found by rejection sampling, which takes `count` tries on average.
+fn target_in_slice(slice_index: usize, count: usize) -> PeerId {
+    loop {
+        let peer = PeerId::random();
+        if slice(&peer, count) == slice_index {
+            return peer;
+        }
+    }
+}
+
+/// Estimate the network size from the closest peers found for `target`.
+///
+/// With `n` peers spread uniformly over the keyspace, the `k` closest ones cover about `k / n` of
+/// it, so the size is `k` divided by the share of the keyspace closer than the farthest of them.
+fn size_estimate<'a>(target: &PeerId, peers: impl Iterator<Item = &'a PeerId>) -> Option<f64> {
+    let distances: Vec<_> = peers.map(|peer| distance(target, peer)).collect();
+    let farthest = distances.iter().max()?;
+    let share =
+        u64::from_be_bytes(farthest[..8].try_into().expect("32 bytes; qed")) as f64 / 2f64.powi(64);
+    if share == 0.0 {
+        return None;
+    }
+
+    Some(distances.len() as f64 / share)
+}
+
+fn median(values: &mut [f64]) -> Option<f64> {
+    values.sort_by(f64::total_cmp);
+    values.get(values.len() / 2).copied()
+}
+
+/// Share of the keyspace buckets the crawl covered.
+///
+/// A bucket is queried if a successful FIND_NODE target fell into it, and inferred if it wasn't
+/// queried but some peer found falls into it. Peers of buckets no query landed in are only known
+/// from responses to queries of neighboring buckets, so those are the likeliest to be incomplete.
+struct Coverage {
+    /// Buckets successful FIND_NODE targets fell into.
+    queried: Vec<bool>,
+    /// Buckets peers found fall into.
+    seen: Vec<bool>,
+    found: usize,
+    size_estimate: Option<f64>,
+}
+
+impl Coverage {
+    fn new(targets: &[PeerId], size_estimate: Option<f64>) -> Self {
+        let mut queried = vec![false; COVERAGE_BUCKETS];
+        for target in targets {
+            queried[slice(target, COVERAGE_BUCKETS)] = true;
+        }
+
+        Self {
+            queried,
+            seen: vec![false; COVERAGE_BUCKETS],
+            found: 0,
+            size_estimate,
+        }
+    }
+
+    /// Count a peer found.
+    fn on_peer(&mut self, peer: &PeerId) {
+        self.found += 1;
+        self.seen[slice(peer, COVERAGE_BUCKETS)] = true;
+    }
+
+    /// Number of queried and inferred buckets.
+    fn buckets(&self) -> (usize, usize) {
+        let queried = self.queried.iter().filter(|queried| **queried).count();
+        let inferred = (0..COVERAGE_BUCKETS)
+            .filter(|bucket| !self.queried[*bucket] && self.seen[*bucket])
+            .count();
+
+        (queried, inferred)
+    }
+
+    /// Estimated network size minus the peers found.
+    fn missed_peers(&self) -> Option<f64> {
+        self.size_estimate
+            .map(|size| (size - self.found as f64).max(0.0))
+    }
+
+    fn share(buckets: usize) -> f64 {
+        buckets as f64 / COVERAGE_BUCKETS as f64
+    }
+
+    fn print(&self) {
+        let (queried, inferred) = self.buckets();
+        println!("Keyspace coverage ({COVERAGE_BUCKETS} buckets):");
+        println!("  queried: {:.1}%", Self::share(queried) * 100.0);
+        println!("  inferred: {:.1}%", Self::share(inferred) * 100.0);
+        println!(
+            "  not covered: {:.1}%",
+            Self::share(COVERAGE_BUCKETS - queried - inferred) * 100.0
+        );
+        match self.missed_peers() {
+            Some(missed) => println!("  estimated missed peers: {missed:.0}"),
+            None => println!("  estimated missed peers: unknown"),
+        }
+    }
+
+    fn json(&self) -> Json {
+        let (queried, inferred) = self.buckets();
+        Json::object()
+            .field("buckets", COVERAGE_BUCKETS)
+            .field("queried", Self::share(queried))
+            .field("inferred", Self::share(inferred))
+            .field(
+                "not_covered",
+                Self::share(COVERAGE_BUCKETS - queried - inferred),
+            )
+            .field("missed_peers", self.missed_peers().map(f64::round))
+    }
+}
+
+/// Distribution of the addresses found by the crawl.
+#[derive(Default)]
+struct AddressDistribution {
+    total: usize,
+    /// Addresses per network protocol: ip4, ip6 or dns.
+    networks: BTreeMap<&'static str, usize>,
+    /// Addresses per transport: tcp, ws or wss.
+    transports: BTreeMap<&'static str, usize>,
+    private: usize,
+    ports: HashMap<u16, usize>,
+}
+
+impl AddressDistribution {
+    /// Count `address`.
+    fn add(&mut self, address: &Multiaddr) {
+        self.total += 1;
+        if is_private(address) {
+            self.private += 1;
+        }
+
+        let (mut tcp, mut ws, mut tls) = (false, false, false);
+        for protocol in address.iter() {
+            match protocol {
+                Protocol::Ip4(_) => *self.networks.entry("ip4").or_default() += 1,
+                Protocol::Ip6(_) => *self.networks.entry("ip6").or_default() += 1,
+                Protocol::Dns(_) | Protocol::Dns4(_) | Protocol::Dns6(_) => {
+                    *self.networks.entry("dns").or_default() += 1
+                }
+                Protocol::Tcp(port) => {
+                    tcp = true;
+                    *self.ports.entry(port).or_default() += 1;
+                }
+                Protocol::Ws(_) => ws = true,
+                Protocol::Wss(_) => (ws, tls) = (true, true),
+                Protocol::Tls => tls = true,
+                _ => {}
+            }
+        }
+        // `/tls/ws` is the same as `/wss`.
+        let transport = match (tcp, ws, tls) {
+            (_, true, true) => "wss",
+            (_, true, false) => "ws",
+            (true, false, _) => "tcp",
+            _ => "other",
+        };
+        *self.transports.entry(transport).or_default() += 1;
+    }
+
+    /// Most used ports, most used first.
+    fn top_ports(&self) -> Vec<(u16, usize)> {
+        let mut ports: Vec<_> = self
+            .ports
+            .iter()
+            .map(|(port, count)| (*port, *count))
+            .collect();
+        ports.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        ports.truncate(TOP_PORTS);
+
+        ports
+    }
+
+    fn print(&self) {
+        println!("Addresses: {}, private: {}", self.total, self.private);
+        for (network, count) in &self.networks {
+            println!("  {network}: {count}");
+        }
+        println!("Transports:");
+        for (transport, count) in &self.transports {
+            println!("  {transport}: {count}");
+        }
+        println!("Most used TCP ports:");
+        for (port, count) in self.top_ports() {
+            println!("  {port}: {count}");
+        }
+    }
+
+    fn json(&self) -> Json {
+        let counts = |counts: &BTreeMap<&str, usize>| {
+            counts.iter().fold(Json::object(), |object, (name, count)| {
+                object.field(name, *count)
+            })
+        };
+
+        Json::object()
+            .field("total", self.total)
+            .field("private", self.private)
+            .field("networks", counts(&self.networks))
+            .field("transports", counts(&self.transports))
+            .field(
+                "top_ports",
+                self.top_ports()
+                    .into_iter()
+                    .map(|(port, count)| {
+                        Json::object()
+                            .field("port", port as usize)
+                            .field("addresses", count)
+                    })
+                    .collect::<Vec<_>>(),
+            )
+    }
+}
+
+/// Peers per public IP, to spot shared hosting, sybils and operators running many nodes.
+#[derive(Default)]
+struct IpDistribution {
+    peers: HashMap<IpAddr, usize>,
+}
+
+impl IpDistribution {
+    /// Count a peer once for every public IP among its `addresses`.
+    fn add_peer(&mut self, addresses: &[Multiaddr]) {
+        let ips: HashSet<_> = addresses
+            .iter()
+            .filter(|address| !is_private(address))
+            .filter_map(|address| match address.iter().next() {
+                Some(Protocol::Ip4(ip)) => Some(IpAddr::V4(ip)),
+                Some(Protocol::Ip6(ip)) => Some(IpAddr::V6(ip)),
+                _ => None,
+            })
+            .collect();
+        for ip in ips {
+            *self.peers.entry(ip).or_default() += 1;
+        }
+    }
+
+    /// Number of IPs hosting more than one peer.
+    fn shared(&self) -> usize {
+        self.peers.values().filter(|peers| **peers > 1).count()
+    }
+
+    /// Number of IPs per number of peers they host, in power of two buckets: 1, 2, 3-4, 5-8...
+    fn histogram(&self) -> Vec<(String, usize)> {
+        let mut buckets = BTreeMap::new();
+        for peers in self.peers.values() {
+            *buckets
+                .entry(peers.next_power_of_two().trailing_zeros())
+                .or_default() += 1;
+        }
+
+        buckets
+            .into_iter()
+            .map(|(bucket, ips)| {
+                let label = match bucket {
+                    0 => "1".to_string(),
+                    1 => "2".to_string(),
+                    bucket => format!("{}-{}", (1usize << (bucket - 1)) + 1, 1usize << bucket),
+                };
+                (label, ips)
+            })
+            .collect()
+    }
+
+    /// IPs hosting more than one peer, the most peers first.
+    fn top_ips(&self) -> Vec<(IpAddr, usize)> {
+        let mut ips: Vec<_> = self
+            .peers
+            .iter()
+            .filter(|(_, peers)| **peers > 1)
+            .map(|(ip, peers)| (*ip, *peers))
+            .collect();
+        ips.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        ips.truncate(TOP_IPS);
+
+        ips
+    }
+
+    fn print(&self) {
+        println!(
+            "Public IPs: {}, shared by several peers: {}",
+            self.peers.len(),
+            self.shared()
+        );
+        println!("IPs by number of peers hosted:");
+        for (peers, ips) in self.histogram() {
+            println!("  {peers}: {ips}");
+        }
+        if self.shared() > 0 {
+            println!("IPs hosting the most peers:");
+            for (ip, peers) in self.top_ips() {
+                println!("  {ip}: {peers}");
+            }
+        }
+    }
+
+    fn json(&self) -> Json {
+        Json::object()
+            .field("total", self.peers.len())
+            .field("shared", self.shared())
+            .field(
+                "peers_per_ip",
+                self.histogram()
+                    .into_iter()
+                    .fold(Json::object(), |object, (peers, ips)| {
+                        object.field(&peers, ips)
+                    }),
+            )
+            .field(
+                "top_ips",
+                self.top_ips()
+                    .into_iter()
+                    .map(|(ip, peers)| {
+                        Json::object()
+                            .field("ip", ip.to_string())
+                            .field("peers", peers)
+                    })
+                    .collect::<Vec<_>>(),
+            )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::{schema::validate, test_utils::spawn_node};
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        query: QueryArgs,
+    }
+
+    #[test]
+    fn slices_keyspace_by_hash_prefix() {
+        for _ in 0..100 {
+            let peer = PeerId::random();
+            let hash = Sha256::digest(peer.to_bytes());
+            assert_eq!(slice(&peer, 1), 0);
+            assert_eq!(slice(&peer, 2), (hash[0] >> 7) as usize);
+            assert_eq!(slice(&peer, 256), hash[0] as usize);
+            assert_eq!(
+                slice(&peer, MAX_TARGETS),
+                u16::from_be_bytes([hash[0], hash[1]]) as usize
+                    >> (16 - MAX_TARGETS.trailing_zeros())
+            );
+        }
+    }
+
+    #[test]
+    fn finds_targets_in_slices() {
+        for count in [1, 2, 16, 256] {
+            for slice_index in [0, count / 2, count - 1] {
+                let target = target_in_slice(slice_index, count);
+                assert_eq!(slice(&target, count), slice_index);
+            }
+        }
+    }
+
+    #[test]
+    fn estimates_network_size() {
+        let target = PeerId::random();
+        assert_eq!(size_estimate(&target, std::iter::empty()), None);
+        // The target itself is at distance zero and covers no share of the keyspace.
+        assert_eq!(size_estimate(&target, [target].iter()), None);
+
+        let size = 5000;
+        let network: Vec<_> = (0..size).map(|_| PeerId::random()).collect();
+        let mut estimates: Vec<_> = (0..9)
+            .map(|_| {
+                let target = PeerId::random();
+                let mut closest = network.clone();
+                closest.sort_by_cached_key(|peer| distance(&target, peer));
+                size_estimate(&target, closest[..20].iter()).unwrap()
+            })
+            .collect();
+        let estimate = median(&mut estimates).unwrap();
+        assert!(
+            (size as f64 / 2.0..size as f64 * 2.0).contains(&estimate),
+            "{estimate}"
+        );
+    }
+
+    #[test]
+    fn computes_coverage() {
+        let targets: Vec<_> = (0..4)
+            .map(|bucket| target_in_slice(bucket, COVERAGE_BUCKETS))
+            .collect();
+        let mut coverage = Coverage::new(&targets, Some(10.0));
+        assert_eq!(coverage.buckets(), (4, 0));
+
+        // A peer in a queried bucket doesn't add to the inferred ones, peers in others do.
+        coverage.on_peer(&target_in_slice(0, COVERAGE_BUCKETS));
+        coverage.on_peer(&target_in_slice(10, COVERAGE_BUCKETS));
+        coverage.on_peer(&target_in_slice(10, COVERAGE_BUCKETS));
+        coverage.on_peer(&target_in_slice(11, COVERAGE_BUCKETS));
+        assert_eq!(coverage.buckets(), (4, 2));
+        assert_eq!(coverage.missed_peers(), Some(6.0));
+
+        for bucket in 0..12 {
+            coverage.on_peer(&target_in_slice(bucket, COVERAGE_BUCKETS));
+        }
+        assert_eq!(coverage.missed_peers(), Some(0.0));
+        assert_eq!(Coverage::new(&targets, None).missed_peers(), None);
+    }
+
+    #[test]
+    fn counts_peers_per_ip() {
+        let address = |address: &str| -> Multiaddr { address.parse().unwrap() };
+        let mut ips = IpDistribution::default();
+        // The same IP twice for one peer counts once.
+        ips.add_peer(&[
+            address("/ip4/1.1.1.1/tcp/30333"),
+            address("/ip4/1.1.1.1/tcp/30334/ws"),
+        ]);
+        for _ in 0..4 {
+            ips.add_peer(&[address("/ip4/2.2.2.2/tcp/30333")]);
+        }
+        ips.add_peer(&[
+            address("/ip4/1.1.1.1/tcp/30333"),
+            address("/ip6/2001:db8::1/tcp/30333"),
+        ]);
+        // Private addresses and DNS names have no public IP.
+        ips.add_peer(&[
+            address("/ip4/192.168.1.1/tcp/30333"),
+            address("/dns/example.com/tcp/30333"),
+        ]);
+
+        assert_eq!(ips.peers.len(), 3);
+        assert_eq!(ips.shared(), 2);
+        assert_eq!(
+            ips.histogram(),
+            [
+                ("1".to_string(), 1),
+                ("2".to_string(), 1),
+                ("3-4".to_string(), 1)
+            ]
+        );
+        assert_eq!(
+            ips.top_ips(),
+            [
+                ("2.2.2.2".parse().unwrap(), 4),
+                ("1.1.1.1".parse().unwrap(), 2)
+            ]
+        );
+    }
+
+    #[test]
+    fn bounds_resident_peers() {
+        let mut crawl = Crawl::new(Some(1));
+        let filter = AddressFilter::new(false);
+        let address = |port: usize| -> Multiaddr {
+            format!("/ip4/1.1.1.1/tcp/{}", port % 65536)
+                .parse()
+                .unwrap()
+        };
+        // About 3 MiB of peers and addresses, three times the cap.
+        for _ in 0..100 {
+            let peers: Vec<_> = (0..100)
+                .map(|port| {
+                    (
+                        PeerId::random(),
+                        (port..port + 3).map(address).collect::<Vec<_>>(),
+                    )
+                })
+                .collect();
+            for (peer, addresses) in &peers {
+                crawl.on_contacted(*peer).unwrap();
+                crawl
+                    .on_identified(&filter, *peer, addresses.clone())
+                    .unwrap();
+            }
+            crawl.on_closest_peers(&PeerId::random(), peers).unwrap();
+        }
+
+        assert_eq!(crawl.peers.len(), 10_000);
+        assert_eq!(crawl.contacted.len(), 10_000);
+        assert!(crawl.peers.spilled() > 0);
+        assert!(crawl.peers.memory() <= 1 << 20);
+    }
+
+    #[test]
+    fn splits_targets_into_shards() {
+        for shard in 0..4 {
+            let targets = shard_targets(shard, 4, 16);
+            assert_eq!(targets.len(), 4);
+            for (index, target) in targets.iter().enumerate() {
+                assert_eq!(slice(target, 16), shard * 4 + index);
+                assert_eq!(slice(target, 4), shard);
+            }
+        }
+        assert_eq!(shard_targets(0, 1, 1).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn crawls_missing_shards() {
+        let (bootnode, address) = spawn_node(HashMap::new());
+        let cli = Cli::parse_from([
+            "dht-inspect",
+            "--bootnode",
+            &format!("{address}/p2p/{bootnode}"),
+            "--kad-proto",
+            "/test/kad",
+            "--allow-private-addresses",
+            "--timeout",
+            "20",
+        ]);
+        let args = CrawlArgs {
+            targets: 4,
+            parallelism: 2,
+            list_peers: false,
+            dot: None,
+            memory_cap: None,
+            shards: Some(2),
+            shard_dir: None,
+            pipeline: Vec::new(),
+            export: None,
+        };
+        let dir = std::env::temp_dir().join(format!("dht-inspect-shards-{}", PeerId::random()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("shard-0-of-2.json"), "{}").unwrap();
+
+        let outcome = crawl_shards(&cli.query, "/test/kad", &args, 4, 2, &dir)
+            .await
+            .unwrap();
+        assert_eq!(
+            outcome,
+            Shards {
+                crawled: 1,
+                skipped: 1,
+                failed: 0
+            }
+        );
+        let result = std::fs::read_to_string(dir.join("shard-1-of-2.json")).unwrap();
+        let document = Json::parse(&result).unwrap();
+        validate(&document, "crawl_result").unwrap();
+        assert_eq!(document.get("queries"), Some(&Json::Number(2.0)));
+        assert_eq!(
+            std::fs::read_to_string(dir.join("shard-0-of-2.json")).unwrap(),
+            "{}"
+        );
+        validate(
+            &outcome.json("/test/kad", 2, &dir, Duration::from_secs(1)),
+            "sharded_crawl_result",
+        )
+        .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn checks_pipelines() {
+        let export = Some(Path::new("crawl.json"));
+        check_pipeline(&[], None).unwrap();
+        check_pipeline(&[Stage::Crawl, Stage::Audit], None).unwrap();
+        check_pipeline(&[Stage::Crawl, Stage::Audit, Stage::ExportJson], export).unwrap();
+        check_pipeline(&[Stage::Audit], None).unwrap_err();
+        check_pipeline(&[Stage::Crawl, Stage::ExportJson, Stage::Audit], export).unwrap_err();
+        check_pipeline(&[Stage::Crawl, Stage::Crawl], None).unwrap_err();
+        check_pipeline(&[Stage::Crawl, Stage::ExportJson], None).unwrap_err();
+        check_pipeline(&[Stage::Crawl], export).unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn audits_crawled_peers() {
+        let (peer, peer_address) = spawn_node(HashMap::new());
+        let (bootnode, address) = spawn_node(HashMap::from([(peer, vec![peer_address])]));
+        let query = Cli::parse_from([
+            "dht-inspect",
+            "--bootnode",
+            &format!("{address}/p2p/{bootnode}"),
+            "--kad-proto",
+            "/test/kad",
+            "--allow-private-addresses",
+        ])
+        .query;
+
+        let mut targets = fixed_targets(1);
+        let (run, crawl) = walk(
+            &query,
+            "/test/kad",
+            known_peers(&query).await.unwrap(),
+            &mut targets,
+            Crawl::new(None),
+            1,
+            QueryControl::default(),
+        )
+        .await
+        .unwrap();
+        let mut crawl = crawl.into_inner();
+        crawl
+            .peers
+            .insert(PeerId::random(), ["/ip4/127.0.0.1/tcp/1".parse().unwrap()])
+            .unwrap();
+        crawl
+            .audit(&query.preset.settings(), &query.socket)
+            .await
+            .unwrap();
+        let audit = crawl.audit.as_ref().unwrap();
+        assert_eq!(audit.len(), 3);
+        assert_eq!(audit.get(&fingerprint(&peer)), Some(&true));
+        assert_eq!(audit.get(&fingerprint(&bootnode)), Some(&true));
+
+        let mut out = Vec::new();
+        Summary::new(&run, crawl)
+            .unwrap()
+            .write_json(&mut out, "CRAWL", "/test/kad", Duration::from_secs(1), true)
+            .unwrap();
+        let document = Json::parse(std::str::from_utf8(&out).unwrap()).unwrap();
+        validate(&document, "crawl_result").unwrap();
+        assert_eq!(
+            document.get("audit").map(ToString::to_string).as_deref(),
+            Some(r#"{"dialed_peers":3,"reachable_peers":2}"#)
+        );
+    }
+
+    #[tokio::test]
+    async fn walks_local_network() {
+        let peers: HashMap<_, _> = (0..4)
+            .map(|_| {
+                let (peer, address) = spawn_node(HashMap::new());
+                (peer, vec![address])
+            })
+            .collect();
+        let (bootnode, address) = spawn_node(peers.clone());
+        let query = Cli::parse_from([
+            "dht-inspect",
+            "--bootnode",
+            &format!("{address}/p2p/{bootnode}"),
+            "--kad-proto",
+            "/test/kad",
+            "--allow-private-addresses",
+            "--timeout",
+            "20",
+        ])
+        .query;
+
+        let mut targets: VecDeque<_> = (0..4).map(|slice| target_in_slice(slice, 4)).collect();
+        let (run, crawl) = walk(
+            &query,
+            "/test/kad",
+            known_peers(&query).await.unwrap(),
+            &mut targets,
+            Crawl::new(None),
+            2,
+            QueryControl::default(),
+        )
+        .await
+        .unwrap();
+        assert!(crawl.is_complete());
+        let mut crawl = crawl.into_inner();
+
+        assert_eq!(crawl.queries, 4);
+        assert_eq!(crawl.failed_queries, 0);
+        let mut found = HashSet::new();
+        crawl
+            .peers
+            .for_each(|peer, _| {
+                found.insert(*peer);
+            })
+            .unwrap();
+        assert!(peers.keys().all(|peer| found.contains(peer)));
+        assert!(crawl.contacted.contains(&fingerprint(&bootnode)));
+
+        let mut out = Vec::new();
+        Summary::new(&run, crawl)
+            .unwrap()
+            .write_json(&mut out, "CRAWL", "/test/kad", Duration::from_secs(1), true)
+            .unwrap();
+        let document = Json::parse(std::str::from_utf8(&out).unwrap()).unwrap();
+        validate(&document, "crawl_result").unwrap();
+        assert_eq!(
+            document
+                .get("discovered")
+                .and_then(Json::as_array)
+                .map(<[_]>::len),
+            Some(5)
+        );
+    }
+}
@@ -0,0 +1,56 @@
+use std::{collections::HashSet, path::Path, str::FromStr};
+
+use anyhow::{anyhow, Context};
+use litep2p::{protocol::libp2p::kademlia::ContentProvider, PeerId};
+
+use crate::parse_multiaddress;
+
+/// Read provider peer IDs exported by kubo, e.g. with `ipfs routing findprovs <cid>`.
+///
+/// One peer ID or `/p2p/` multiaddress per line. The `provider: <peer id>` lines of verbose
+/// `ipfs dht findprovs -v` output are understood as well, other lines of it (indented addresses
+/// and `*` progress lines) are ignored, as are empty lines and `#` comments.
+pub fn read_provider_export(path: &Path) -> anyhow::Result<HashSet<PeerId>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+
+    content
+        .lines()
+        .filter(|line| !line.starts_with(char::is_whitespace))
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#') && !line.starts_with('*'))
+        .map(|line| {
+            let entry = line.strip_prefix("provider:").unwrap_or(line).trim();
+            if entry.starts_with('/') {
+                parse_multiaddress(entry).map(|(peer, _)| peer)
+            } else {
+                PeerId::from_str(entry).map_err(|error| anyhow!("{error:?}"))
+            }
+            .with_context(|| format!("invalid exported provider `{line}`"))
+        })
+        .collect()
+}
+
+/// Compare the providers found from the outside with the ones exported by the publishing side.
+pub fn print_cross_check(found: &[ContentProvider], exported: &HashSet<PeerId>) {
+    let found: HashSet<_> = found.iter().map(|provider| provider.peer).collect();
+
+    println!();
+    println!(
+        "Cross-check with export: {} exported, {} found, {} in both",
+        exported.len(),
+        found.len(),
+        exported.intersection(&found).count(),
+    );
+    for peer in exported.difference(&found) {
+        println!("{peer}: exported, but not found from the outside");
+    }
+    for peer in found.difference(exported) {
+        println!("{peer}: found, but not exported");
+    }
+    if !exported.is_empty() && found.is_disjoint(exported) {
+        println!(
+            "None of the exported providers found: records don't reach the DHT peers close to the key"
+        );
+    }
+}
@@ -0,0 +1,79 @@
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use litep2p::{protocol::libp2p::kademlia::ContentProvider, PeerId};
+use multiaddr::Multiaddr;
+
+/// When a peer was first contacted and the addresses it was reached at.
+pub struct Sighting {
+    pub first_seen: SystemTime,
+    pub addresses: Vec<Multiaddr>,
+}
+
+/// Write `providers.csv` and `peers.csv` with the providers found and the contacted peers to
+/// `directory`, creating it if needed.
+///
+/// Rows hold the peer ID, space-separated addresses and the time the peer was first seen, in
+/// milliseconds since UNIX epoch. Providers are seen when the query returns them. Contacted peers
+/// list the addresses they were connected at and the listen addresses advertised via identify.
+pub fn write_csv(
+    directory: &Path,
+    providers: &[ContentProvider],
+    providers_seen: SystemTime,
+    contacted: &HashMap<PeerId, Sighting>,
+    identified: &HashMap<PeerId, Vec<Multiaddr>>,
+) -> anyhow::Result<()> {
+    std::fs::create_dir_all(directory)
+        .with_context(|| format!("failed to create {}", directory.display()))?;
+
+    let rows = providers
+        .iter()
+        .map(|provider| (&provider.peer, provider.addresses.clone(), providers_seen));
+    write_file(&directory.join("providers.csv"), rows)?;
+
+    let mut peers: Vec<_> = contacted
+        .iter()
+        .map(|(peer, sighting)| {
+            let mut addresses = sighting.addresses.clone();
+            for address in identified.get(peer).into_iter().flatten() {
+                if !addresses.contains(address) {
+                    addresses.push(address.clone());
+                }
+            }
+            (peer, addresses, sighting.first_seen)
+        })
+        .collect();
+    peers.sort_by_key(|(_, _, first_seen)| *first_seen);
+    write_file(&directory.join("peers.csv"), peers)
+}
+
+fn write_file<'a>(
+    path: &Path,
+    rows: impl IntoIterator<Item = (&'a PeerId, Vec<Multiaddr>, SystemTime)>,
+) -> anyhow::Result<()> {
+    let mut csv = String::from("peer_id,addresses,first_seen_ms\n");
+    for (peer, addresses, first_seen) in rows {
+        let addresses: Vec<_> = addresses.iter().map(ToString::to_string).collect();
+        let first_seen = first_seen
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        writeln!(csv, "{peer},{},{first_seen}", quote(&addresses.join(" ")))?;
+    }
+
+    std::fs::write(path, csv).with_context(|| format!("failed to write {}", path.display()))
+}
+
+/// Quote a CSV field if it contains separators or quotes.
+fn quote(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
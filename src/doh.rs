@@ -0,0 +1,361 @@
+use std::{collections::HashMap, net::IpAddr, sync::Arc, time::Duration};
+
+use anyhow::{anyhow, Context};
+use hickory_proto::{
+    op::{Message, Query},
+    rr::{Name, RData, RecordType},
+    serialize::binary::BinEncodable,
+};
+use multiaddr::{Multiaddr, Protocol};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+use tokio_rustls::{
+    rustls::{self, ClientConfig, RootCertStore, ServerName},
+    TlsConnector,
+};
+use url::{Host, Url};
+
+use crate::{resources, retry::RetryPolicy};
+
+/// Time for a DoH query to connect, complete the TLS handshake and receive the response.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Largest HTTP response accepted: a DNS message of at most 64 KiB plus headers.
+pub const MAX_RESPONSE_SIZE: usize = 80 * 1024;
+
+/// DNS-over-HTTPS resolver (RFC 8484) caching answers for the duration of the run.
+///
+/// The server is connected to by IP, either the one in its URL or a bootstrap IP, so resolving it
+/// doesn't go through the system resolver either.
+pub struct DohResolver {
+    url: Url,
+    /// IP of the server.
+    ip: IpAddr,
+    connector: TlsConnector,
+    retry: RetryPolicy,
+    cache: HashMap<(String, RecordType), Vec<IpAddr>>,
+}
+
+impl DohResolver {
+    /// Create new [`DohResolver`] sending queries to `url`, retrying them according to `retry`.
+    ///
+    /// If the host of `url` is a name rather than an IP, the server is connected to at
+    /// `bootstrap`, which is then required.
+    pub fn new(url: &str, bootstrap: Option<IpAddr>, retry: RetryPolicy) -> anyhow::Result<Self> {
+        let url = Url::parse(url).context("invalid DNS-over-HTTPS URL")?;
+        if url.scheme() != "https" {
+            return Err(anyhow!("DNS-over-HTTPS URL must be https://<host>/<path>"));
+        }
+        let ip = match (url.host(), bootstrap) {
+            (Some(Host::Ipv4(ip)), _) => IpAddr::V4(ip),
+            (Some(Host::Ipv6(ip)), _) => IpAddr::V6(ip),
+            (Some(Host::Domain(_)), Some(ip)) => ip,
+            (Some(Host::Domain(host)), None) => {
+                return Err(anyhow!(
+                    "pass the IP of {host} with --doh-bootstrap or use an IP in the \
+                     DNS-over-HTTPS URL, resolving it would go through the system resolver"
+                ))
+            }
+            (None, _) => return Err(anyhow!("DNS-over-HTTPS URL must be https://<host>/<path>")),
+        };
+
+        Ok(Self {
+            url,
+            ip,
+            connector: tls_connector()?,
+            retry,
+            cache: HashMap::new(),
+        })
+    }
+
+    /// Replace the `/dns`, `/dns4` or `/dns6` component of the address with resolved IPs.
+    ///
+    /// Other addresses are returned unchanged.
+    pub async fn resolve_address(&mut self, address: &Multiaddr) -> anyhow::Result<Vec<Multiaddr>> {
+        let mut components = address.iter();
+        let host = components.next();
+        let rest: Vec<_> = components.collect();
+        let ips = match host {
+            Some(Protocol::Dns(host)) => {
+                let mut ips = self.lookup(&host, RecordType::A).await?;
+                ips.extend(self.lookup(&host, RecordType::AAAA).await?);
+                ips
+            }
+            Some(Protocol::Dns4(host)) => self.lookup(&host, RecordType::A).await?,
+            Some(Protocol::Dns6(host)) => self.lookup(&host, RecordType::AAAA).await?,
+            _ => return Ok(vec![address.clone()]),
+        };
+
+        if ips.is_empty() {
+            return Err(anyhow!("no DNS records found for {address}"));
+        }
+
+        Ok(ips
+            .into_iter()
+            .map(|ip| {
+                std::iter::once(Protocol::from(ip))
+                    .chain(rest.iter().cloned())
+                    .collect()
+            })
+            .collect())
+    }
+
+    /// Look up records of `record_type` for `host`.
+    async fn lookup(&mut self, host: &str, record_type: RecordType) -> anyhow::Result<Vec<IpAddr>> {
+        let cache_key = (host.to_string(), record_type);
+        if let Some(ips) = self.cache.get(&cache_key) {
+            return Ok(ips.clone());
+        }
+
+        let mut message = Message::new();
+        message
+            .set_id(0)
+            .set_recursion_desired(true)
+            .add_query(Query::query(Name::from_ascii(host)?, record_type));
+        let body = message.to_bytes()?;
+        let this = &*self;
+        let response = self.retry.run(|| this.query(body.clone())).await?;
+        let response = Message::from_vec(&response)?;
+
+        let ips: Vec<_> = response
+            .answers()
+            .iter()
+            .filter_map(|record| match record.data() {
+                Some(RData::A(a)) => Some(IpAddr::V4(a.0)),
+                Some(RData::AAAA(aaaa)) => Some(IpAddr::V6(aaaa.0)),
+                _ => None,
+            })
+            .collect();
+        self.cache.insert(cache_key, ips.clone());
+
+        Ok(ips)
+    }
+
+    /// POST a DNS wire format query to the DoH server and return the response body.
+    ///
+    /// Fails if the exchange takes longer than [`QUERY_TIMEOUT`], so a server that doesn't answer
+    /// is retried like one that refuses the connection.
+    async fn query(&self, body: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        tokio::time::timeout(QUERY_TIMEOUT, self.exchange(body))
+            .await
+            .map_err(|_| anyhow!("DoH query timed out after {} s", QUERY_TIMEOUT.as_secs()))?
+    }
+
+    async fn exchange(&self, body: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        let host = self.url.host_str().expect("checked in constructor; qed");
+        let port = self.url.port_or_known_default().unwrap_or(443);
+        let server_name = match self.url.host() {
+            Some(Host::Domain(host)) => ServerName::try_from(host),
+            _ => Ok(ServerName::IpAddress(self.ip)),
+        }
+        .context("invalid DoH server name")?;
+        resources::on_dns_query();
+
+        let tcp = TcpStream::connect((self.ip, port))
+            .await
+            .context("failed to connect to DoH server")?;
+        let mut stream = self.connector.connect(server_name, tcp).await?;
+
+        let request = format!(
+            "POST {} HTTP/1.1\r\nHost: {host}\r\nContent-Type: application/dns-message\r\n\
+             Accept: application/dns-message\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            &self.url[url::Position::BeforePath..],
+            body.len(),
+        );
+        stream.write_all(request.as_bytes()).await?;
+        stream.write_all(&body).await?;
+
+        parse_http_response(&read_http_response(&mut stream).await?)
+    }
+}
+
+/// Read an HTTP response until the server closes the connection, up to [`MAX_RESPONSE_SIZE`].
+pub async fn read_http_response(stream: &mut (impl AsyncRead + Unpin)) -> anyhow::Result<Vec<u8>> {
+    let mut response = Vec::new();
+    let limit = MAX_RESPONSE_SIZE as u64 + 1;
+    // Servers may close TLS without `close_notify`, the response is complete anyway.
+    if let Err(error) = stream.take(limit).read_to_end(&mut response).await {
+        if response.is_empty() {
+            return Err(error.into());
+        }
+    }
+    if response.len() > MAX_RESPONSE_SIZE {
+        return Err(anyhow!("HTTP response exceeds {MAX_RESPONSE_SIZE} bytes"));
+    }
+
+    Ok(response)
+}
+
+/// TLS connector trusting the system root certificates.
+pub fn tls_connector() -> anyhow::Result<TlsConnector> {
+    let mut roots = RootCertStore::empty();
+    for certificate in
+        rustls_native_certs::load_native_certs().context("failed to load root certificates")?
+    {
+        // Skip certificates rustls can't parse, like the system TLS stack would.
+        let _ = roots.add(&rustls::Certificate(certificate.0));
+    }
+    let config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    Ok(TlsConnector::from(Arc::new(config)))
+}
+
+/// Parse an HTTP/1.1 response and return its body.
+pub fn parse_http_response(response: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut headers = [httparse::EMPTY_HEADER; 32];
+    let mut parsed = httparse::Response::new(&mut headers);
+    let httparse::Status::Complete(header_len) = parsed.parse(response)? else {
+        return Err(anyhow!("incomplete HTTP response"));
+    };
+
+    match parsed.code {
+        Some(200) => {}
+        code => return Err(anyhow!("HTTP request failed with status {code:?}")),
+    }
+
+    let chunked = parsed.headers.iter().any(|header| {
+        header.name.eq_ignore_ascii_case("transfer-encoding")
+            && String::from_utf8_lossy(header.value).contains("chunked")
+    });
+    let body = &response[header_len..];
+    if !chunked {
+        let content_length = parsed
+            .headers
+            .iter()
+            .find(|header| header.name.eq_ignore_ascii_case("content-length"))
+            .map(|header| {
+                std::str::from_utf8(header.value)
+                    .ok()
+                    .and_then(|value| value.trim().parse::<usize>().ok())
+                    .ok_or_else(|| anyhow!("invalid Content-Length"))
+            })
+            .transpose()?;
+        return match content_length {
+            Some(len) => body
+                .get(..len)
+                .map(<[u8]>::to_vec)
+                .ok_or_else(|| anyhow!("truncated HTTP body")),
+            None => Ok(body.to_vec()),
+        };
+    }
+
+    let mut decoded = Vec::new();
+    let mut rest = body;
+    loop {
+        let httparse::Status::Complete((offset, size)) =
+            httparse::parse_chunk_size(rest).map_err(|_| anyhow!("invalid chunked HTTP body"))?
+        else {
+            return Err(anyhow!("truncated chunked HTTP body"));
+        };
+        if size == 0 {
+            return Ok(decoded);
+        }
+        let end = usize::try_from(size)
+            .ok()
+            .and_then(|size| offset.checked_add(size))
+            .ok_or_else(|| anyhow!("invalid chunk size"))?;
+        let chunk = rest
+            .get(offset..end)
+            .ok_or_else(|| anyhow!("truncated chunked HTTP body"))?;
+        decoded.extend_from_slice(chunk);
+        rest = rest.get(end.saturating_add(2)..).unwrap_or_default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_plain_response() {
+        let response = b"HTTP/1.1 200 OK\r\nContent-Type: application/dns-message\r\n\
+                         Content-Length: 5\r\n\r\nhello";
+        assert_eq!(parse_http_response(response).unwrap(), b"hello");
+        let response = b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nhello";
+        assert_eq!(parse_http_response(response).unwrap(), b"he");
+        let response = b"HTTP/1.1 200 OK\r\nConnection: close\r\n\r\nhello";
+        assert_eq!(parse_http_response(response).unwrap(), b"hello");
+
+        let error = parse_http_response(b"HTTP/1.1 400 Bad Request\r\n\r\n").unwrap_err();
+        assert!(error.to_string().contains("Some(400)"));
+    }
+
+    #[test]
+    fn parses_chunked_response() {
+        let response = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+                         5\r\nhello\r\n7;name=value\r\n, world\r\n0\r\nTrailer: x\r\n\r\n";
+        assert_eq!(parse_http_response(response).unwrap(), b"hello, world");
+
+        let response = b"HTTP/1.1 200 OK\r\ntransfer-encoding: gzip, chunked\r\n\r\n\
+                         A\r\n0123456789\r\n0\r\n\r\n";
+        assert_eq!(parse_http_response(response).unwrap(), b"0123456789");
+    }
+
+    #[test]
+    fn rejects_truncated_responses() {
+        for response in [
+            b"".as_slice(),
+            b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n",
+            b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhel",
+            b"HTTP/1.1 200 OK\r\nContent-Length: five\r\n\r\nhello",
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n",
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhel",
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n",
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\nhello\r\n0\r\n\r\n",
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nffffffffffffffff\r\nhello\r\n",
+        ] {
+            assert!(
+                parse_http_response(response).is_err(),
+                "{}",
+                String::from_utf8_lossy(response)
+            );
+        }
+    }
+
+    #[test]
+    fn requires_server_ip() {
+        let retry = RetryPolicy::default();
+        let resolver = DohResolver::new("https://1.1.1.1/dns-query", None, retry.clone()).unwrap();
+        assert_eq!(resolver.ip, IpAddr::from([1, 1, 1, 1]));
+        let resolver = DohResolver::new("https://[2606:4700::1111]/dns-query", None, retry.clone());
+        assert_eq!(
+            resolver.unwrap().ip,
+            "2606:4700::1111".parse::<IpAddr>().unwrap()
+        );
+
+        let url = "https://cloudflare-dns.com/dns-query";
+        assert!(DohResolver::new(url, None, retry.clone()).is_err());
+        let bootstrap = IpAddr::from([1, 0, 0, 1]);
+        let resolver = DohResolver::new(url, Some(bootstrap), retry.clone()).unwrap();
+        assert_eq!(resolver.ip, bootstrap);
+
+        assert!(DohResolver::new("http://1.1.1.1/dns-query", None, retry).is_err());
+    }
+
+    #[tokio::test]
+    async fn caps_response_size() {
+        let response = vec![b'a'; MAX_RESPONSE_SIZE];
+        assert_eq!(
+            read_http_response(&mut response.as_slice()).await.unwrap(),
+            response
+        );
+        let response = vec![b'a'; MAX_RESPONSE_SIZE + 1];
+        assert!(read_http_response(&mut response.as_slice()).await.is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn times_out_silent_servers() {
+        // Connections complete in the listen backlog, but nothing ever answers.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("https://{}/dns-query", listener.local_addr().unwrap());
+        let resolver = DohResolver::new(&url, None, RetryPolicy::default()).unwrap();
+
+        let error = resolver.query(Vec::new()).await.unwrap_err();
+        assert!(error.to_string().contains("timed out"), "{error}");
+    }
+}
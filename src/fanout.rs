@@ -0,0 +1,50 @@
+/// Lookup parallelism of litep2p Kademlia queries (not configurable).
+pub const ALPHA: usize = 3;
+
+/// Branching factor achieved during lookups.
+///
+/// litep2p reports every response carrying closer peers as a routing table update, so these are
+/// accounted per response rather than per lookup round.
+#[derive(Default)]
+pub struct FanOut {
+    responses: usize,
+    returned: usize,
+    new: usize,
+}
+
+impl FanOut {
+    /// Account a response with `returned` closer peers, `new` of which were not known before.
+    pub fn on_response(&mut self, returned: usize, new: usize) {
+        self.responses += 1;
+        self.returned += returned;
+        self.new += new;
+    }
+
+    /// Number of responses with closer peers received.
+    pub fn responses(&self) -> usize {
+        self.responses
+    }
+
+    /// Print fan-out statistics compared to alpha and the configured replication factor `k`.
+    pub fn print(&self, k: usize) {
+        if self.responses == 0 {
+            return;
+        }
+
+        let returned = self.returned as f64 / self.responses as f64;
+        let new = self.new as f64 / self.responses as f64;
+
+        progress!("Responses with closer peers: {}", self.responses);
+        progress!(
+            "Peers per response: {returned:.1} returned, {new:.1} new (k = {k}, alpha = {ALPHA})"
+        );
+        if returned < k as f64 / 2.0 {
+            progress!("Peers return short lists: lookup is limited by peer quality");
+        } else if new < 1.0 {
+            progress!("Responses mostly repeat known peers: lookup has converged");
+        } else {
+            progress!("Peers return full lists: lookup is limited by parallelism");
+        }
+        progress!();
+    }
+}
@@ -0,0 +1,489 @@
+use std::{collections::HashMap, path::PathBuf, str::FromStr, time::Duration};
+
+use anyhow::anyhow;
+use litep2p::PeerId;
+use multiaddr::Multiaddr;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    json::Json,
+    json_output, kademlia_protocol, known_peers, print_document, print_protocol_hint,
+    print_statistics, query_batch, query_dht,
+    reachability::{verify_peer, PeerReachability},
+    read_list, run_json, Query, QueryArgs, QueryRun,
+};
+
+/// Run a FIND_NODE query for a peer and print the closest peers found.
+#[derive(clap::Args, Debug)]
+pub struct FindNodeArgs {
+    /// Target peer ID.
+    #[arg(value_name = "PEER_ID", required_unless_present = "peers_file")]
+    peer: Option<PeerId>,
+    /// File with peer IDs to resolve instead of PEER_ID, one per line. All peers are looked up on
+    /// the same node, so the routing table is only populated once.
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["peer", "verify"])]
+    peers_file: Option<PathBuf>,
+    /// Number of --peers-file lookups run concurrently.
+    #[arg(long, value_name = "N", default_value_t = 8, requires = "peers_file")]
+    concurrency: usize,
+    /// When the target is found, dial each of its addresses from a fresh node and report which
+    /// ones accept a connection, the connect latency and the identify information received.
+    #[arg(long)]
+    verify: bool,
+}
+
+/// Run the FIND_NODE query.
+pub async fn run(args: FindNodeArgs, query: &QueryArgs) -> anyhow::Result<()> {
+    let known_peers = known_peers(query).await?;
+    let kad_proto = kademlia_protocol(query);
+    let settings = query.preset.settings();
+
+    if let Some(path) = &args.peers_file {
+        let peers = read_list(path, "peer ID", |line| Ok(PeerId::from_str(line)?))?;
+        return run_batch(&peers, args.concurrency, query, &kad_proto, known_peers).await;
+    }
+    let target = args
+        .peer
+        .expect("required unless --peers-file is given; qed");
+
+    let dht_query = Query::Peer(target);
+    let mut run = query_dht(query, &dht_query, &kad_proto, known_peers, &settings, None).await?;
+    run.closest_peers
+        .sort_by_key(|(peer, _)| distance(&target, peer));
+
+    let mut verification = None;
+    if args.verify && run.result.is_ok() {
+        let addresses = target_addresses(&target, &run.closest_peers, &run);
+        if !addresses.is_empty() {
+            progress!("Dialing {} addresses of the target...", addresses.len());
+            verification = Some(verify_peer(target, &addresses, &settings, &query.socket).await);
+        }
+    }
+
+    if json_output() {
+        let distances = run
+            .closest_peers
+            .iter()
+            .map(|(peer, _)| {
+                let distance = distance(&target, peer);
+                Json::object()
+                    .field("peer_id", peer.to_string())
+                    .field("distance", hex::encode(distance))
+                    .field("bucket", bucket(&distance))
+            })
+            .collect::<Vec<_>>();
+        print_document(
+            run_json(&dht_query, &kad_proto, &run)
+                .field("filtered_addresses", run.address_filter.skipped())
+                .field("distances", distances)
+                .field(
+                    "verification",
+                    verification.as_ref().map(PeerReachability::json),
+                ),
+        );
+        return run.result;
+    }
+
+    print_statistics(&run, &settings);
+    if let Err(error) = run.result {
+        print_protocol_hint(query, &run.fan_out, &kad_proto);
+        return Err(error);
+    }
+    run.address_filter.print_skipped();
+    print_closest(&target, run.closest_peers);
+    if args.verify {
+        println!();
+        match &verification {
+            Some(verification) => print_verification(verification),
+            None => println!("Target not found, no addresses to verify"),
+        }
+    }
+
+    Ok(())
+}
+
+/// Addresses of `target` among the `found` peers of a query or advertised by the target via
+/// identify, without the ones the address filter of `run` rejects.
+fn target_addresses(
+    target: &PeerId,
+    found: &[(PeerId, Vec<Multiaddr>)],
+    run: &QueryRun,
+) -> Vec<Multiaddr> {
+    let found = found
+        .iter()
+        .filter(|(peer, _)| peer == target)
+        .flat_map(|(_, addresses)| addresses);
+    let mut addresses: Vec<Multiaddr> = Vec::new();
+    for address in found.chain(run.identified.get(target).into_iter().flatten()) {
+        if run.address_filter.allows(address) && !addresses.contains(address) {
+            addresses.push(address.clone());
+        }
+    }
+
+    addresses
+}
+
+/// Outcome of resolving a peer in a batch.
+enum Resolution {
+    /// The lookup found the peer with these addresses.
+    Found(Vec<Multiaddr>),
+    /// The lookup finished without finding the peer.
+    NotFound,
+    /// The lookup failed.
+    Failed,
+}
+
+impl Resolution {
+    fn new(target: &PeerId, peers: Option<&[(PeerId, Vec<Multiaddr>)]>, run: &QueryRun) -> Self {
+        match peers {
+            Some(peers) if peers.iter().any(|(peer, _)| peer == target) => {
+                Resolution::Found(target_addresses(target, peers, run))
+            }
+            Some(_) => Resolution::NotFound,
+            None => Resolution::Failed,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Resolution::Found(_) => "found",
+            Resolution::NotFound => "not_found",
+            Resolution::Failed => "failed",
+        }
+    }
+}
+
+/// Outcome of a FIND_NODE lookup in a batch: the peer, its resolution and the lookup time.
+type PeerOutcome<'a> = (&'a PeerId, Resolution, Duration);
+
+/// Look up every peer on the same node and print a summary per peer.
+///
+/// The first lookup populates the routing table, the others reuse it, running up to `concurrency`
+/// at a time. The time of the first peer includes the prepopulation.
+async fn run_batch(
+    peers: &[PeerId],
+    concurrency: usize,
+    query: &QueryArgs,
+    kad_proto: &str,
+    known_peers: HashMap<PeerId, Vec<Multiaddr>>,
+) -> anyhow::Result<()> {
+    let settings = query.preset.settings();
+    let (first, rest) = peers.split_first().expect("peers are not empty; qed");
+
+    let mut run = query_dht(
+        query,
+        &Query::Peer(*first),
+        kad_proto,
+        known_peers,
+        &settings,
+        None,
+    )
+    .await?;
+    if run.result.is_err() && run.fan_out.responses() == 0 {
+        if !json_output() {
+            print_statistics(&run, &settings);
+            print_protocol_hint(query, &run.fan_out, kad_proto);
+        }
+        return Err(anyhow!("no peer responded to the first FIND_NODE query"));
+    }
+
+    let found = run.result.is_ok().then_some(&run.closest_peers[..]);
+    let mut outcomes = vec![(first, Resolution::new(first, found, &run), run.elapsed)];
+    outcomes.extend(query_concurrently(&mut run, rest, concurrency).await?);
+    print_batch(kad_proto, &outcomes);
+
+    Ok(())
+}
+
+/// Run FIND_NODE for `peers` on the node of `run`, up to `concurrency` queries at a time.
+///
+/// Returns the resolution and the lookup time for every peer in order.
+async fn query_concurrently<'a>(
+    run: &mut QueryRun,
+    peers: &'a [PeerId],
+    concurrency: usize,
+) -> anyhow::Result<Vec<PeerOutcome<'a>>> {
+    let total = peers.len();
+    query_batch(run, peers, concurrency, |run, finished, peer, found| {
+        let resolution = Resolution::new(peer, found.as_deref(), run);
+        match &resolution {
+            Resolution::Found(addresses) => progress!(
+                "Peer {finished}/{total}: {} addresses for {peer}",
+                addresses.len()
+            ),
+            Resolution::NotFound => progress!("Peer {finished}/{total}: {peer} not found"),
+            Resolution::Failed => progress!("Peer {finished}/{total}: query failed for {peer}"),
+        }
+        resolution
+    })
+    .await
+}
+
+/// Print the summary table of a batch lookup, or a JSON document with the addresses per peer.
+fn print_batch(kad_proto: &str, outcomes: &[PeerOutcome]) {
+    if json_output() {
+        let peers: Vec<_> = outcomes
+            .iter()
+            .map(|(peer, resolution, elapsed)| {
+                let addresses = match resolution {
+                    Resolution::Found(addresses) => Some(
+                        addresses
+                            .iter()
+                            .map(|address| address.to_string())
+                            .collect::<Vec<_>>(),
+                    ),
+                    Resolution::NotFound | Resolution::Failed => None,
+                };
+                Json::object()
+                    .field("peer_id", peer.to_string())
+                    .field("outcome", resolution.as_str())
+                    .field("addresses", addresses)
+                    .field("elapsed_ms", elapsed.as_millis() as u64)
+            })
+            .collect();
+        let document = Json::object()
+            .field("query", "FIND_NODE")
+            .field("protocol", kad_proto)
+            .field("peers", peers);
+        print_document(document);
+        return;
+    }
+
+    let width = outcomes
+        .iter()
+        .map(|(peer, ..)| peer.to_string().len())
+        .max()
+        .unwrap_or_default();
+    println!();
+    println!("{:<width$}  {:>9}  {:>8}", "PEER", "ADDRESSES", "TIME");
+    for (peer, resolution, elapsed) in outcomes {
+        let addresses = match resolution {
+            Resolution::Found(addresses) => addresses.len().to_string(),
+            Resolution::NotFound => "not found".to_string(),
+            Resolution::Failed => "failed".to_string(),
+        };
+        println!(
+            "{:<width$}  {addresses:>9}  {:>5} ms",
+            peer.to_string(),
+            elapsed.as_millis()
+        );
+        if let Resolution::Found(addresses) = resolution {
+            for address in addresses {
+                println!("  {address}");
+            }
+        }
+    }
+
+    let found = outcomes
+        .iter()
+        .filter(|(_, resolution, _)| matches!(resolution, Resolution::Found(_)))
+        .count();
+    println!();
+    println!("Peers found: {found}/{}", outcomes.len());
+}
+
+/// Print which addresses of the target accepted a connection and what the target identified as.
+fn print_verification(verification: &PeerReachability) {
+    println!("Address verification of {}:", verification.peer);
+    for outcome in &verification.addresses {
+        let elapsed = match &outcome.result {
+            Ok(elapsed) => elapsed,
+            Err(error) => {
+                println!("  {} {error}", outcome.address);
+                continue;
+            }
+        };
+        println!(
+            "  {} connected in {} ms",
+            outcome.address,
+            elapsed.as_millis()
+        );
+        let Some(identified) = &outcome.identified else {
+            println!("    no identify response");
+            continue;
+        };
+        println!(
+            "    agent: {}, protocol version: {}, {} protocols",
+            identified.user_agent.as_deref().unwrap_or("unknown"),
+            identified.protocol_version.as_deref().unwrap_or("unknown"),
+            identified.protocols.len()
+        );
+        println!("    observed address: {}", identified.observed_address);
+        for address in &identified.listen_addresses {
+            println!("    listens on {address}");
+        }
+    }
+    println!(
+        "Reachable addresses: {}/{}",
+        verification
+            .addresses
+            .iter()
+            .filter(|outcome| outcome.result.is_ok())
+            .count(),
+        verification.addresses.len()
+    );
+}
+
+/// Kademlia XOR distance between two peers: the XOR of SHA-256 hashes of their IDs.
+pub fn distance(a: &PeerId, b: &PeerId) -> [u8; 32] {
+    key_distance(&a.to_bytes(), b)
+}
+
+/// Kademlia XOR distance between a key and a peer: the XOR of the SHA-256 hashes of the key and
+/// the peer ID.
+pub fn key_distance(key: &[u8], peer: &PeerId) -> [u8; 32] {
+    let a = Sha256::digest(key);
+    let b = Sha256::digest(peer.to_bytes());
+
+    std::array::from_fn(|i| a[i] ^ b[i])
+}
+
+/// Index of the k-bucket `distance` falls into, i.e. the position of its highest set bit.
+fn bucket(distance: &[u8; 32]) -> Option<usize> {
+    let zeros: usize = distance
+        .iter()
+        .position(|byte| *byte != 0)
+        .map(|i| i * 8 + distance[i].leading_zeros() as usize)?;
+
+    Some(255 - zeros)
+}
+
+/// Print `peers` sorted by distance to `target`.
+fn print_closest(target: &PeerId, peers: Vec<(PeerId, Vec<Multiaddr>)>) {
+    println!("Closest peers to {target}: {}", peers.len());
+    for (peer, addresses) in peers {
+        let distance = distance(target, &peer);
+        match bucket(&distance) {
+            Some(bucket) => println!(
+                "{peer}: distance 0x{}, bucket {bucket}",
+                hex::encode(distance)
+            ),
+            None => println!("{peer}: the target itself"),
+        }
+        for address in addresses {
+            println!("  {address}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::{
+        schema::validate,
+        test_utils::{spawn_node, KAD_PROTO},
+    };
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        query: QueryArgs,
+    }
+
+    #[test]
+    fn computes_xor_distance() {
+        let (a, b) = (PeerId::random(), PeerId::random());
+        assert_eq!(distance(&a, &a), [0; 32]);
+        assert_eq!(distance(&a, &b), distance(&b, &a));
+
+        let a_hash = Sha256::digest(a.to_bytes());
+        let b_hash = Sha256::digest(b.to_bytes());
+        assert_eq!(distance(&a, &b)[0], a_hash[0] ^ b_hash[0]);
+        assert_eq!(distance(&a, &b)[31], a_hash[31] ^ b_hash[31]);
+    }
+
+    #[test]
+    fn finds_bucket_of_highest_bit() {
+        let mut distance = [0; 32];
+        assert_eq!(bucket(&distance), None);
+
+        distance[31] = 0x01;
+        assert_eq!(bucket(&distance), Some(0));
+        distance[31] = 0xff;
+        assert_eq!(bucket(&distance), Some(7));
+        distance[1] = 0x01;
+        assert_eq!(bucket(&distance), Some(240));
+        distance[0] = 0x80;
+        assert_eq!(bucket(&distance), Some(255));
+    }
+
+    #[test]
+    fn reads_peer_lists() {
+        let (a, b) = (PeerId::random(), PeerId::random());
+        let path = std::env::temp_dir().join(format!("dht-inspect-peers-{a}"));
+        std::fs::write(&path, format!("# telemetry export\n{a}\n\n  {b}  \n")).unwrap();
+        let read = |path| read_list(path, "peer ID", |line| Ok(PeerId::from_str(line)?));
+        assert_eq!(read(&path).unwrap(), vec![a, b]);
+
+        std::fs::write(&path, "# nothing\n").unwrap();
+        assert_eq!(
+            read(&path).unwrap_err().to_string(),
+            format!("no peer IDs in {}", path.display())
+        );
+        std::fs::write(&path, format!("{a}\nnot-a-peer\n")).unwrap();
+        assert_eq!(
+            read(&path).unwrap_err().to_string(),
+            "invalid peer ID `not-a-peer`"
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn resolves_peers_concurrently() {
+        let peers: HashMap<_, _> = (0..3)
+            .map(|_| {
+                let (peer, address) = spawn_node(HashMap::new());
+                (peer, vec![address])
+            })
+            .collect();
+        let (bootnode, address) = spawn_node(peers.clone());
+        let query = Cli::parse_from([
+            "dht-inspect",
+            "--bootnode",
+            &format!("{address}/p2p/{bootnode}"),
+            "--kad-proto",
+            KAD_PROTO,
+            "--allow-private-addresses",
+            "--timeout",
+            "20",
+        ])
+        .query;
+
+        let mut targets: Vec<_> = peers.keys().copied().collect();
+        let first = targets.pop().unwrap();
+        let unknown = PeerId::random();
+        targets.push(unknown);
+        let settings = query.preset.settings();
+        let mut run = query_dht(
+            &query,
+            &Query::Peer(first),
+            KAD_PROTO,
+            known_peers(&query).await.unwrap(),
+            &settings,
+            None,
+        )
+        .await
+        .unwrap();
+        validate(
+            &run_json(&Query::Peer(first), KAD_PROTO, &run),
+            "query_result",
+        )
+        .unwrap();
+        assert!(matches!(
+            Resolution::new(&first, Some(&run.closest_peers), &run),
+            Resolution::Found(_)
+        ));
+
+        let outcomes = query_concurrently(&mut run, &targets, 2).await.unwrap();
+        assert_eq!(outcomes.len(), targets.len());
+        for (peer, resolution, _) in outcomes {
+            match resolution {
+                Resolution::Found(addresses) => assert_eq!(addresses, peers[peer]),
+                Resolution::NotFound => assert_eq!(*peer, unknown),
+                Resolution::Failed => panic!("lookup of {peer} failed"),
+            }
+        }
+    }
+}
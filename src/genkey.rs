@@ -0,0 +1,99 @@
+use std::{
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc,
+    },
+    thread,
+    time::Instant,
+};
+
+use litep2p::{crypto::ed25519::Keypair, PeerId};
+use sha2::{Digest, Sha256};
+
+use crate::{json::Json, json_output, parse_key};
+
+/// Generate a keypair whose peer ID is close to a key in the Kademlia keyspace.
+#[derive(clap::Args, Debug)]
+pub struct GenkeyArgs {
+    /// Peer ID, hex key or CID to get close to.
+    #[arg(long, value_name = "KEY")]
+    near: String,
+    /// Number of leading bits the SHA-256 hashes of the peer ID and the key must share. Every bit
+    /// doubles the expected number of attempts.
+    #[arg(long, value_name = "N", default_value_t = 12, value_parser = clap::value_parser!(u32).range(1..=32))]
+    bits: u32,
+}
+
+/// Number of leading bits `a` and `b` share.
+fn common_prefix(a: &[u8], b: &[u8]) -> u32 {
+    a.iter()
+        .zip(b)
+        .position(|(a, b)| a != b)
+        .map_or(a.len() as u32 * 8, |i| {
+            i as u32 * 8 + (a[i] ^ b[i]).leading_zeros()
+        })
+}
+
+/// Brute-force an ed25519 keypair on all cores and print its peer ID and secret key.
+///
+/// The secret key is printed as 32 hex bytes, the format of the `--node-key` option of Substrate
+/// nodes.
+pub fn run(args: GenkeyArgs) -> anyhow::Result<()> {
+    let target = match PeerId::from_str(&args.near) {
+        Ok(peer) => peer.to_bytes(),
+        Err(_) => parse_key(&args.near)?.to_vec(),
+    };
+    let target = Sha256::digest(target);
+
+    let threads = thread::available_parallelism().map_or(1, |threads| threads.get());
+    progress!(
+        "Searching for a peer ID sharing {} bits with the key, about {} attempts on {threads} threads",
+        args.bits,
+        1u64 << args.bits
+    );
+    let start = Instant::now();
+    let found = AtomicBool::new(false);
+    let attempts = AtomicU64::new(0);
+    let (sender, receiver) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            let sender = sender.clone();
+            let (found, attempts) = (&found, &attempts);
+            scope.spawn(move || {
+                while !found.load(Ordering::Relaxed) {
+                    attempts.fetch_add(1, Ordering::Relaxed);
+                    let keypair = Keypair::generate();
+                    let peer = keypair.public().to_peer_id();
+                    if common_prefix(&Sha256::digest(peer.to_bytes()), &target) >= args.bits {
+                        found.store(true, Ordering::Relaxed);
+                        let _ = sender.send((keypair, peer));
+                    }
+                }
+            });
+        }
+    });
+    let (keypair, peer) = receiver.recv().expect("a thread found a keypair; qed");
+    let matching_bits = common_prefix(&Sha256::digest(peer.to_bytes()), &target);
+    let secret_key = hex::encode(keypair.secret().to_bytes());
+    let attempts = attempts.into_inner();
+    let elapsed = start.elapsed();
+
+    if json_output() {
+        let document = Json::object()
+            .field("peer_id", peer.to_string())
+            .field("secret_key", secret_key)
+            .field("matching_bits", matching_bits as usize)
+            .field("attempts", attempts)
+            .field("elapsed_ms", elapsed.as_millis() as u64);
+        println!("{document}");
+        return Ok(());
+    }
+
+    println!("Peer ID: {peer}");
+    println!("Secret key: {secret_key}");
+    println!("Matching bits: {matching_bits}");
+    println!("{attempts} attempts in {:.1} s", elapsed.as_secs_f64());
+
+    Ok(())
+}
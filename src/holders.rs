@@ -0,0 +1,195 @@
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+use futures::StreamExt;
+use litep2p::{
+    protocol::{
+        libp2p::kademlia::RecordKey as KademliaKey,
+        request_response::{DialOptions, RequestResponseEvent, RequestResponseHandle},
+    },
+    Litep2p, PeerId,
+};
+use multiaddr::Multiaddr;
+use prost::Message;
+
+use crate::{
+    topology::{request_node, KademliaMessage, GET_VALUE},
+    QueryArgs,
+};
+
+/// Answer of a probed record holder.
+#[derive(Debug, PartialEq)]
+pub enum Availability {
+    /// The peer returned the record with this value.
+    Holds(Vec<u8>),
+    /// The peer answered without the record.
+    Missing,
+    /// The request failed.
+    Unreachable(String),
+}
+
+/// Node sending GET_VALUE requests straight to the known holders of a record, without lookups.
+pub struct HolderProbe {
+    litep2p: Litep2p,
+    handle: RequestResponseHandle,
+}
+
+impl HolderProbe {
+    /// Start a node speaking only `kad_proto`.
+    pub fn new(kad_proto: &str, query: &QueryArgs) -> anyhow::Result<Self> {
+        let (litep2p, handle) = request_node(kad_proto, query)?;
+
+        Ok(Self { litep2p, handle })
+    }
+
+    /// Ask every peer of `holders`, which are distinct, at its addresses for the record under
+    /// `key`, and return their answers in order.
+    pub async fn probe(
+        &mut self,
+        key: &KademliaKey,
+        holders: &[(PeerId, Vec<Multiaddr>)],
+    ) -> anyhow::Result<Vec<(PeerId, Availability)>> {
+        let request = KademliaMessage {
+            kind: GET_VALUE,
+            key: key.to_vec(),
+            record: None,
+            closer_peers: Vec::new(),
+        }
+        .encode_to_vec();
+        let mut answers = HashMap::new();
+        let mut pending = HashMap::new();
+
+        for (peer, addresses) in holders {
+            if addresses.is_empty() {
+                answers.insert(*peer, Availability::Unreachable("no known address".into()));
+                continue;
+            }
+            self.litep2p
+                .add_known_address(*peer, addresses.iter().cloned());
+            match self
+                .handle
+                .send_request(*peer, request.clone(), DialOptions::Dial)
+                .await
+            {
+                Ok(request_id) => {
+                    pending.insert(request_id, *peer);
+                }
+                Err(error) => {
+                    answers.insert(*peer, Availability::Unreachable(error.to_string()));
+                }
+            }
+        }
+
+        while !pending.is_empty() {
+            tokio::select! {
+                _ = self.litep2p.next_event() => {},
+                event = self.handle.next() => match event {
+                    Some(RequestResponseEvent::ResponseReceived { request_id, response, .. }) => {
+                        let Some(peer) = pending.remove(&request_id) else {
+                            continue
+                        };
+                        let availability = match KademliaMessage::decode(response.as_slice()) {
+                            Ok(KademliaMessage { record: Some(record), .. }) if record.key == key.to_vec() => {
+                                Availability::Holds(record.value)
+                            },
+                            Ok(_) => Availability::Missing,
+                            Err(error) => Availability::Unreachable(format!("invalid response: {error}")),
+                        };
+                        answers.insert(peer, availability);
+                    },
+                    Some(RequestResponseEvent::RequestFailed { request_id, error, .. }) => {
+                        if let Some(peer) = pending.remove(&request_id) {
+                            answers.insert(peer, Availability::Unreachable(format!("{error:?}")));
+                        }
+                    },
+                    Some(RequestResponseEvent::RequestReceived { request_id, .. }) => {
+                        self.handle.reject_request(request_id);
+                    },
+                    None => return Err(anyhow!("request-response protocol terminated")),
+                },
+            }
+        }
+
+        Ok(holders
+            .iter()
+            .map(|(peer, _)| {
+                let availability = answers.remove(peer).expect("every holder answered; qed");
+                (*peer, availability)
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use clap::Parser;
+
+    use super::*;
+    use crate::{
+        known_peers, query_dht, record,
+        test_utils::{spawn_node, KAD_PROTO},
+        Query,
+    };
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        query: QueryArgs,
+    }
+
+    #[tokio::test]
+    async fn probes_record_holders() {
+        let (holder, holder_address) = spawn_node(HashMap::new());
+        let query = Cli::parse_from([
+            "dht-inspect",
+            "--bootnode",
+            &format!("{holder_address}/p2p/{holder}"),
+            "--kad-proto",
+            KAD_PROTO,
+            "--allow-private-addresses",
+        ])
+        .query;
+        let key = KademliaKey::new(&b"key".to_vec());
+        let settings = query.preset.settings();
+        let mut run = query_dht(
+            &query,
+            &Query::Record(key.clone()),
+            KAD_PROTO,
+            known_peers(&query).await.unwrap(),
+            &settings,
+            None,
+        )
+        .await
+        .unwrap();
+        let stored = record::publish(
+            &mut run,
+            key.clone(),
+            b"value".to_vec(),
+            Duration::from_secs(1),
+            None,
+        )
+        .await
+        .unwrap()
+        .into_inner();
+        assert_eq!(stored, vec![holder]);
+
+        let (other, other_address) = spawn_node(HashMap::new());
+        let mut probe = HolderProbe::new(KAD_PROTO, &query).unwrap();
+        let answers = probe
+            .probe(
+                &key,
+                &[
+                    (holder, vec![holder_address]),
+                    (other, vec![other_address]),
+                    (PeerId::random(), Vec::new()),
+                ],
+            )
+            .await
+            .unwrap();
+        assert_eq!(answers[0], (holder, Availability::Holds(b"value".to_vec())));
+        assert_eq!(answers[1], (other, Availability::Missing));
+        assert!(matches!(answers[2].1, Availability::Unreachable(_)));
+    }
+}
@@ -0,0 +1,135 @@
+use std::time::Duration;
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+/// Maximum size of an HTTP request head read by the built-in servers.
+const MAX_REQUEST_SIZE: usize = 8192;
+
+/// Time a client has to send the request head.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Delay after a failed accept, doubled on every consecutive failure.
+const MIN_ACCEPT_BACKOFF: Duration = Duration::from_millis(10);
+
+/// Maximum delay after a failed accept.
+const MAX_ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Accept the next connection.
+///
+/// Accept errors like running out of file descriptors persist until connections are closed, so
+/// they are retried with a backoff rather than in a busy loop.
+pub async fn accept(listener: &TcpListener) -> TcpStream {
+    let mut backoff = MIN_ACCEPT_BACKOFF;
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => return stream,
+            Err(_) => {
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_ACCEPT_BACKOFF);
+            }
+        }
+    }
+}
+
+/// Read the head of an HTTP/1.1 request and return its method and path.
+///
+/// Returns `None` if the connection was closed, the request is malformed or too large, or it
+/// wasn't received within [`REQUEST_TIMEOUT`]. Request bodies are not read.
+pub async fn read_request(stream: &mut TcpStream) -> std::io::Result<Option<(String, String)>> {
+    tokio::time::timeout(REQUEST_TIMEOUT, read_request_head(stream))
+        .await
+        .unwrap_or(Ok(None))
+}
+
+async fn read_request_head(stream: &mut TcpStream) -> std::io::Result<Option<(String, String)>> {
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 || request.len() + read > MAX_REQUEST_SIZE {
+            return Ok(None);
+        }
+        request.extend_from_slice(&buffer[..read]);
+    }
+
+    let mut headers = [httparse::EMPTY_HEADER; 32];
+    let mut parsed = httparse::Request::new(&mut headers);
+    if parsed.parse(&request).is_err() {
+        return Ok(None);
+    }
+
+    Ok(parsed
+        .method
+        .zip(parsed.path)
+        .map(|(method, path)| (method.to_string(), path.to_string())))
+}
+
+/// Write a response with `status`, e.g. `200 OK`, and close the connection.
+pub async fn write_response(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &str,
+) -> std::io::Result<()> {
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{body}",
+        body.len()
+    );
+
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Connect a client to a local listener, returning the client and the accepted stream.
+    async fn connect() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+
+        (client, accept(&listener).await)
+    }
+
+    #[tokio::test]
+    async fn reads_request_head() {
+        let (mut client, mut server) = connect().await;
+        client
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+
+        let request = read_request(&mut server).await.unwrap();
+        assert_eq!(request, Some(("GET".to_string(), "/metrics".to_string())));
+    }
+
+    #[tokio::test]
+    async fn rejects_oversized_request() {
+        let (mut client, mut server) = connect().await;
+        let path = "a".repeat(MAX_REQUEST_SIZE);
+        tokio::spawn(async move {
+            let _ = client
+                .write_all(format!("GET /{path} HTTP/1.1\r\n\r\n").as_bytes())
+                .await;
+        });
+
+        assert_eq!(read_request(&mut server).await.unwrap(), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn times_out_slow_requests() {
+        let (mut client, mut server) = connect().await;
+        client.write_all(b"GET / HTTP/1.1\r\n").await.unwrap();
+
+        let start = tokio::time::Instant::now();
+        assert_eq!(read_request(&mut server).await.unwrap(), None);
+        assert!(start.elapsed() >= REQUEST_TIMEOUT);
+    }
+}
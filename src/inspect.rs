@@ -0,0 +1,393 @@
+use std::fmt;
+
+use futures::future::join_all;
+use litep2p::{
+    protocol::libp2p::kademlia::{ContentProvider, RecordKey as KademliaKey},
+    PeerId,
+};
+
+use crate::{
+    find_node::key_distance,
+    json::Json,
+    json_output, kademlia_protocol, known_peers, parse_key, print_document, print_protocol_hint,
+    print_statistics,
+    providers::{print_providers, print_reachability},
+    query_dht,
+    reachability::{dial_peer, dial_providers, PeerReachability},
+    run_json, Query, QueryArgs, QueryRun,
+};
+
+/// Run GET_PROVIDERS for a key, audit the peers closest to it and dial the providers found, and
+/// report the health of the key.
+#[derive(clap::Args, Debug)]
+pub struct InspectArgs {
+    /// Key (hex or CID) of the content provider record to inspect.
+    #[arg(short, long, value_name = "KEY", value_parser = parse_key)]
+    provider_key: KademliaKey,
+}
+
+/// Peer close to the key and whether it is alive.
+struct ClosestPeer {
+    /// Whether the peer responded during the query.
+    contacted: bool,
+    /// Dial outcomes of the addresses the peer advertised via identify.
+    reachability: PeerReachability,
+}
+
+impl ClosestPeer {
+    /// Whether the peer responded during the query or accepted a connection afterwards.
+    fn alive(&self) -> bool {
+        self.contacted || self.reachability.online()
+    }
+}
+
+/// Verdict of the inspection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Health {
+    /// Providers are reachable and the neighborhood of the key is alive.
+    Healthy,
+    /// The content is reachable, but some providers or closest peers are not.
+    Degraded,
+    /// No provider accepts connections.
+    Unavailable,
+}
+
+impl fmt::Display for Health {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Health::Healthy => "healthy",
+            Health::Degraded => "degraded",
+            Health::Unavailable => "unavailable",
+        })
+    }
+}
+
+/// Outcome of the closest peer audit and of the provider dials.
+struct Report {
+    /// Error of the GET_PROVIDERS query, if it failed.
+    query_error: Option<String>,
+    /// Peers closest to the key, closest first.
+    closest_peers: Vec<(PeerId, ClosestPeer)>,
+    providers: Vec<PeerReachability>,
+}
+
+impl Report {
+    /// The verdict and the problems leading to it.
+    fn health(&self) -> (Health, Vec<String>) {
+        let mut problems = Vec::new();
+        if let Some(error) = &self.query_error {
+            problems.push(format!("GET_PROVIDERS query failed: {error}"));
+        }
+        let online = self
+            .providers
+            .iter()
+            .filter(|provider| provider.online())
+            .count();
+        match (self.providers.len(), online) {
+            (0, _) => problems.push("no providers found".to_string()),
+            (_, 0) => problems.push("no provider is reachable".to_string()),
+            (found, online) if online < found => problems.push(format!(
+                "{} of {found} providers unreachable",
+                found - online
+            )),
+            _ => {}
+        }
+        let alive = self
+            .closest_peers
+            .iter()
+            .filter(|(_, peer)| peer.alive())
+            .count();
+        if alive * 2 < self.closest_peers.len() {
+            problems.push(format!(
+                "only {alive} of the {} closest peers are alive",
+                self.closest_peers.len()
+            ));
+        }
+
+        let health = if online == 0 {
+            Health::Unavailable
+        } else if problems.is_empty() {
+            Health::Healthy
+        } else {
+            Health::Degraded
+        };
+        (health, problems)
+    }
+
+    /// Add the report to the JSON `document` of the query.
+    fn json(&self, document: Json) -> Json {
+        let (health, problems) = self.health();
+        document
+            .field(
+                "closest_peer_audit",
+                self.closest_peers
+                    .iter()
+                    .map(|(peer, closest)| {
+                        Json::object()
+                            .field("peer_id", peer.to_string())
+                            .field("contacted", closest.contacted)
+                            .field("reachability", closest.reachability.json())
+                    })
+                    .collect::<Vec<_>>(),
+            )
+            .field(
+                "reachability",
+                self.providers
+                    .iter()
+                    .map(PeerReachability::json)
+                    .collect::<Vec<_>>(),
+            )
+            .field(
+                "health",
+                Json::object()
+                    .field("status", health.to_string())
+                    .field("problems", problems),
+            )
+    }
+
+    fn print(&self) {
+        println!();
+        println!("Peers closest to the key:");
+        for (peer, closest) in &self.closest_peers {
+            let contacted = if closest.contacted {
+                "responded"
+            } else {
+                "didn't respond"
+            };
+            let reachable = if closest.reachability.addresses.is_empty() {
+                "no known address"
+            } else if closest.reachability.online() {
+                "online"
+            } else {
+                "offline"
+            };
+            println!("{peer}: {contacted}, {reachable}");
+        }
+        println!(
+            "Closest peers alive: {}/{}",
+            self.closest_peers
+                .iter()
+                .filter(|(_, peer)| peer.alive())
+                .count(),
+            self.closest_peers.len()
+        );
+
+        println!();
+        println!("Providers:");
+        print_reachability(&self.providers);
+
+        let (health, problems) = self.health();
+        println!();
+        println!("Health: {health}");
+        for problem in problems {
+            println!("  {problem}");
+        }
+    }
+}
+
+/// Audit the `k` discovered peers of `run` closest to `key` and dial the `providers`.
+async fn inspect(
+    run: &QueryRun,
+    query: &QueryArgs,
+    key: &KademliaKey,
+    providers: &[ContentProvider],
+) -> Report {
+    let settings = query.preset.settings();
+    let mut closest: Vec<_> = run.discovered_peers.iter().copied().collect();
+    closest.sort_by_cached_key(|peer| key_distance(key.as_ref(), peer));
+    closest.truncate(settings.replication_factor);
+
+    progress!("Auditing the {} peers closest to the key...", closest.len());
+    let audits = join_all(closest.iter().map(|peer| {
+        let addresses = run.identified.get(peer).cloned().unwrap_or_default();
+        let (settings, socket) = (&settings, &query.socket);
+        async move { dial_peer(*peer, &addresses, settings, socket, false).await }
+    }))
+    .await;
+    let closest_peers = closest
+        .into_iter()
+        .zip(audits)
+        .map(|(peer, reachability)| {
+            let contacted = run.contacted_peers.contains(&peer);
+            (
+                peer,
+                ClosestPeer {
+                    contacted,
+                    reachability,
+                },
+            )
+        })
+        .collect();
+
+    progress!("Dialing providers...");
+    Report {
+        query_error: run.result.as_ref().err().map(|error| format!("{error:#}")),
+        closest_peers,
+        providers: dial_providers(providers, &settings, &query.socket).await,
+    }
+}
+
+/// Run the inspection.
+///
+/// A failed GET_PROVIDERS query still gets the closest peer audit, and is reported as an error
+/// after the report.
+pub async fn run(args: InspectArgs, query: &QueryArgs) -> anyhow::Result<()> {
+    let known_peers = known_peers(query).await?;
+    let kad_proto = kademlia_protocol(query);
+    let settings = query.preset.settings();
+    let dht_query = Query::Providers(args.provider_key.clone());
+    let mut run = query_dht(query, &dht_query, &kad_proto, known_peers, &settings, None).await?;
+    let providers = std::mem::take(&mut run.providers);
+    let report = inspect(&run, query, &args.provider_key, &providers).await;
+
+    if json_output() {
+        run.providers = providers;
+        print_document(report.json(run_json(&dht_query, &kad_proto, &run)));
+        return run.result;
+    }
+
+    print_statistics(&run, &settings);
+    if run.result.is_err() {
+        print_protocol_hint(query, &run.fan_out, &kad_proto);
+    } else {
+        print_providers(&providers);
+    }
+    report.print();
+
+    run.result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use clap::Parser;
+    use multiaddr::Multiaddr;
+
+    use super::*;
+    use crate::{
+        reachability::AddressOutcome,
+        schema::validate,
+        test_utils::{spawn_node, KAD_PROTO},
+    };
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        query: QueryArgs,
+    }
+
+    fn reachability(online: bool) -> PeerReachability {
+        PeerReachability {
+            peer: PeerId::random(),
+            addresses: vec![AddressOutcome {
+                address: "/ip4/1.1.1.1/tcp/30333".parse::<Multiaddr>().unwrap(),
+                result: if online {
+                    Ok(Default::default())
+                } else {
+                    Err("timeout".to_string())
+                },
+                identified: None,
+            }],
+        }
+    }
+
+    fn closest(contacted: bool, online: bool) -> (PeerId, ClosestPeer) {
+        (
+            PeerId::random(),
+            ClosestPeer {
+                contacted,
+                reachability: reachability(online),
+            },
+        )
+    }
+
+    #[test]
+    fn judges_health() {
+        let mut report = Report {
+            query_error: None,
+            closest_peers: vec![closest(true, false), closest(false, true)],
+            providers: vec![reachability(true)],
+        };
+        assert_eq!(report.health(), (Health::Healthy, Vec::new()));
+
+        report.providers.push(reachability(false));
+        report.closest_peers.extend([
+            closest(false, false),
+            closest(false, false),
+            closest(false, false),
+        ]);
+        assert_eq!(
+            report.health(),
+            (
+                Health::Degraded,
+                vec![
+                    "1 of 2 providers unreachable".to_string(),
+                    "only 2 of the 5 closest peers are alive".to_string()
+                ]
+            )
+        );
+
+        report.providers.remove(0);
+        assert_eq!(report.health().0, Health::Unavailable);
+        report.providers.clear();
+        report.query_error = Some("no providers".to_string());
+        assert_eq!(
+            report.health(),
+            (
+                Health::Unavailable,
+                vec![
+                    "GET_PROVIDERS query failed: no providers".to_string(),
+                    "no providers found".to_string(),
+                    "only 2 of the 5 closest peers are alive".to_string()
+                ]
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn audits_closest_peers() {
+        let peers: HashMap<_, _> = (0..2)
+            .map(|_| {
+                let (peer, address) = spawn_node(HashMap::new());
+                (peer, vec![address])
+            })
+            .collect();
+        let (bootnode, address) = spawn_node(peers);
+        let query = Cli::parse_from([
+            "dht-inspect",
+            "--bootnode",
+            &format!("{address}/p2p/{bootnode}"),
+            "--kad-proto",
+            KAD_PROTO,
+            "--allow-private-addresses",
+            "--timeout",
+            "20",
+        ])
+        .query;
+        let key = KademliaKey::new(&[1, 2, 3]);
+        let dht_query = Query::Providers(key.clone());
+        let run = query_dht(
+            &query,
+            &dht_query,
+            KAD_PROTO,
+            known_peers(&query).await.unwrap(),
+            &query.preset.settings(),
+            None,
+        )
+        .await
+        .unwrap();
+
+        let report = inspect(&run, &query, &key, &[]).await;
+        assert_eq!(
+            report.closest_peers.len(),
+            run.discovered_peers
+                .len()
+                .min(query.preset.settings().replication_factor)
+        );
+        assert!(report.closest_peers.iter().all(|(_, peer)| peer.alive()));
+        assert_eq!(report.health().0, Health::Unavailable);
+        let document = report.json(run_json(&dht_query, KAD_PROTO, &run));
+        validate(&document, "inspect_result").unwrap();
+    }
+}
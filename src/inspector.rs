@@ -0,0 +1,546 @@
+use std::{collections::HashMap, future::Future, time::Duration};
+
+use anyhow::anyhow;
+use futures::{channel::mpsc::UnboundedSender, Stream};
+use litep2p::{
+    protocol::libp2p::kademlia::{ContentProvider, PeerRecord, RecordKey as KademliaKey},
+    PeerId,
+};
+use multiaddr::Multiaddr;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    crawl::{walk_with, Walk},
+    kademlia_protocol, known_peers,
+    network::Network,
+    preset::Preset,
+    query_dht_with, record, Query, QueryArgs, QueryCancelled, QueryControl, QueryRun, QUIET,
+};
+
+/// Statistics of a query.
+#[derive(Debug, Clone)]
+pub struct Statistics {
+    /// Peers discovered via routing table updates.
+    pub discovered_peers: usize,
+    /// Peers a connection was established to.
+    pub contacted_peers: usize,
+    /// Time spent in the query, including the routing table prepopulation.
+    pub elapsed: Duration,
+}
+
+/// Peers found by [`DhtInspector::crawl`] or [`DhtInspector::explore`].
+#[derive(Debug, Clone)]
+pub struct CrawlSummary {
+    /// FIND_NODE queries run, including the failed ones.
+    pub queries: usize,
+    pub failed_queries: usize,
+    /// Network size estimated from the closest peers of every target.
+    pub network_size_estimate: Option<f64>,
+    /// Peers a connection was established to.
+    pub contacted_peers: usize,
+    /// Every peer found with its addresses, sorted by peer ID.
+    pub peers: Vec<(PeerId, Vec<Multiaddr>)>,
+}
+
+/// Progress of a query run by a [`DhtInspector`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProgressEvent {
+    /// New peer added to the routing table.
+    PeerDiscovered(PeerId),
+    /// First connection established to a peer.
+    PeerContacted(PeerId),
+    /// Provider found by a GET_PROVIDERS query.
+    ProviderFound(ContentProvider),
+    /// FIND_NODE query of the routing table prepopulation completed.
+    RoundCompleted {
+        /// Number of the query, starting from 1.
+        round: usize,
+        /// Peers discovered so far.
+        discovered_peers: usize,
+    },
+}
+
+/// Result of an operation that can be cancelled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Partial<T> {
+    /// The operation ran to completion.
+    Complete(T),
+    /// The operation was cancelled, with the results found until then.
+    Cancelled(T),
+}
+
+impl<T> Partial<T> {
+    /// Whether the operation ran to completion.
+    pub fn is_complete(&self) -> bool {
+        matches!(self, Partial::Complete(_))
+    }
+
+    /// Results, complete or not.
+    pub fn into_inner(self) -> T {
+        match self {
+            Partial::Complete(value) | Partial::Cancelled(value) => value,
+        }
+    }
+
+    /// Apply `f` to the results, keeping whether the operation ran to completion.
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> Partial<U> {
+        match self {
+            Partial::Complete(value) => Partial::Complete(f(value)),
+            Partial::Cancelled(value) => Partial::Cancelled(f(value)),
+        }
+    }
+
+    /// Wrap `value` as complete, as cancelled if `result` is a [`QueryCancelled`] error, or fail
+    /// with any other error.
+    fn from_result(result: anyhow::Result<()>, value: T) -> anyhow::Result<Self> {
+        match result {
+            Ok(()) => Ok(Partial::Complete(value)),
+            Err(error) if error.is::<QueryCancelled>() => Ok(Partial::Cancelled(value)),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+/// Configuration of a [`DhtInspector`]. The defaults match the ones of the command line tool.
+#[derive(Debug, Clone)]
+pub struct InspectorConfig {
+    /// Network to take the bootnodes and the Kademlia protocol name from, unless overridden.
+    pub network: Network,
+    /// Bootnodes to use instead of the ones of `network`.
+    pub bootnodes: Vec<(PeerId, Multiaddr)>,
+    /// Additional peers to seed the routing table with.
+    pub known_peers: Vec<(PeerId, Multiaddr)>,
+    /// Kademlia protocol name to use instead of the one of `network`.
+    pub kad_proto: Option<String>,
+    /// Number of FIND_NODE queries prepopulating the routing table before every query.
+    pub prepopulate: usize,
+    /// Kademlia and transport parameters.
+    pub preset: Preset,
+    /// Keep private and loopback addresses learned from other peers.
+    pub allow_private_addresses: bool,
+    /// Abort queries not finished within this time, rounded up to whole seconds.
+    pub timeout: Option<Duration>,
+    /// Print progress messages the way the command line tool does.
+    pub print_progress: bool,
+}
+
+impl Default for InspectorConfig {
+    fn default() -> Self {
+        Self {
+            network: Network::Polkadot,
+            bootnodes: Vec::new(),
+            known_peers: Vec::new(),
+            kad_proto: None,
+            prepopulate: 0,
+            preset: Preset::Substrate,
+            allow_private_addresses: false,
+            timeout: None,
+            print_progress: false,
+        }
+    }
+}
+
+impl InspectorConfig {
+    /// Options of the queries run by the inspector.
+    fn query_args(&self) -> QueryArgs {
+        QueryArgs {
+            network: self.network,
+            bootnode: self.bootnodes.clone(),
+            known_peer: self.known_peers.clone(),
+            kad_proto: self.kad_proto.clone(),
+            prepopulate: self.prepopulate,
+            preset: self.preset,
+            allow_private_addresses: self.allow_private_addresses,
+            timeout: self
+                .timeout
+                .map(|timeout| timeout.as_secs() + u64::from(timeout.subsec_nanos() > 0)),
+            ..QueryArgs::default()
+        }
+    }
+}
+
+/// DHT inspector for embedding into other programs.
+///
+/// Every query runs on a fresh litep2p node seeded with the configured bootnode and known peers,
+/// the same way the command line tool does. Nothing is printed unless
+/// [`InspectorConfig::print_progress`] is set, and stdin is never read.
+pub struct DhtInspector {
+    args: QueryArgs,
+    print_progress: bool,
+    kad_proto: String,
+    known_peers: HashMap<PeerId, Vec<Multiaddr>>,
+    statistics: Option<Statistics>,
+    /// Receives the progress of the next query.
+    events: Option<UnboundedSender<ProgressEvent>>,
+}
+
+impl DhtInspector {
+    /// Create new [`DhtInspector`], collecting the configured bootnode and known peers.
+    pub async fn new(config: InspectorConfig) -> anyhow::Result<Self> {
+        let args = config.query_args();
+        let print_progress = config.print_progress;
+        let (known_peers, kad_proto) = report(print_progress, async {
+            anyhow::Ok((known_peers(&args).await?, kademlia_protocol(&args)))
+        })
+        .await?;
+
+        Ok(Self {
+            args,
+            print_progress,
+            kad_proto,
+            known_peers,
+            statistics: None,
+            events: None,
+        })
+    }
+
+    /// Kademlia protocol name the queries run on.
+    pub fn kademlia_protocol(&self) -> &str {
+        &self.kad_proto
+    }
+
+    /// Run a GET_PROVIDERS query for `key` and return the found providers.
+    ///
+    /// Cancelling `cancel` aborts the query. litep2p reports the providers only once the query
+    /// completes, so a cancelled query usually returns none, but [`Self::statistics`] still
+    /// covers it.
+    pub async fn get_providers(
+        &mut self,
+        key: KademliaKey,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<Partial<Vec<ContentProvider>>> {
+        let run = self.run(Query::Providers(key), cancel).await?;
+
+        Partial::from_result(run.result, run.providers)
+    }
+
+    /// Run a FIND_NODE query for `peer` and return the closest peers found with their addresses.
+    ///
+    /// Cancelling `cancel` aborts the query, like with [`Self::get_providers`].
+    pub async fn find_node(
+        &mut self,
+        peer: PeerId,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<Partial<Vec<(PeerId, Vec<Multiaddr>)>>> {
+        let run = self.run(Query::Peer(peer), cancel).await?;
+
+        Partial::from_result(run.result, run.closest_peers)
+    }
+
+    /// Run a GET_VALUE query for `key` and return the records found.
+    ///
+    /// Cancelling `cancel` aborts the query and returns the records received until then.
+    pub async fn get_record(
+        &mut self,
+        key: KademliaKey,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<Partial<Vec<PeerRecord>>> {
+        let run = self.run(Query::Record(key), cancel).await?;
+
+        Partial::from_result(run.result, run.records)
+    }
+
+    /// Publish `value` under `key` with PUT_VALUE and return the remote peers storing it after
+    /// `settle`, see `dht-inspect put-record`.
+    ///
+    /// Cancelling `cancel` aborts the publication and returns the peers known to store the record
+    /// until then, none if the record wasn't checked yet.
+    pub async fn put_record(
+        &mut self,
+        key: KademliaKey,
+        value: Vec<u8>,
+        settle: Duration,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<Partial<Vec<PeerId>>> {
+        let mut run = self.run(Query::Record(key.clone()), cancel).await?;
+        match &run.result {
+            Err(error) if error.is::<QueryCancelled>() => {
+                return Ok(Partial::Cancelled(Vec::new()))
+            }
+            Err(_) if run.fan_out.responses() == 0 => {
+                return Err(anyhow!("no peer responded to the key lookup"))
+            }
+            _ => {}
+        }
+
+        report(
+            self.print_progress,
+            record::publish(&mut run, key, value, settle, Some(cancel)),
+        )
+        .await
+    }
+
+    /// Crawl `targets` FIND_NODE targets spread evenly over the keyspace, rounded up to a power of
+    /// two, querying at most `parallelism` at a time, see `dht-inspect crawl`.
+    ///
+    /// Cancelling `cancel` stops the crawl and returns the peers found until then. Progress events
+    /// only cover the first FIND_NODE query, and [`Self::statistics`] counts every peer found as
+    /// discovered.
+    pub async fn crawl(
+        &mut self,
+        targets: u32,
+        parallelism: usize,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<Partial<CrawlSummary>> {
+        self.walk(Walk::Crawl { targets }, parallelism, cancel)
+            .await
+    }
+
+    /// Explore the keyspace for `duration`, picking the targets adaptively, like [`Self::crawl`]
+    /// otherwise, see `dht-inspect explore`.
+    pub async fn explore(
+        &mut self,
+        duration: Duration,
+        parallelism: usize,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<Partial<CrawlSummary>> {
+        self.walk(Walk::Explore { duration }, parallelism, cancel)
+            .await
+    }
+
+    /// Stream of the progress events of the next query. It ends when the query finishes.
+    ///
+    /// Poll it concurrently with the query, e.g. with `futures::join!`. Calling this again before
+    /// the next query replaces the previous stream, which then ends right away.
+    pub fn progress_events(&mut self) -> impl Stream<Item = ProgressEvent> {
+        let (sender, receiver) = futures::channel::mpsc::unbounded();
+        self.events = Some(sender);
+
+        receiver
+    }
+
+    /// Statistics of the last query, if any was run.
+    pub fn statistics(&self) -> Option<&Statistics> {
+        self.statistics.as_ref()
+    }
+
+    async fn walk(
+        &mut self,
+        walk: Walk,
+        parallelism: usize,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<Partial<CrawlSummary>> {
+        let control = self.control(cancel);
+        let (summary, statistics) = report(
+            self.print_progress,
+            walk_with(
+                &self.args,
+                &self.kad_proto,
+                self.known_peers.clone(),
+                walk,
+                parallelism,
+                control,
+            ),
+        )
+        .await?;
+        self.statistics = Some(statistics);
+
+        Ok(summary)
+    }
+
+    async fn run(&mut self, query: Query, cancel: &CancellationToken) -> anyhow::Result<QueryRun> {
+        let control = self.control(cancel);
+        let run = report(
+            self.print_progress,
+            query_dht_with(
+                &self.args,
+                &query,
+                &self.kad_proto,
+                self.known_peers.clone(),
+                &self.args.preset.settings(),
+                None,
+                control,
+            ),
+        )
+        .await?;
+        self.statistics = Some(Statistics {
+            discovered_peers: run.discovered_peers.len(),
+            contacted_peers: run.contacted_peers.len(),
+            elapsed: run.elapsed,
+        });
+
+        Ok(run)
+    }
+
+    /// Control of the next query, cancelled by `cancel` and reporting to the progress stream.
+    fn control(&mut self, cancel: &CancellationToken) -> QueryControl {
+        QueryControl {
+            cancel: Some(cancel.clone()),
+            events: self.events.take(),
+            ..QueryControl::default()
+        }
+    }
+}
+
+/// Run `future`, silencing its progress messages unless `print_progress` is set.
+async fn report<T>(print_progress: bool, future: impl Future<Output = T>) -> T {
+    if print_progress {
+        future.await
+    } else {
+        QUIET.scope((), future).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+
+    use super::*;
+    use crate::test_utils::{spawn_node, KAD_PROTO};
+
+    /// Bootnode nothing listens on, so queries fail right away.
+    fn unreachable_bootnode() -> (PeerId, Multiaddr) {
+        let peer = PeerId::random();
+        let address = format!("/ip4/127.0.0.1/tcp/1/p2p/{peer}").parse().unwrap();
+
+        (peer, address)
+    }
+
+    #[tokio::test]
+    async fn builds_from_config() {
+        let config = InspectorConfig {
+            bootnodes: vec![unreachable_bootnode()],
+            kad_proto: Some("/test/kad".to_string()),
+            timeout: Some(Duration::from_millis(1500)),
+            ..Default::default()
+        };
+        assert_eq!(config.query_args().timeout, Some(2));
+
+        let mut inspector = DhtInspector::new(config).await.unwrap();
+        assert_eq!(inspector.kademlia_protocol(), "/test/kad");
+        assert!(inspector.statistics().is_none());
+
+        let cancel = CancellationToken::new();
+        assert!(inspector
+            .find_node(PeerId::random(), &cancel)
+            .await
+            .is_err());
+        let statistics = inspector.statistics().unwrap();
+        assert_eq!(statistics.contacted_peers, 0);
+    }
+
+    #[tokio::test]
+    async fn defaults_to_network_protocol() {
+        let inspector = DhtInspector::new(InspectorConfig {
+            network: Network::Kusama,
+            bootnodes: vec![unreachable_bootnode()],
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(
+            inspector.kademlia_protocol(),
+            Network::Kusama.kademlia_protocol()
+        );
+    }
+
+    #[tokio::test]
+    async fn cancels_query() {
+        // Accept connections without ever answering, so the query hangs until cancelled.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut connections = Vec::new();
+            while let Ok((connection, _)) = listener.accept().await {
+                connections.push(connection);
+            }
+        });
+        let peer = PeerId::random();
+        let bootnode = format!("/ip4/127.0.0.1/tcp/{port}/p2p/{peer}")
+            .parse()
+            .unwrap();
+        let mut inspector = DhtInspector::new(InspectorConfig {
+            bootnodes: vec![(peer, bootnode)],
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        let cancel = CancellationToken::new();
+        let canceller = cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            canceller.cancel();
+        });
+        let providers = inspector
+            .get_providers(KademliaKey::new(&b"key"), &cancel)
+            .await
+            .unwrap();
+
+        assert_eq!(providers, Partial::Cancelled(Vec::new()));
+        assert!(inspector.statistics().unwrap().elapsed < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn crawls_until_cancelled() {
+        let peers: HashMap<_, _> = (0..3)
+            .map(|_| {
+                let (peer, address) = spawn_node(HashMap::new());
+                (peer, vec![address])
+            })
+            .collect();
+        let (bootnode, address) = spawn_node(peers.clone());
+        let mut inspector = DhtInspector::new(InspectorConfig {
+            bootnodes: vec![(bootnode, address)],
+            kad_proto: Some(KAD_PROTO.to_string()),
+            allow_private_addresses: true,
+            timeout: Some(Duration::from_secs(20)),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        let cancel = CancellationToken::new();
+        let summary = inspector.crawl(4, 2, &cancel).await.unwrap();
+        assert!(summary.is_complete());
+        let summary = summary.into_inner();
+        assert_eq!(summary.queries, 4);
+        assert!(peers
+            .keys()
+            .all(|peer| summary.peers.iter().any(|(found, _)| found == peer)));
+        assert_eq!(
+            inspector.statistics().unwrap().discovered_peers,
+            summary.peers.len()
+        );
+
+        // A walk cancelled up front stops before any FIND_NODE response.
+        cancel.cancel();
+        let summary = inspector
+            .explore(Duration::from_secs(60), 2, &cancel)
+            .await
+            .unwrap();
+        assert!(!summary.is_complete());
+        assert_eq!(summary.into_inner().failed_queries, 1);
+    }
+
+    #[tokio::test]
+    async fn reports_progress_events() {
+        // A local Kademlia node as the only bootnode.
+        let (peer, address) = spawn_node(HashMap::new());
+
+        let mut inspector = DhtInspector::new(InspectorConfig {
+            bootnodes: vec![(peer, address)],
+            kad_proto: Some(KAD_PROTO.to_string()),
+            prepopulate: 1,
+            allow_private_addresses: true,
+            timeout: Some(Duration::from_secs(20)),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        let events = inspector.progress_events();
+        let cancel = CancellationToken::new();
+        let (closest_peers, events) = futures::join!(
+            inspector.find_node(PeerId::random(), &cancel),
+            events.collect::<Vec<_>>()
+        );
+
+        assert!(closest_peers.unwrap().is_complete());
+        assert!(events.contains(&ProgressEvent::PeerContacted(peer)));
+        assert!(events
+            .iter()
+            .any(|event| matches!(event, ProgressEvent::RoundCompleted { round: 1, .. })));
+    }
+}
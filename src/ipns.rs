@@ -0,0 +1,549 @@
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::anyhow;
+use cid::Cid;
+use litep2p::{
+    crypto::PublicKey,
+    protocol::libp2p::kademlia::{PeerRecord, RecordKey as KademliaKey},
+    PeerId,
+};
+use prost::Message;
+
+use crate::{
+    json::Json, json_output, kademlia_protocol, keys::embedded_public_key, known_peers,
+    namespace::IpnsEntry, print_document, print_protocol_hint, print_statistics, query_dht, Query,
+    QueryArgs,
+};
+
+/// Prefix of the data signed by IPNS V2 signatures.
+const SIGNATURE_PREFIX: &[u8] = b"ipns-signature:";
+
+/// Fetch, validate and decode the IPNS record of a name. Use with `--network ipfs`.
+#[derive(clap::Args, Debug)]
+pub struct IpnsArgs {
+    /// IPNS name: peer ID or `libp2p-key` CID, optionally prefixed with `/ipns/`.
+    #[arg(value_name = "PEER_ID|NAME", value_parser = parse_name)]
+    name: PeerId,
+}
+
+/// Parse an IPNS name into the peer ID of its key.
+fn parse_name(name: &str) -> anyhow::Result<PeerId> {
+    let name = name.strip_prefix("/ipns/").unwrap_or(name);
+    if let Ok(peer) = PeerId::from_str(name) {
+        return Ok(peer);
+    }
+
+    let cid = Cid::try_from(name)
+        .map_err(|_| anyhow!("expected a peer ID or a CID, DNSLink names are not supported"))?;
+    PeerId::from_bytes(&cid.hash().to_bytes()).map_err(|_| anyhow!("CID doesn't contain a peer ID"))
+}
+
+/// IPNS record whose V2 signature is valid.
+struct IpnsRecord {
+    value: String,
+    sequence: u64,
+    ttl: Option<Duration>,
+    /// End of validity as an RFC 3339 timestamp.
+    validity: String,
+    expired: bool,
+}
+
+impl IpnsRecord {
+    /// Decode `value` and check its signature against the key of `name`.
+    fn validate(name: &PeerId, value: &[u8]) -> Result<Self, String> {
+        let entry =
+            IpnsEntry::decode(value).map_err(|error| format!("invalid IPNS record: {error}"))?;
+        let (Some(data), Some(signature)) = (&entry.data, &entry.signature_v2) else {
+            return Err("no V2 signature, V1-only records are not accepted".to_string());
+        };
+
+        let key = entry
+            .public_key
+            .clone()
+            .or_else(|| embedded_public_key(name))
+            .ok_or("public key is neither in the record nor in the name")?;
+        let key = PublicKey::from_protobuf_encoding(&key)
+            .map_err(|error| format!("invalid public key: {error:?}"))?;
+        if key.to_peer_id() != *name {
+            return Err("public key doesn't match the name".to_string());
+        }
+        if !key.verify(&[SIGNATURE_PREFIX, data].concat(), signature) {
+            return Err("invalid signature".to_string());
+        }
+
+        let fields = decode_cbor_map(data).ok_or("signed data is not a DAG-CBOR map")?;
+        let bytes = |name: &str| match fields.get(name) {
+            Some(CborValue::Bytes(bytes)) => Some(bytes.clone()),
+            _ => None,
+        };
+        let unsigned = |name: &str| match fields.get(name) {
+            Some(CborValue::Unsigned(value)) => Some(*value),
+            _ => None,
+        };
+        let (Some(value), Some(validity), Some(sequence)) =
+            (bytes("Value"), bytes("Validity"), unsigned("Sequence"))
+        else {
+            return Err("signed data lacks Value, Validity or Sequence".to_string());
+        };
+        if unsigned("ValidityType") != Some(0) {
+            return Err("unsupported validity type".to_string());
+        }
+        let ttl = unsigned("TTL");
+
+        // Legacy protobuf fields are not signed, they must repeat the signed ones if present.
+        let mismatch = entry.value.as_ref().is_some_and(|field| *field != value)
+            || entry
+                .validity
+                .as_ref()
+                .is_some_and(|field| *field != validity)
+            || entry.sequence.is_some_and(|field| field != sequence)
+            || entry.ttl.is_some_and(|field| Some(field) != ttl);
+        if mismatch {
+            return Err("unsigned fields don't match the signed data".to_string());
+        }
+
+        let validity = String::from_utf8_lossy(&validity).into_owned();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let expired =
+            parse_rfc3339(&validity).ok_or_else(|| format!("invalid validity: {validity}"))? < now;
+
+        Ok(Self {
+            value: String::from_utf8_lossy(&value).into_owned(),
+            sequence,
+            ttl: ttl.map(Duration::from_nanos),
+            validity,
+            expired,
+        })
+    }
+
+    fn json(&self) -> Json {
+        Json::object()
+            .field("value", self.value.as_str())
+            .field("sequence", self.sequence)
+            .field("ttl_s", self.ttl.map(|ttl| ttl.as_secs()))
+            .field("validity", self.validity.as_str())
+            .field("expired", self.expired)
+    }
+}
+
+/// Lines describing the IPNS record `value` of `name`, validated like `dht-inspect ipns` does.
+pub(crate) fn describe(name: &PeerId, value: &[u8]) -> Vec<String> {
+    let record = match IpnsRecord::validate(name, value) {
+        Ok(record) => record,
+        Err(error) => return vec![format!("Invalid IPNS record: {error}")],
+    };
+
+    let mut lines = vec![
+        format!("IPNS path: {}", record.value),
+        format!("IPNS sequence: {}", record.sequence),
+    ];
+    if let Some(ttl) = record.ttl {
+        lines.push(format!("IPNS TTL: {} s", ttl.as_secs()));
+    }
+    let expired = if record.expired { ", expired" } else { "" };
+    lines.push(format!("IPNS validity: {}{expired}", record.validity));
+
+    lines
+}
+
+/// Value of the DAG-CBOR map in IPNS records.
+enum CborValue {
+    Unsigned(u64),
+    Bytes(Vec<u8>),
+    /// Text string, not used by the validation.
+    Text,
+}
+
+/// Decode a CBOR map with text keys and unsigned, byte or text string values.
+fn decode_cbor_map(data: &[u8]) -> Option<HashMap<String, CborValue>> {
+    let mut data = data;
+    let (5, entries) = cbor_head(&mut data)? else {
+        return None;
+    };
+
+    let mut map = HashMap::new();
+    for _ in 0..entries {
+        let (3, len) = cbor_head(&mut data)? else {
+            return None;
+        };
+        let key = String::from_utf8(cbor_take(&mut data, len)?.to_vec()).ok()?;
+        let value = match cbor_head(&mut data)? {
+            (0, value) => CborValue::Unsigned(value),
+            (2, len) => CborValue::Bytes(cbor_take(&mut data, len)?.to_vec()),
+            (3, len) => {
+                cbor_take(&mut data, len)?;
+                CborValue::Text
+            }
+            _ => return None,
+        };
+        map.insert(key, value);
+    }
+
+    data.is_empty().then_some(map)
+}
+
+/// Read the major type and argument of the next CBOR data item.
+fn cbor_head(data: &mut &[u8]) -> Option<(u8, u64)> {
+    let (&initial, rest) = data.split_first()?;
+    *data = rest;
+    let size = match initial & 0x1f {
+        info @ 0..=23 => return Some((initial >> 5, info as u64)),
+        24 => 1,
+        25 => 2,
+        26 => 4,
+        27 => 8,
+        _ => return None,
+    };
+
+    let argument = cbor_take(data, size)?
+        .iter()
+        .fold(0, |argument, byte| argument << 8 | *byte as u64);
+    Some((initial >> 5, argument))
+}
+
+fn cbor_take<'a>(data: &mut &'a [u8], len: u64) -> Option<&'a [u8]> {
+    let len = usize::try_from(len).ok().filter(|len| *len <= data.len())?;
+    let (taken, rest) = data.split_at(len);
+    *data = rest;
+
+    Some(taken)
+}
+
+/// Parse an RFC 3339 UTC timestamp, e.g. `2024-05-01T12:00:00.123456789Z`, into seconds since
+/// UNIX epoch. Fractional seconds are ignored.
+///
+/// Years are limited to the four digits RFC 3339 allows, which also keeps the arithmetic below
+/// from overflowing.
+fn parse_rfc3339(timestamp: &str) -> Option<u64> {
+    let timestamp = timestamp.strip_suffix('Z')?;
+    let (date, time) = timestamp.split_once('T')?;
+    let time = time.split('.').next()?;
+    let number = |part: Option<&str>| part?.parse::<i64>().ok();
+    let mut date = date.splitn(3, '-');
+    let (year, month, day) = (
+        number(date.next())?,
+        number(date.next())?,
+        number(date.next())?,
+    );
+    let mut time = time.splitn(3, ':');
+    let (hour, minute, second) = (
+        number(time.next())?,
+        number(time.next())?,
+        number(time.next())?,
+    );
+    if !(0..=9999).contains(&year)
+        || !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || !(0..=23).contains(&hour)
+        || !(0..=59).contains(&minute)
+        // Leap seconds.
+        || !(0..=60).contains(&second)
+    {
+        return None;
+    }
+
+    // Days since the epoch of the proleptic Gregorian calendar date.
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    u64::try_from(days * 86_400 + hour * 3600 + minute * 60 + second).ok()
+}
+
+/// Run the GET_VALUE query for `/ipns/<name>` and print the latest valid record.
+///
+/// Every record found is validated. Records with a lower sequence number than the latest valid one
+/// are reported as stale.
+pub async fn run(args: IpnsArgs, query: &QueryArgs) -> anyhow::Result<()> {
+    let known_peers = known_peers(query).await?;
+    let kad_proto = kademlia_protocol(query);
+    let settings = query.preset.settings();
+
+    let key = KademliaKey::new(&[b"/ipns/".as_slice(), &args.name.to_bytes()].concat());
+    let dht_query = Query::Record(key);
+    let run = query_dht(query, &dht_query, &kad_proto, known_peers, &settings, None).await?;
+
+    let records: Vec<_> = run
+        .records
+        .iter()
+        .map(|PeerRecord { peer, record }| (peer, IpnsRecord::validate(&args.name, &record.value)))
+        .collect();
+    let latest = records
+        .iter()
+        .filter_map(|(_, record)| record.as_ref().ok())
+        .max_by_key(|record| record.sequence);
+
+    if json_output() {
+        let document = Json::object()
+            .field("name", args.name.to_string())
+            .field("protocol", kad_proto.as_str())
+            .field("latest", latest.map(IpnsRecord::json))
+            .field(
+                "records",
+                records
+                    .iter()
+                    .map(|(peer, record)| {
+                        let document = Json::object().field("peer_id", peer.to_string());
+                        match record {
+                            Ok(record) => document.field("record", record.json()),
+                            Err(error) => document.field("error", error.as_str()),
+                        }
+                    })
+                    .collect::<Vec<_>>(),
+            );
+        print_document(document);
+        return run.result;
+    }
+
+    print_statistics(&run, &settings);
+    if let Err(error) = run.result {
+        print_protocol_hint(query, &run.fan_out, &kad_proto);
+        return Err(error);
+    }
+
+    for (peer, record) in &records {
+        match record {
+            Ok(record) if latest.is_some_and(|latest| record.sequence < latest.sequence) => {
+                println!("Record from {peer}: stale, sequence {}", record.sequence)
+            }
+            Ok(record) => println!("Record from {peer}: valid, sequence {}", record.sequence),
+            Err(error) => println!("Record from {peer}: {error}"),
+        }
+    }
+
+    let Some(latest) = latest else {
+        return Err(anyhow!("no valid IPNS record found for {}", args.name));
+    };
+    println!();
+    println!("Value: {}", latest.value);
+    println!("Sequence: {}", latest.sequence);
+    match latest.ttl {
+        Some(ttl) => println!("TTL: {} s", ttl.as_secs()),
+        None => println!("TTL: unknown"),
+    }
+    let expired = if latest.expired { ", expired" } else { "" };
+    println!("Valid until: {}{expired}", latest.validity);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use litep2p::crypto::{ed25519::Keypair, PublicKey as Litep2pPublicKey};
+
+    use super::*;
+
+    /// Encode the head of a CBOR data item.
+    fn encode_head(major: u8, argument: u64) -> Vec<u8> {
+        match argument {
+            0..=23 => vec![major << 5 | argument as u8],
+            24..=0xff => vec![major << 5 | 24, argument as u8],
+            0x100..=0xffff => [
+                vec![major << 5 | 25],
+                (argument as u16).to_be_bytes().to_vec(),
+            ]
+            .concat(),
+            _ => [vec![major << 5 | 27], argument.to_be_bytes().to_vec()].concat(),
+        }
+    }
+
+    /// Encode the signed DAG-CBOR data of an IPNS record.
+    fn signed_data(value: &[u8], validity: &str, sequence: u64, ttl: u64) -> Vec<u8> {
+        let bytes = |bytes: &[u8]| [encode_head(2, bytes.len() as u64), bytes.to_vec()].concat();
+        let mut data = encode_head(5, 5);
+        for (key, item) in [
+            ("TTL", encode_head(0, ttl)),
+            ("Value", bytes(value)),
+            ("Sequence", encode_head(0, sequence)),
+            ("Validity", bytes(validity.as_bytes())),
+            ("ValidityType", encode_head(0, 0)),
+        ] {
+            data.extend(encode_head(3, key.len() as u64));
+            data.extend(key.as_bytes());
+            data.extend(item);
+        }
+
+        data
+    }
+
+    /// Create an IPNS record for `value` signed by a new key, returning the name and the entry.
+    fn signed_entry(value: &[u8], validity: &str) -> (PeerId, IpnsEntry) {
+        let keypair = Keypair::generate();
+        let name = Litep2pPublicKey::Ed25519(keypair.public()).to_peer_id();
+        let data = signed_data(value, validity, 7, 3_600_000_000_000);
+        let entry = IpnsEntry {
+            value: Some(value.to_vec()),
+            validity: Some(validity.as_bytes().to_vec()),
+            sequence: Some(7),
+            ttl: Some(3_600_000_000_000),
+            public_key: None,
+            signature_v2: Some(keypair.sign(&[SIGNATURE_PREFIX, &data].concat())),
+            data: Some(data),
+        };
+
+        (name, entry)
+    }
+
+    #[test]
+    fn parses_timestamps() {
+        assert_eq!(parse_rfc3339("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(
+            parse_rfc3339("2024-05-01T12:00:00.123456789Z"),
+            Some(1_714_564_800)
+        );
+        assert_eq!(parse_rfc3339("2000-03-01T00:00:00Z"), Some(951_868_800));
+        assert_eq!(parse_rfc3339("9999-12-31T23:59:60Z"), Some(253_402_300_800));
+        assert_eq!(parse_rfc3339("1969-12-31T23:59:59Z"), None);
+        assert_eq!(parse_rfc3339("2024-05-01T12:00:00+02:00"), None);
+        assert_eq!(parse_rfc3339("2024-13-01T12:00:00Z"), None);
+        assert_eq!(parse_rfc3339("2024-05-01T24:00:00Z"), None);
+        assert_eq!(parse_rfc3339("9223372036854775807-05-01T12:00:00Z"), None);
+        assert_eq!(parse_rfc3339("10000-01-01T00:00:00Z"), None);
+    }
+
+    #[test]
+    fn decodes_cbor_maps() {
+        let data = signed_data(b"/ipfs/cid", "2030-01-01T00:00:00Z", 300, 5);
+        let map = decode_cbor_map(&data).unwrap();
+        assert!(matches!(map.get("Value"), Some(CborValue::Bytes(value)) if value == b"/ipfs/cid"));
+        assert!(matches!(
+            map.get("Sequence"),
+            Some(CborValue::Unsigned(300))
+        ));
+        assert!(matches!(map.get("TTL"), Some(CborValue::Unsigned(5))));
+
+        let text = [
+            encode_head(5, 1),
+            encode_head(3, 1),
+            b"k".to_vec(),
+            encode_head(3, 1),
+            b"v".to_vec(),
+        ];
+        assert!(matches!(
+            decode_cbor_map(&text.concat()).unwrap().get("k"),
+            Some(CborValue::Text)
+        ));
+
+        // Truncated input, trailing bytes, non-map items, non-text keys, reserved argument sizes and
+        // lengths beyond the input.
+        assert!(decode_cbor_map(&data[..data.len() - 1]).is_none());
+        assert!(decode_cbor_map(&[data.clone(), vec![0]].concat()).is_none());
+        assert!(decode_cbor_map(&encode_head(0, 1)).is_none());
+        assert!(decode_cbor_map(
+            &[encode_head(5, 1), encode_head(0, 1), encode_head(0, 1)].concat()
+        )
+        .is_none());
+        assert!(decode_cbor_map(&[0xa1, 0x7c]).is_none());
+        assert!(
+            decode_cbor_map(&[0xa1, 0x7b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff])
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn validates_v2_records() {
+        let (name, entry) = signed_entry(b"/ipfs/cid", "2999-01-01T00:00:00Z");
+        let record = IpnsRecord::validate(&name, &entry.encode_to_vec()).unwrap();
+        assert_eq!(record.value, "/ipfs/cid");
+        assert_eq!(record.sequence, 7);
+        assert_eq!(record.ttl, Some(Duration::from_secs(3600)));
+        assert!(!record.expired);
+
+        // Legacy fields may be left out.
+        let legacy_free = IpnsEntry {
+            value: None,
+            validity: None,
+            sequence: None,
+            ttl: None,
+            ..entry.clone()
+        };
+        assert!(IpnsRecord::validate(&name, &legacy_free.encode_to_vec()).is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_records() {
+        let (name, entry) = signed_entry(b"/ipfs/cid", "2999-01-01T00:00:00Z");
+
+        let tampered = IpnsEntry {
+            value: Some(b"/ipfs/other".to_vec()),
+            ..entry.clone()
+        };
+        assert_eq!(
+            IpnsRecord::validate(&name, &tampered.encode_to_vec())
+                .err()
+                .unwrap(),
+            "unsigned fields don't match the signed data"
+        );
+        let tampered = IpnsEntry {
+            sequence: Some(8),
+            ..entry.clone()
+        };
+        assert!(IpnsRecord::validate(&name, &tampered.encode_to_vec()).is_err());
+
+        let mut data = entry.data.clone().unwrap();
+        *data.last_mut().unwrap() ^= 1;
+        let forged = IpnsEntry {
+            data: Some(data),
+            ..entry.clone()
+        };
+        assert_eq!(
+            IpnsRecord::validate(&name, &forged.encode_to_vec())
+                .err()
+                .unwrap(),
+            "invalid signature"
+        );
+
+        let other_name = Litep2pPublicKey::Ed25519(Keypair::generate().public()).to_peer_id();
+        assert!(IpnsRecord::validate(&other_name, &entry.encode_to_vec()).is_err());
+
+        let v1 = IpnsEntry {
+            signature_v2: None,
+            ..entry
+        };
+        assert!(IpnsRecord::validate(&name, &v1.encode_to_vec()).is_err());
+    }
+
+    #[test]
+    fn describes_validated_records() {
+        let (name, entry) = signed_entry(b"/ipfs/cid", "2001-01-01T00:00:00Z");
+        assert_eq!(
+            describe(&name, &entry.encode_to_vec()),
+            [
+                "IPNS path: /ipfs/cid",
+                "IPNS sequence: 7",
+                "IPNS TTL: 3600 s",
+                "IPNS validity: 2001-01-01T00:00:00Z, expired",
+            ]
+        );
+
+        let forged = IpnsEntry {
+            sequence: Some(8),
+            ..entry
+        };
+        assert_eq!(
+            describe(&name, &forged.encode_to_vec()),
+            ["Invalid IPNS record: unsigned fields don't match the signed data"]
+        );
+    }
+
+    #[test]
+    fn flags_expired_records() {
+        let (name, entry) = signed_entry(b"/ipfs/cid", "2001-01-01T00:00:00.5Z");
+        let record = IpnsRecord::validate(&name, &entry.encode_to_vec()).unwrap();
+        assert!(record.expired);
+        assert_eq!(record.validity, "2001-01-01T00:00:00.5Z");
+
+        let (name, entry) = signed_entry(b"/ipfs/cid", "not a timestamp");
+        assert!(IpnsRecord::validate(&name, &entry.encode_to_vec()).is_err());
+    }
+}
@@ -0,0 +1,530 @@
+use std::fmt;
+
+/// Maximum nesting of arrays and objects [`Json::parse`] accepts, to bound its recursion.
+const MAX_DEPTH: usize = 128;
+
+/// JSON value, serialized compactly by its [`fmt::Display`] implementation and parsed by
+/// [`Json::parse`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    /// Object with fields in insertion order.
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// Empty object.
+    pub fn object() -> Self {
+        Json::Object(Vec::new())
+    }
+
+    /// Add a field to an object.
+    pub fn field(mut self, key: &str, value: impl Into<Json>) -> Self {
+        if let Json::Object(fields) = &mut self {
+            fields.push((key.to_string(), value.into()));
+        }
+
+        self
+    }
+}
+
+impl From<bool> for Json {
+    fn from(value: bool) -> Self {
+        Json::Bool(value)
+    }
+}
+
+impl From<usize> for Json {
+    fn from(value: usize) -> Self {
+        Json::Number(value as f64)
+    }
+}
+
+impl From<u64> for Json {
+    fn from(value: u64) -> Self {
+        Json::Number(value as f64)
+    }
+}
+
+impl From<f64> for Json {
+    fn from(value: f64) -> Self {
+        Json::Number(value)
+    }
+}
+
+impl From<&str> for Json {
+    fn from(value: &str) -> Self {
+        Json::String(value.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(value: String) -> Self {
+        Json::String(value)
+    }
+}
+
+impl<T: Into<Json>> From<Option<T>> for Json {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Json::Null, Into::into)
+    }
+}
+
+impl<T: Into<Json>> From<Vec<T>> for Json {
+    fn from(values: Vec<T>) -> Self {
+        Json::Array(values.into_iter().map(Into::into).collect())
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Json::Null => f.write_str("null"),
+            Json::Bool(value) => write!(f, "{value}"),
+            Json::Number(value) if value.is_finite() => write!(f, "{value}"),
+            Json::Number(_) => f.write_str("null"),
+            Json::String(value) => write_string(f, value),
+            Json::Array(values) => {
+                f.write_str("[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{value}")?;
+                }
+                f.write_str("]")
+            }
+            Json::Object(fields) => {
+                f.write_str("{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{value}")?;
+                }
+                f.write_str("}")
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, value: &str) -> fmt::Result {
+    f.write_str("\"")?;
+    for c in value.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if c.is_control() => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{c}")?,
+        }
+    }
+    f.write_str("\"")
+}
+
+impl Json {
+    /// Parse a JSON document.
+    pub fn parse(input: &str) -> anyhow::Result<Json> {
+        let mut parser = Parser {
+            input: input.as_bytes(),
+            position: 0,
+            depth: 0,
+        };
+        let value = parser.value()?;
+        parser.whitespace();
+        if parser.position != parser.input.len() {
+            return Err(parser.error("trailing characters"));
+        }
+
+        Ok(value)
+    }
+
+    /// Field of an object.
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields
+                .iter()
+                .find_map(|(name, value)| (name == key).then_some(value)),
+            _ => None,
+        }
+    }
+
+    /// String value.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Array elements.
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+/// Recursive descent JSON parser.
+struct Parser<'a> {
+    input: &'a [u8],
+    position: usize,
+    /// Arrays and objects the parser is in.
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> anyhow::Error {
+        anyhow::anyhow!("invalid JSON at byte {}: {message}", self.position)
+    }
+
+    fn whitespace(&mut self) {
+        while self
+            .input
+            .get(self.position)
+            .is_some_and(|byte| byte.is_ascii_whitespace())
+        {
+            self.position += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.whitespace();
+        self.input.get(self.position).copied()
+    }
+
+    fn expect(&mut self, literal: &str) -> anyhow::Result<()> {
+        if !self.input[self.position..].starts_with(literal.as_bytes()) {
+            return Err(self.error(&format!("expected `{literal}`")));
+        }
+        self.position += literal.len();
+
+        Ok(())
+    }
+
+    fn value(&mut self) -> anyhow::Result<Json> {
+        match self.peek() {
+            Some(b'[' | b'{') if self.depth == MAX_DEPTH => Err(self.error("nesting too deep")),
+            Some(b'n') => self.expect("null").map(|()| Json::Null),
+            Some(b't') => self.expect("true").map(|()| Json::Bool(true)),
+            Some(b'f') => self.expect("false").map(|()| Json::Bool(false)),
+            Some(b'"') => self.string().map(Json::String),
+            Some(b'[') => {
+                self.depth += 1;
+                let array = self.array();
+                self.depth -= 1;
+                array
+            }
+            Some(b'{') => {
+                self.depth += 1;
+                let object = self.object();
+                self.depth -= 1;
+                object
+            }
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(_) => Err(self.error("unexpected character")),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn array(&mut self) -> anyhow::Result<Json> {
+        self.expect("[")?;
+        let mut values = Vec::new();
+        if self.peek() == Some(b']') {
+            self.position += 1;
+            return Ok(Json::Array(values));
+        }
+
+        loop {
+            values.push(self.value()?);
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(b']') => {
+                    self.position += 1;
+                    return Ok(Json::Array(values));
+                }
+                _ => return Err(self.error("expected `,` or `]`")),
+            }
+        }
+    }
+
+    fn object(&mut self) -> anyhow::Result<Json> {
+        self.expect("{")?;
+        let mut fields = Vec::new();
+        if self.peek() == Some(b'}') {
+            self.position += 1;
+            return Ok(Json::Object(fields));
+        }
+
+        loop {
+            if self.peek() != Some(b'"') {
+                return Err(self.error("expected field name"));
+            }
+            let key = self.string()?;
+            if self.peek() != Some(b':') {
+                return Err(self.error("expected `:`"));
+            }
+            self.position += 1;
+            fields.push((key, self.value()?));
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(b'}') => {
+                    self.position += 1;
+                    return Ok(Json::Object(fields));
+                }
+                _ => return Err(self.error("expected `,` or `}`")),
+            }
+        }
+    }
+
+    /// Number following the JSON grammar: `-?(0|[1-9][0-9]*)(\.[0-9]+)?([eE][+-]?[0-9]+)?`.
+    fn number(&mut self) -> anyhow::Result<Json> {
+        let start = self.position;
+        self.skip(|byte| byte == b'-', 1);
+        match self.input.get(self.position) {
+            Some(b'0') => self.position += 1,
+            Some(b'1'..=b'9') => {
+                self.skip(|byte| byte.is_ascii_digit(), usize::MAX);
+            }
+            _ => return Err(self.error("invalid number")),
+        }
+        if self.skip(|byte| byte == b'.', 1) && !self.skip(|byte| byte.is_ascii_digit(), usize::MAX)
+        {
+            return Err(self.error("invalid number"));
+        }
+        if self.skip(|byte| matches!(byte, b'e' | b'E'), 1) {
+            self.skip(|byte| matches!(byte, b'+' | b'-'), 1);
+            if !self.skip(|byte| byte.is_ascii_digit(), usize::MAX) {
+                return Err(self.error("invalid number"));
+            }
+        }
+
+        std::str::from_utf8(&self.input[start..self.position])
+            .ok()
+            .and_then(|number| number.parse().ok())
+            .map(Json::Number)
+            .ok_or_else(|| self.error("invalid number"))
+    }
+
+    /// Skip up to `max` bytes matching `matches`. Returns whether any were skipped.
+    fn skip(&mut self, matches: impl Fn(u8) -> bool, max: usize) -> bool {
+        let start = self.position;
+        while self.position - start < max
+            && self
+                .input
+                .get(self.position)
+                .is_some_and(|byte| matches(*byte))
+        {
+            self.position += 1;
+        }
+
+        self.position > start
+    }
+
+    fn string(&mut self) -> anyhow::Result<String> {
+        self.expect("\"")?;
+        let mut value = String::new();
+
+        loop {
+            let start = self.position;
+            while self
+                .input
+                .get(self.position)
+                .is_some_and(|byte| *byte != b'"' && *byte != b'\\' && *byte >= 0x20)
+            {
+                self.position += 1;
+            }
+            value.push_str(
+                std::str::from_utf8(&self.input[start..self.position])
+                    .map_err(|_| self.error("invalid UTF-8"))?,
+            );
+
+            match self.input.get(self.position) {
+                Some(b'"') => {
+                    self.position += 1;
+                    return Ok(value);
+                }
+                Some(b'\\') => {
+                    self.position += 1;
+                    let escaped = match self.input.get(self.position) {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => {
+                            self.position += 1;
+                            let mut code = self.hex4()?;
+                            // Characters outside the BMP are encoded as UTF-16 surrogate pairs.
+                            if (0xd800..0xdc00).contains(&code) {
+                                self.expect("\\u")?;
+                                let low = self.hex4()?;
+                                if !(0xdc00..0xe000).contains(&low) {
+                                    return Err(self.error("invalid surrogate pair"));
+                                }
+                                code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                            }
+                            value.push(
+                                char::from_u32(code).ok_or_else(|| self.error("invalid escape"))?,
+                            );
+                            continue;
+                        }
+                        _ => return Err(self.error("invalid escape")),
+                    };
+                    value.push(escaped);
+                    self.position += 1;
+                }
+                Some(_) => return Err(self.error("control character in string")),
+                None => return Err(self.error("unterminated string")),
+            }
+        }
+    }
+
+    /// Four hex digits of a `\u` escape.
+    fn hex4(&mut self) -> anyhow::Result<u32> {
+        let digits = self
+            .input
+            .get(self.position..self.position + 4)
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+            .ok_or_else(|| self.error("invalid escape"))?;
+        self.position += 4;
+
+        Ok(digits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str) -> Json {
+        Json::parse(input).unwrap()
+    }
+
+    fn string(value: &str) -> Json {
+        Json::String(value.to_string())
+    }
+
+    #[test]
+    fn parses_escapes() {
+        assert_eq!(
+            parse(r#""a\"b\\c\/d\b\f\n\r\t""#),
+            string("a\"b\\c/d\u{8}\u{c}\n\r\t")
+        );
+        assert_eq!(parse(r#""\u0041\u00e9\u20AC""#), string("Aé€"));
+        assert_eq!(parse(r#""ünïcödé""#), string("ünïcödé"));
+
+        for invalid in [r#""\x""#, r#""\u12""#, r#""\u12g4""#, "\"a\nb\"", r#""abc"#] {
+            assert!(Json::parse(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn parses_surrogate_pairs() {
+        assert_eq!(parse(r#""\ud83d\ude00""#), string("😀"));
+        assert_eq!(parse(r#""a\uD834\uDD1Eb""#), string("a𝄞b"));
+
+        // Lone surrogates and high surrogates followed by anything but a low one.
+        for invalid in [
+            r#""\ud83d""#,
+            r#""\ud83dx""#,
+            r#""\ude00""#,
+            r#""\ud83d\u0041""#,
+            r#""\ud83d\ud83d""#,
+        ] {
+            assert!(Json::parse(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn parses_numbers() {
+        for (input, value) in [
+            ("0", 0.0),
+            ("-0", -0.0),
+            ("42", 42.0),
+            ("-1.5", -1.5),
+            ("1e3", 1000.0),
+            ("2.5E-1", 0.25),
+            ("1e+2", 100.0),
+        ] {
+            assert_eq!(parse(input), Json::Number(value), "{input}");
+        }
+
+        for invalid in [
+            "01", "1.", ".5", "-", "+1", "1e", "1e+", "--1", "1.2.3", "0x10",
+        ] {
+            assert!(Json::parse(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn parses_nested_values() {
+        let value = parse(r#" { "a" : [1, {"b": null}, [true, false]], "c": {} , "d": [] } "#);
+        assert_eq!(
+            value,
+            Json::Object(vec![
+                (
+                    "a".to_string(),
+                    Json::Array(vec![
+                        Json::Number(1.0),
+                        Json::Object(vec![("b".to_string(), Json::Null)]),
+                        Json::Array(vec![Json::Bool(true), Json::Bool(false)]),
+                    ])
+                ),
+                ("c".to_string(), Json::Object(Vec::new())),
+                ("d".to_string(), Json::Array(Vec::new())),
+            ])
+        );
+        assert_eq!(
+            value.get("a").and_then(Json::as_array).map(<[_]>::len),
+            Some(3)
+        );
+
+        let nested = |depth: usize| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+        assert!(Json::parse(&nested(MAX_DEPTH)).is_ok());
+        assert!(Json::parse(&nested(MAX_DEPTH + 1)).is_err());
+        assert!(Json::parse(&nested(100_000)).is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        for invalid in [
+            "",
+            "   ",
+            "nul",
+            "True",
+            "[1,]",
+            "[1 2]",
+            "{\"a\" 1}",
+            "{\"a\": 1,}",
+            "{a: 1}",
+            "[1] [2]",
+            "{\"a\": [1}",
+            "[",
+        ] {
+            assert!(Json::parse(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn round_trips_serialized_values() {
+        let value = Json::object()
+            .field("text", "quote \" backslash \\ newline \n control \u{1}")
+            .field("number", 1.25)
+            .field("list", vec![Some(1usize), None]);
+
+        assert_eq!(parse(&value.to_string()), value);
+    }
+}
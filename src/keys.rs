@@ -0,0 +1,37 @@
+use std::{collections::HashSet, fmt::Write, path::Path};
+
+use anyhow::Context;
+use litep2p::PeerId;
+
+use crate::namespace::IDENTITY_MULTIHASH;
+
+/// Public key of a peer embedded into its peer ID, protobuf-encoded as in libp2p.
+///
+/// litep2p doesn't expose the keys exchanged in the Noise handshake, but Ed25519 peer IDs, the
+/// only ones litep2p connects to, are the identity multihash of the key. Keys hashed into the peer
+/// ID can't be recovered.
+pub fn embedded_public_key(peer: &PeerId) -> Option<Vec<u8>> {
+    match peer.to_bytes().as_slice() {
+        [IDENTITY_MULTIHASH, len, key @ ..] if *len as usize == key.len() => Some(key.to_vec()),
+        _ => None,
+    }
+}
+
+/// Write the public keys of `peers` to `path`, one `<peer id> <hex key>` line per peer.
+///
+/// Returns the number of peers whose key couldn't be extracted. They are left out of the export.
+pub fn write_key_export(path: &Path, peers: &HashSet<PeerId>) -> anyhow::Result<usize> {
+    let mut export = String::new();
+    let mut missing = 0;
+
+    for peer in peers {
+        match embedded_public_key(peer) {
+            Some(key) => writeln!(export, "{peer} {}", hex::encode(key))?,
+            None => missing += 1,
+        }
+    }
+
+    std::fs::write(path, export).with_context(|| format!("failed to write {}", path.display()))?;
+
+    Ok(missing)
+}
@@ -0,0 +1,1782 @@
+use std::{
+    collections::{HashMap, HashSet},
+    net::IpAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Context};
+use cid::Cid;
+use clap::{Args as _, FromArgMatches};
+use futures::{
+    channel::mpsc::{UnboundedReceiver, UnboundedSender},
+    Stream, StreamExt,
+};
+use litep2p::{
+    config::ConfigBuilder as Litep2pConfigBuilder,
+    protocol::libp2p::{
+        identify::{Config as IdentifyConfig, IdentifyEvent},
+        kademlia::{
+            ConfigBuilder as KademliaConfigBuilder, ContentProvider, KademliaEvent, KademliaHandle,
+            PeerRecord, QueryId, Quorum, RecordKey as KademliaKey, RoutingTableUpdateMode,
+        },
+    },
+    protocol::request_response::{
+        ConfigBuilder as RequestResponseConfigBuilder, RequestResponseHandle,
+    },
+    transport::{
+        tcp::config::Config as TcpConfig, websocket::config::Config as WsConfig, Endpoint,
+    },
+    Litep2p, Litep2pEvent, PeerId,
+};
+use multiaddr::{Multiaddr, Protocol};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    address::{check_advertised, is_private, AddressFilter},
+    chainspec::{parse_chain_spec, ChainSpec},
+    connections::ConnectionTable,
+    csv::{write_csv, Sighting},
+    doh::DohResolver,
+    fanout::FanOut,
+    json::Json,
+    keys::write_key_export,
+    limits::Limits,
+    logfmt::Logfmt,
+    manifest::Signer,
+    network::{kademlia_protocol_name, legacy_kademlia_protocol_name, parse_genesis_hash, Network},
+    prefix::PrefixIndex,
+    preset::{Preset, Settings},
+    refresh::Refresh,
+    resources::Usage,
+    retry::{parse_duration, sleep_until, RetryPolicy},
+    routing_cache::write_routing_table,
+    schema::SCHEMA_VERSION,
+    status::{Status, PROGRESS_FILE_INTERVAL},
+    verify::record_identified,
+};
+
+pub use crate::inspector::{
+    CrawlSummary, DhtInspector, InspectorConfig, Partial, ProgressEvent, Statistics,
+};
+
+/// Format of the results. Set once at startup.
+static OUTPUT: OnceLock<OutputFormat> = OnceLock::new();
+
+/// Signer of the result documents given `--sign-output`. Set once at startup.
+static SIGNER: OnceLock<Signer> = OnceLock::new();
+
+/// Whether progress messages are printed. Set once at startup, printed if never set.
+static PROGRESS: OnceLock<bool> = OnceLock::new();
+
+/// Console commands passed to [`set_console`], held by one running query at a time.
+static CONSOLE: Mutex<Option<UnboundedReceiver<String>>> = Mutex::new(None);
+
+tokio::task_local! {
+    /// Set while a [`DhtInspector`] runs a query without printing progress messages.
+    static QUIET: ();
+}
+
+/// Print a progress message: to stdout with text output, to stderr with JSON output so that
+/// stdout carries only JSON, or to the log panel of the `--tui` dashboard while it is shown.
+/// Nothing is printed with progress messages turned off by [`set_progress`], or by a
+/// [`DhtInspector`] without progress reporting.
+macro_rules! progress {
+    () => {
+        progress!("")
+    };
+    ($($arg:tt)*) => {
+        if crate::QUIET.try_with(|_| ()).is_ok() {
+        } else if crate::tui::active() {
+            crate::tui::log(format!($($arg)*))
+        } else if !crate::PROGRESS.get().copied().unwrap_or(true) {
+        } else if crate::json_output() {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
+
+mod address;
+pub mod authorities;
+pub mod chainspec;
+mod confidence;
+mod connections;
+pub mod crawl;
+mod crosscheck;
+mod csv;
+mod doh;
+mod fanout;
+pub mod find_node;
+pub mod genkey;
+mod holders;
+mod http;
+pub mod inspect;
+mod inspector;
+pub mod ipns;
+mod json;
+mod keys;
+pub mod limits;
+mod logfmt;
+pub mod manifest;
+mod metrics;
+mod namespace;
+pub mod network;
+pub mod overlap;
+mod peer_store;
+mod prefix;
+pub mod preset;
+pub mod probe;
+pub mod provide;
+pub mod providers;
+mod reachability;
+pub mod record;
+mod refresh;
+mod resources;
+pub mod retry;
+mod routing_cache;
+mod rpc;
+pub mod schema;
+pub mod serve;
+mod signal;
+mod status;
+#[cfg(test)]
+mod test_utils;
+mod topology;
+mod tui;
+mod verify;
+
+const IDENTIFY_PROTOCOL_VERSION: &str = "/dht-inspect/1.0.0";
+const USER_AGENT: &str = concat!("dht-inspect/", env!("CARGO_PKG_VERSION"));
+/// Timeout of the application protocol request sent to providers to verify them.
+const VERIFY_TIMEOUT: Duration = Duration::from_secs(10);
+/// Minimum number of peers discovered during a failed GET_PROVIDERS query to re-run it.
+const AUTO_REQUERY_MIN_GROWTH: usize = 20;
+
+/// Parse a multiaddress into [`PeerId`] and [`Multiaddr`].
+fn parse_multiaddress(addr: &str) -> Result<(PeerId, Multiaddr), anyhow::Error> {
+    let addr = Multiaddr::from_str(addr).context("invalid multiaddress")?;
+    let peer_id = match addr.iter().last() {
+        Some(Protocol::P2p(multihash)) => PeerId::from_multihash(multihash)
+            .map_err(|m| anyhow!("multihash is not a peer ID in a multiaddress: {m:?}"))?,
+        _ => return Err(anyhow!("multiaddress doesn't contain peer ID")),
+    };
+
+    Ok((peer_id, addr))
+}
+
+/// Read multiaddresses from a file, one per line. Empty lines and `#` comments are ignored.
+fn read_multiaddresses(path: &Path) -> Result<Vec<(PeerId, Multiaddr)>, anyhow::Error> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            parse_multiaddress(line).with_context(|| format!("invalid known peer `{line}`"))
+        })
+        .collect()
+}
+
+/// Decode a Kademlia key from a hex string, or take the multihash of a CID as the key, as IPFS
+/// does for provider records.
+fn parse_key(key: &str) -> anyhow::Result<KademliaKey> {
+    if let Ok(bytes) = hex::decode(key) {
+        return Ok(KademliaKey::new(&bytes));
+    }
+    let cid = Cid::try_from(key).map_err(|_| anyhow!("expected a hex key or a CID"))?;
+
+    Ok(KademliaKey::new(&cid.hash().to_bytes()))
+}
+
+/// Read a list from `path`, one item per line parsed with `parse`. Empty lines and `#` comments are
+/// skipped. `noun` names an item in the errors.
+fn read_list<T>(
+    path: &Path,
+    noun: &str,
+    parse: impl Fn(&str) -> anyhow::Result<T>,
+) -> anyhow::Result<Vec<T>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+
+    let items = content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| parse(line).with_context(|| format!("invalid {noun} `{line}`")))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    if items.is_empty() {
+        return Err(anyhow!("no {noun}s in {}", path.display()));
+    }
+
+    Ok(items)
+}
+
+/// Options shared by the DHT queries.
+#[derive(clap::Args, Debug)]
+pub struct QueryArgs {
+    /// Network to query: selects the bootnodes and the Kademlia protocol name, unless overridden by
+    /// --bootnode, --kad-proto or --genesis-hash.
+    #[arg(long, global = true, value_enum, default_value_t = Network::Polkadot)]
+    pub network: Network,
+    /// Substrate chain spec JSON file to take the bootnodes and the Kademlia protocol name from,
+    /// instead of --network. Chain specs don't contain the genesis hash, so the protocol name is
+    /// derived from `protocolId`: the legacy name Substrate nodes still serve. --genesis-hash is
+    /// still required for the current name, which then includes the `forkId` of the chain spec.
+    #[arg(long, global = true, value_name = "PATH", value_parser = parse_chain_spec, conflicts_with = "network")]
+    pub chainspec: Option<ChainSpec>,
+    /// Bootnode multiaddress. Can be repeated or given as a comma-separated list. Defaults to the
+    /// bootnodes of --chainspec or --network.
+    #[arg(short, long, global = true, value_name = "MULTIADDR", value_parser = parse_multiaddress, value_delimiter = ',')]
+    pub bootnode: Vec<(PeerId, Multiaddr)>,
+    /// Additional known peer multiaddress to seed the routing table with. Can be repeated.
+    #[arg(long, global = true, value_name = "MULTIADDR", value_parser = parse_multiaddress)]
+    pub known_peer: Vec<(PeerId, Multiaddr)>,
+    /// File with additional known peer multiaddresses, one per line.
+    #[arg(long, global = true, value_name = "PATH")]
+    pub known_peers_file: Option<PathBuf>,
+    /// Seed the routing table with the peers saved by --save-routing-table. A missing file is
+    /// skipped, so the same command line works for the first run.
+    #[arg(long, global = true, value_name = "PATH")]
+    pub load_routing_table: Option<PathBuf>,
+    /// Save the peers with known addresses to this file after every query, for
+    /// --load-routing-table of later runs.
+    #[arg(long, global = true, value_name = "PATH")]
+    pub save_routing_table: Option<PathBuf>,
+    /// Resolve DNS names of bootnode and known peer addresses via this DNS-over-HTTPS endpoint,
+    /// e.g. https://1.1.1.1/dns-query. Only the `/dns*` addresses of bootnodes, known peers and the
+    /// routing table cache are resolved this way. Addresses learned from other peers are resolved
+    /// by litep2p with the system resolver.
+    #[arg(long, global = true, value_name = "URL")]
+    pub doh: Option<String>,
+    /// IP to connect to the --doh server at, required if its URL has a host name rather than an IP.
+    #[arg(long, global = true, value_name = "IP", requires = "doh")]
+    pub doh_bootstrap: Option<IpAddr>,
+    /// Kademlia protocol name. Defaults to the protocol of --chainspec or --network.
+    #[arg(short, long, global = true, value_name = "PROTOCOL")]
+    pub kad_proto: Option<String>,
+    /// Derive the Kademlia protocol name from the chain genesis hash (hex) instead of --kad-proto.
+    #[arg(long, global = true, value_name = "HASH", value_parser = parse_genesis_hash, conflicts_with = "kad_proto")]
+    pub genesis_hash: Option<[u8; 32]>,
+    /// Fork ID of the chain, used together with --genesis-hash. Defaults to the `forkId` of
+    /// --chainspec.
+    #[arg(long, global = true, value_name = "FORK_ID", requires = "genesis_hash")]
+    pub fork_id: Option<String>,
+    /// Legacy protocol ID of the chain, e.g. `dot` or the Substrate default `sup`. The legacy
+    /// `/<protocol id>/kad` name is negotiated as a fallback to the main protocol name. Defaults to
+    /// the `protocolId` of --chainspec.
+    #[arg(long, global = true, value_name = "ID")]
+    pub protocol_id: Option<String>,
+    /// Prepopulate routing table with FIND_NODE queries before executing the main query.
+    #[arg(long, global = true, value_name = "ITERATIONS", default_value_t = 0)]
+    pub prepopulate: usize,
+    /// Stop prepopulating when no new peers were discovered for this many seconds (0 disables).
+    #[arg(long, global = true, value_name = "SECS", default_value_t = 60)]
+    pub stall_window: u64,
+    /// Keep private and loopback addresses (useful in lab networks). Without this flag they are
+    /// dropped from the known peers, the routing table cache and every reported peer, but not
+    /// from the bootnodes. Addresses returned by peers during a query are still dialed.
+    #[arg(long, global = true)]
+    pub allow_private_addresses: bool,
+    /// Re-run a failed query once if the routing table grew substantially during it.
+    #[arg(long, global = true)]
+    pub auto_requery: bool,
+    /// Retry policy for dials, DNS lookups and queries, e.g. "3x, backoff=2s..30s, jitter".
+    #[arg(long, global = true, value_name = "POLICY", default_value = "none")]
+    pub retry: RetryPolicy,
+    /// Keep alternating prepopulation and query attempts until something is found.
+    /// Every new attempt runs at least one FIND_NODE query.
+    #[arg(long, global = true)]
+    pub until_success: bool,
+    /// Time budget for --until-success, e.g. 10m.
+    #[arg(long, global = true, value_name = "DURATION", default_value = "10m", value_parser = parse_duration, requires = "until_success")]
+    pub budget: Duration,
+    /// Abort a query not finished within this many seconds, including the routing table
+    /// prepopulation, and exit with code 3.
+    #[arg(long, global = true, value_name = "SECS")]
+    pub timeout: Option<u64>,
+    /// Kademlia and transport parameters: replication factor, timeouts and parallel dials.
+    #[arg(long, global = true, value_enum, default_value_t = Preset::Substrate)]
+    pub preset: Preset,
+    /// Format of the results printed to stdout: text if stdout is a terminal, JSON otherwise.
+    /// Progress messages go to stderr with JSON output. The query, crawl and add-provider
+    /// documents follow the schema printed by `--schema`.
+    #[arg(long, global = true, value_enum)]
+    pub output: Option<OutputFormat>,
+    /// When to print progress messages: by default only if stdout is a terminal.
+    #[arg(long, global = true, value_name = "WHEN", value_enum, default_value_t = Progress::Auto)]
+    pub progress: Progress,
+    /// Write the public keys of the contacted peers to this file, one `<peer id> <hex key>` line
+    /// per peer. Keys are protobuf-encoded as in libp2p.
+    #[arg(long, global = true, value_name = "PATH")]
+    pub export_keys: Option<PathBuf>,
+    /// Write the providers found and the contacted peers to `providers.csv` and `peers.csv` in this
+    /// directory.
+    #[arg(long, global = true, value_name = "DIR")]
+    pub csv: Option<PathBuf>,
+    /// Print a single-line logfmt summary of every query run.
+    #[arg(long, global = true)]
+    pub summary_logfmt: bool,
+    /// Sign the JSON result document with the ed25519 key in this file, the hex secret key
+    /// `genkey` prints or a PKCS#8 PEM file. A manifest with the tool version, the command line,
+    /// the hash of the document and the signature is added as its last field. Check it with
+    /// `verify-output`.
+    #[arg(long, global = true, value_name = "PATH")]
+    pub sign_output: Option<PathBuf>,
+    /// Show a live dashboard of every query on stderr instead of the progress messages.
+    #[arg(long, global = true)]
+    pub tui: bool,
+    /// Rewrite this file every few seconds with the phase, counters and ETA of the running query
+    /// in JSON.
+    #[arg(long, global = true, value_name = "PATH")]
+    pub progress_file: Option<PathBuf>,
+    #[command(flatten)]
+    pub limits: Limits,
+    #[command(flatten)]
+    pub socket: SocketOptions,
+}
+
+impl Default for QueryArgs {
+    /// Defaults of the command line options.
+    fn default() -> Self {
+        let matches = QueryArgs::augment_args(clap::Command::new("dht-inspect"))
+            .get_matches_from(["dht-inspect"]);
+
+        QueryArgs::from_arg_matches(&matches).expect("defaults are valid; qed")
+    }
+}
+
+/// Format of the results.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Human-readable text.
+    Text,
+    /// A single JSON document.
+    Json,
+    /// One JSON line per event while the query runs, followed by the JSON document.
+    Ndjson,
+}
+
+/// When to print progress messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Progress {
+    /// If stdout is a terminal.
+    Auto,
+    Always,
+    Never,
+}
+
+impl QueryArgs {
+    /// Format of the results given `--output` and whether stdout is a `terminal`.
+    pub fn output_format(&self, terminal: bool) -> OutputFormat {
+        match self.output {
+            Some(format) => format,
+            None if terminal => OutputFormat::Text,
+            None => OutputFormat::Json,
+        }
+    }
+
+    /// Whether progress messages are printed given `--progress` and whether stdout is a
+    /// `terminal`.
+    pub fn show_progress(&self, terminal: bool) -> bool {
+        match self.progress {
+            Progress::Auto => terminal,
+            Progress::Always => true,
+            Progress::Never => false,
+        }
+    }
+}
+
+/// DHT query run after the routing table prepopulation.
+enum Query {
+    /// GET_PROVIDERS query for the key.
+    Providers(KademliaKey),
+    /// GET_VALUE query for the key.
+    Record(KademliaKey),
+    /// FIND_NODE query for the peer.
+    Peer(PeerId),
+}
+
+impl Query {
+    /// Kademlia message name of the query.
+    fn name(&self) -> &'static str {
+        match self {
+            Query::Providers(_) => "GET_PROVIDERS",
+            Query::Record(_) => "GET_VALUE",
+            Query::Peer(_) => "FIND_NODE",
+        }
+    }
+
+    /// Queried key.
+    fn key(&self) -> Vec<u8> {
+        match self {
+            Query::Providers(key) | Query::Record(key) => key.to_vec(),
+            Query::Peer(peer) => peer.to_bytes(),
+        }
+    }
+
+    /// Start the query.
+    async fn start(&self, handle: &mut KademliaHandle) -> QueryId {
+        emit_event("query_started", |line| {
+            line.field("query", self.name())
+                .field("key", hex::encode(self.key()))
+        });
+
+        match self {
+            Query::Providers(key) => handle.get_providers(key.clone()).await,
+            Query::Record(key) => handle.get_record(key.clone(), Quorum::One).await,
+            Query::Peer(peer) => handle.find_node(*peer).await,
+        }
+    }
+}
+
+/// Item of a batch queried by [`query_batch`]: a key or a peer.
+trait BatchItem {
+    /// Result of a successful query.
+    type Found;
+
+    fn query(&self) -> Query;
+
+    /// Query ID and result of the query `event` completes, with the addresses `filter` rejects
+    /// dropped. `None` if `event` doesn't complete a query of this kind.
+    fn found(event: KademliaEvent, filter: &mut AddressFilter) -> Option<(QueryId, Self::Found)>;
+}
+
+impl BatchItem for KademliaKey {
+    type Found = Vec<ContentProvider>;
+
+    fn query(&self) -> Query {
+        Query::Providers(self.clone())
+    }
+
+    fn found(event: KademliaEvent, filter: &mut AddressFilter) -> Option<(QueryId, Self::Found)> {
+        let KademliaEvent::GetProvidersSuccess {
+            query_id,
+            providers,
+            ..
+        } = event
+        else {
+            return None;
+        };
+        let providers = providers
+            .into_iter()
+            .map(|provider| ContentProvider {
+                addresses: filter.filter(provider.addresses),
+                ..provider
+            })
+            .collect();
+
+        Some((query_id, providers))
+    }
+}
+
+impl BatchItem for PeerId {
+    type Found = Vec<(PeerId, Vec<Multiaddr>)>;
+
+    fn query(&self) -> Query {
+        Query::Peer(*self)
+    }
+
+    fn found(event: KademliaEvent, filter: &mut AddressFilter) -> Option<(QueryId, Self::Found)> {
+        let KademliaEvent::FindNodeSuccess {
+            query_id, peers, ..
+        } = event
+        else {
+            return None;
+        };
+
+        Some((query_id, filter.filter_peers(peers)))
+    }
+}
+
+/// Run the queries of `items` on the node of `run`, up to `concurrency` at a time.
+///
+/// When the query of an item finishes, `finish` turns its result, `None` if it failed, into the
+/// outcome of the item. It also gets the number of queries finished so far, for progress
+/// messages. Returns the outcome and the query time of every item in order, or [`NodeStalled`] if
+/// the node stops producing events.
+async fn query_batch<'a, T: BatchItem, O>(
+    run: &mut QueryRun,
+    items: &'a [T],
+    concurrency: usize,
+    mut finish: impl FnMut(&QueryRun, usize, &T, Option<T::Found>) -> O,
+) -> anyhow::Result<Vec<(&'a T, O, Duration)>> {
+    let mut outcomes: Vec<Option<(O, Duration)>> = items.iter().map(|_| None).collect();
+    let mut pending: HashMap<QueryId, (usize, Instant)> = HashMap::new();
+    let mut next = 0;
+    let mut finished = 0;
+
+    while finished < items.len() {
+        while pending.len() < concurrency.max(1) && next < items.len() {
+            let query_id = items[next].query().start(&mut run.kademlia_handle).await;
+            pending.insert(query_id, (next, Instant::now()));
+            next += 1;
+        }
+
+        let (query_id, found) = tokio::select! {
+            event = run.litep2p.next_event() => {
+                if let Some(Litep2pEvent::ConnectionEstablished { peer, .. }) = event {
+                    run.contacted_peers.insert(peer);
+                }
+                continue
+            },
+            event = run.identify_events.next() => {
+                record_identified(&mut run.identified, event);
+                continue
+            },
+            event = run.kademlia_handle.next() => match event {
+                Some(KademliaEvent::QueryFailed { query_id }) => (query_id, None),
+                Some(KademliaEvent::RoutingTableUpdate { peers }) => {
+                    run.discovered_peers.extend(peers);
+                    continue
+                },
+                Some(event) => match T::found(event, &mut run.address_filter) {
+                    Some((query_id, found)) => (query_id, Some(found)),
+                    None => continue,
+                },
+                None => return Err(anyhow!("libp2p Kademlia terminated")),
+            },
+            _ = tokio::time::sleep(STALL_TIMEOUT) => return Err(NodeStalled.into()),
+        };
+        let Some((index, start)) = pending.remove(&query_id) else {
+            continue;
+        };
+
+        finished += 1;
+        let outcome = finish(run, finished, &items[index], found);
+        outcomes[index] = Some((outcome, start.elapsed()));
+    }
+
+    Ok(items
+        .iter()
+        .zip(outcomes)
+        .map(|(item, outcome)| {
+            let (outcome, elapsed) = outcome.expect("all queries finished; qed");
+            (item, outcome, elapsed)
+        })
+        .collect())
+}
+
+/// Socket options of the TCP and WebSocket transports.
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct SocketOptions {
+    /// Set TCP_NODELAY on connections.
+    #[arg(long, global = true)]
+    pub tcp_nodelay: bool,
+    /// Don't set SO_REUSEPORT on outbound sockets.
+    #[arg(long, global = true)]
+    pub no_reuse_port: bool,
+    /// Number of 65 KB Noise frames read from the socket per call.
+    #[arg(long, global = true, value_name = "FRAMES")]
+    pub noise_read_ahead: Option<usize>,
+    /// Number of 65 KB Noise frames coalesced into a single socket write.
+    #[arg(long, global = true, value_name = "FRAMES")]
+    pub noise_write_buffer: Option<usize>,
+}
+
+/// Litep2p configuration with TCP and WebSocket transports, not listening on any address.
+fn transport_config(settings: &Settings, socket: &SocketOptions) -> Litep2pConfigBuilder {
+    let tcp = TcpConfig::default();
+    let ws = WsConfig::default();
+
+    Litep2pConfigBuilder::new()
+        .with_tcp(TcpConfig {
+            listen_addresses: Vec::new(),
+            reuse_port: !socket.no_reuse_port,
+            nodelay: socket.tcp_nodelay,
+            noise_read_ahead_frame_count: socket
+                .noise_read_ahead
+                .unwrap_or(tcp.noise_read_ahead_frame_count),
+            noise_write_buffer_size: socket
+                .noise_write_buffer
+                .unwrap_or(tcp.noise_write_buffer_size),
+            connection_open_timeout: settings.connection_open_timeout,
+            substream_open_timeout: settings.substream_open_timeout,
+            ..tcp
+        })
+        .with_websocket(WsConfig {
+            listen_addresses: Vec::new(),
+            reuse_port: !socket.no_reuse_port,
+            nodelay: socket.tcp_nodelay,
+            noise_read_ahead_frame_count: socket
+                .noise_read_ahead
+                .unwrap_or(ws.noise_read_ahead_frame_count),
+            noise_write_buffer_size: socket
+                .noise_write_buffer
+                .unwrap_or(ws.noise_write_buffer_size),
+            connection_open_timeout: settings.connection_open_timeout,
+            substream_open_timeout: settings.substream_open_timeout,
+            ..ws
+        })
+        .with_max_parallel_dials(settings.max_parallel_dials)
+}
+
+/// Collect the bootnode and known peers, resolving their addresses via DoH if requested.
+async fn known_peers(args: &QueryArgs) -> anyhow::Result<HashMap<PeerId, Vec<Multiaddr>>> {
+    let mut known_peers: HashMap<PeerId, Vec<Multiaddr>> = HashMap::new();
+    let extra_peers = match &args.known_peers_file {
+        Some(path) => read_multiaddresses(path)?,
+        None => Vec::new(),
+    };
+    let cached_peers = match &args.load_routing_table {
+        Some(path) if path.exists() => {
+            let cached = read_multiaddresses(path)?;
+            progress!(
+                "Loaded {} cached addresses from {}",
+                cached.len(),
+                path.display()
+            );
+            cached
+        }
+        Some(path) => {
+            progress!(
+                "No routing table cache at {}, starting cold",
+                path.display()
+            );
+            Vec::new()
+        }
+        None => Vec::new(),
+    };
+    for (peer, address) in bootnodes(args) {
+        known_peers.entry(peer).or_default().push(address);
+    }
+    let mut skipped = 0;
+    for (peer, address) in args
+        .known_peer
+        .iter()
+        .cloned()
+        .chain(extra_peers)
+        .chain(cached_peers)
+    {
+        if !args.allow_private_addresses && is_private(&address) {
+            skipped += 1;
+            continue;
+        }
+        known_peers.entry(peer).or_default().push(address);
+    }
+    if skipped > 0 {
+        progress!(
+            "Skipped {skipped} private known peer addresses, pass --allow-private-addresses to keep them"
+        );
+    }
+
+    if let Some(url) = &args.doh {
+        let mut resolver = DohResolver::new(url, args.doh_bootstrap, args.retry.clone())?;
+        for addresses in known_peers.values_mut() {
+            let mut resolved = Vec::new();
+            for address in addresses.iter() {
+                resolved.extend(resolver.resolve_address(address).await?);
+            }
+            *addresses = resolved;
+        }
+    }
+
+    Ok(known_peers)
+}
+
+/// Kademlia protocol name given directly or derived from the genesis hash.
+fn kademlia_protocol(args: &QueryArgs) -> String {
+    let chain_spec_protocol = args.chainspec.as_ref().map(|spec| &spec.protocol_id);
+    let kad_proto = match (&args.genesis_hash, &args.kad_proto, chain_spec_protocol) {
+        (Some(genesis_hash), ..) => {
+            let chain_spec_fork_id = args
+                .chainspec
+                .as_ref()
+                .and_then(|spec| spec.fork_id.as_deref());
+            kademlia_protocol_name(genesis_hash, args.fork_id.as_deref().or(chain_spec_fork_id))
+        }
+        (None, Some(kad_proto), _) => kad_proto.clone(),
+        (None, None, Some(Some(protocol_id))) => {
+            progress!("Chain specs don't contain the genesis hash, pass --genesis-hash for the current protocol name");
+            legacy_kademlia_protocol_name(protocol_id)
+        }
+        (None, None, Some(None)) => {
+            progress!("Warning: chain spec has no protocolId, pass --genesis-hash or --kad-proto");
+            args.network.kademlia_protocol()
+        }
+        (None, None, None) => args.network.kademlia_protocol(),
+    };
+    if args.genesis_hash.is_some()
+        || (args.kad_proto.is_none()
+            && (args.chainspec.is_some() || args.network != Network::Polkadot))
+    {
+        progress!("Using Kademlia protocol {kad_proto}");
+    }
+    if let Some(fallback) =
+        fallback_kademlia_protocol(args).filter(|fallback| *fallback != kad_proto)
+    {
+        progress!("Using fallback Kademlia protocol {fallback}");
+    }
+
+    kad_proto
+}
+
+/// Legacy Kademlia protocol name negotiated as a fallback, from --protocol-id or the chain spec.
+fn fallback_kademlia_protocol(args: &QueryArgs) -> Option<String> {
+    let chain_spec_protocol = || args.chainspec.as_ref()?.protocol_id.clone();
+
+    args.protocol_id
+        .clone()
+        .or_else(chain_spec_protocol)
+        .map(|protocol_id| legacy_kademlia_protocol_name(&protocol_id))
+}
+
+/// Bootnodes given with --bootnode, or the ones of --network.
+fn bootnodes(args: &QueryArgs) -> Vec<(PeerId, Multiaddr)> {
+    if !args.bootnode.is_empty() {
+        return args.bootnode.clone();
+    }
+    if let Some(spec) = &args.chainspec {
+        return spec.boot_nodes.clone();
+    }
+
+    args.network
+        .bootnodes()
+        .iter()
+        .map(|address| parse_multiaddress(address).expect("valid bootnode address; qed"))
+        .collect()
+}
+
+/// Exit code when a query is aborted by --timeout.
+pub const TIMEOUT_EXIT_CODE: i32 = 3;
+
+/// Error of a query aborted by --timeout.
+#[derive(Debug)]
+pub struct QueryTimeout(pub Duration);
+
+impl std::fmt::Display for QueryTimeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "query timed out after {} s", self.0.as_secs())
+    }
+}
+
+impl std::error::Error for QueryTimeout {}
+
+/// Error of a query aborted through [`QueryControl::cancel`].
+#[derive(Debug)]
+struct QueryCancelled;
+
+impl std::fmt::Display for QueryCancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "query cancelled")
+    }
+}
+
+impl std::error::Error for QueryCancelled {}
+
+/// How long a node may go without events while it has queries outstanding before it counts as
+/// stalled. litep2p fails a query well before that, so the silence means the node itself got
+/// stuck.
+const STALL_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Error of queries whose node stalled, see [`STALL_TIMEOUT`].
+#[derive(Debug)]
+struct NodeStalled;
+
+impl std::fmt::Display for NodeStalled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "no node events for {} s with queries outstanding",
+            STALL_TIMEOUT.as_secs()
+        )
+    }
+}
+
+impl std::error::Error for NodeStalled {}
+
+/// Hooks of embedders and long-running commands into a running query.
+#[derive(Default)]
+struct QueryControl {
+    /// Abort the query with [`QueryCancelled`], keeping the results found so far.
+    cancel: Option<CancellationToken>,
+    /// Receives the progress of the query.
+    events: Option<UnboundedSender<ProgressEvent>>,
+    /// Add discovered peers to the routing table only once they were dialed successfully, see
+    /// [`add_dialed_peer`].
+    dialed_routing: bool,
+}
+
+impl QueryControl {
+    /// Report `event` to the embedder, if it listens.
+    fn emit(&self, event: ProgressEvent) {
+        if let Some(events) = &self.events {
+            let _ = events.unbounded_send(event);
+        }
+    }
+}
+
+/// Add `peer` to the routing table if the node dialed it at the address of `endpoint`.
+///
+/// With the routing table in manual mode, peers whose dials time out never enter it and so never
+/// seed later lookups. litep2p still dials them when responses return them as lookup candidates.
+async fn add_dialed_peer(kademlia_handle: &KademliaHandle, peer: PeerId, endpoint: &Endpoint) {
+    if let Endpoint::Dialer { address, .. } = endpoint {
+        kademlia_handle
+            .add_known_peer(peer, vec![address.clone()])
+            .await;
+    }
+}
+
+/// Outcome of a DHT query together with the node that executed it.
+struct QueryRun {
+    litep2p: Litep2p,
+    kademlia_handle: KademliaHandle,
+    verify_handle: Option<RequestResponseHandle>,
+    identify_events: Box<dyn Stream<Item = IdentifyEvent> + Send + Unpin>,
+    identified: HashMap<PeerId, Vec<Multiaddr>>,
+    result: anyhow::Result<()>,
+    /// Providers found by a GET_PROVIDERS query.
+    providers: Vec<ContentProvider>,
+    /// Records found by a GET_VALUE query.
+    records: Vec<PeerRecord>,
+    /// Closest peers found by a FIND_NODE query.
+    closest_peers: Vec<(PeerId, Vec<Multiaddr>)>,
+    discovered_peers: HashSet<PeerId>,
+    contacted_peers: HashSet<PeerId>,
+    fan_out: FanOut,
+    address_filter: AddressFilter,
+    connections: ConnectionTable,
+    elapsed: Duration,
+}
+
+impl QueryRun {
+    /// Resources used by the tool so far.
+    fn usage(&self) -> Usage {
+        Usage::measure(self.connections.peak(), &self.litep2p.bandwidth_sink())
+    }
+
+    /// Drive the node until `deadline`, ignoring events.
+    async fn drive_until(&mut self, deadline: tokio::time::Instant) -> anyhow::Result<()> {
+        self.drive_while(tokio::time::sleep_until(deadline)).await
+    }
+
+    /// Drive the node until `future` completes, ignoring events, so connections stay serviced
+    /// while waiting on something else.
+    async fn drive_while<F: std::future::Future>(
+        &mut self,
+        future: F,
+    ) -> anyhow::Result<F::Output> {
+        tokio::pin!(future);
+        loop {
+            tokio::select! {
+                output = &mut future => return Ok(output),
+                _ = self.litep2p.next_event() => {},
+                _ = self.identify_events.next() => {},
+                event = self.kademlia_handle.next() => {
+                    if event.is_none() {
+                        return Err(anyhow!("libp2p Kademlia terminated"))
+                    }
+                },
+            }
+        }
+    }
+
+    /// Drive the node until `deadline` like [`QueryRun::drive_until`], running the due `refresh`
+    /// queries meanwhile.
+    async fn maintain_until(
+        &mut self,
+        deadline: tokio::time::Instant,
+        refresh: &mut Refresh,
+    ) -> anyhow::Result<()> {
+        loop {
+            refresh.start(&mut self.kademlia_handle).await;
+            let due = refresh.due().unwrap_or(deadline);
+            tokio::select! {
+                _ = tokio::time::sleep_until(deadline) => break,
+                _ = tokio::time::sleep_until(due) => {},
+                _ = self.litep2p.next_event() => {},
+                _ = self.identify_events.next() => {},
+                event = self.kademlia_handle.next() => match event {
+                    Some(KademliaEvent::RoutingTableUpdate { peers }) => {
+                        self.discovered_peers.extend(peers);
+                    },
+                    Some(event) => refresh.on_event(&event),
+                    None => return Err(anyhow!("libp2p Kademlia terminated")),
+                },
+            }
+        }
+        refresh.abandon();
+
+        Ok(())
+    }
+
+    /// Replace the node after it stalled with a new one, started with the known peers of `args`
+    /// and the peers this node identified, so the long-running commands keep going unattended.
+    ///
+    /// The peers discovered, contacted and identified so far carry over to the new node.
+    async fn restart(&mut self, args: &QueryArgs, kad_proto: &str) -> anyhow::Result<()> {
+        let mut known = known_peers(args).await?;
+        for (peer, addresses) in &self.identified {
+            let known = known.entry(*peer).or_default();
+            for address in addresses {
+                if !known.contains(address) {
+                    known.push(address.clone());
+                }
+            }
+        }
+        progress!(
+            "Restarting the stalled node with {} known peers",
+            known.len()
+        );
+        emit_event("node_restarted", |line| {
+            line.field("known_peers", known.len())
+        });
+
+        let warmup = Query::Peer(PeerId::random());
+        let settings = args.preset.settings();
+        let mut run = query_dht(args, &warmup, kad_proto, known, &settings, None).await?;
+        run.discovered_peers
+            .extend(std::mem::take(&mut self.discovered_peers));
+        run.contacted_peers
+            .extend(std::mem::take(&mut self.contacted_peers));
+        for (peer, addresses) in std::mem::take(&mut self.identified) {
+            run.identified.entry(peer).or_insert(addresses);
+        }
+        *self = run;
+
+        Ok(())
+    }
+}
+
+/// Start a node with `settings` and run `query`.
+///
+/// If `verify_protocol` is set, the node also supports this request-response protocol.
+async fn query_dht(
+    args: &QueryArgs,
+    query: &Query,
+    kad_proto: &str,
+    known_peers: HashMap<PeerId, Vec<Multiaddr>>,
+    settings: &Settings,
+    verify_protocol: Option<&str>,
+) -> anyhow::Result<QueryRun> {
+    query_dht_with(
+        args,
+        query,
+        kad_proto,
+        known_peers,
+        settings,
+        verify_protocol,
+        QueryControl::default(),
+    )
+    .await
+}
+
+/// [`query_dht`] under the `control` of an embedder.
+async fn query_dht_with(
+    args: &QueryArgs,
+    query: &Query,
+    kad_proto: &str,
+    known_peers: HashMap<PeerId, Vec<Multiaddr>>,
+    settings: &Settings,
+    verify_protocol: Option<&str>,
+    control: QueryControl,
+) -> anyhow::Result<QueryRun> {
+    let mut kademlia_config = KademliaConfigBuilder::new()
+        .with_protocol_names(
+            std::iter::once(kad_proto.to_string())
+                .chain(fallback_kademlia_protocol(args).filter(|fallback| fallback != kad_proto))
+                .map(Into::into)
+                .collect(),
+        )
+        .with_replication_factor(settings.replication_factor)
+        .with_known_peers(known_peers);
+    if control.dialed_routing {
+        kademlia_config =
+            kademlia_config.with_routing_table_update_mode(RoutingTableUpdateMode::Manual);
+    }
+    if let Some(interval) = settings.provider_refresh_interval {
+        kademlia_config = kademlia_config.with_provider_refresh_interval(interval);
+    }
+    let (kademlia_config, mut kademlia_handle) = kademlia_config.build();
+    let (identify_config, mut identify_events) =
+        IdentifyConfig::new(IDENTIFY_PROTOCOL_VERSION.into(), Some(USER_AGENT.into()));
+
+    let mut litep2p_config = transport_config(settings, &args.socket)
+        .with_libp2p_kademlia(kademlia_config)
+        .with_libp2p_identify(identify_config);
+
+    let mut verify_handle = None;
+    if let Some(protocol) = verify_protocol {
+        let (config, handle) = RequestResponseConfigBuilder::new(protocol.to_string().into())
+            .with_timeout(VERIFY_TIMEOUT)
+            .build();
+        litep2p_config = litep2p_config.with_request_response_protocol(config);
+        verify_handle = Some(handle);
+    }
+
+    let mut litep2p =
+        Litep2p::new(litep2p_config.build()).context("litep2p initialization error")?;
+
+    let mut address_filter = AddressFilter::new(args.allow_private_addresses);
+    let bootnodes = bootnodes(args);
+    let mut discovered_peers = HashSet::new();
+    let mut contacted_peers = HashSet::new();
+    // First contact and connection addresses of the contacted peers, for `--csv`.
+    let mut sightings: HashMap<PeerId, Sighting> = HashMap::new();
+    let mut providers_seen = SystemTime::now();
+    let mut fan_out = FanOut::default();
+    let mut connections = ConnectionTable::default();
+    // Listen addresses advertised via identify.
+    let mut identified = HashMap::new();
+    // Console commands typed while the query is running.
+    let mut console = Console::take();
+    // Next redraw of the `--tui` dashboard or check whether the `--progress-file` is due.
+    let mut next_status =
+        (args.tui || args.progress_file.is_some()).then(tokio::time::Instant::now);
+    let mut progress_written: Option<Instant> = None;
+    let dashboard = args.tui.then(tui::start);
+    // Ctrl-C ends the query, so the dashboard restores the terminal before the process exits.
+    let interrupted = async {
+        if args.tui {
+            signal::ctrl_c().await
+        } else {
+            std::future::pending().await
+        }
+    };
+    tokio::pin!(interrupted);
+
+    let mut find_node_query = None;
+    let mut main_query = None;
+    let mut iterations = args.prepopulate;
+    // Number of discovered peers when the main query was started.
+    let mut main_query_baseline = 0;
+    let mut providers = Vec::new();
+    let mut records = Vec::new();
+    let mut closest_peers = Vec::new();
+    let mut requeried = false;
+    // Completed FIND_NODE queries of the routing table prepopulation.
+    let mut rounds = 0;
+    let stall_window = Duration::from_secs(args.stall_window);
+    let mut last_discovery = Instant::now();
+    // Failed query to restart once the retry backoff elapses.
+    let mut query_retries = 0;
+    let mut retry_at = None;
+    let mut retry_find_node = false;
+    // Attempts of `--until-success`.
+    let mut attempt = 1;
+    let mut restart_attempt = false;
+    let budget_deadline = args
+        .until_success
+        .then(|| tokio::time::Instant::now() + args.budget);
+    let timeout_deadline = args
+        .timeout
+        .map(|timeout| tokio::time::Instant::now() + Duration::from_secs(timeout));
+
+    if iterations > 0 {
+        iterations -= 1;
+        progress!("Prepopulating Kademlia routing table...");
+        find_node_query = Some(kademlia_handle.find_node(PeerId::random()).await);
+    } else {
+        progress!("Running {} query...", query.name());
+        main_query = Some(query.start(&mut kademlia_handle).await);
+    }
+
+    let start = Instant::now();
+
+    let result: anyhow::Result<()> = loop {
+        if restart_attempt {
+            restart_attempt = false;
+            attempt += 1;
+            query_retries = 0;
+            iterations = args.prepopulate.saturating_sub(1);
+            progress!("Attempt {attempt}: prepopulating Kademlia routing table...");
+            find_node_query = Some(kademlia_handle.find_node(PeerId::random()).await);
+        }
+
+        tokio::select! {
+            _ = sleep_until(budget_deadline) => {
+                break Err(anyhow!("nothing found within the budget after {attempt} attempts"))
+            },
+            _ = sleep_until(timeout_deadline) => {
+                break Err(QueryTimeout(Duration::from_secs(args.timeout.unwrap_or_default())).into())
+            },
+            _ = cancelled(control.cancel.as_ref()) => break Err(QueryCancelled.into()),
+            _ = &mut interrupted => break Err(anyhow!("interrupted")),
+            event = litep2p.next_event() => match event {
+                Some(Litep2pEvent::ConnectionEstablished { peer, endpoint }) => {
+                    emit_event("connection_established", |line| {
+                        line.field("peer_id", peer.to_string())
+                            .field("address", endpoint.address().to_string())
+                    });
+                    if contacted_peers.insert(peer) {
+                        control.emit(ProgressEvent::PeerContacted(peer));
+                    }
+                    let sighting = sightings.entry(peer).or_insert_with(|| Sighting {
+                        first_seen: SystemTime::now(),
+                        addresses: Vec::new(),
+                    });
+                    if !sighting.addresses.contains(endpoint.address()) {
+                        sighting.addresses.push(endpoint.address().clone());
+                    }
+                    if control.dialed_routing {
+                        add_dialed_peer(&kademlia_handle, peer, &endpoint).await;
+                    }
+                    connections.on_connection_established(peer, endpoint);
+                },
+                Some(Litep2pEvent::ConnectionClosed { peer, connection_id }) => {
+                    emit_event("connection_closed", |line| line.field("peer_id", peer.to_string()));
+                    connections.on_connection_closed(connection_id);
+                },
+                _ => {}
+            },
+            event = identify_events.next() => {
+                if let Some(IdentifyEvent::PeerIdentified { peer, listen_addresses, .. }) = event {
+                    for (_, address) in bootnodes.iter().filter(|(bootnode, _)| *bootnode == peer) {
+                        if let Some(mismatch) = check_advertised(address, &listen_addresses) {
+                            progress!("Warning: bootnode {address} {mismatch}");
+                        }
+                    }
+                    identified.insert(peer, listen_addresses);
+                }
+            },
+            _ = sleep_until(retry_at) => {
+                retry_at = None;
+                if retry_find_node {
+                    progress!("Retrying FIND_NODE query...");
+                    find_node_query = Some(kademlia_handle.find_node(PeerId::random()).await);
+                } else {
+                    progress!("Retrying {} query...", query.name());
+                    main_query_baseline = discovered_peers.len();
+                    main_query = Some(query.start(&mut kademlia_handle).await);
+                }
+            },
+            _ = sleep_until(next_status) => {
+                let interval = if args.tui { tui::REDRAW_INTERVAL } else { PROGRESS_FILE_INTERVAL };
+                next_status = Some(tokio::time::Instant::now() + interval);
+                let started = args.prepopulate - iterations;
+                let phase = match (main_query, attempt) {
+                    (Some(_), 1) => format!("running {}", query.name()),
+                    (Some(_), attempt) => format!("attempt {attempt}, running {}", query.name()),
+                    (None, _) => format!(
+                        "prepopulating, FIND_NODE query {started}/{}",
+                        args.prepopulate
+                    ),
+                };
+                // Extrapolated from the FIND_NODE queries completed since the start, so only known
+                // while the first prepopulation is running.
+                let eta = (main_query.is_none() && attempt == 1 && started > 1)
+                    .then(|| start.elapsed() / (started - 1) as u32 * (iterations + 1) as u32);
+                let found = match query {
+                    Query::Record(_) => records.iter().map(|record: &PeerRecord| record.peer.to_string()).collect(),
+                    _ => Vec::new(),
+                };
+                let status = Status {
+                    query: query.name(),
+                    key: hex::encode(query.key()),
+                    protocol: kad_proto,
+                    phase,
+                    attempt,
+                    prepopulation: (if main_query.is_none() { started } else { args.prepopulate }, args.prepopulate),
+                    elapsed: start.elapsed(),
+                    eta,
+                    discovered_peers: discovered_peers.len(),
+                    contacted_peers: contacted_peers.len(),
+                    open_connections: connections.len(),
+                    responses: fan_out.responses(),
+                    found,
+                };
+                if args.tui {
+                    tui::draw(&status);
+                }
+                if let Some(path) = &args.progress_file {
+                    if progress_written.is_none_or(|written| written.elapsed() >= PROGRESS_FILE_INTERVAL) {
+                        progress_written = Some(Instant::now());
+                        if let Err(error) = status.write(path) {
+                            break Err(error);
+                        }
+                    }
+                }
+            },
+            line = console.next() => match line.split_whitespace().collect::<Vec<_>>()[..] {
+                ["connections"] => connections.print(&litep2p.bandwidth_sink()),
+                ["resolve", prefix] => {
+                    let mut index = PrefixIndex::default();
+                    index.insert(hex::encode(query.key()));
+                    for peer in discovered_peers
+                        .iter()
+                        .chain(&contacted_peers)
+                        .chain(providers.iter().map(|provider: &ContentProvider| &provider.peer))
+                        .chain(records.iter().map(|record: &PeerRecord| &record.peer))
+                    {
+                        index.insert(peer.to_string());
+                    }
+                    index.print_resolution(prefix);
+                },
+                [] => {},
+                _ => progress!(
+                    "unknown command: {}, available: connections, resolve <prefix>",
+                    line.trim()
+                ),
+            },
+            kademlia_event = kademlia_handle.next() => {
+                let Some(kademlia_event) = kademlia_event else {
+                    return Err(anyhow!("libp2p Kademlia terminated"))
+                };
+
+                match kademlia_event {
+                    KademliaEvent::FindNodeSuccess { query_id, .. } if Some(query_id) == find_node_query => {
+                        query_retries = 0;
+                        rounds += 1;
+                        control.emit(ProgressEvent::RoundCompleted {
+                            round: rounds,
+                            discovered_peers: discovered_peers.len(),
+                        });
+                        if iterations > 0 && !stall_window.is_zero() && last_discovery.elapsed() >= stall_window {
+                            progress!(
+                                "Prepopulation stopped with {iterations} iterations left: \
+                                 no new peers discovered in the last {} s",
+                                stall_window.as_secs(),
+                            );
+                            iterations = 0;
+                        }
+
+                        if iterations > 0 {
+                            iterations -= 1;
+                            find_node_query = Some(kademlia_handle.find_node(PeerId::random()).await);
+                            progress!("Prepopulating Kademlia routing table...");
+                        } else {
+                            progress!("Running {} query...", query.name());
+                            main_query_baseline = discovered_peers.len();
+                            main_query = Some(query.start(&mut kademlia_handle).await);
+                        }
+                    },
+                    KademliaEvent::GetProvidersSuccess { query_id, provided_key, providers: found } => {
+                        if Some(query_id) == main_query && matches!(query, Query::Providers(key) if *key == provided_key) {
+                            if found.is_empty() && args.until_success {
+                                progress!(
+                                    "Attempt {attempt}: no providers found, {} peers discovered so far",
+                                    discovered_peers.len(),
+                                );
+                                restart_attempt = true;
+                                continue
+                            }
+
+                            providers = found
+                                .into_iter()
+                                .map(|provider| ContentProvider {
+                                    addresses: address_filter.filter(provider.addresses),
+                                    ..provider
+                                })
+                                .collect();
+                            providers_seen = SystemTime::now();
+                            for provider in &providers {
+                                control.emit(ProgressEvent::ProviderFound(provider.clone()));
+                                emit_event("provider_found", |line| {
+                                    line.field("peer_id", provider.peer.to_string()).field(
+                                        "addresses",
+                                        provider.addresses.iter().map(ToString::to_string).collect::<Vec<_>>(),
+                                    )
+                                });
+                            }
+                            break Ok(())
+                        }
+                    },
+                    KademliaEvent::QueryFailed { query_id } if Some(query_id) == find_node_query => {
+                        if query_retries < args.retry.retries() {
+                            let backoff = args.retry.backoff(query_retries);
+                            query_retries += 1;
+                            progress!("FIND_NODE query failed, retrying in {} ms", backoff.as_millis());
+                            retry_at = Some(tokio::time::Instant::now() + backoff);
+                            retry_find_node = true;
+                            continue
+                        }
+
+                        if args.until_success {
+                            progress!("Attempt {attempt}: FIND_NODE query failed");
+                            restart_attempt = true;
+                            continue
+                        }
+
+                        break Err(anyhow!("FIND_NODE query failed"))
+                    },
+                    KademliaEvent::FindNodeSuccess { query_id, peers, .. } if Some(query_id) == main_query => {
+                        if peers.is_empty() && args.until_success {
+                            progress!(
+                                "Attempt {attempt}: no peers found, {} peers discovered so far",
+                                discovered_peers.len(),
+                            );
+                            restart_attempt = true;
+                            continue
+                        }
+
+                        closest_peers = address_filter.filter_peers(peers);
+                        break Ok(())
+                    },
+                    KademliaEvent::GetRecordPartialResult { query_id, record } if Some(query_id) == main_query => {
+                        emit_event("record_found", |line| {
+                            line.field("peer_id", record.peer.to_string())
+                                .field("value", hex::encode(&record.record.value))
+                        });
+                        records.push(record);
+                    },
+                    KademliaEvent::GetRecordSuccess { query_id } if Some(query_id) == main_query => {
+                        break Ok(())
+                    },
+                    KademliaEvent::QueryFailed { query_id } if Some(query_id) == main_query => {
+                        if !records.is_empty() {
+                            break Ok(())
+                        }
+
+                        let growth = discovered_peers.len() - main_query_baseline;
+                        if args.auto_requery && !requeried && growth >= AUTO_REQUERY_MIN_GROWTH {
+                            requeried = true;
+                            progress!(
+                                "{} query failed, but {growth} peers were discovered meanwhile. \
+                                 Re-running the query...",
+                                query.name(),
+                            );
+                            main_query_baseline = discovered_peers.len();
+                            main_query = Some(query.start(&mut kademlia_handle).await);
+                            continue
+                        }
+
+                        if query_retries < args.retry.retries() {
+                            let backoff = args.retry.backoff(query_retries);
+                            query_retries += 1;
+                            progress!("{} query failed, retrying in {} ms", query.name(), backoff.as_millis());
+                            retry_at = Some(tokio::time::Instant::now() + backoff);
+                            retry_find_node = false;
+                            continue
+                        }
+
+                        if args.until_success {
+                            progress!(
+                                "Attempt {attempt}: {} query failed, {} peers discovered so far",
+                                query.name(),
+                                discovered_peers.len(),
+                            );
+                            restart_attempt = true;
+                            continue
+                        }
+
+                        break Err(anyhow!("Kademlia query failed"))
+                    },
+                    KademliaEvent::RoutingTableUpdate { peers } => {
+                        let returned = peers.len();
+                        let known = discovered_peers.len();
+                        for peer in peers {
+                            if discovered_peers.insert(peer) {
+                                control.emit(ProgressEvent::PeerDiscovered(peer));
+                            }
+                        }
+                        if discovered_peers.len() > known {
+                            last_discovery = Instant::now();
+                        }
+                        emit_event("routing_table_update", |line| {
+                            line.field("peers", returned)
+                                .field("new_peers", discovered_peers.len() - known)
+                                .field("discovered_peers", discovered_peers.len())
+                        });
+                        fan_out.on_response(returned, discovered_peers.len() - known);
+                    },
+                    event => {
+                        progress!("kademlia event: {event:?}");
+                    }
+                }
+            }
+        }
+    };
+
+    let elapsed = start.elapsed();
+    if args.tui || args.progress_file.is_some() {
+        let found = match query {
+            Query::Providers(_) => providers
+                .iter()
+                .map(|provider| provider.peer.to_string())
+                .collect(),
+            Query::Record(_) => records
+                .iter()
+                .map(|record| record.peer.to_string())
+                .collect(),
+            Query::Peer(_) => closest_peers
+                .iter()
+                .map(|(peer, _)| peer.to_string())
+                .collect(),
+        };
+        let phase = match &result {
+            Ok(()) => "finished".to_string(),
+            Err(error) => format!("failed: {error}"),
+        };
+        let status = Status {
+            query: query.name(),
+            key: hex::encode(query.key()),
+            protocol: kad_proto,
+            phase,
+            attempt,
+            prepopulation: (args.prepopulate - iterations, args.prepopulate),
+            elapsed,
+            eta: result.is_ok().then_some(Duration::ZERO),
+            discovered_peers: discovered_peers.len(),
+            contacted_peers: contacted_peers.len(),
+            open_connections: connections.len(),
+            responses: fan_out.responses(),
+            found,
+        };
+        if args.tui {
+            tui::draw(&status);
+            drop(dashboard);
+        }
+        if let Some(path) = &args.progress_file {
+            status.write(path)?;
+        }
+    }
+    emit_event("query_finished", |line| {
+        line.field("query", query.name())
+            .field("discovered_peers", discovered_peers.len())
+            .field("contacted_peers", contacted_peers.len())
+            .field("elapsed_ms", elapsed.as_millis() as u64)
+            .field(
+                "error",
+                result.as_ref().err().map(|error| error.to_string()),
+            )
+    });
+    if let Some(directory) = &args.csv {
+        write_csv(
+            directory,
+            &providers,
+            providers_seen,
+            &sightings,
+            &identified,
+        )?;
+        progress!(
+            "Wrote {} providers and {} contacted peers to {}",
+            providers.len(),
+            sightings.len(),
+            directory.display(),
+        );
+    }
+    if let Some(path) = &args.save_routing_table {
+        let saved = write_routing_table(
+            path,
+            &sightings,
+            &identified,
+            &closest_peers,
+            args.allow_private_addresses,
+        )?;
+        progress!("Saved {saved} peers to {}", path.display());
+    }
+    if let Some(path) = &args.export_keys {
+        let missing = write_key_export(path, &contacted_peers)?;
+        progress!(
+            "Exported public keys of {} contacted peers to {}",
+            contacted_peers.len() - missing,
+            path.display(),
+        );
+        if missing > 0 {
+            progress!(
+                "Public keys of {missing} peers are hashed into their peer IDs, not exported"
+            );
+        }
+    }
+    if args.summary_logfmt {
+        let mut summary = Logfmt::default()
+            .field("query", query.name())
+            .field("key", hex::encode(query.key()))
+            .field("network", kad_proto);
+        summary = match query {
+            Query::Providers(_) => summary.field("providers_found", providers.len()),
+            Query::Record(_) => summary.field("records_found", records.len()),
+            Query::Peer(_) => summary.field("peers_found", closest_peers.len()),
+        };
+        summary = summary
+            .field("peers_discovered", discovered_peers.len())
+            .field("peers_contacted", contacted_peers.len())
+            .field("duration_ms", elapsed.as_millis());
+        summary = match &result {
+            Ok(()) => summary.field("result", "ok"),
+            Err(error) => summary.field("result", "error").field("error", error),
+        };
+        progress!("{}", summary.finish());
+    }
+
+    Ok(QueryRun {
+        litep2p,
+        kademlia_handle,
+        verify_handle,
+        identify_events,
+        identified,
+        result,
+        providers,
+        records,
+        closest_peers,
+        discovered_peers,
+        contacted_peers,
+        fan_out,
+        address_filter,
+        connections,
+        elapsed,
+    })
+}
+
+/// Take console commands, e.g. lines typed on stdin, from `commands` while queries run. Only one
+/// query reads them at a time. Without a console, queries don't read any input.
+pub fn set_console(commands: UnboundedReceiver<String>) {
+    *CONSOLE.lock().expect("lock is not poisoned; qed") = Some(commands);
+}
+
+/// Set the format of the results. Only the first call has an effect.
+pub fn set_output_format(format: OutputFormat) {
+    let _ = OUTPUT.set(format);
+}
+
+/// Sign the result documents with `signer`. Only the first call has an effect.
+pub fn set_signer(signer: Signer) {
+    let _ = SIGNER.set(signer);
+}
+
+/// Whether result documents are signed.
+fn signing() -> bool {
+    SIGNER.get().is_some()
+}
+
+/// Print the JSON result `document`, signed if a signer was set with [`set_signer`].
+fn print_document(document: impl std::fmt::Display) {
+    match SIGNER.get() {
+        Some(signer) => println!("{}", signer.sign(&document.to_string())),
+        None => println!("{document}"),
+    }
+}
+
+/// Turn progress messages on or off. Only the first call has an effect.
+pub fn set_progress(enabled: bool) {
+    let _ = PROGRESS.set(enabled);
+}
+
+/// Whether results are printed as JSON.
+fn json_output() -> bool {
+    matches!(
+        OUTPUT.get(),
+        Some(OutputFormat::Json | OutputFormat::Ndjson)
+    )
+}
+
+/// Print an event line with `--output ndjson`. `fields` add the event details to the object
+/// carrying the timestamp and event name.
+fn emit_event(event: &str, fields: impl FnOnce(Json) -> Json) {
+    if OUTPUT.get() != Some(&OutputFormat::Ndjson) {
+        return;
+    }
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    let line = Json::object()
+        .field("timestamp_ms", timestamp)
+        .field("event", event);
+    println!("{}", fields(line));
+}
+
+/// JSON document describing the run of `query`: statistics, results and error, if any.
+fn run_json(query: &Query, kad_proto: &str, run: &QueryRun) -> Json {
+    let mut document = Json::object()
+        .field("schema_version", SCHEMA_VERSION)
+        .field("query", query.name())
+        .field("key", hex::encode(query.key()))
+        .field("protocol", kad_proto)
+        .field(
+            "statistics",
+            Json::object()
+                .field("discovered_peers", run.discovered_peers.len())
+                .field("contacted_peers", run.contacted_peers.len())
+                .field("elapsed_ms", run.elapsed.as_millis() as u64)
+                .field("resources", run.usage().json()),
+        );
+
+    document = match query {
+        Query::Providers(_) => document.field(
+            "providers",
+            run.providers
+                .iter()
+                .map(|provider| peer_json(&provider.peer, &provider.addresses))
+                .collect::<Vec<_>>(),
+        ),
+        Query::Record(_) => {
+            let now = Instant::now();
+            document.field(
+                "records",
+                run.records
+                    .iter()
+                    .map(|PeerRecord { peer, record }| {
+                        Json::object()
+                            .field("peer_id", peer.to_string())
+                            .field("value", hex::encode(&record.value))
+                            .field(
+                                "publisher",
+                                record.publisher.map(|publisher| publisher.to_string()),
+                            )
+                            .field(
+                                "expires_in_s",
+                                record.expires.map(|expires| {
+                                    expires.saturating_duration_since(now).as_secs()
+                                }),
+                            )
+                    })
+                    .collect::<Vec<_>>(),
+            )
+        }
+        Query::Peer(_) => document.field(
+            "closest_peers",
+            run.closest_peers
+                .iter()
+                .map(|(peer, addresses)| peer_json(peer, addresses))
+                .collect::<Vec<_>>(),
+        ),
+    };
+
+    document.field(
+        "error",
+        run.result.as_ref().err().map(|error| error.to_string()),
+    )
+}
+
+/// JSON object with the peer ID and addresses.
+fn peer_json(peer: &PeerId, addresses: &[Multiaddr]) -> Json {
+    Json::object().field("peer_id", peer.to_string()).field(
+        "addresses",
+        addresses
+            .iter()
+            .map(|address| address.to_string())
+            .collect::<Vec<_>>(),
+    )
+}
+
+/// Wait until `token` is cancelled. Never resolves without a token.
+async fn cancelled(token: Option<&CancellationToken>) {
+    match token {
+        Some(token) => token.cancelled().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Console commands borrowed by a running query and handed back to [`CONSOLE`] when it finishes.
+struct Console(Option<UnboundedReceiver<String>>);
+
+impl Console {
+    /// Take the console, unless another query holds it.
+    fn take() -> Self {
+        Self(CONSOLE.lock().expect("lock is not poisoned; qed").take())
+    }
+
+    /// Next console command. Never resolves without a console or once it is closed.
+    async fn next(&mut self) -> String {
+        if let Some(commands) = &mut self.0 {
+            match commands.next().await {
+                Some(command) => return command,
+                None => self.0 = None,
+            }
+        }
+
+        std::future::pending().await
+    }
+}
+
+impl Drop for Console {
+    fn drop(&mut self) {
+        if let Some(commands) = self.0.take() {
+            *CONSOLE.lock().expect("lock is not poisoned; qed") = Some(commands);
+        }
+    }
+}
+
+fn print_statistics(run: &QueryRun, settings: &Settings) {
+    println!("Discovered peers: {:?}", run.discovered_peers.len());
+    println!("Contacted peers: {:?}", run.contacted_peers.len());
+    println!("Time spent: {} s", run.elapsed.as_secs());
+    run.usage().print();
+    println!();
+    run.fan_out.print(settings.replication_factor);
+}
+
+/// Hint at a wrong protocol name if the query failed without any peer responding.
+fn print_protocol_hint(args: &QueryArgs, fan_out: &FanOut, kad_proto: &str) {
+    if args.genesis_hash.is_some() && fan_out.responses() == 0 {
+        progress!("Warning: no peer responded on {kad_proto}, check --genesis-hash and --fork-id");
+    }
+}
+
+fn print_violations(violations: Vec<String>) {
+    for violation in violations {
+        println!("Warning: {violation}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        query: QueryArgs,
+    }
+
+    fn query_args(args: &[&str]) -> QueryArgs {
+        Cli::parse_from(std::iter::once("dht-inspect").chain(args.iter().copied())).query
+    }
+
+    #[test]
+    fn selects_output_by_terminal() {
+        let query = query_args(&[]);
+        assert_eq!(query.output_format(true), OutputFormat::Text);
+        assert_eq!(query.output_format(false), OutputFormat::Json);
+        assert!(query.show_progress(true));
+        assert!(!query.show_progress(false));
+    }
+
+    #[tokio::test]
+    async fn restarts_stalled_nodes() {
+        let peers: HashMap<_, _> = (0..2)
+            .map(|_| {
+                let (peer, address) = test_utils::spawn_node(HashMap::new());
+                (peer, vec![address])
+            })
+            .collect();
+        let (bootnode, address) = test_utils::spawn_node(peers.clone());
+        let args = query_args(&[
+            "--bootnode",
+            &format!("{address}/p2p/{bootnode}"),
+            "--kad-proto",
+            test_utils::KAD_PROTO,
+            "--allow-private-addresses",
+        ]);
+        let settings = args.preset.settings();
+        let query = Query::Peer(PeerId::random());
+        let mut run = query_dht(
+            &args,
+            &query,
+            test_utils::KAD_PROTO,
+            known_peers(&args).await.unwrap(),
+            &settings,
+            None,
+        )
+        .await
+        .unwrap();
+        let discovered = run.discovered_peers.clone();
+        let local_peer_id = *run.litep2p.local_peer_id();
+
+        run.restart(&args, test_utils::KAD_PROTO).await.unwrap();
+        assert_ne!(*run.litep2p.local_peer_id(), local_peer_id);
+        assert!(run.discovered_peers.is_superset(&discovered));
+        assert!(peers.keys().all(|peer| run.discovered_peers.contains(peer)));
+    }
+
+    #[test]
+    fn explicit_flags_override_terminal() {
+        let query = query_args(&["--output", "text", "--progress", "always"]);
+        assert_eq!(query.output_format(false), OutputFormat::Text);
+        assert!(query.show_progress(false));
+
+        let query = query_args(&["--output", "ndjson", "--progress", "never"]);
+        assert_eq!(query.output_format(true), OutputFormat::Ndjson);
+        assert!(!query.show_progress(true));
+    }
+}
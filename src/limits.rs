@@ -0,0 +1,102 @@
+use litep2p::protocol::libp2p::kademlia::{ContentProvider, PeerRecord};
+
+/// Record limits of the network. Observations exceeding them are flagged, since some
+/// implementations silently drop or truncate such records.
+///
+/// Defaults are the litep2p record store limits. They belong to the node implementation rather
+/// than to the chain, so they are the same for every `--network`; pass the flags when inspecting
+/// networks of other implementations, e.g. IPFS.
+#[derive(clap::Args, Debug, Clone)]
+pub struct Limits {
+    /// Maximum number of providers per key peers keep.
+    #[arg(long, global = true, value_name = "N", default_value_t = 20)]
+    max_providers: usize,
+    /// Maximum number of addresses per provider peers keep.
+    #[arg(long, global = true, value_name = "N", default_value_t = 30)]
+    max_provider_addresses: usize,
+    /// Record value size in bytes from which peers reject records.
+    #[arg(long, global = true, value_name = "BYTES", default_value_t = 65 * 1024)]
+    max_record_size: usize,
+}
+
+impl Limits {
+    /// Maximum number of providers per key.
+    pub fn max_providers(&self) -> usize {
+        self.max_providers
+    }
+
+    /// Describe providers exceeding the limits.
+    pub fn check_providers(&self, providers: &[ContentProvider]) -> Vec<String> {
+        let mut violations = Vec::new();
+
+        if providers.len() > self.max_providers {
+            violations.push(format!(
+                "{} providers found, more than the {} peers keep per key",
+                providers.len(),
+                self.max_providers,
+            ));
+        }
+        for provider in providers {
+            if provider.addresses.len() > self.max_provider_addresses {
+                violations.push(format!(
+                    "provider {} has {} addresses, more than the {} peers keep",
+                    provider.peer,
+                    provider.addresses.len(),
+                    self.max_provider_addresses,
+                ));
+            }
+        }
+
+        violations
+    }
+
+    /// Describe records exceeding the limits.
+    pub fn check_records(&self, records: &[PeerRecord]) -> Vec<String> {
+        records
+            .iter()
+            .filter_map(|record| {
+                self.check_value(&record.record.value)
+                    .map(|violation| format!("record from {}: {violation}", record.peer))
+            })
+            .collect()
+    }
+
+    /// Describe a record value peers reject for its size. litep2p only stores values below the
+    /// limit.
+    pub fn check_value(&self, value: &[u8]) -> Option<String> {
+        (value.len() >= self.max_record_size).then(|| {
+            format!(
+                "value of {} bytes reaches the {} byte limit",
+                value.len(),
+                self.max_record_size,
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        limits: Limits,
+    }
+
+    #[test]
+    fn rejects_values_at_the_limit() {
+        let limits = Cli::parse_from(["dht-inspect"]).limits;
+        assert!(limits.check_value(&vec![0; 65 * 1024 - 1]).is_none());
+        assert!(limits.check_value(&vec![0; 65 * 1024]).is_some());
+
+        let limits = Cli::parse_from(["dht-inspect", "--max-record-size", "4"]).limits;
+        assert!(limits.check_value(b"abc").is_none());
+        assert_eq!(
+            limits.check_value(b"abcd").unwrap(),
+            "value of 4 bytes reaches the 4 byte limit"
+        );
+    }
+}
@@ -0,0 +1,34 @@
+use std::fmt::Write;
+
+/// Single-line logfmt record built from `key=value` pairs.
+#[derive(Default)]
+pub struct Logfmt {
+    line: String,
+}
+
+impl Logfmt {
+    /// Append a field, quoting the value if needed.
+    pub fn field(mut self, key: &str, value: impl ToString) -> Self {
+        let value = value.to_string();
+        if !self.line.is_empty() {
+            self.line.push(' ');
+        }
+
+        let needs_quotes = value.is_empty()
+            || value
+                .chars()
+                .any(|c| c.is_whitespace() || c == '=' || c == '"' || c.is_control());
+        if needs_quotes {
+            let _ = write!(self.line, "{key}={value:?}");
+        } else {
+            let _ = write!(self.line, "{key}={value}");
+        }
+
+        self
+    }
+
+    /// The record.
+    pub fn finish(self) -> String {
+        self.line
+    }
+}
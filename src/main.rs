@@ -1,173 +1,158 @@
-use std::{collections::HashSet, str::FromStr, time::Instant};
-
-use anyhow::{anyhow, Context};
-use clap::Parser;
-use futures::StreamExt;
-use litep2p::{
-    config::ConfigBuilder as Litep2pConfigBuilder,
-    protocol::libp2p::kademlia::{
-        ConfigBuilder as KademliaConfigBuilder, ContentProvider, KademliaEvent,
-        RecordKey as KademliaKey,
-    },
-    transport::{tcp::config::Config as TcpConfig, websocket::config::Config as WsConfig},
-    Litep2p, Litep2pEvent, PeerId,
+use std::io::IsTerminal;
+
+use clap::{error::ErrorKind, CommandFactory, Parser, Subcommand};
+use dht_inspect::{
+    authorities::{self, MonitorAuthoritiesArgs},
+    crawl::{self, CrawlArgs, ExploreArgs},
+    find_node::{self, FindNodeArgs},
+    genkey::{self, GenkeyArgs},
+    inspect::{self, InspectArgs},
+    ipns::{self, IpnsArgs},
+    manifest::{self, Signer, VerifyOutputArgs},
+    overlap::{self, OverlapArgs},
+    probe::{self, ProbeArgs},
+    provide::{self, AddProviderArgs, KeepProvidingArgs},
+    providers::{self, GetProvidersArgs},
+    record::{self, GetRecordArgs, PutRecordArgs},
+    schema::SCHEMA,
+    serve::{self, ServeArgs},
+    set_console, set_output_format, set_progress, set_signer, OutputFormat, QueryArgs,
+    QueryTimeout, TIMEOUT_EXIT_CODE,
 };
-use multiaddr::{Multiaddr, Protocol};
-
-const DEFAULT_BOOTNODE: &str =
-    "/dns/polkadot-bootnode-0.polkadot.io/tcp/30333/p2p/12D3KooWSz8r2WyCdsfWHgPyvD8GKQdJ1UAiRmrcrs8sQB3fe2KU";
-const DEFALT_PROTOCOL: &str =
-    "/91b171bb158e2d3848fa23a9f1c25182fb8e20313b2c1eb49219da7a70ce90c3/kad";
-
-/// Parse a multiaddress into [`PeerId`] and [`Multiaddr`].
-fn parse_multiaddress(addr: &str) -> Result<(PeerId, Multiaddr), anyhow::Error> {
-    let addr = Multiaddr::from_str(addr).context("invalid multiaddress")?;
-    let peer_id = match addr.iter().last() {
-        Some(Protocol::P2p(multihash)) => PeerId::from_multihash(multihash)
-            .map_err(|m| anyhow!("multihash is not a peer ID in a multiaddress: {m:?}"))?,
-        _ => return Err(anyhow!("multiaddress doesn't contain peer ID")),
-    };
-
-    Ok((peer_id, addr))
-}
-
-/// Decode a Kademlia key from a hex string.
-fn parse_key(hex: &str) -> Result<KademliaKey, hex::FromHexError> {
-    hex::decode(hex).map(|bytes| KademliaKey::new(&bytes))
-}
 
-/// Query Kademlia DHT content provider records.
+/// Inspect Kademlia DHT records and peers.
 #[derive(Parser, Debug)]
 struct Args {
-    /// Key (hex) of the content provider record to query.
-    #[arg(short, long, value_name = "KEY", value_parser = parse_key)]
-    provider_key: KademliaKey,
-    /// Bootnode multiaddress.
-    #[arg(short, long, value_name = "MULTIADDR", value_parser = parse_multiaddress, default_value = DEFAULT_BOOTNODE)]
-    bootnode: (PeerId, Multiaddr),
-    /// Kademlia protocol name.
-    #[arg(short, long, value_name = "PROTOCOL", default_value = DEFALT_PROTOCOL)]
-    kad_proto: String,
-    /// Prepopulate routing table with FIND_NODE queries before executing the main query.
-    #[arg(long, value_name = "ITERATIONS", default_value_t = 0)]
-    prepopulate: usize,
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Print the JSON schema of the `--output json` result documents and exit.
+    #[arg(long, exclusive = true)]
+    schema: bool,
+    #[command(flatten)]
+    query: QueryArgs,
+}
+
+/// Operations.
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run a GET_PROVIDERS query and print the found providers.
+    GetProviders(GetProvidersArgs),
+    /// Run a GET_VALUE query and print the found records.
+    GetRecord(GetRecordArgs),
+    /// Publish a record with PUT_VALUE and report which peers stored it.
+    PutRecord(PutRecordArgs),
+    /// Fetch, validate and decode the IPNS record of a name. Use with `--network ipfs`.
+    Ipns(IpnsArgs),
+    /// Run GET_PROVIDERS for a key, audit the peers closest to it, dial the providers found and
+    /// report the health of the key.
+    Inspect(InspectArgs),
+    /// Announce the local node as a content provider for a key.
+    AddProvider(AddProviderArgs),
+    /// Keep providing a key, republishing the provider record periodically.
+    KeepProviding(KeepProvidingArgs),
+    /// Audit the authority discovery records of a validator set periodically and serve
+    /// per-validator Prometheus metrics.
+    MonitorAuthorities(MonitorAuthoritiesArgs),
+    /// Run a FIND_NODE query for a peer and print the closest peers found.
+    FindNode(FindNodeArgs),
+    /// Generate a keypair whose peer ID is close to a key in the Kademlia keyspace.
+    Genkey(GenkeyArgs),
+    /// Walk the keyspace with FIND_NODE queries and summarize the peers found.
+    Crawl(CrawlArgs),
+    /// Discover as many peers as possible within a time budget and dump them.
+    Explore(ExploreArgs),
+    /// Compare the peers of saved crawls of several networks.
+    Overlap(OverlapArgs),
+    /// Serve DHT queries over an HTTP API from a long-lived node.
+    Serve(ServeArgs),
+    /// Send a request over an arbitrary request-response protocol and dump the reply.
+    Probe(ProbeArgs),
+    /// Check the signature of a result document written with `--sign-output`.
+    VerifyOutput(VerifyOutputArgs),
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let args = Args::parse();
-
-    let (kademlia_config, mut kademlia_handle) = KademliaConfigBuilder::new()
-        .with_protocol_names(vec![args.kad_proto.into()])
-        .with_known_peers(
-            [(args.bootnode.0, vec![args.bootnode.1])]
-                .into_iter()
-                .collect(),
-        )
-        .build();
-
-    let mut litep2p = Litep2p::new(
-        Litep2pConfigBuilder::new()
-            .with_tcp(TcpConfig {
-                listen_addresses: Vec::new(),
-                ..Default::default()
-            })
-            .with_websocket(WsConfig {
-                listen_addresses: Vec::new(),
-                ..Default::default()
-            })
-            .with_libp2p_kademlia(kademlia_config)
-            .build(),
-    )
-    .context("litep2p initialization error")?;
-
-    let mut find_node_query = None;
-    let mut get_providers_query = None;
-    let mut iterations = args.prepopulate;
-
-    if iterations > 0 {
-        iterations -= 1;
-        println!("Prepopulating Kademlia routing table...");
-        find_node_query = Some(kademlia_handle.find_node(PeerId::random()).await);
-    } else {
-        println!("Running GET_PROVIDERS query...");
-        get_providers_query = Some(
-            kademlia_handle
-                .get_providers(args.provider_key.clone())
-                .await,
-        );
+    if args.schema {
+        if args.command.is_some() {
+            Args::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    "--schema can't be used with a subcommand",
+                )
+                .exit()
+        }
+        print!("{SCHEMA}");
+        return Ok(());
+    }
+    let Some(command) = args.command else {
+        Args::command()
+            .error(ErrorKind::MissingSubcommand, "a subcommand is required")
+            .exit()
+    };
+    let terminal = std::io::stdout().is_terminal();
+    set_output_format(args.query.output_format(terminal));
+    set_progress(args.query.show_progress(terminal));
+    if let Some(path) = &args.query.sign_output {
+        if args.query.output_format(terminal) == OutputFormat::Text {
+            Args::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    "--sign-output requires --output json or ndjson",
+                )
+                .exit()
+        }
+        set_signer(Signer::from_file(path, std::env::args().collect())?);
+    }
+    if std::io::stdin().is_terminal() {
+        spawn_console();
     }
 
-    let mut discovered_peers = HashSet::new();
-    let mut contacted_peers = HashSet::new();
-    let start = Instant::now();
-
-    loop {
-        tokio::select! {
-            event = litep2p.next_event() => match event {
-                Some(Litep2pEvent::ConnectionEstablished { peer, endpoint: _ }) => {
-                    contacted_peers.insert(peer);
-                },
-                _ => {}
-            },
-            kademlia_event = kademlia_handle.next() => {
-                let Some(kademlia_event) = kademlia_event else {
-                    return Err(anyhow!("libp2p Kademlia terminated"))
-                };
+    let result = match command {
+        Command::GetProviders(get_providers) => providers::run(get_providers, &args.query).await,
+        Command::GetRecord(get_record) => record::run(get_record, &args.query).await,
+        Command::PutRecord(put_record) => record::run_put(put_record, &args.query).await,
+        Command::Ipns(ipns) => ipns::run(ipns, &args.query).await,
+        Command::Inspect(inspect) => inspect::run(inspect, &args.query).await,
+        Command::AddProvider(add_provider) => provide::run(add_provider, &args.query).await,
+        Command::KeepProviding(keep_providing) => {
+            provide::run_keep_providing(keep_providing, &args.query).await
+        }
+        Command::MonitorAuthorities(monitor) => authorities::run(monitor, &args.query).await,
+        Command::FindNode(find_node) => find_node::run(find_node, &args.query).await,
+        Command::Genkey(genkey) => genkey::run(genkey),
+        Command::Crawl(crawl) => crawl::run(crawl, &args.query).await,
+        Command::Explore(explore) => crawl::run_explore(explore, &args.query).await,
+        Command::Overlap(overlap) => overlap::run(overlap),
+        Command::Serve(serve) => serve::run(serve, &args.query).await,
+        Command::Probe(probe) => probe::run(probe, &args.query.socket).await,
+        Command::VerifyOutput(verify_output) => manifest::run(verify_output),
+    };
 
-                match kademlia_event {
-                    KademliaEvent::FindNodeSuccess { query_id, .. } if Some(query_id) == find_node_query => {
-                        if iterations > 0 {
-                            iterations -= 1;
-                            find_node_query = Some(kademlia_handle.find_node(PeerId::random()).await);
-                            println!("Prepopulating Kademlia routing table...");
-                        } else {
-                            println!("Running GET_PROVIDERS query...");
-                            get_providers_query = Some(
-                                kademlia_handle
-                                    .get_providers(args.provider_key.clone())
-                                    .await,
-                            );
-                        }
-                    },
-                    KademliaEvent::GetProvidersSuccess { query_id, provided_key, providers } => {
-                        if Some(query_id) == get_providers_query && provided_key == args.provider_key {
-                            print_statistics(&discovered_peers, &contacted_peers, &start);
-                            print_providers(providers);
-                            return Ok(())
-                        }
-                    },
-                    KademliaEvent::QueryFailed { query_id } if Some(query_id) == find_node_query => {
-                        print_statistics(&discovered_peers, &contacted_peers, &start);
-                        return Err(anyhow!("FIND_NODE query failed"))
-                    },
-                    KademliaEvent::QueryFailed { query_id } if Some(query_id) == get_providers_query => {
-                        print_statistics(&discovered_peers, &contacted_peers, &start);
-                        return Err(anyhow!("Kademlia query failed"))
-                    },
-                    KademliaEvent::RoutingTableUpdate { peers } => {
-                        for peer in peers {
-                            discovered_peers.insert(peer);
-                        }
-                    },
-                    event => {
-                        println!("kademlia event: {event:?}");
-                    }
-                }
-            }
+    if let Err(error) = &result {
+        if error.is::<QueryTimeout>() {
+            eprintln!("Error: {error:?}");
+            std::process::exit(TIMEOUT_EXIT_CODE);
         }
     }
-}
 
-fn print_statistics(discovered: &HashSet<PeerId>, contacted: &HashSet<PeerId>, start: &Instant) {
-    println!("Discovered peers: {:?}", discovered.len());
-    println!("Contacted peers: {:?}", contacted.len());
-    println!("Time spent: {} s", start.elapsed().as_secs());
-    println!("");
+    result
 }
 
-fn print_providers(providers: Vec<ContentProvider>) {
-    for provider in providers {
-        println!("{:?}", provider);
-    }
+/// Forward the lines typed on stdin to the running query as console commands.
+///
+/// Blocking reads from stdin can't be cancelled, so they run on a thread of their own that doesn't
+/// keep the process alive once `main` returns.
+fn spawn_console() {
+    let (sender, receiver) = futures::channel::mpsc::unbounded();
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lines() {
+            let Ok(line) = line else { break };
+            if sender.unbounded_send(line).is_err() {
+                break;
+            }
+        }
+    });
+    set_console(receiver);
 }
@@ -0,0 +1,203 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context};
+use litep2p::crypto::ed25519::{Keypair, PublicKey, SecretKey};
+use sha2::{Digest, Sha256};
+
+use crate::json::Json;
+
+/// DER prefix of a PKCS#8 ed25519 private key, followed by the 32-byte seed.
+const PKCS8_ED25519_PREFIX: [u8; 16] = [
+    0x30, 0x2e, 0x02, 0x01, 0x00, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x04, 0x22, 0x04, 0x20,
+];
+
+/// Check the signed manifest of a result document written with `--sign-output`.
+#[derive(clap::Args, Debug)]
+pub struct VerifyOutputArgs {
+    /// File with the signed JSON document.
+    #[arg(value_name = "PATH")]
+    path: PathBuf,
+}
+
+/// Signer of the result documents, appending a manifest with the tool version, the command line,
+/// the SHA-256 hash of the document and an ed25519 signature.
+///
+/// The signature covers the manifest without the `signature` field, serialized like the rest of
+/// the document, so the version and configuration are signed along with the hash.
+pub struct Signer {
+    keypair: Keypair,
+    configuration: Vec<String>,
+}
+
+impl Signer {
+    /// Signer with the ed25519 key in `path` for results of the `configuration` command line.
+    ///
+    /// The key is either the 32 hex bytes `genkey` prints or a PKCS#8 PEM file, e.g. from
+    /// `openssl genpkey -algorithm ed25519`.
+    pub fn from_file(path: &Path, configuration: Vec<String>) -> anyhow::Result<Self> {
+        let key = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let seed = parse_key(key.trim())
+            .with_context(|| format!("invalid signing key in {}", path.display()))?;
+        let secret = SecretKey::try_from_bytes(seed).map_err(|error| anyhow!("{error}"))?;
+
+        Ok(Self {
+            keypair: Keypair::from(secret),
+            configuration,
+        })
+    }
+
+    /// Append the manifest to the serialized JSON object `document`.
+    pub fn sign(&self, document: &str) -> String {
+        let manifest = Json::object()
+            .field("tool", env!("CARGO_PKG_NAME"))
+            .field("version", env!("CARGO_PKG_VERSION"))
+            .field("configuration", self.configuration.clone())
+            .field("sha256", hex::encode(Sha256::digest(document)))
+            .field("public_key", hex::encode(self.keypair.public().to_bytes()));
+        let signature = self.keypair.sign(manifest.to_string().as_bytes());
+        let manifest = manifest.field("signature", hex::encode(signature));
+
+        let fields = document
+            .strip_suffix('}')
+            .expect("document is an object; qed");
+        let separator = if fields.ends_with('{') { "" } else { "," };
+        format!(r#"{fields}{separator}"manifest":{manifest}}}"#)
+    }
+}
+
+/// Seed of the ed25519 key `key`, in hex or PKCS#8 PEM.
+fn parse_key(key: &str) -> anyhow::Result<[u8; 32]> {
+    let seed = if key.starts_with("-----BEGIN") {
+        let pem = pem::parse(key)?;
+        if pem.tag != "PRIVATE KEY" {
+            return Err(anyhow!("expected a PRIVATE KEY, found {}", pem.tag));
+        }
+        pem.contents
+            .strip_prefix(&PKCS8_ED25519_PREFIX)
+            .ok_or_else(|| anyhow!("not an ed25519 private key"))?
+            .to_vec()
+    } else {
+        hex::decode(key)?
+    };
+
+    seed.try_into()
+        .map_err(|_| anyhow!("expected a 32-byte ed25519 key"))
+}
+
+/// Check the manifest of the signed `document` and return it.
+///
+/// The document is parsed and serialized again without the manifest, which reproduces the bytes
+/// that were hashed since the tool serializes documents the same way.
+fn verify(document: &str) -> anyhow::Result<Json> {
+    let Json::Object(mut fields) = Json::parse(document.trim())? else {
+        return Err(anyhow!("not a JSON object"));
+    };
+    let Some((name, Json::Object(mut manifest))) = fields.pop() else {
+        return Err(anyhow!("no manifest"));
+    };
+    if name != "manifest" {
+        return Err(anyhow!("no manifest, the last field is `{name}`"));
+    }
+
+    let field = |manifest: &[(String, Json)], name: &str| -> anyhow::Result<Vec<u8>> {
+        let value = manifest
+            .iter()
+            .find_map(|(field, value)| (field == name).then_some(value))
+            .and_then(Json::as_str)
+            .ok_or_else(|| anyhow!("manifest has no {name}"))?;
+        hex::decode(value).with_context(|| format!("invalid {name}"))
+    };
+    if field(&manifest, "sha256")? != Sha256::digest(Json::Object(fields).to_string()).to_vec() {
+        return Err(anyhow!(
+            "the document doesn't match the hash of the manifest"
+        ));
+    }
+    let public_key = PublicKey::try_from_bytes(&field(&manifest, "public_key")?)
+        .map_err(|error| anyhow!("invalid public key: {error}"))?;
+    let signature = field(&manifest, "signature")?;
+    let signed = Json::Object(manifest.clone());
+    manifest.retain(|(field, _)| field != "signature");
+    if !public_key.verify(Json::Object(manifest).to_string().as_bytes(), &signature) {
+        return Err(anyhow!("invalid signature"));
+    }
+
+    Ok(signed)
+}
+
+/// Check the signed document and print its manifest.
+pub fn run(args: VerifyOutputArgs) -> anyhow::Result<()> {
+    let document = std::fs::read_to_string(&args.path)
+        .with_context(|| format!("failed to read {}", args.path.display()))?;
+    let manifest =
+        verify(&document).with_context(|| format!("failed to verify {}", args.path.display()))?;
+
+    let text = |name: &str| {
+        manifest
+            .get(name)
+            .and_then(Json::as_str)
+            .unwrap_or_default()
+    };
+    println!("Signature valid");
+    println!("Public key: {}", text("public_key"));
+    println!("SHA-256: {}", text("sha256"));
+    println!("Tool: {} {}", text("tool"), text("version"));
+    if let Some(configuration) = manifest.get("configuration").and_then(Json::as_array) {
+        let arguments: Vec<_> = configuration.iter().filter_map(Json::as_str).collect();
+        println!("Configuration: {}", arguments.join(" "));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_keys() {
+        let seed = [7; 32];
+        assert_eq!(parse_key(&hex::encode(seed)).unwrap(), seed);
+
+        let pem = pem::encode(&pem::Pem {
+            tag: "PRIVATE KEY".to_string(),
+            contents: [PKCS8_ED25519_PREFIX.as_slice(), &seed].concat(),
+        });
+        assert_eq!(parse_key(pem.trim()).unwrap(), seed);
+
+        assert!(parse_key("00").is_err());
+        let certificate = pem::encode(&pem::Pem {
+            tag: "CERTIFICATE".to_string(),
+            contents: vec![0; 48],
+        });
+        assert!(parse_key(certificate.trim()).is_err());
+    }
+
+    #[test]
+    fn signs_documents() {
+        let signer = Signer {
+            keypair: Keypair::generate(),
+            configuration: vec!["dht-inspect".to_string(), "crawl".to_string()],
+        };
+        let document = Json::object()
+            .field("query", "CRAWL")
+            .field("peers", 12usize)
+            .field("ratio", 0.25)
+            .field("discovered", vec!["a\"b".to_string()])
+            .to_string();
+        let signed = signer.sign(&document);
+
+        let manifest = verify(&signed).unwrap();
+        assert_eq!(
+            manifest
+                .get("configuration")
+                .map(ToString::to_string)
+                .as_deref(),
+            Some(r#"["dht-inspect","crawl"]"#)
+        );
+        assert!(verify(&signed.replace(r#""peers":12"#, r#""peers":13"#)).is_err());
+        assert!(verify(&signed.replace("crawl\"]", "explore\"]")).is_err());
+        assert!(verify(&document).is_err());
+        assert!(verify(&signer.sign("{}")).is_ok());
+    }
+}
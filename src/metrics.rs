@@ -0,0 +1,268 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use litep2p::protocol::libp2p::kademlia::{ContentProvider, RecordKey as KademliaKey};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    time::Instant,
+};
+
+use crate::http::{self, read_request, write_response};
+
+/// Metrics of the queries for one key.
+#[derive(Default)]
+struct KeyMetrics {
+    queries: u64,
+    failures: u64,
+    /// Providers found by the last successful query.
+    providers: Option<usize>,
+    /// Duration of the last query.
+    duration: Duration,
+}
+
+/// Metrics of the audits of one authority discovery record.
+struct AuthorityMetrics {
+    name: String,
+    /// Whether the last audit found the record.
+    present: bool,
+    /// Creation time of the record found by the last audit.
+    created: Option<SystemTime>,
+    /// Addresses of the record that accepted a connection in the last audit.
+    reachable: usize,
+}
+
+/// Metrics of the daemon mode, rendered in the Prometheus text format.
+#[derive(Default)]
+pub struct Metrics {
+    keys: BTreeMap<String, KeyMetrics>,
+    /// Audited authorities by public key.
+    authorities: BTreeMap<String, AuthorityMetrics>,
+    discovered_peers: usize,
+    contacted_peers: usize,
+    refreshes: u64,
+    refresh_failures: u64,
+    /// When the routing table was last refreshed.
+    refreshed: Option<Instant>,
+}
+
+impl Metrics {
+    /// Record the outcome of a GET_PROVIDERS query for `key`.
+    pub fn on_query(
+        &mut self,
+        key: &KademliaKey,
+        providers: Option<&[ContentProvider]>,
+        duration: Duration,
+    ) {
+        let metrics = self.keys.entry(hex::encode(key.as_ref())).or_default();
+        metrics.queries += 1;
+        metrics.duration = duration;
+        match providers {
+            Some(providers) => metrics.providers = Some(providers.len()),
+            None => metrics.failures += 1,
+        }
+    }
+
+    /// Record the audit of the authority discovery record of the authority with `public_key`,
+    /// named `name`: whether the record was `present`, when it was `created` and how many of its
+    /// addresses were `reachable`.
+    pub fn on_authority(
+        &mut self,
+        public_key: &str,
+        name: &str,
+        present: bool,
+        created: Option<SystemTime>,
+        reachable: usize,
+    ) {
+        self.authorities.insert(
+            public_key.to_string(),
+            AuthorityMetrics {
+                name: name.to_string(),
+                present,
+                created,
+                reachable,
+            },
+        );
+    }
+
+    /// Update the number of peers the node knows about.
+    pub fn on_peers(&mut self, discovered_peers: usize, contacted_peers: usize) {
+        self.discovered_peers = discovered_peers;
+        self.contacted_peers = contacted_peers;
+    }
+
+    /// Update the routing table refreshes started and failed so far, and when the table was last
+    /// refreshed.
+    pub fn on_refresh(&mut self, refreshes: u64, failures: u64, refreshed: Instant) {
+        self.refreshes = refreshes;
+        self.refresh_failures = failures;
+        self.refreshed = Some(refreshed);
+    }
+
+    fn render(&self) -> String {
+        let mut text = String::new();
+        let mut family = |name: &str, kind: &str, help: &str, values: Vec<(String, String)>| {
+            let _ = writeln!(text, "# HELP dht_inspect_{name} {help}");
+            let _ = writeln!(text, "# TYPE dht_inspect_{name} {kind}");
+            for (labels, value) in values {
+                let _ = writeln!(text, "dht_inspect_{name}{labels} {value}");
+            }
+        };
+        let per_key = |value: &dyn Fn(&KeyMetrics) -> Option<String>| {
+            self.keys
+                .iter()
+                .filter_map(|(key, metrics)| Some((format!("{{key=\"{key}\"}}"), value(metrics)?)))
+                .collect()
+        };
+        let per_authority = |value: &dyn Fn(&AuthorityMetrics) -> Option<String>| {
+            self.authorities
+                .iter()
+                .filter_map(|(public_key, metrics)| {
+                    let labels = format!(
+                        "{{authority=\"{public_key}\",name=\"{}\"}}",
+                        escape_label(&metrics.name)
+                    );
+                    Some((labels, value(metrics)?))
+                })
+                .collect()
+        };
+
+        family(
+            "queries_total",
+            "counter",
+            "GET_PROVIDERS queries run.",
+            per_key(&|metrics| Some(metrics.queries.to_string())),
+        );
+        family(
+            "query_failures_total",
+            "counter",
+            "GET_PROVIDERS queries failed.",
+            per_key(&|metrics| Some(metrics.failures.to_string())),
+        );
+        family(
+            "providers",
+            "gauge",
+            "Providers found by the last successful query.",
+            per_key(&|metrics| metrics.providers.map(|providers| providers.to_string())),
+        );
+        family(
+            "query_duration_seconds",
+            "gauge",
+            "Duration of the last query.",
+            per_key(&|metrics| Some(metrics.duration.as_secs_f64().to_string())),
+        );
+        family(
+            "discovered_peers",
+            "gauge",
+            "Peers discovered via routing table updates.",
+            vec![(String::new(), self.discovered_peers.to_string())],
+        );
+        family(
+            "contacted_peers",
+            "gauge",
+            "Peers a connection was established to.",
+            vec![(String::new(), self.contacted_peers.to_string())],
+        );
+        family(
+            "routing_refreshes_total",
+            "counter",
+            "Routing table refresh queries started.",
+            vec![(String::new(), self.refreshes.to_string())],
+        );
+        family(
+            "routing_refresh_failures_total",
+            "counter",
+            "Routing table refresh queries failed.",
+            vec![(String::new(), self.refresh_failures.to_string())],
+        );
+        family(
+            "routing_table_age_seconds",
+            "gauge",
+            "Time since the routing table was last refreshed.",
+            self.refreshed
+                .map(|refreshed| (String::new(), refreshed.elapsed().as_secs_f64().to_string()))
+                .into_iter()
+                .collect(),
+        );
+        if !self.authorities.is_empty() {
+            family(
+                "record_present",
+                "gauge",
+                "Whether the last audit found the authority discovery record.",
+                per_authority(&|metrics| Some(u8::from(metrics.present).to_string())),
+            );
+            family(
+                "record_age_seconds",
+                "gauge",
+                "Time since the authority discovery record found by the last audit was created.",
+                per_authority(&|metrics| {
+                    let age = metrics.created?.elapsed().unwrap_or_default();
+                    Some(age.as_secs_f64().to_string())
+                }),
+            );
+            family(
+                "addresses_reachable",
+                "gauge",
+                "Addresses of the authority discovery record that accepted a connection.",
+                per_authority(&|metrics| Some(metrics.reachable.to_string())),
+            );
+        }
+
+        text
+    }
+}
+
+/// Escape `value` for a label value of the Prometheus text format.
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Serve `metrics` at `/metrics` over HTTP/1.1 to the connections accepted by `listener`.
+pub async fn serve(listener: TcpListener, metrics: Arc<Mutex<Metrics>>) {
+    loop {
+        let stream = http::accept(&listener).await;
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            let _ = respond(stream, &metrics).await;
+        });
+    }
+}
+
+async fn respond(mut stream: TcpStream, metrics: &Mutex<Metrics>) -> std::io::Result<()> {
+    match read_request(&mut stream).await? {
+        Some((method, path)) if method == "GET" && path == "/metrics" => {
+            let body = metrics.lock().expect("metrics lock poisoned").render();
+            write_response(&mut stream, "200 OK", "text/plain; version=0.0.4", &body).await
+        }
+        Some(_) => write_response(&mut stream, "404 Not Found", "text/plain", "").await,
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_authority_metrics() {
+        let mut metrics = Metrics::default();
+        assert!(!metrics.render().contains("record_present"));
+
+        let created = SystemTime::now() - Duration::from_secs(90);
+        metrics.on_authority("01", "alice \"stash\"", true, Some(created), 2);
+        metrics.on_authority("02", "", false, None, 0);
+        let text = metrics.render();
+        let labels = r#"{authority="01",name="alice \"stash\""}"#;
+        assert!(text.contains(&format!("dht_inspect_record_present{labels} 1\n")));
+        assert!(text.contains(r#"dht_inspect_record_present{authority="02",name=""} 0"#));
+        assert!(text.contains(&format!("dht_inspect_addresses_reachable{labels} 2\n")));
+        assert!(text.contains(&format!("dht_inspect_record_age_seconds{labels} 90.")));
+        assert!(!text.contains(r#"dht_inspect_record_age_seconds{authority="02""#));
+    }
+}
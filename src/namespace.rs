@@ -0,0 +1,234 @@
+use std::{
+    fmt,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use litep2p::PeerId;
+use multiaddr::Multiaddr;
+use prost::Message;
+
+use crate::ipns;
+
+/// Multihash code of the identity hash.
+pub const IDENTITY_MULTIHASH: u8 = 0x00;
+
+/// Well-known namespace of a DHT key, detected from the key's shape.
+pub enum Namespace {
+    /// IPNS record of a peer (`/ipns/<peer id>`).
+    Ipns(PeerId),
+    /// Public key of a peer (`/pk/<peer id>`).
+    PublicKey(PeerId),
+    /// 32-byte hash, e.g. an authority discovery key. Values are decoded as authority discovery
+    /// records.
+    Hash,
+    /// Unrecognized key.
+    Unknown,
+}
+
+impl Namespace {
+    /// Detect the namespace of `key`.
+    pub fn detect(key: &[u8]) -> Self {
+        if let Some(peer) = key.strip_prefix(b"/ipns/") {
+            if let Ok(peer) = PeerId::from_bytes(peer) {
+                return Self::Ipns(peer);
+            }
+        }
+        if let Some(peer) = key.strip_prefix(b"/pk/") {
+            if let Ok(peer) = PeerId::from_bytes(peer) {
+                return Self::PublicKey(peer);
+            }
+        }
+        if key.len() == 32 {
+            return Self::Hash;
+        }
+
+        Self::Unknown
+    }
+
+    /// Decode and validate a record value stored under a key of this namespace.
+    ///
+    /// Returns human-readable lines describing the value, or `None` if there is no decoder.
+    pub fn decode(&self, value: &[u8]) -> Option<Vec<String>> {
+        match self {
+            Self::Ipns(name) => Some(ipns::describe(name, value)),
+            Self::PublicKey(peer) => Some(decode_public_key(peer, value)),
+            Self::Hash => decode_authority_record(value),
+            Self::Unknown => None,
+        }
+    }
+
+    /// Whether `value` decodes as a record of this namespace. Values of unknown namespaces don't.
+    pub fn decodes(&self, value: &[u8]) -> bool {
+        match self {
+            Self::Ipns(_) => IpnsEntry::decode(value).is_ok(),
+            Self::PublicKey(_) => PublicKey::decode(value).is_ok(),
+            Self::Hash => decode_authority_record(value).is_some(),
+            Self::Unknown => false,
+        }
+    }
+}
+
+impl fmt::Display for Namespace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ipns(peer) => write!(f, "IPNS record of {peer}"),
+            Self::PublicKey(peer) => write!(f, "public key of {peer}"),
+            Self::Hash => write!(f, "32-byte hash (e.g. authority discovery)"),
+            Self::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+/// IPNS record (`IpnsEntry`).
+#[derive(Clone, PartialEq, Message)]
+pub struct IpnsEntry {
+    #[prost(bytes = "vec", optional, tag = "1")]
+    pub value: Option<Vec<u8>>,
+    #[prost(bytes = "vec", optional, tag = "4")]
+    pub validity: Option<Vec<u8>>,
+    #[prost(uint64, optional, tag = "5")]
+    pub sequence: Option<u64>,
+    #[prost(uint64, optional, tag = "6")]
+    pub ttl: Option<u64>,
+    /// Protobuf-encoded public key, if it can't be derived from the name.
+    #[prost(bytes = "vec", optional, tag = "7")]
+    pub public_key: Option<Vec<u8>>,
+    #[prost(bytes = "vec", optional, tag = "8")]
+    pub signature_v2: Option<Vec<u8>>,
+    /// DAG-CBOR encoded fields covered by the V2 signature.
+    #[prost(bytes = "vec", optional, tag = "9")]
+    pub data: Option<Vec<u8>>,
+}
+
+/// libp2p public key.
+#[derive(Clone, PartialEq, Message)]
+struct PublicKey {
+    #[prost(int32, tag = "1")]
+    key_type: i32,
+    #[prost(bytes = "vec", tag = "2")]
+    data: Vec<u8>,
+}
+
+/// Substrate authority discovery record, signed by the authority.
+#[derive(Clone, PartialEq, Message)]
+struct SignedAuthorityRecord {
+    #[prost(bytes = "vec", tag = "1")]
+    record: Vec<u8>,
+    #[prost(bytes = "vec", tag = "2")]
+    auth_signature: Vec<u8>,
+    #[prost(message, optional, tag = "3")]
+    peer_signature: Option<PeerSignature>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct PeerSignature {
+    #[prost(bytes = "vec", tag = "1")]
+    signature: Vec<u8>,
+    #[prost(bytes = "vec", tag = "2")]
+    public_key: Vec<u8>,
+}
+
+/// Addresses of an authority.
+#[derive(Clone, PartialEq, Message)]
+struct AuthorityRecord {
+    #[prost(bytes = "vec", repeated, tag = "1")]
+    addresses: Vec<Vec<u8>>,
+    #[prost(message, optional, tag = "2")]
+    creation_time: Option<TimestampInfo>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct TimestampInfo {
+    /// SCALE-encoded `u128` nanoseconds since UNIX epoch.
+    #[prost(bytes = "vec", tag = "1")]
+    timestamp: Vec<u8>,
+}
+
+fn decode_public_key(peer: &PeerId, value: &[u8]) -> Vec<String> {
+    let key = match PublicKey::decode(value) {
+        Ok(key) => key,
+        Err(error) => return vec![format!("invalid public key: {error}")],
+    };
+    let key_type = match key.key_type {
+        0 => "RSA",
+        1 => "Ed25519",
+        2 => "Secp256k1",
+        3 => "ECDSA",
+        _ => "unknown",
+    };
+
+    // Peer IDs of short keys are the identity multihash of the encoded key.
+    let mut identity = vec![IDENTITY_MULTIHASH, value.len() as u8];
+    identity.extend_from_slice(value);
+    let matches = match PeerId::from_bytes(&identity) {
+        Ok(derived) if value.len() <= 42 => {
+            if derived == *peer {
+                "matches the peer ID"
+            } else {
+                "doesn't match the peer ID"
+            }
+        }
+        _ => "not verified, peer ID is a hash of the key",
+    };
+
+    vec![format!(
+        "{key_type} public key ({} bytes), {matches}",
+        key.data.len()
+    )]
+}
+
+/// Addresses and creation time of a signed authority discovery record. `None` if `value` isn't
+/// one. The signatures aren't checked.
+pub fn authority_addresses(value: &[u8]) -> Option<(Vec<Multiaddr>, Option<SystemTime>)> {
+    let signed = SignedAuthorityRecord::decode(value).ok()?;
+    let record = AuthorityRecord::decode(signed.record.as_slice()).ok()?;
+    if signed.auth_signature.is_empty() {
+        return None;
+    }
+
+    let addresses = record
+        .addresses
+        .into_iter()
+        .filter_map(|address| Multiaddr::try_from(address).ok())
+        .collect();
+    let created = record.creation_time.as_ref().and_then(creation_time);
+
+    Some((addresses, created))
+}
+
+/// `None` if the timestamp isn't a SCALE-encoded `u128`.
+fn creation_time(creation_time: &TimestampInfo) -> Option<SystemTime> {
+    let nanos = <[u8; 16]>::try_from(creation_time.timestamp.as_slice()).ok()?;
+    Some(UNIX_EPOCH + Duration::from_nanos(u128::from_le_bytes(nanos) as u64))
+}
+
+fn decode_authority_record(value: &[u8]) -> Option<Vec<String>> {
+    let signed = SignedAuthorityRecord::decode(value).ok()?;
+    let record = AuthorityRecord::decode(signed.record.as_slice()).ok()?;
+    if signed.auth_signature.is_empty() {
+        return None;
+    }
+
+    let mut lines = vec![format!(
+        "Authority discovery record with {} addresses",
+        record.addresses.len()
+    )];
+    for address in &record.addresses {
+        match Multiaddr::try_from(address.clone()) {
+            Ok(address) => lines.push(format!("  {address}")),
+            Err(_) => lines.push(format!("  invalid address 0x{}", hex::encode(address))),
+        }
+    }
+    if let Some(timestamp) = &record.creation_time {
+        match creation_time(timestamp).map(|created| created.elapsed()) {
+            Some(Ok(age)) => lines.push(format!("Created {} s ago", age.as_secs())),
+            Some(Err(_)) => lines.push("Warning: creation time is in the future".to_string()),
+            None => lines.push("Warning: invalid creation time".to_string()),
+        }
+    }
+    if signed.peer_signature.is_none() {
+        lines.push("Warning: record is not signed by the peer key".to_string());
+    }
+
+    Some(lines)
+}
@@ -0,0 +1,127 @@
+/// Build the Substrate Kademlia protocol name `/<genesis hash>[/<fork id>]/kad`.
+pub fn kademlia_protocol_name(genesis_hash: &[u8], fork_id: Option<&str>) -> String {
+    match fork_id {
+        Some(fork_id) => format!("/{}/{fork_id}/kad", hex::encode(genesis_hash)),
+        None => format!("/{}/kad", hex::encode(genesis_hash)),
+    }
+}
+
+/// Build the legacy Substrate Kademlia protocol name `/<protocol id>/kad`.
+pub fn legacy_kademlia_protocol_name(protocol_id: &str) -> String {
+    format!("/{protocol_id}/kad")
+}
+
+/// Decode a 32-byte genesis hash from hex, with or without the `0x` prefix.
+pub fn parse_genesis_hash(hash: &str) -> Result<[u8; 32], anyhow::Error> {
+    let bytes = hex::decode(hash.trim_start_matches("0x"))?;
+
+    bytes.try_into().map_err(|bytes: Vec<u8>| {
+        anyhow::anyhow!("genesis hash must be 32 bytes, got {}", bytes.len())
+    })
+}
+
+/// Kademlia protocol name of the public IPFS DHT.
+pub const IPFS_KADEMLIA_PROTOCOL: &str = "/ipfs/kad/1.0.0";
+
+/// Well-known Polkadot SDK networks and the public IPFS DHT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Network {
+    Polkadot,
+    Kusama,
+    Westend,
+    Paseo,
+    Ipfs,
+}
+
+impl Network {
+    /// Kademlia protocol name of the network.
+    pub fn kademlia_protocol(&self) -> String {
+        match self.genesis_hash() {
+            Some(genesis_hash) => kademlia_protocol_name(&genesis_hash, None),
+            None => IPFS_KADEMLIA_PROTOCOL.to_string(),
+        }
+    }
+
+    /// Genesis hash of the relay chain, `None` for IPFS.
+    pub fn genesis_hash(&self) -> Option<[u8; 32]> {
+        let hash = match self {
+            Network::Polkadot => "91b171bb158e2d3848fa23a9f1c25182fb8e20313b2c1eb49219da7a70ce90c3",
+            Network::Kusama => "b0a8d493285c2df73290dfb7e61f870f17b41801197a149ca93654499ea3dafe",
+            Network::Westend => "e143f23803ac50e8f6f8e62695d1ce9e4e1d68aa36c1cd2cfd15340213f3423e",
+            Network::Paseo => "77afd6190f1554ad45fd0d31aee62aacc33c6db0ea801129acb813f913e0764f",
+            Network::Ipfs => return None,
+        };
+
+        let mut genesis_hash = [0; 32];
+        hex::decode_to_slice(hash, &mut genesis_hash).expect("valid genesis hash; qed");
+        Some(genesis_hash)
+    }
+
+    /// Bootnodes of the relay chain, taken from its chain spec.
+    ///
+    /// The IPFS bootnode is the only Ed25519 one of the kubo defaults: litep2p can't complete the
+    /// handshake with the RSA ones. Its `/dnsaddr` entry is given as `/dns4`, which litep2p dials.
+    pub fn bootnodes(&self) -> &'static [&'static str] {
+        match self {
+            Network::Polkadot => &[
+                "/dns/polkadot-bootnode-0.polkadot.io/tcp/30333/p2p/12D3KooWSz8r2WyCdsfWHgPyvD8GKQdJ1UAiRmrcrs8sQB3fe2KU",
+            ],
+            Network::Kusama => &[
+                "/dns/kusama-bootnode-0.polkadot.io/tcp/30333/p2p/12D3KooWSueCPH3puP2PcvqPJdNaDNF3jMZjtJtDiSy35pWrbt5h",
+                "/dns/kusama-bootnode-1.polkadot.io/tcp/30333/p2p/12D3KooWQKqane1SqWJNWMQkbia9qiMWXkcHtAdfW5eVF8hbwEDw",
+            ],
+            Network::Westend => &[
+                "/dns/westend-bootnode-0.polkadot.io/tcp/30333/p2p/12D3KooWKer94o1REDPtAhjtYR4SdLehnSrN8PEhBnZm5NBoCrMC",
+            ],
+            Network::Paseo => &[
+                "/dns/paseo.bootnode.amforc.com/tcp/29999/wss/p2p/12D3KooWFD81HC9memUwuGMLvhDDEfmXjn6jC4n7zyNs3vToXapS",
+            ],
+            Network::Ipfs => &[
+                "/dns4/va1.bootstrap.libp2p.io/tcp/4001/p2p/12D3KooWKnDdG3iXw9eTFijk3EWSunZcFi54Zka4wmtqtt6rPxc8",
+            ],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::QueryArgs;
+
+    const POLKADOT_GENESIS: &str =
+        "0x91b171bb158e2d3848fa23a9f1c25182fb8e20313b2c1eb49219da7a70ce90c3";
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        query: QueryArgs,
+    }
+
+    #[test]
+    fn parses_genesis_hash() {
+        let hash = parse_genesis_hash(POLKADOT_GENESIS).unwrap();
+        assert_eq!(Some(hash), Network::Polkadot.genesis_hash());
+        assert_eq!(
+            parse_genesis_hash(POLKADOT_GENESIS.trim_start_matches("0x")).unwrap(),
+            hash
+        );
+        assert!(parse_genesis_hash("0x91b1").is_err());
+        assert!(parse_genesis_hash("not hex").is_err());
+    }
+
+    #[test]
+    fn genesis_hash_flag_derives_protocol() {
+        let cli = Cli::try_parse_from(["dht-inspect", "--genesis-hash", POLKADOT_GENESIS]).unwrap();
+        let hash = cli.query.genesis_hash.unwrap();
+        assert_eq!(
+            kademlia_protocol_name(&hash, None),
+            format!("/{}/kad", POLKADOT_GENESIS.trim_start_matches("0x"))
+        );
+        assert_eq!(
+            kademlia_protocol_name(&hash, Some("fork")),
+            format!("/{}/fork/kad", POLKADOT_GENESIS.trim_start_matches("0x"))
+        );
+    }
+}
@@ -0,0 +1,178 @@
+use std::{
+    collections::HashSet,
+    net::IpAddr,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context};
+use multiaddr::{Multiaddr, Protocol};
+
+use crate::{address::is_private, json::Json, json_output};
+
+/// Compare the peers of saved crawls of several networks.
+#[derive(clap::Args, Debug)]
+pub struct OverlapArgs {
+    /// JSON output of `crawl --list-peers` or `explore`, one file per network.
+    #[arg(value_name = "PATH", num_args = 2.., required = true)]
+    crawls: Vec<PathBuf>,
+}
+
+/// Peers and public IPs of a saved crawl.
+struct Participants {
+    name: String,
+    protocol: Option<String>,
+    peers: HashSet<String>,
+    ips: HashSet<IpAddr>,
+}
+
+impl Participants {
+    /// Read the peer list of the crawl saved at `path`.
+    fn read(path: &Path) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let document = Json::parse(&content)
+            .map_err(|error| anyhow!("failed to parse {}: {error}", path.display()))?;
+        let discovered = document
+            .get("discovered")
+            .and_then(Json::as_array)
+            .ok_or_else(|| {
+                anyhow!(
+                    "no peer list in {}, save the JSON output of `crawl --list-peers` or `explore`",
+                    path.display()
+                )
+            })?;
+
+        let mut peers = HashSet::new();
+        let mut ips = HashSet::new();
+        for peer in discovered {
+            if let Some(peer_id) = peer.get("peer_id").and_then(Json::as_str) {
+                peers.insert(peer_id.to_string());
+            }
+            let addresses = peer
+                .get("addresses")
+                .and_then(Json::as_array)
+                .unwrap_or_default();
+            ips.extend(
+                addresses
+                    .iter()
+                    .filter_map(Json::as_str)
+                    .filter_map(|address| address.parse::<Multiaddr>().ok())
+                    .filter(|address| !is_private(address))
+                    .filter_map(|address| match address.iter().next() {
+                        Some(Protocol::Ip4(ip)) => Some(IpAddr::V4(ip)),
+                        Some(Protocol::Ip6(ip)) => Some(IpAddr::V6(ip)),
+                        _ => None,
+                    }),
+            );
+        }
+
+        Ok(Self {
+            name: path.display().to_string(),
+            protocol: document
+                .get("protocol")
+                .and_then(Json::as_str)
+                .map(String::from),
+            peers,
+            ips,
+        })
+    }
+}
+
+/// Share of `part` in `total`, in percent.
+fn percent(part: usize, total: usize) -> f64 {
+    if total == 0 {
+        return 0.0;
+    }
+
+    part as f64 * 100.0 / total as f64
+}
+
+/// Report the peer IDs and public IPs every pair of crawls has in common.
+///
+/// Only IPs of `/ip4` and `/ip6` addresses are compared. Peers reachable only via DNS names are
+/// counted by peer ID alone.
+pub fn run(args: OverlapArgs) -> anyhow::Result<()> {
+    let networks = args
+        .crawls
+        .iter()
+        .map(|path| Participants::read(path))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let mut pairs = Vec::new();
+    for (i, a) in networks.iter().enumerate() {
+        for b in &networks[i + 1..] {
+            let peers = a.peers.intersection(&b.peers).count();
+            let ips = a.ips.intersection(&b.ips).count();
+            pairs.push((a, b, peers, ips));
+        }
+    }
+    let all_peers = networks
+        .iter()
+        .skip(1)
+        .fold(networks[0].peers.clone(), |shared, network| {
+            shared.intersection(&network.peers).cloned().collect()
+        });
+
+    if json_output() {
+        let document = Json::object()
+            .field(
+                "networks",
+                networks
+                    .iter()
+                    .map(|network| {
+                        Json::object()
+                            .field("name", network.name.as_str())
+                            .field("protocol", network.protocol.clone())
+                            .field("peers", network.peers.len())
+                            .field("ips", network.ips.len())
+                    })
+                    .collect::<Vec<_>>(),
+            )
+            .field(
+                "pairs",
+                pairs
+                    .iter()
+                    .map(|(a, b, peers, ips)| {
+                        Json::object()
+                            .field("a", a.name.as_str())
+                            .field("b", b.name.as_str())
+                            .field("shared_peers", *peers)
+                            .field("shared_ips", *ips)
+                    })
+                    .collect::<Vec<_>>(),
+            )
+            .field("peers_in_all", all_peers.len());
+        println!("{document}");
+        return Ok(());
+    }
+
+    for network in &networks {
+        println!(
+            "{} ({}): {} peers, {} public IPs",
+            network.name,
+            network.protocol.as_deref().unwrap_or("unknown protocol"),
+            network.peers.len(),
+            network.ips.len()
+        );
+    }
+    println!();
+    for (a, b, peers, ips) in &pairs {
+        println!("{} and {}:", a.name, b.name);
+        println!(
+            "  shared peer IDs: {peers} ({:.1}% / {:.1}%)",
+            percent(*peers, a.peers.len()),
+            percent(*peers, b.peers.len())
+        );
+        println!(
+            "  shared public IPs: {ips} ({:.1}% / {:.1}%)",
+            percent(*ips, a.ips.len()),
+            percent(*ips, b.ips.len())
+        );
+    }
+    if networks.len() > 2 {
+        println!();
+        println!("Peer IDs in all networks: {}", all_peers.len());
+    }
+
+    Ok(())
+}
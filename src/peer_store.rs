@@ -0,0 +1,339 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{File, OpenOptions},
+    hash::{DefaultHasher, Hash, Hasher},
+    io::{self, BufRead, BufReader, BufWriter, Seek, SeekFrom, Write},
+    path::PathBuf,
+    str::FromStr,
+};
+
+use anyhow::{anyhow, Context};
+use litep2p::PeerId;
+use multiaddr::Multiaddr;
+
+/// Estimated memory of a peer entry without its addresses, in bytes.
+const PEER_OVERHEAD: usize = 160;
+
+/// Estimated memory of an address entry on top of its encoded length, in bytes.
+const ADDRESS_OVERHEAD: usize = 48;
+
+/// Peers and their addresses, moved to a spill file on disk once they take more memory than a
+/// cap.
+///
+/// Spilled peers are deduplicated by 64-bit fingerprints of their IDs, so a new peer is mistaken
+/// for a spilled one with a probability of about `spilled / 2^64`. Addresses of spilled peers
+/// found later are dropped.
+pub struct PeerStore {
+    peers: HashMap<PeerId, HashSet<Multiaddr>>,
+    /// Estimated memory held by `peers`, in bytes.
+    memory: usize,
+    cap: Option<usize>,
+    spill: Option<Spill>,
+}
+
+/// Peers written to disk, one `<peer id> <address>...` line each.
+///
+/// The file is created with a random name and removed right away where the platform allows it,
+/// so it doesn't outlive the process however it exits.
+struct Spill {
+    /// Path of the file if it couldn't be removed while open.
+    path: Option<PathBuf>,
+    file: BufWriter<File>,
+    fingerprints: HashSet<u64>,
+}
+
+impl Spill {
+    /// Create a new spill file in the temporary directory.
+    fn create() -> anyhow::Result<Self> {
+        let dir = std::env::temp_dir();
+        let (path, file) = loop {
+            let path = dir.join(format!("dht-inspect-{:016x}.peers", rand::random::<u64>()));
+            let mut options = OpenOptions::new();
+            options.read(true).write(true).create_new(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            match options.open(&path) {
+                Ok(file) => break (path, file),
+                Err(error) if error.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(error) => {
+                    return Err(error)
+                        .with_context(|| format!("failed to create {}", path.display()))
+                }
+            }
+        };
+        progress!(
+            "Peers exceed the memory cap, spilling them to a temporary file in {}",
+            dir.display()
+        );
+
+        Ok(Self {
+            path: std::fs::remove_file(&path).is_err().then_some(path),
+            file: BufWriter::new(file),
+            fingerprints: HashSet::new(),
+        })
+    }
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        if let Some(path) = &self.path {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+impl PeerStore {
+    /// Create new [`PeerStore`] keeping at most about `cap` bytes of peers in memory, or all of
+    /// them if `None`.
+    pub fn new(cap: Option<usize>) -> Self {
+        Self {
+            peers: HashMap::new(),
+            memory: 0,
+            cap,
+            spill: None,
+        }
+    }
+
+    /// Add `addresses` of `peer`. Returns whether the peer was not seen before.
+    pub fn insert(
+        &mut self,
+        peer: PeerId,
+        addresses: impl IntoIterator<Item = Multiaddr>,
+    ) -> anyhow::Result<bool> {
+        if let Some(known) = self.peers.get_mut(&peer) {
+            for address in addresses {
+                let size = address.len() + ADDRESS_OVERHEAD;
+                if known.insert(address) {
+                    self.memory += size;
+                }
+            }
+            return Ok(false);
+        }
+        if self.contains(&peer) {
+            return Ok(false);
+        }
+
+        let addresses: HashSet<_> = addresses.into_iter().collect();
+        self.memory += PEER_OVERHEAD
+            + addresses
+                .iter()
+                .map(|address| address.len() + ADDRESS_OVERHEAD)
+                .sum::<usize>();
+        self.peers.insert(peer, addresses);
+        if self.cap.is_some_and(|cap| self.memory > cap) {
+            self.spill()?;
+        }
+
+        Ok(true)
+    }
+
+    /// Whether `peer` was added, possibly mistaking it for a spilled peer.
+    pub fn contains(&self, peer: &PeerId) -> bool {
+        self.peers.contains_key(peer)
+            || self
+                .spill
+                .as_ref()
+                .is_some_and(|spill| spill.fingerprints.contains(&fingerprint(peer)))
+    }
+
+    /// Number of peers added.
+    pub fn len(&self) -> usize {
+        self.peers.len() + self.spilled()
+    }
+
+    /// Number of peers moved to disk.
+    pub fn spilled(&self) -> usize {
+        self.spill
+            .as_ref()
+            .map_or(0, |spill| spill.fingerprints.len())
+    }
+
+    /// Estimated memory of the peers held in memory, in bytes.
+    #[cfg(test)]
+    pub fn memory(&self) -> usize {
+        self.memory
+    }
+
+    /// Peers held in memory. All of them unless some were spilled.
+    pub fn in_memory(&self) -> &HashMap<PeerId, HashSet<Multiaddr>> {
+        &self.peers
+    }
+
+    /// Call `visit` for every peer with its addresses, reading the spilled ones back from disk one
+    /// at a time.
+    pub fn for_each(&mut self, mut visit: impl FnMut(&PeerId, &[Multiaddr])) -> anyhow::Result<()> {
+        for (peer, addresses) in &self.peers {
+            visit(peer, &addresses.iter().cloned().collect::<Vec<_>>());
+        }
+        let Some(spill) = &mut self.spill else {
+            return Ok(());
+        };
+
+        // The clone shares the file offset, which is moved back to the end for later writes.
+        spill
+            .file
+            .flush()
+            .context("failed to write the spill file")?;
+        let mut file = spill
+            .file
+            .get_ref()
+            .try_clone()
+            .context("failed to read the spill file")?;
+        file.seek(SeekFrom::Start(0))
+            .context("failed to read the spill file")?;
+        let result = read_spill(file, &mut visit);
+        spill
+            .file
+            .seek(SeekFrom::End(0))
+            .context("failed to write the spill file")?;
+
+        result
+    }
+
+    /// Move the peers held in memory to the spill file, creating it on first use.
+    fn spill(&mut self) -> anyhow::Result<()> {
+        let spill = match &mut self.spill {
+            Some(spill) => spill,
+            None => self.spill.insert(Spill::create()?),
+        };
+
+        for (peer, addresses) in self.peers.drain() {
+            let mut line = peer.to_string();
+            for address in addresses {
+                line.push(' ');
+                line.push_str(&address.to_string());
+            }
+            writeln!(spill.file, "{line}").context("failed to write the spill file")?;
+            spill.fingerprints.insert(fingerprint(&peer));
+        }
+        self.memory = 0;
+
+        Ok(())
+    }
+}
+
+/// Call `visit` for every peer line of a spill file.
+fn read_spill(file: File, visit: &mut impl FnMut(&PeerId, &[Multiaddr])) -> anyhow::Result<()> {
+    for line in BufReader::new(file).lines() {
+        let line = line.context("failed to read the spill file")?;
+        let mut fields = line.split(' ');
+        let peer = fields
+            .next()
+            .and_then(|peer| PeerId::from_str(peer).ok())
+            .ok_or_else(|| anyhow!("corrupted spill file"))?;
+        let addresses = fields
+            .map(Multiaddr::from_str)
+            .collect::<Result<Vec<_>, _>>()
+            .context("corrupted spill file")?;
+        visit(&peer, &addresses);
+    }
+
+    Ok(())
+}
+
+/// 64-bit fingerprint of `peer` standing in for its ID where only membership matters.
+pub fn fingerprint(peer: &PeerId) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    peer.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(port: u16) -> Multiaddr {
+        format!("/ip4/1.1.1.1/tcp/{port}").parse().unwrap()
+    }
+
+    fn collect(store: &mut PeerStore) -> HashMap<PeerId, HashSet<Multiaddr>> {
+        let mut peers = HashMap::new();
+        store
+            .for_each(|peer, addresses| {
+                let previous = peers.insert(*peer, addresses.iter().cloned().collect());
+                assert!(previous.is_none(), "{peer} visited twice");
+            })
+            .unwrap();
+        peers
+    }
+
+    #[test]
+    fn merges_addresses_in_memory() {
+        let mut store = PeerStore::new(None);
+        let peer = PeerId::random();
+
+        assert!(store.insert(peer, [address(1)]).unwrap());
+        assert!(!store.insert(peer, [address(1), address(2)]).unwrap());
+        assert!(store.contains(&peer));
+        assert!(!store.contains(&PeerId::random()));
+        assert_eq!(store.len(), 1);
+        assert_eq!(store.spilled(), 0);
+        assert_eq!(
+            collect(&mut store),
+            HashMap::from([(peer, HashSet::from([address(1), address(2)]))])
+        );
+    }
+
+    #[test]
+    fn spills_peers_over_the_cap() {
+        // Every peer exceeds the cap, so every insert spills.
+        let mut store = PeerStore::new(Some(1));
+        let peers: Vec<_> = (0..10).map(|_| PeerId::random()).collect();
+        for (port, peer) in peers.iter().enumerate() {
+            assert!(store.insert(*peer, [address(port as u16)]).unwrap());
+        }
+
+        assert_eq!(store.len(), 10);
+        assert_eq!(store.spilled(), 10);
+        assert!(store.in_memory().is_empty());
+        let expected: HashMap<_, _> = peers
+            .iter()
+            .enumerate()
+            .map(|(port, peer)| (*peer, HashSet::from([address(port as u16)])))
+            .collect();
+        assert_eq!(collect(&mut store), expected);
+
+        // Spilled peers are deduplicated and their new addresses dropped.
+        assert!(!store.insert(peers[0], [address(100)]).unwrap());
+        assert!(peers.iter().all(|peer| store.contains(peer)));
+        assert_eq!(store.len(), 10);
+
+        // Writes after reading the file back append to it.
+        let peer = PeerId::random();
+        assert!(store.insert(peer, [address(100)]).unwrap());
+        let mut expected = expected;
+        expected.insert(peer, HashSet::from([address(100)]));
+        assert_eq!(collect(&mut store), expected);
+    }
+
+    #[test]
+    fn visits_memory_and_spilled_peers() {
+        let first = PeerId::random();
+        let cap = PEER_OVERHEAD + address(1).len() + ADDRESS_OVERHEAD;
+        let mut store = PeerStore::new(Some(cap));
+        store.insert(first, [address(1)]).unwrap();
+        assert_eq!(store.spilled(), 0);
+
+        // The second peer takes the store over the cap and spills both.
+        let second = PeerId::random();
+        store.insert(second, [address(2)]).unwrap();
+        assert_eq!(store.spilled(), 2);
+
+        let third = PeerId::random();
+        store.insert(third, [address(3)]).unwrap();
+        assert_eq!(store.in_memory().len(), 1);
+        assert_eq!(
+            collect(&mut store).into_keys().collect::<HashSet<_>>(),
+            HashSet::from([first, second, third])
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn removes_spill_file_right_away() {
+        let mut store = PeerStore::new(Some(1));
+        store.insert(PeerId::random(), [address(1)]).unwrap();
+
+        assert_eq!(store.spill.as_ref().unwrap().path, None);
+    }
+}
@@ -0,0 +1,140 @@
+use std::{collections::BTreeSet, ops::Bound};
+
+/// Shortest prefix [`PrefixIndex::abbreviate`] returns, so abbreviations stay recognizable.
+const MIN_ABBREVIATION: usize = 8;
+
+/// Most matches of an ambiguous prefix [`PrefixIndex::print_resolution`] lists.
+const MAX_LISTED: usize = 10;
+
+/// Outcome of resolving a prefix.
+#[derive(Debug, PartialEq)]
+pub enum Resolution<'a> {
+    NotFound,
+    Unique(&'a str),
+    /// Every match, sorted.
+    Ambiguous(Vec<&'a str>),
+}
+
+/// Sorted peer IDs and hex keys, to expand the short prefixes typed on the console.
+#[derive(Default)]
+pub struct PrefixIndex {
+    entries: BTreeSet<String>,
+}
+
+impl PrefixIndex {
+    pub fn insert(&mut self, entry: String) {
+        self.entries.insert(entry);
+    }
+
+    /// Expand `prefix` to the entries starting with it.
+    pub fn resolve(&self, prefix: &str) -> Resolution<'_> {
+        let mut matches: Vec<_> = self
+            .entries
+            .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(|entry| entry.starts_with(prefix))
+            .map(String::as_str)
+            .collect();
+        match matches.len() {
+            0 => Resolution::NotFound,
+            1 => Resolution::Unique(matches.remove(0)),
+            _ => Resolution::Ambiguous(matches),
+        }
+    }
+
+    /// Shortest prefix of `entry`, of at least [`MIN_ABBREVIATION`] characters, that no other
+    /// entry starts with.
+    ///
+    /// Only the sorted neighbors of `entry` can share a longer prefix with it than the others.
+    pub fn abbreviate<'a>(&self, entry: &'a str) -> &'a str {
+        let common = |other: &str| {
+            entry
+                .chars()
+                .zip(other.chars())
+                .take_while(|(a, b)| a == b)
+                .count()
+        };
+        let before = self
+            .entries
+            .range::<str, _>((Bound::Unbounded, Bound::Excluded(entry)))
+            .next_back()
+            .map_or(0, |other| common(other));
+        let after = self
+            .entries
+            .range::<str, _>((Bound::Excluded(entry), Bound::Unbounded))
+            .next()
+            .map_or(0, |other| common(other));
+        let length = (before.max(after) + 1).max(MIN_ABBREVIATION);
+
+        entry
+            .char_indices()
+            .nth(length)
+            .map_or(entry, |(end, _)| &entry[..end])
+    }
+
+    /// Print what `prefix` expands to.
+    pub fn print_resolution(&self, prefix: &str) {
+        match self.resolve(prefix) {
+            Resolution::NotFound => progress!("{prefix}: no peer or key seen starts with it"),
+            Resolution::Unique(entry) => progress!("{prefix}: {entry}"),
+            Resolution::Ambiguous(matches) => {
+                progress!("{prefix}: ambiguous, {} matches", matches.len());
+                for entry in matches.iter().take(MAX_LISTED) {
+                    progress!("  {}… {entry}", self.abbreviate(entry));
+                }
+                if matches.len() > MAX_LISTED {
+                    progress!("  and {} more", matches.len() - MAX_LISTED);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index(entries: &[&str]) -> PrefixIndex {
+        let mut index = PrefixIndex::default();
+        for entry in entries {
+            index.insert(entry.to_string());
+        }
+        index
+    }
+
+    #[test]
+    fn resolves_prefixes() {
+        let index = index(&["12D3KooWAbc", "12D3KooWAbd", "12D3KooWXyz", "0a1b2c"]);
+        assert_eq!(
+            index.resolve("12D3KooWX"),
+            Resolution::Unique("12D3KooWXyz")
+        );
+        assert_eq!(index.resolve("0a"), Resolution::Unique("0a1b2c"));
+        assert_eq!(
+            index.resolve("12D3KooWAb"),
+            Resolution::Ambiguous(vec!["12D3KooWAbc", "12D3KooWAbd"])
+        );
+        assert_eq!(
+            index.resolve("12D3KooWAbc"),
+            Resolution::Unique("12D3KooWAbc")
+        );
+        assert_eq!(index.resolve("12D3KooWB"), Resolution::NotFound);
+        assert!(matches!(index.resolve(""), Resolution::Ambiguous(matches) if matches.len() == 4));
+    }
+
+    #[test]
+    fn abbreviates_entries() {
+        let index = index(&[
+            "12D3KooWAbc1",
+            "12D3KooWAbd2",
+            "12D3KooWXyz3",
+            "0a1b2c3d4e5f",
+        ]);
+        assert_eq!(index.abbreviate("12D3KooWAbc1"), "12D3KooWAbc");
+        assert_eq!(index.abbreviate("12D3KooWAbd2"), "12D3KooWAbd");
+        assert_eq!(index.abbreviate("12D3KooWXyz3"), "12D3KooWX");
+        assert_eq!(index.abbreviate("0a1b2c3d4e5f"), "0a1b2c3d");
+        // An entry that is a prefix of another can't be abbreviated.
+        let index = self::index(&["0a1b2c3d4e", "0a1b2c3d4e5f"]);
+        assert_eq!(index.abbreviate("0a1b2c3d4e"), "0a1b2c3d4e");
+    }
+}
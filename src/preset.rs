@@ -0,0 +1,122 @@
+use std::{fmt, time::Duration};
+
+use clap::ValueEnum;
+use rand::Rng;
+
+/// Bundles of Kademlia and transport parameters.
+///
+/// The lookup parallelism (alpha) is fixed to 3 by litep2p and is not part of the presets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Preset {
+    /// litep2p defaults, as used by Polkadot SDK nodes.
+    Substrate,
+    /// More patient timeouts, closer to rust-libp2p and go-libp2p defaults.
+    Libp2pDefault,
+    /// Larger lookups with short timeouts and many parallel dials.
+    Aggressive,
+}
+
+/// Query parameters.
+#[derive(Debug, Clone)]
+pub struct Settings {
+    /// Kademlia replication factor (k).
+    pub replication_factor: usize,
+    /// Transport connection open timeout.
+    pub connection_open_timeout: Duration,
+    /// Substream open timeout.
+    pub substream_open_timeout: Duration,
+    /// Maximum number of parallel dials.
+    pub max_parallel_dials: usize,
+    /// Republish interval of the local provider records. litep2p's default if `None`.
+    pub provider_refresh_interval: Option<Duration>,
+}
+
+/// Parameters that can be randomized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Parameter {
+    /// Replication factor, 5-50.
+    K,
+    /// Connection open timeout of 2-30 s and substream open timeout of 1-15 s.
+    Timeouts,
+    /// Parallel dials, 1-64.
+    Dials,
+}
+
+impl Settings {
+    /// Copy of the settings with `parameters` sampled uniformly from their ranges.
+    pub fn randomized(&self, parameters: &[Parameter]) -> Settings {
+        let mut rng = rand::thread_rng();
+        let mut settings = self.clone();
+
+        for parameter in parameters {
+            match parameter {
+                Parameter::K => settings.replication_factor = rng.gen_range(5..=50),
+                Parameter::Timeouts => {
+                    settings.connection_open_timeout = Duration::from_secs(rng.gen_range(2..=30));
+                    settings.substream_open_timeout = Duration::from_secs(rng.gen_range(1..=15));
+                }
+                Parameter::Dials => settings.max_parallel_dials = rng.gen_range(1..=64),
+            }
+        }
+
+        settings
+    }
+}
+
+impl fmt::Display for Settings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "k={}, timeouts={}s/{}s, dials={}",
+            self.replication_factor,
+            self.connection_open_timeout.as_secs(),
+            self.substream_open_timeout.as_secs(),
+            self.max_parallel_dials,
+        )
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Preset::Substrate.settings()
+    }
+}
+
+impl Preset {
+    /// All presets, in the order they are benchmarked.
+    pub const ALL: [Preset; 3] = [Preset::Substrate, Preset::Libp2pDefault, Preset::Aggressive];
+
+    /// Parameters of the preset.
+    pub fn settings(&self) -> Settings {
+        match self {
+            Preset::Substrate => Settings {
+                replication_factor: 20,
+                connection_open_timeout: Duration::from_secs(10),
+                substream_open_timeout: Duration::from_secs(5),
+                max_parallel_dials: 8,
+                provider_refresh_interval: None,
+            },
+            Preset::Libp2pDefault => Settings {
+                replication_factor: 20,
+                connection_open_timeout: Duration::from_secs(20),
+                substream_open_timeout: Duration::from_secs(10),
+                max_parallel_dials: 16,
+                provider_refresh_interval: None,
+            },
+            Preset::Aggressive => Settings {
+                replication_factor: 30,
+                connection_open_timeout: Duration::from_secs(5),
+                substream_open_timeout: Duration::from_secs(3),
+                max_parallel_dials: 32,
+                provider_refresh_interval: None,
+            },
+        }
+    }
+}
+
+impl fmt::Display for Preset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = self.to_possible_value().expect("no skipped variants; qed");
+        f.write_str(value.get_name())
+    }
+}
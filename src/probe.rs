@@ -0,0 +1,95 @@
+use std::{path::PathBuf, time::Duration};
+
+use anyhow::{anyhow, Context};
+use futures::StreamExt;
+use litep2p::{
+    protocol::request_response::{
+        ConfigBuilder as RequestResponseConfigBuilder, DialOptions, RequestResponseEvent,
+    },
+    Litep2p, PeerId,
+};
+use multiaddr::Multiaddr;
+
+use crate::{parse_multiaddress, preset::Settings, transport_config, SocketOptions};
+
+/// Maximum size of the probed request and response.
+const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+/// Send a request over an arbitrary request-response protocol and dump the reply.
+#[derive(clap::Args, Debug)]
+pub struct ProbeArgs {
+    /// Request-response protocol name.
+    #[arg(long, value_name = "PROTOCOL")]
+    protocol: String,
+    /// Peer multiaddress.
+    #[arg(long, value_name = "MULTIADDR", value_parser = parse_multiaddress)]
+    peer: (PeerId, Multiaddr),
+    /// File with the raw request payload. The request is empty if not set.
+    #[arg(long, value_name = "PATH")]
+    payload: Option<PathBuf>,
+    /// Write the raw response to this file instead of printing it as hex.
+    #[arg(long, value_name = "PATH")]
+    response_file: Option<PathBuf>,
+    /// Request timeout in seconds.
+    #[arg(long, value_name = "SECS", default_value_t = 10)]
+    timeout: u64,
+}
+
+/// Run the probe.
+pub async fn run(args: ProbeArgs, socket: &SocketOptions) -> anyhow::Result<()> {
+    let payload = match &args.payload {
+        Some(path) => {
+            std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?
+        }
+        None => Vec::new(),
+    };
+
+    let (config, mut handle) = RequestResponseConfigBuilder::new(args.protocol.clone().into())
+        .with_max_size(MAX_MESSAGE_SIZE)
+        .with_timeout(Duration::from_secs(args.timeout))
+        .build();
+    let mut litep2p = Litep2p::new(
+        transport_config(&Settings::default(), socket)
+            .with_request_response_protocol(config)
+            .build(),
+    )
+    .context("litep2p initialization error")?;
+
+    let (peer, address) = args.peer;
+    litep2p.add_known_address(peer, std::iter::once(address));
+
+    println!(
+        "Sending {} byte request over {}...",
+        payload.len(),
+        args.protocol
+    );
+    handle
+        .send_request(peer, payload, DialOptions::Dial)
+        .await
+        .context("failed to send request")?;
+
+    let response = loop {
+        tokio::select! {
+            _ = litep2p.next_event() => {},
+            event = handle.next() => match event {
+                Some(RequestResponseEvent::ResponseReceived { response, .. }) => break response,
+                Some(RequestResponseEvent::RequestFailed { error, .. }) => {
+                    return Err(anyhow!("request failed: {error:?}"))
+                },
+                Some(RequestResponseEvent::RequestReceived { request_id, .. }) => {
+                    handle.reject_request(request_id);
+                },
+                None => return Err(anyhow!("request-response protocol terminated")),
+            },
+        }
+    };
+
+    println!("Received {} byte response", response.len());
+    match &args.response_file {
+        Some(path) => std::fs::write(path, &response)
+            .with_context(|| format!("failed to write {}", path.display()))?,
+        None => println!("{}", hex::encode(&response)),
+    }
+
+    Ok(())
+}
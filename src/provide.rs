@@ -0,0 +1,405 @@
+use std::time::Duration;
+
+use anyhow::anyhow;
+use futures::StreamExt;
+use litep2p::{
+    protocol::libp2p::kademlia::{KademliaEvent, RecordKey as KademliaKey},
+    PeerId,
+};
+use multiaddr::Multiaddr;
+
+use crate::{
+    json::Json, json_output, kademlia_protocol, known_peers, parse_key, preset::Settings,
+    print_document, print_protocol_hint, print_statistics, query_dht, retry::parse_duration,
+    run_json, signal, Query, QueryArgs, QueryRun,
+};
+
+/// Announce the local node as a content provider for a key.
+#[derive(clap::Args, Debug)]
+pub struct AddProviderArgs {
+    /// Key (hex or CID) to provide.
+    #[arg(long, value_name = "KEY", value_parser = parse_key)]
+    key: KademliaKey,
+    /// Public address to advertise in the provider record. Can be repeated. The record carries no
+    /// addresses if not set, since the tool doesn't listen.
+    #[arg(long, value_name = "MULTIADDR")]
+    public_address: Vec<Multiaddr>,
+    /// How long to stay online after publishing before checking the record, e.g. 1m.
+    #[arg(long, value_name = "DURATION", default_value = "1m", value_parser = parse_duration)]
+    online: Duration,
+    /// After the check, stop providing, wait this long for the record to expire on the peers, e.g.
+    /// 49h for the 48 h TTL of libp2p networks, and check that the network no longer returns it.
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    audit_expiry: Option<Duration>,
+}
+
+/// Shortest republish interval accepted by keep-providing.
+const MIN_REPUBLISH: Duration = Duration::from_secs(10 * 60);
+
+/// How long after every publication keep-providing checks that the network returns the record.
+const REPUBLISH_CHECK_DELAY: Duration = Duration::from_secs(60);
+
+/// Provider record TTL of Substrate and libp2p peers.
+const PROVIDER_TTL: Duration = Duration::from_secs(48 * 60 * 60);
+
+/// Keep providing a key, republishing the provider record periodically.
+#[derive(clap::Args, Debug)]
+pub struct KeepProvidingArgs {
+    /// Key (hex or CID) to provide.
+    #[arg(long, value_name = "KEY", value_parser = parse_key)]
+    key: KademliaKey,
+    /// Public address to advertise in the provider record. Can be repeated.
+    #[arg(long, value_name = "MULTIADDR")]
+    public_address: Vec<Multiaddr>,
+    /// Republish interval of the provider record, at least 10m.
+    #[arg(long, value_name = "DURATION", default_value = "12h", value_parser = parse_duration)]
+    republish: Duration,
+}
+
+/// Publish the provider record and check whether the network returns it.
+///
+/// The record is published after a GET_PROVIDERS lookup of the key that fills the routing table
+/// around it. litep2p doesn't report delivery of ADD_PROVIDER requests, nor which peer returned
+/// which provider, so after staying online the key is queried again to check whether the local
+/// node is among the providers returned.
+pub async fn run(args: AddProviderArgs, query: &QueryArgs) -> anyhow::Result<()> {
+    let known_peers = known_peers(query).await?;
+    let kad_proto = kademlia_protocol(query);
+    let settings = query.preset.settings();
+
+    let dht_query = Query::Providers(args.key.clone());
+    let mut run = query_dht(query, &dht_query, &kad_proto, known_peers, &settings, None).await?;
+    let document = run_json(&dht_query, &kad_proto, &run);
+    if run.result.is_err() && run.fan_out.responses() == 0 {
+        if json_output() {
+            print_document(document);
+        } else {
+            print_statistics(&run, &settings);
+            print_protocol_hint(query, &run.fan_out, &kad_proto);
+        }
+        return Err(anyhow!("no peer responded to the key lookup"));
+    }
+    if !json_output() {
+        print_statistics(&run, &settings);
+    }
+    progress!("Providers found before publishing: {}", run.providers.len());
+
+    for address in &args.public_address {
+        run.litep2p
+            .public_addresses()
+            .add_address(address.clone())
+            .map_err(|error| anyhow!("invalid public address {address}: {error:?}"))?;
+    }
+
+    progress!(
+        "Publishing provider record, staying online for {} s...",
+        args.online.as_secs()
+    );
+    run.kademlia_handle.start_providing(args.key.clone()).await;
+    run.drive_until(tokio::time::Instant::now() + args.online)
+        .await?;
+
+    progress!("Checking the provider record...");
+    let local_peer_id = *run.litep2p.local_peer_id();
+    let providers = find_providers(&mut run, &args.key).await?;
+    if json_output() {
+        let expired = match args.audit_expiry {
+            Some(wait) => audit_expiry(&mut run, &args.key, wait)
+                .await?
+                .map(|returned| !returned),
+            None => None,
+        };
+        print_document(audit_json(
+            document,
+            &local_peer_id,
+            providers.as_deref(),
+            expired,
+        ));
+        return Ok(());
+    }
+    match providers {
+        Some(providers) => {
+            match providers
+                .iter()
+                .find(|provider| provider.0 == local_peer_id)
+            {
+                Some((_, addresses)) => println!(
+                    "Provider record found in the DHT with {} addresses",
+                    addresses.len()
+                ),
+                None => println!("Provider record not returned by the DHT"),
+            }
+            println!("Providers found after publishing: {}", providers.len());
+            if providers.len() > query.limits.max_providers() {
+                println!(
+                    "Warning: more than {} providers, peers may not keep the record",
+                    query.limits.max_providers()
+                );
+            }
+        }
+        None => println!("GET_PROVIDERS query failed, provider record not confirmed"),
+    }
+
+    if let Some(wait) = args.audit_expiry {
+        match audit_expiry(&mut run, &args.key, wait).await? {
+            Some(true) => println!(
+                "Provider record still returned {} s after it was last published: \
+                 some peers don't expire provider records",
+                wait.as_secs()
+            ),
+            Some(false) => println!("Provider record expired"),
+            None => println!("GET_PROVIDERS query failed, expiry not verified"),
+        }
+    }
+
+    Ok(())
+}
+
+/// Provide the key until interrupted, checking after every publication whether the network
+/// returns the record.
+///
+/// litep2p republishes the record itself at the configured interval, so the checks are scheduled
+/// to follow its publications.
+pub async fn run_keep_providing(args: KeepProvidingArgs, query: &QueryArgs) -> anyhow::Result<()> {
+    if args.republish < MIN_REPUBLISH {
+        return Err(anyhow!(
+            "republish interval must be at least {} s",
+            MIN_REPUBLISH.as_secs()
+        ));
+    }
+    if args.republish >= PROVIDER_TTL {
+        progress!(
+            "Warning: peers drop provider records after {} h, before they are republished",
+            PROVIDER_TTL.as_secs() / 3600
+        );
+    }
+
+    let known_peers = known_peers(query).await?;
+    let kad_proto = kademlia_protocol(query);
+    let settings = Settings {
+        provider_refresh_interval: Some(args.republish),
+        ..query.preset.settings()
+    };
+
+    let dht_query = Query::Providers(args.key.clone());
+    let mut run = query_dht(query, &dht_query, &kad_proto, known_peers, &settings, None).await?;
+    if run.result.is_err() && run.fan_out.responses() == 0 {
+        if !json_output() {
+            print_statistics(&run, &settings);
+            print_protocol_hint(query, &run.fan_out, &kad_proto);
+        }
+        return Err(anyhow!("no peer responded to the key lookup"));
+    }
+
+    for address in &args.public_address {
+        run.litep2p
+            .public_addresses()
+            .add_address(address.clone())
+            .map_err(|error| anyhow!("invalid public address {address}: {error:?}"))?;
+    }
+
+    progress!(
+        "Providing the key, republishing every {} s...",
+        args.republish.as_secs()
+    );
+    run.kademlia_handle.start_providing(args.key.clone()).await;
+    let local_peer_id = *run.litep2p.local_peer_id();
+    let mut published = tokio::time::Instant::now();
+
+    let interrupted = signal::ctrl_c();
+    tokio::pin!(interrupted);
+    let mut publications = 0;
+    loop {
+        let publication = publications + 1;
+        let round = async {
+            run.drive_until(published + REPUBLISH_CHECK_DELAY).await?;
+            let found = find_providers(&mut run, &args.key).await?.map(|providers| {
+                let found = providers.iter().any(|provider| provider.0 == local_peer_id);
+                (found, providers.len())
+            });
+            report_publication(publication, found);
+
+            published += args.republish;
+            run.drive_until(published).await
+        };
+        tokio::select! {
+            result = round => result?,
+            _ = &mut interrupted => break,
+        }
+        publications = publication;
+    }
+
+    run.kademlia_handle.stop_providing(args.key).await;
+    progress!("Interrupted, stopped providing after {publications} publications");
+
+    Ok(())
+}
+
+/// Print whether the provider record of `publication` was found among the providers.
+fn report_publication(publication: usize, found: Option<(bool, usize)>) {
+    if json_output() {
+        let document = Json::object()
+            .field("publication", publication)
+            .field("provider_record_found", found.map(|(found, _)| found))
+            .field("providers", found.map(|(_, providers)| providers));
+        println!("{document}");
+        return;
+    }
+
+    match found {
+        Some((true, providers)) => {
+            println!("Publication {publication}: provider record found among {providers} providers")
+        }
+        Some((false, providers)) => println!(
+            "Publication {publication}: provider record not returned, {providers} providers found"
+        ),
+        None => println!("Publication {publication}: GET_PROVIDERS query failed"),
+    }
+}
+
+/// Stop providing `key`, wait for `wait` and check whether the network still returns the local
+/// node as a provider.
+///
+/// Returns `None` if the query failed. litep2p doesn't report which peer returned which provider,
+/// so peers serving the expired record can't be told apart from the compliant ones.
+async fn audit_expiry(
+    run: &mut QueryRun,
+    key: &KademliaKey,
+    wait: Duration,
+) -> anyhow::Result<Option<bool>> {
+    progress!(
+        "Stopping providing, waiting {} s for the record to expire...",
+        wait.as_secs()
+    );
+    run.kademlia_handle.stop_providing(key.clone()).await;
+    run.drive_until(tokio::time::Instant::now() + wait).await?;
+
+    progress!("Checking the provider record expired...");
+    let local_peer_id = *run.litep2p.local_peer_id();
+    let providers = find_providers(run, key).await?;
+
+    Ok(providers.map(|providers| providers.iter().any(|provider| provider.0 == local_peer_id)))
+}
+
+/// JSON document of an add-provider run: the key lookup `document`, whether the published record
+/// is among the `providers` returned afterwards and whether it `expired` after --audit-expiry.
+fn audit_json(
+    document: Json,
+    local_peer_id: &PeerId,
+    providers: Option<&[(PeerId, Vec<Multiaddr>)]>,
+    expired: Option<bool>,
+) -> Json {
+    let published = providers.map(|providers| {
+        let record = providers
+            .iter()
+            .find(|provider| provider.0 == *local_peer_id);
+        Json::object()
+            .field("provider_record_found", record.is_some())
+            .field(
+                "record_addresses",
+                record.map(|(_, addresses)| addresses.len()),
+            )
+            .field("providers", providers.len())
+    });
+
+    document
+        .field("published", published)
+        .field("expired", expired)
+}
+
+/// Run GET_PROVIDERS for `key` and return the providers, or `None` if the query failed.
+async fn find_providers(
+    run: &mut QueryRun,
+    key: &KademliaKey,
+) -> anyhow::Result<Option<Vec<(PeerId, Vec<Multiaddr>)>>> {
+    let query_id = run.kademlia_handle.get_providers(key.clone()).await;
+
+    loop {
+        tokio::select! {
+            _ = run.litep2p.next_event() => {},
+            _ = run.identify_events.next() => {},
+            event = run.kademlia_handle.next() => match event {
+                Some(KademliaEvent::GetProvidersSuccess { query_id: id, providers, .. }) if id == query_id => {
+                    return Ok(Some(
+                        providers
+                            .into_iter()
+                            .map(|provider| (provider.peer, provider.addresses))
+                            .collect(),
+                    ))
+                },
+                Some(KademliaEvent::QueryFailed { query_id: id }) if id == query_id => {
+                    return Ok(None)
+                },
+                Some(_) => {},
+                None => return Err(anyhow!("libp2p Kademlia terminated")),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use clap::Parser;
+
+    use super::*;
+    use crate::{
+        schema::validate,
+        test_utils::{spawn_node, KAD_PROTO},
+    };
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        query: QueryArgs,
+    }
+
+    #[tokio::test]
+    async fn reports_publication_in_json() {
+        let peers: HashMap<_, _> = (0..2)
+            .map(|_| {
+                let (peer, address) = spawn_node(HashMap::new());
+                (peer, vec![address])
+            })
+            .collect();
+        let (bootnode, address) = spawn_node(peers);
+        let query = Cli::parse_from([
+            "dht-inspect",
+            "--bootnode",
+            &format!("{address}/p2p/{bootnode}"),
+            "--kad-proto",
+            KAD_PROTO,
+            "--allow-private-addresses",
+            "--timeout",
+            "20",
+        ])
+        .query;
+
+        let key = KademliaKey::new(&[1, 2, 3]);
+        let dht_query = Query::Providers(key.clone());
+        let mut run = query_dht(
+            &query,
+            &dht_query,
+            KAD_PROTO,
+            known_peers(&query).await.unwrap(),
+            &query.preset.settings(),
+            None,
+        )
+        .await
+        .unwrap();
+        let document = run_json(&dht_query, KAD_PROTO, &run);
+        run.kademlia_handle.start_providing(key.clone()).await;
+        run.drive_until(tokio::time::Instant::now() + Duration::from_millis(500))
+            .await
+            .unwrap();
+        let providers = find_providers(&mut run, &key).await.unwrap();
+        let local_peer_id = *run.litep2p.local_peer_id();
+
+        let document = audit_json(document, &local_peer_id, providers.as_deref(), None);
+        validate(&document, "audit_result").unwrap();
+        assert!(document
+            .get("published")
+            .is_some_and(|published| published != &Json::Null));
+    }
+}
@@ -0,0 +1,1209 @@
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context};
+use futures::StreamExt;
+use litep2p::{
+    protocol::libp2p::kademlia::{
+        ConfigBuilder as KademliaConfigBuilder, ContentProvider, KademliaEvent, QueryId,
+        RecordKey as KademliaKey,
+    },
+    Litep2p, PeerId,
+};
+use multiaddr::Multiaddr;
+use tokio::net::TcpListener;
+
+use crate::{
+    address::{AddressFilter, Freshness},
+    confidence::Confidence,
+    crosscheck::{print_cross_check, read_provider_export},
+    fallback_kademlia_protocol,
+    find_node::key_distance,
+    json::Json,
+    json_output, kademlia_protocol, known_peers,
+    metrics::{self, Metrics},
+    namespace::Namespace,
+    parse_key, peer_json,
+    preset::{Parameter, Preset, Settings},
+    print_document, print_protocol_hint, print_statistics, print_violations, query_batch,
+    query_dht,
+    reachability::{dial_providers, PeerReachability},
+    read_list,
+    refresh::Refresh,
+    retry::sleep_until,
+    rpc::{ChainEpoch, RelayChainRpc},
+    run_json, transport_config,
+    verify::{record_identified, verify_providers, ProtocolSupport},
+    BatchItem, NodeStalled, Query, QueryArgs, QueryRun, QueryTimeout, STALL_TIMEOUT,
+};
+
+/// Run a GET_PROVIDERS query and print the found providers.
+#[derive(clap::Args, Debug)]
+pub struct GetProvidersArgs {
+    /// Key (hex or CID) of the content provider record to query.
+    #[arg(short, long, value_name = "KEY", value_parser = parse_key, required_unless_present = "keys_file")]
+    provider_key: Option<KademliaKey>,
+    /// File with keys (hex or CID) to query instead of --provider-key, one per line. All keys are
+    /// queried on the same node, so the routing table is only populated once.
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = ["provider_key", "verify_protocol", "verify_providers", "bench_presets", "randomize", "expected_providers", "minimal", "watch"]
+    )]
+    keys_file: Option<PathBuf>,
+    /// Number of --keys-file queries run concurrently.
+    #[arg(long, value_name = "N", default_value_t = 8, requires = "keys_file")]
+    concurrency: usize,
+    /// Run as a daemon repeating the queries of --provider-key or --keys-file, and serve
+    /// Prometheus metrics at `http://<ADDR>/metrics`.
+    #[arg(
+        long,
+        value_name = "IP:PORT",
+        conflicts_with_all = ["verify_protocol", "verify_providers", "bench_presets", "randomize", "expected_providers", "minimal", "watch"]
+    )]
+    metrics_addr: Option<SocketAddr>,
+    /// Seconds between the query rounds of --metrics-addr.
+    #[arg(
+        long,
+        value_name = "SECS",
+        default_value_t = 60,
+        requires = "metrics_addr"
+    )]
+    metrics_interval: u64,
+    /// After the query, open this application protocol to every provider and report whether they
+    /// actually serve it.
+    #[arg(long, value_name = "PROTOCOL")]
+    verify_protocol: Option<String>,
+    /// After the query, dial every address of every provider from a fresh node and report which
+    /// providers are online and on which addresses.
+    #[arg(long)]
+    verify_providers: bool,
+    /// Run the query once under every preset and compare the outcomes.
+    #[arg(long, conflicts_with_all = ["preset", "verify_protocol", "verify_providers", "randomize"])]
+    bench_presets: bool,
+    /// Run the query --trials times with the given parameters of --preset sampled from ranges.
+    /// Lookup parallelism (alpha) is fixed by litep2p and can't be randomized.
+    #[arg(
+        long,
+        value_name = "PARAMS",
+        value_enum,
+        value_delimiter = ',',
+        conflicts_with_all = ["verify_protocol", "verify_providers"]
+    )]
+    randomize: Vec<Parameter>,
+    /// Number of --randomize trials.
+    #[arg(long, value_name = "N", default_value_t = 10, requires = "randomize")]
+    trials: usize,
+    /// Cross-check found providers against a kubo provider export, e.g. the output of
+    /// `ipfs routing findprovs <cid>` on the publishing side.
+    #[arg(long, value_name = "PATH")]
+    expected_providers: Option<PathBuf>,
+    /// Run just the GET_PROVIDERS query, without identify, routing table prepopulation, peer
+    /// bookkeeping or statistics, and print one `<peer id> <addresses>` line per provider. The
+    /// query isn't retried or rerun.
+    #[arg(
+        long,
+        conflicts_with_all = ["verify_protocol", "verify_providers", "bench_presets", "randomize", "expected_providers", "prepopulate", "retry", "until_success", "auto_requery"]
+    )]
+    minimal: bool,
+    /// Rerun the query every this many seconds on the same node and print the provider set with
+    /// the changes since the previous run, until interrupted.
+    #[arg(
+        long,
+        value_name = "SECS",
+        conflicts_with_all = ["verify_protocol", "verify_providers", "bench_presets", "randomize", "expected_providers", "minimal"]
+    )]
+    watch: Option<u64>,
+    /// Substrate relay chain RPC endpoint (`http://` or `https://`) to annotate every --watch
+    /// iteration with the current session and era, e.g. to correlate provider changes with
+    /// validator set rotations.
+    #[arg(long, value_name = "URL", requires = "watch")]
+    rpc: Option<String>,
+}
+
+/// Run a bare GET_PROVIDERS query on a node with only Kademlia enabled.
+///
+/// litep2p fails GET_PROVIDERS queries that find no providers, so a failed query some peer
+/// responded to is reported as an empty result.
+async fn lookup_providers(
+    query: &QueryArgs,
+    key: KademliaKey,
+    kad_proto: &str,
+    known_peers: HashMap<PeerId, Vec<Multiaddr>>,
+    settings: &Settings,
+) -> anyhow::Result<Vec<ContentProvider>> {
+    let (kademlia_config, mut kademlia_handle) = KademliaConfigBuilder::new()
+        .with_protocol_names(
+            std::iter::once(kad_proto.to_string())
+                .chain(fallback_kademlia_protocol(query).filter(|fallback| fallback != kad_proto))
+                .map(Into::into)
+                .collect(),
+        )
+        .with_replication_factor(settings.replication_factor)
+        .with_known_peers(known_peers)
+        .build();
+    let mut litep2p = Litep2p::new(
+        transport_config(settings, &query.socket)
+            .with_libp2p_kademlia(kademlia_config)
+            .build(),
+    )
+    .context("litep2p initialization error")?;
+    let mut address_filter = AddressFilter::new(query.allow_private_addresses);
+    let query_id = kademlia_handle.get_providers(key).await;
+    // Responses with closer peers, reported as routing table updates.
+    let mut responses = 0;
+    let timeout_deadline = query
+        .timeout
+        .map(|timeout| tokio::time::Instant::now() + Duration::from_secs(timeout));
+
+    loop {
+        tokio::select! {
+            _ = sleep_until(timeout_deadline) => {
+                return Err(QueryTimeout(Duration::from_secs(query.timeout.unwrap_or_default())).into())
+            },
+            _ = litep2p.next_event() => {},
+            event = kademlia_handle.next() => match event {
+                Some(KademliaEvent::GetProvidersSuccess { query_id: id, providers, .. }) if id == query_id => {
+                    return Ok(providers
+                        .into_iter()
+                        .map(|provider| ContentProvider {
+                            addresses: address_filter.filter(provider.addresses),
+                            ..provider
+                        })
+                        .collect())
+                },
+                Some(KademliaEvent::QueryFailed { query_id: id }) if id == query_id => {
+                    if responses == 0 {
+                        return Err(anyhow!("Kademlia query failed"))
+                    }
+                    return Ok(Vec::new())
+                },
+                Some(KademliaEvent::RoutingTableUpdate { .. }) => responses += 1,
+                Some(_) => {},
+                None => return Err(anyhow!("libp2p Kademlia terminated")),
+            },
+        }
+    }
+}
+
+/// Run the GET_PROVIDERS query.
+pub async fn run(args: GetProvidersArgs, query: &QueryArgs) -> anyhow::Result<()> {
+    let known_peers = known_peers(query).await?;
+    let kad_proto = kademlia_protocol(query);
+
+    if let Some(address) = args.metrics_addr {
+        let keys = match &args.keys_file {
+            Some(path) => read_list(path, "key", parse_key)?,
+            None => vec![args
+                .provider_key
+                .expect("required unless --keys-file is given; qed")],
+        };
+        let interval = Duration::from_secs(args.metrics_interval);
+        return run_daemon(
+            &keys,
+            args.concurrency,
+            address,
+            interval,
+            query,
+            &kad_proto,
+            known_peers,
+        )
+        .await;
+    }
+
+    if let Some(path) = &args.keys_file {
+        let keys = read_list(path, "key", parse_key)?;
+        return run_batch(&keys, args.concurrency, query, &kad_proto, known_peers).await;
+    }
+    let provider_key = args
+        .provider_key
+        .clone()
+        .expect("required unless --keys-file is given; qed");
+
+    if args.minimal {
+        let settings = query.preset.settings();
+        for provider in
+            lookup_providers(query, provider_key, &kad_proto, known_peers, &settings).await?
+        {
+            let addresses: Vec<_> = provider.addresses.iter().map(ToString::to_string).collect();
+            println!("{} {}", provider.peer, addresses.join(" "));
+        }
+        return Ok(());
+    }
+
+    let exported = match &args.expected_providers {
+        Some(path) => Some(read_provider_export(path)?),
+        None => None,
+    };
+    let rpc = args.rpc.as_deref().map(RelayChainRpc::new).transpose()?;
+
+    if args.bench_presets || !args.randomize.is_empty() {
+        let configurations = if args.bench_presets {
+            Preset::ALL
+                .into_iter()
+                .map(|preset| (format!("preset {preset}"), preset.settings()))
+                .collect()
+        } else {
+            (1..=args.trials)
+                .map(|trial| {
+                    let settings = query.preset.settings().randomized(&args.randomize);
+                    (format!("trial {trial}"), settings)
+                })
+                .collect()
+        };
+        return compare_settings(
+            query,
+            &provider_key,
+            &kad_proto,
+            &known_peers,
+            configurations,
+        )
+        .await;
+    }
+
+    let settings = query.preset.settings();
+    let namespace = Namespace::detect(provider_key.as_ref());
+    progress!("Key namespace: {namespace}");
+    let dht_query = Query::Providers(provider_key);
+    let mut run = query_dht(
+        query,
+        &dht_query,
+        &kad_proto,
+        known_peers,
+        &settings,
+        args.verify_protocol.as_deref(),
+    )
+    .await?;
+
+    if let Some(interval) = args.watch {
+        if !json_output() {
+            print_statistics(&run, &settings);
+        }
+        if let Err(error) = run.result {
+            print_protocol_hint(query, &run.fan_out, &kad_proto);
+            return Err(error);
+        }
+        let Query::Providers(key) = dht_query else {
+            unreachable!("GET_PROVIDERS query; qed");
+        };
+        return watch(
+            run,
+            query,
+            &kad_proto,
+            key,
+            Duration::from_secs(interval),
+            rpc,
+        )
+        .await;
+    }
+
+    if json_output() {
+        return print_json(
+            &args, query, &dht_query, &kad_proto, namespace, run, exported,
+        )
+        .await;
+    }
+
+    print_statistics(&run, &settings);
+    if let Err(error) = run.result {
+        print_protocol_hint(query, &run.fan_out, &kad_proto);
+        return Err(error);
+    }
+    let providers = std::mem::take(&mut run.providers);
+    print_filtered(&run.address_filter);
+    print_providers(&providers);
+    print_violations(query.limits.check_providers(&providers));
+    if let Some(exported) = &exported {
+        print_cross_check(&providers, exported);
+    }
+
+    let mut support = HashMap::new();
+    if let (Some(protocol), Some(handle)) = (&args.verify_protocol, &mut run.verify_handle) {
+        println!();
+        println!("Verifying providers serve {protocol}...");
+        let results = verify_providers(
+            &mut run.litep2p,
+            handle,
+            &mut run.identify_events,
+            &mut run.identified,
+            &providers,
+            &query.retry,
+        )
+        .await;
+        for (peer, support) in &results {
+            println!("{peer}: {support}");
+        }
+        print_freshness(&providers, &run.identified, query.allow_private_addresses);
+        support = results.into_iter().collect();
+    }
+    print_confidence(&providers, &run.contacted_peers, &support);
+    if args.verify_providers {
+        println!();
+        println!("Dialing providers...");
+        let reachability = dial_providers(&providers, &settings, &query.socket).await;
+        print_reachability(&reachability);
+    }
+
+    Ok(())
+}
+
+/// Print which providers and addresses accepted a connection.
+pub(crate) fn print_reachability(reachability: &[PeerReachability]) {
+    for provider in reachability {
+        let state = if provider.online() {
+            "online"
+        } else {
+            "offline"
+        };
+        println!("{}: {state}", provider.peer);
+        for outcome in &provider.addresses {
+            match &outcome.result {
+                Ok(elapsed) => println!(
+                    "  {} connected in {} ms",
+                    outcome.address,
+                    elapsed.as_millis()
+                ),
+                Err(error) => println!("  {} {error}", outcome.address),
+            }
+        }
+        if provider.addresses.is_empty() {
+            println!("  no addresses");
+        }
+    }
+    println!(
+        "Providers online: {}/{}",
+        reachability
+            .iter()
+            .filter(|provider| provider.online())
+            .count(),
+        reachability.len()
+    );
+}
+
+/// Outcome of a GET_PROVIDERS query in a batch: the key, the providers or `None` if the query
+/// failed, and the query time.
+type KeyOutcome<'a> = (&'a KademliaKey, Option<Vec<ContentProvider>>, Duration);
+
+/// Run GET_PROVIDERS for every key on the same node and print a summary per key.
+async fn run_batch(
+    keys: &[KademliaKey],
+    concurrency: usize,
+    query: &QueryArgs,
+    kad_proto: &str,
+    known_peers: HashMap<PeerId, Vec<Multiaddr>>,
+) -> anyhow::Result<()> {
+    let (_, outcomes) = first_round(keys, concurrency, query, kad_proto, known_peers).await?;
+    print_batch(kad_proto, &outcomes);
+
+    Ok(())
+}
+
+/// Repeat GET_PROVIDERS for every key every `interval` on the same node and serve the outcomes as
+/// Prometheus metrics at `address` until interrupted. The routing table is refreshed between the
+/// rounds, and a node that stalls during a round is restarted and the round rerun.
+async fn run_daemon(
+    keys: &[KademliaKey],
+    concurrency: usize,
+    address: SocketAddr,
+    interval: Duration,
+    query: &QueryArgs,
+    kad_proto: &str,
+    known_peers: HashMap<PeerId, Vec<Multiaddr>>,
+) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(address)
+        .await
+        .with_context(|| format!("failed to listen on {address}"))?;
+    let metrics = Arc::new(Mutex::new(Metrics::default()));
+    tokio::spawn(metrics::serve(listener, metrics.clone()));
+    progress!("Serving metrics at http://{address}/metrics");
+
+    let (mut run, mut outcomes) =
+        first_round(keys, concurrency, query, kad_proto, known_peers).await?;
+    let mut refresh = Refresh::new();
+    for round in 1usize.. {
+        let succeeded = outcomes
+            .iter()
+            .filter(|(_, providers, _)| providers.is_some())
+            .count();
+        progress!(
+            "Round {round}: {succeeded}/{} queries succeeded",
+            outcomes.len()
+        );
+        {
+            let mut metrics = metrics.lock().expect("metrics lock poisoned");
+            for (key, providers, elapsed) in &outcomes {
+                metrics.on_query(key, providers.as_deref(), *elapsed);
+            }
+            metrics.on_peers(run.discovered_peers.len(), run.contacted_peers.len());
+            metrics.on_refresh(refresh.started(), refresh.failed(), refresh.refreshed());
+        }
+
+        run.maintain_until(tokio::time::Instant::now() + interval, &mut refresh)
+            .await?;
+        outcomes = loop {
+            match query_concurrently(&mut run, keys, concurrency).await {
+                Err(error) if error.is::<NodeStalled>() => {
+                    progress!("Round {}: {error}", round + 1);
+                    run.restart(query, kad_proto).await?;
+                    refresh.restart();
+                }
+                outcomes => break outcomes?,
+            }
+        };
+    }
+
+    Ok(())
+}
+
+/// Start a node and run GET_PROVIDERS for every key on it.
+///
+/// The first query populates the routing table, the others reuse it, running up to `concurrency`
+/// at a time. The time of the first key includes the prepopulation.
+async fn first_round<'a>(
+    keys: &'a [KademliaKey],
+    concurrency: usize,
+    query: &QueryArgs,
+    kad_proto: &str,
+    known_peers: HashMap<PeerId, Vec<Multiaddr>>,
+) -> anyhow::Result<(QueryRun, Vec<KeyOutcome<'a>>)> {
+    let settings = query.preset.settings();
+    let (first, rest) = keys.split_first().expect("keys are not empty; qed");
+
+    let mut run = query_dht(
+        query,
+        &Query::Providers(first.clone()),
+        kad_proto,
+        known_peers,
+        &settings,
+        None,
+    )
+    .await?;
+    if run.result.is_err() && run.fan_out.responses() == 0 {
+        if !json_output() {
+            print_statistics(&run, &settings);
+            print_protocol_hint(query, &run.fan_out, kad_proto);
+        }
+        return Err(anyhow!(
+            "no peer responded to the first GET_PROVIDERS query"
+        ));
+    }
+
+    let providers = run
+        .result
+        .is_ok()
+        .then(|| std::mem::take(&mut run.providers));
+    let mut outcomes = vec![(first, providers, run.elapsed)];
+    outcomes.extend(query_concurrently(&mut run, rest, concurrency).await?);
+
+    Ok((run, outcomes))
+}
+
+/// Run GET_PROVIDERS for `keys` on the node of `run`, up to `concurrency` queries at a time.
+///
+/// Returns the providers, `None` if the query failed, and the query time for every key in order.
+async fn query_concurrently<'a>(
+    run: &mut QueryRun,
+    keys: &'a [KademliaKey],
+    concurrency: usize,
+) -> anyhow::Result<Vec<KeyOutcome<'a>>> {
+    let total = keys.len();
+    query_batch(run, keys, concurrency, |_, finished, key, providers| {
+        let key = hex::encode(key.as_ref());
+        match &providers {
+            Some(providers) => progress!(
+                "Key {finished}/{total}: {} providers for {key}",
+                providers.len()
+            ),
+            None => progress!("Key {finished}/{total}: query failed for {key}"),
+        }
+        providers
+    })
+    .await
+}
+
+/// Print the summary table of a batch query, or a JSON document with the providers per key.
+fn print_batch(kad_proto: &str, outcomes: &[KeyOutcome]) {
+    if json_output() {
+        let keys: Vec<_> = outcomes
+            .iter()
+            .map(|(key, providers, elapsed)| {
+                let providers = providers.as_ref().map(|providers| {
+                    providers
+                        .iter()
+                        .map(|provider| peer_json(&provider.peer, &provider.addresses))
+                        .collect::<Vec<_>>()
+                });
+                Json::object()
+                    .field("key", hex::encode(key.as_ref()))
+                    .field("providers", providers)
+                    .field("elapsed_ms", elapsed.as_millis() as u64)
+            })
+            .collect();
+        let document = Json::object()
+            .field("query", "GET_PROVIDERS")
+            .field("protocol", kad_proto)
+            .field("keys", keys);
+        print_document(document);
+        return;
+    }
+
+    let width = outcomes
+        .iter()
+        .map(|(key, ..)| key.as_ref().len() * 2)
+        .max()
+        .unwrap_or_default();
+    println!();
+    println!("{:<width$}  {:>9}  {:>8}", "KEY", "PROVIDERS", "TIME");
+    for (key, providers, elapsed) in outcomes {
+        let providers = match providers {
+            Some(providers) => providers.len().to_string(),
+            None => "failed".to_string(),
+        };
+        println!(
+            "{:<width$}  {providers:>9}  {:>5} ms",
+            hex::encode(key.as_ref()),
+            elapsed.as_millis()
+        );
+    }
+
+    let found = outcomes
+        .iter()
+        .filter(|(_, providers, _)| {
+            providers
+                .as_ref()
+                .is_some_and(|providers| !providers.is_empty())
+        })
+        .count();
+    println!();
+    println!("Keys with providers: {found}/{}", outcomes.len());
+}
+
+/// The replication factor's worth of peers closest to a watched key, tracked across iterations.
+///
+/// The neighborhood of an iteration is taken from the peers that its lookup returned, so peers
+/// leave it once the peers around the key stop returning them.
+struct Neighborhood {
+    k: usize,
+    peers: HashSet<PeerId>,
+    joined: Vec<PeerId>,
+    left: Vec<PeerId>,
+    /// Iterations in which providers disappeared.
+    removals: usize,
+    /// Of these, iterations in which the neighborhood changed too.
+    removals_with_churn: usize,
+}
+
+impl Neighborhood {
+    /// Neighborhood of `key` among the `peers` returned by the first lookup.
+    fn new(key: &KademliaKey, peers: &HashSet<PeerId>, k: usize) -> Self {
+        Self {
+            k,
+            peers: closest_to_key(key, peers, k),
+            joined: Vec::new(),
+            left: Vec::new(),
+            removals: 0,
+            removals_with_churn: 0,
+        }
+    }
+
+    /// Replace the neighborhood by the one of `key` among the `peers` returned by the latest
+    /// lookup.
+    fn update(&mut self, key: &KademliaKey, peers: &HashSet<PeerId>) {
+        let peers = closest_to_key(key, peers, self.k);
+        self.joined = peers.difference(&self.peers).copied().collect();
+        self.left = self.peers.difference(&peers).copied().collect();
+        self.joined.sort();
+        self.left.sort();
+        self.peers = peers;
+    }
+
+    /// Account an iteration in which providers disappeared.
+    fn on_removal(&mut self) {
+        self.removals += 1;
+        if !self.joined.is_empty() || !self.left.is_empty() {
+            self.removals_with_churn += 1;
+        }
+    }
+}
+
+/// The `k` of `peers` closest to `key`.
+fn closest_to_key(key: &KademliaKey, peers: &HashSet<PeerId>, k: usize) -> HashSet<PeerId> {
+    let mut peers: Vec<_> = peers.iter().copied().collect();
+    peers.sort_by_cached_key(|peer| key_distance(key.as_ref(), peer));
+    peers.truncate(k);
+
+    peers.into_iter().collect()
+}
+
+/// Rerun the GET_PROVIDERS query for `key` every `interval` on the node of the finished `run` and
+/// print every provider set with the changes since the previous one, together with the churn of
+/// the peers closest to the key. A failed rerun keeps the previous sets, and a node that stalls
+/// during a rerun is restarted. The routing table is refreshed between the reruns.
+async fn watch(
+    mut run: QueryRun,
+    query: &QueryArgs,
+    kad_proto: &str,
+    key: KademliaKey,
+    interval: Duration,
+    rpc: Option<RelayChainRpc>,
+) -> anyhow::Result<()> {
+    let mut providers = std::mem::take(&mut run.providers);
+    let mut previous = HashSet::new();
+    let mut refresh = Refresh::new();
+    let k = query.preset.settings().replication_factor;
+    let mut neighborhood = Neighborhood::new(&key, &run.discovered_peers, k);
+
+    for iteration in 1.. {
+        let epoch = match &rpc {
+            Some(rpc) => run
+                .drive_while(rpc.epoch())
+                .await?
+                .inspect_err(|error| progress!("Failed to query the relay chain: {error:#}"))
+                .ok(),
+            None => None,
+        };
+        let current: HashSet<_> = providers.iter().map(|provider| provider.peer).collect();
+        let mut removed: Vec<_> = previous.difference(&current).collect();
+        removed.sort();
+        if !removed.is_empty() {
+            neighborhood.on_removal();
+        }
+        print_watch_iteration(
+            iteration,
+            epoch.as_ref(),
+            &providers,
+            &previous,
+            &removed,
+            &neighborhood,
+        );
+        previous = current;
+
+        run.maintain_until(tokio::time::Instant::now() + interval, &mut refresh)
+            .await?;
+        let query_id = run.kademlia_handle.get_providers(key.clone()).await;
+        match rerun(&mut run, query_id).await {
+            Ok((Some(found), returned)) => {
+                providers = found;
+                neighborhood.update(&key, &returned);
+            }
+            Ok((None, _)) => progress!("Iteration {}: GET_PROVIDERS query failed", iteration + 1),
+            Err(error) if error.is::<NodeStalled>() => {
+                progress!("Iteration {}: {error}", iteration + 1);
+                run.restart(query, kad_proto).await?;
+                refresh.restart();
+            }
+            Err(error) => return Err(error),
+        }
+    }
+
+    Ok(())
+}
+
+/// Wait for the GET_PROVIDERS query `query_id` to finish. Returns the providers, `None` if it
+/// failed, with the peers the lookup returned, or [`NodeStalled`] if the node stops producing
+/// events.
+async fn rerun(
+    run: &mut QueryRun,
+    query_id: QueryId,
+) -> anyhow::Result<(Option<Vec<ContentProvider>>, HashSet<PeerId>)> {
+    let mut returned = HashSet::new();
+    loop {
+        tokio::select! {
+            _ = run.litep2p.next_event() => {},
+            event = run.identify_events.next() => record_identified(&mut run.identified, event),
+            event = run.kademlia_handle.next() => match event {
+                Some(KademliaEvent::QueryFailed { query_id: id }) if id == query_id => {
+                    return Ok((None, returned))
+                },
+                Some(KademliaEvent::RoutingTableUpdate { peers }) => {
+                    run.discovered_peers.extend(peers.iter().copied());
+                    returned.extend(peers);
+                },
+                Some(event) => {
+                    if let Some((id, providers)) = KademliaKey::found(event, &mut run.address_filter) {
+                        if id == query_id {
+                            return Ok((Some(providers), returned))
+                        }
+                    }
+                },
+                None => return Err(anyhow!("libp2p Kademlia terminated")),
+            },
+            _ = tokio::time::sleep(STALL_TIMEOUT) => return Err(NodeStalled.into()),
+        }
+    }
+}
+
+/// Print the providers of a watch iteration, marking the added ones with `+` and listing the
+/// removed ones with `-`, with the relay chain session and era if known and the churn of the
+/// `neighborhood` of the key.
+fn print_watch_iteration(
+    iteration: usize,
+    epoch: Option<&ChainEpoch>,
+    providers: &[ContentProvider],
+    previous: &HashSet<PeerId>,
+    removed: &[&PeerId],
+    neighborhood: &Neighborhood,
+) {
+    let added: Vec<_> = providers
+        .iter()
+        .filter(|provider| !previous.contains(&provider.peer))
+        .map(|provider| provider.peer)
+        .collect();
+
+    if json_output() {
+        let document = Json::object()
+            .field("iteration", iteration)
+            .field("session", epoch.map(|epoch| epoch.session as u64))
+            .field("era", epoch.and_then(|epoch| epoch.era).map(u64::from))
+            .field(
+                "providers",
+                providers
+                    .iter()
+                    .map(|provider| peer_json(&provider.peer, &provider.addresses))
+                    .collect::<Vec<_>>(),
+            )
+            .field(
+                "added",
+                added.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            )
+            .field(
+                "removed",
+                removed.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            )
+            .field(
+                "neighborhood",
+                Json::object()
+                    .field("peers", sorted_strings(&neighborhood.peers))
+                    .field("joined", sorted_strings(&neighborhood.joined))
+                    .field("left", sorted_strings(&neighborhood.left))
+                    .field("removals", neighborhood.removals)
+                    .field("removals_with_churn", neighborhood.removals_with_churn),
+            );
+        println!("{document}");
+        return;
+    }
+
+    let epoch = match epoch {
+        Some(ChainEpoch {
+            session,
+            era: Some(era),
+        }) => format!(" (session {session}, era {era})"),
+        Some(ChainEpoch { session, era: None }) => format!(" (session {session})"),
+        None => String::new(),
+    };
+    println!();
+    println!(
+        "Iteration {iteration}{epoch}: {} providers, {} added, {} removed",
+        providers.len(),
+        added.len(),
+        removed.len()
+    );
+    for provider in providers {
+        let marker = if added.contains(&provider.peer) {
+            '+'
+        } else {
+            ' '
+        };
+        println!("{marker} {}", provider.peer);
+    }
+    for peer in removed {
+        println!("- {peer}");
+    }
+    println!(
+        "Neighborhood: {} closest peers, {} joined, {} left",
+        neighborhood.peers.len(),
+        neighborhood.joined.len(),
+        neighborhood.left.len()
+    );
+    if neighborhood.removals > 0 {
+        println!(
+            "Providers disappeared in {} iterations, {} of them with neighborhood churn",
+            neighborhood.removals, neighborhood.removals_with_churn
+        );
+    }
+}
+
+/// String forms of `peers`, sorted.
+fn sorted_strings<'a>(peers: impl IntoIterator<Item = &'a PeerId>) -> Vec<String> {
+    let mut peers: Vec<_> = peers.into_iter().map(ToString::to_string).collect();
+    peers.sort();
+
+    peers
+}
+
+/// Print the outcome of the GET_PROVIDERS query as a JSON document.
+async fn print_json(
+    args: &GetProvidersArgs,
+    query: &QueryArgs,
+    dht_query: &Query,
+    kad_proto: &str,
+    namespace: Namespace,
+    mut run: QueryRun,
+    exported: Option<HashSet<PeerId>>,
+) -> anyhow::Result<()> {
+    let mut document = run_json(dht_query, kad_proto, &run)
+        .field("namespace", namespace.to_string())
+        .field("filtered_addresses", run.address_filter.skipped());
+    if let Err(error) = run.result {
+        print_document(document);
+        return Err(error);
+    }
+
+    let providers = std::mem::take(&mut run.providers);
+    document = document.field("limit_violations", query.limits.check_providers(&providers));
+    if let Some(exported) = &exported {
+        let found: HashSet<_> = providers.iter().map(|provider| provider.peer).collect();
+        let peers = |peers: Vec<&PeerId>| {
+            peers
+                .into_iter()
+                .map(|peer| peer.to_string())
+                .collect::<Vec<_>>()
+        };
+        document = document.field(
+            "cross_check",
+            Json::object()
+                .field("exported", exported.len())
+                .field("found", found.len())
+                .field("not_found", peers(exported.difference(&found).collect()))
+                .field("not_exported", peers(found.difference(exported).collect())),
+        );
+    }
+
+    let mut support = HashMap::new();
+    if let (Some(protocol), Some(handle)) = (&args.verify_protocol, &mut run.verify_handle) {
+        progress!("Verifying providers serve {protocol}...");
+        let results = verify_providers(
+            &mut run.litep2p,
+            handle,
+            &mut run.identify_events,
+            &mut run.identified,
+            &providers,
+            &query.retry,
+        )
+        .await;
+        let verification = results
+            .iter()
+            .map(|(peer, support)| {
+                let freshness = providers
+                    .iter()
+                    .find(|provider| provider.peer == *peer)
+                    .zip(run.identified.get(peer))
+                    .map(|(provider, advertised)| {
+                        Freshness::new(
+                            &provider.addresses,
+                            advertised,
+                            query.allow_private_addresses,
+                        )
+                        .ratio()
+                    });
+                Json::object()
+                    .field("peer_id", peer.to_string())
+                    .field("support", support.to_string())
+                    .field("freshness", freshness)
+            })
+            .collect::<Vec<_>>();
+        document = document.field(
+            "verification",
+            Json::object()
+                .field("protocol", protocol.as_str())
+                .field("providers", verification),
+        );
+        support = results.into_iter().collect();
+    }
+
+    let confidence = providers
+        .iter()
+        .map(|provider| {
+            let confidence = Confidence::new(
+                provider,
+                run.contacted_peers.contains(&provider.peer),
+                support.get(&provider.peer),
+            );
+            Json::object()
+                .field("peer_id", provider.peer.to_string())
+                .field("score", confidence.score)
+                .field("signals", confidence.signals)
+        })
+        .collect::<Vec<_>>();
+    document = document.field("confidence", confidence);
+    if args.verify_providers {
+        progress!("Dialing providers...");
+        let reachability =
+            dial_providers(&providers, &query.preset.settings(), &query.socket).await;
+        document = document.field(
+            "reachability",
+            reachability
+                .iter()
+                .map(PeerReachability::json)
+                .collect::<Vec<_>>(),
+        );
+    }
+
+    print_document(document);
+    Ok(())
+}
+
+/// Run the same GET_PROVIDERS query under every configuration and compare the outcomes.
+async fn compare_settings(
+    args: &QueryArgs,
+    provider_key: &KademliaKey,
+    kad_proto: &str,
+    known_peers: &HashMap<PeerId, Vec<Multiaddr>>,
+    configurations: Vec<(String, Settings)>,
+) -> anyhow::Result<()> {
+    let mut outcomes = Vec::new();
+    for (label, settings) in configurations {
+        progress!("Running the query with {label} ({settings})...");
+        let run = query_dht(
+            args,
+            &Query::Providers(provider_key.clone()),
+            kad_proto,
+            known_peers.clone(),
+            &settings,
+            None,
+        )
+        .await?;
+        if !json_output() {
+            print_statistics(&run, &settings);
+        }
+
+        let outcome = match &run.result {
+            Ok(()) => format!("{} providers", run.providers.len()),
+            Err(error) => format!("failed: {error}"),
+        };
+        outcomes.push((
+            label,
+            settings,
+            run.result.is_ok(),
+            outcome,
+            run.discovered_peers.len(),
+            run.contacted_peers.len(),
+            run.elapsed,
+        ));
+    }
+
+    if json_output() {
+        let configurations = outcomes
+            .iter()
+            .map(
+                |(label, settings, success, outcome, discovered, contacted, elapsed)| {
+                    Json::object()
+                        .field("label", label.as_str())
+                        .field("settings", settings.to_string())
+                        .field("success", *success)
+                        .field("outcome", outcome.as_str())
+                        .field("discovered_peers", *discovered)
+                        .field("contacted_peers", *contacted)
+                        .field("elapsed_ms", elapsed.as_millis() as u64)
+                },
+            )
+            .collect::<Vec<_>>();
+        println!(
+            "{}",
+            Json::object()
+                .field("query", "GET_PROVIDERS")
+                .field("key", hex::encode(provider_key.as_ref()))
+                .field("protocol", kad_proto)
+                .field("configurations", configurations)
+        );
+        return Ok(());
+    }
+
+    let width = outcomes
+        .iter()
+        .map(|(label, settings, ..)| label.len() + settings.to_string().len() + 3)
+        .max()
+        .unwrap_or_default()
+        .max("CONFIGURATION".len());
+    println!(
+        "{:<width$} {:<32} {:>10} {:>10} {:>8}",
+        "CONFIGURATION", "OUTCOME", "DISCOVERED", "CONTACTED", "TIME"
+    );
+    for (label, settings, _, outcome, discovered, contacted, elapsed) in &outcomes {
+        println!(
+            "{:<width$} {:<32} {:>10} {:>10} {:>6} s",
+            format!("{label} ({settings})"),
+            outcome,
+            discovered,
+            contacted,
+            elapsed.as_secs(),
+        );
+    }
+    println!(
+        "Succeeded: {} of {}",
+        outcomes
+            .iter()
+            .filter(|(_, _, success, ..)| *success)
+            .count(),
+        outcomes.len(),
+    );
+
+    Ok(())
+}
+
+fn print_filtered(filter: &AddressFilter) {
+    if filter.skipped() > 0 {
+        filter.print_skipped();
+        println!();
+    }
+}
+
+/// Compare provider record addresses with the addresses providers advertise via identify.
+fn print_freshness(
+    providers: &[ContentProvider],
+    identified: &HashMap<PeerId, Vec<Multiaddr>>,
+    allow_private: bool,
+) {
+    let mut ratios = Vec::new();
+    println!();
+    for provider in providers {
+        match identified.get(&provider.peer) {
+            Some(advertised) => {
+                let freshness = Freshness::new(&provider.addresses, advertised, allow_private);
+                println!("{}: record addresses {freshness}", provider.peer);
+                ratios.push(freshness.ratio());
+            }
+            None => println!("{}: not identified, freshness unknown", provider.peer),
+        }
+    }
+
+    if !ratios.is_empty() {
+        println!(
+            "Record freshness: {:.0}% of stored addresses still advertised ({} of {} providers identified)",
+            ratios.iter().sum::<f64>() / ratios.len() as f64 * 100.0,
+            ratios.len(),
+            providers.len(),
+        );
+    }
+}
+
+/// Print the confidence score of every provider.
+fn print_confidence(
+    providers: &[ContentProvider],
+    contacted: &HashSet<PeerId>,
+    support: &HashMap<PeerId, ProtocolSupport>,
+) {
+    if providers.is_empty() {
+        return;
+    }
+
+    println!();
+    for provider in providers {
+        let confidence = Confidence::new(
+            provider,
+            contacted.contains(&provider.peer),
+            support.get(&provider.peer),
+        );
+        println!("{}: confidence {confidence}", provider.peer);
+    }
+}
+
+pub(crate) fn print_providers(providers: &[ContentProvider]) {
+    for provider in providers {
+        println!("{:?}", provider);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::test_utils::{spawn_node, KAD_PROTO};
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        get_providers: GetProvidersArgs,
+        #[command(flatten)]
+        query: QueryArgs,
+    }
+
+    #[test]
+    fn tracks_neighborhood_churn() {
+        let key = KademliaKey::new(&b"key".to_vec());
+        let mut peers: Vec<_> = (0..4).map(|_| PeerId::random()).collect();
+        peers.sort_by_cached_key(|peer| key_distance(key.as_ref(), peer));
+        let returned = |indices: &[usize]| indices.iter().map(|&i| peers[i]).collect();
+
+        let mut neighborhood = Neighborhood::new(&key, &returned(&[1, 2, 3]), 2);
+        assert_eq!(neighborhood.peers, returned(&[1, 2]));
+        neighborhood.on_removal();
+        assert_eq!(
+            (neighborhood.removals, neighborhood.removals_with_churn),
+            (1, 0)
+        );
+
+        neighborhood.update(&key, &returned(&[0, 2, 3]));
+        assert_eq!(neighborhood.peers, returned(&[0, 2]));
+        assert_eq!(neighborhood.joined, vec![peers[0]]);
+        assert_eq!(neighborhood.left, vec![peers[1]]);
+        neighborhood.on_removal();
+        assert_eq!(
+            (neighborhood.removals, neighborhood.removals_with_churn),
+            (2, 1)
+        );
+    }
+
+    #[test]
+    fn rejects_minimal_with_retries() {
+        let parse = |args: &[&str]| {
+            Cli::try_parse_from(
+                ["dht-inspect", "--provider-key", "00", "--minimal"]
+                    .into_iter()
+                    .chain(args.iter().copied()),
+            )
+        };
+
+        assert!(parse(&[]).is_ok());
+        assert!(parse(&["--timeout", "10"]).is_ok());
+        for conflicting in [
+            &["--prepopulate", "2"][..],
+            &["--retry", "3x"],
+            &["--until-success"],
+            &["--auto-requery"],
+        ] {
+            assert!(parse(conflicting).is_err(), "{conflicting:?} accepted");
+        }
+    }
+
+    #[tokio::test]
+    async fn minimal_lookup_reports_no_providers() {
+        let peers: HashMap<_, _> = (0..2)
+            .map(|_| {
+                let (peer, address) = spawn_node(HashMap::new());
+                (peer, vec![address])
+            })
+            .collect();
+        let (bootnode, address) = spawn_node(peers);
+        let query = Cli::parse_from([
+            "dht-inspect",
+            "--provider-key",
+            "00",
+            "--minimal",
+            &format!("--bootnode={address}/p2p/{bootnode}"),
+            "--kad-proto",
+            KAD_PROTO,
+            "--allow-private-addresses",
+            "--timeout",
+            "20",
+        ])
+        .query;
+
+        let providers = lookup_providers(
+            &query,
+            KademliaKey::new(&[1, 2, 3]),
+            KAD_PROTO,
+            known_peers(&query).await.unwrap(),
+            &query.preset.settings(),
+        )
+        .await
+        .unwrap();
+        assert!(providers.is_empty());
+    }
+}
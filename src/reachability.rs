@@ -0,0 +1,312 @@
+use std::time::{Duration, Instant};
+
+use futures::{future::join_all, StreamExt};
+use litep2p::{
+    error::DialError,
+    protocol::libp2p::{
+        identify::{Config as IdentifyConfig, IdentifyEvent},
+        kademlia::ContentProvider,
+    },
+    Litep2p, Litep2pEvent, PeerId,
+};
+use multiaddr::{Multiaddr, Protocol};
+
+use crate::{
+    json::Json, preset::Settings, transport_config, SocketOptions, IDENTIFY_PROTOCOL_VERSION,
+    USER_AGENT,
+};
+
+/// Time on top of the connection open timeout to wait for the dial outcome.
+const DIAL_GRACE: Duration = Duration::from_secs(2);
+/// Time to wait for the identify response once the connection is established.
+const IDENTIFY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Identify information a peer sent over a verification connection.
+pub struct Identified {
+    pub protocol_version: Option<String>,
+    pub user_agent: Option<String>,
+    /// Address of the local node as seen by the peer.
+    pub observed_address: Multiaddr,
+    pub listen_addresses: Vec<Multiaddr>,
+    pub protocols: Vec<String>,
+}
+
+impl Identified {
+    pub fn json(&self) -> Json {
+        Json::object()
+            .field("protocol_version", self.protocol_version.clone())
+            .field("user_agent", self.user_agent.clone())
+            .field("observed_address", self.observed_address.to_string())
+            .field(
+                "listen_addresses",
+                self.listen_addresses
+                    .iter()
+                    .map(|address| Json::from(address.to_string()))
+                    .collect::<Vec<_>>(),
+            )
+            .field(
+                "protocols",
+                self.protocols
+                    .iter()
+                    .map(|protocol| Json::from(protocol.as_str()))
+                    .collect::<Vec<_>>(),
+            )
+    }
+}
+
+/// Outcome of dialing one peer address.
+pub struct AddressOutcome {
+    pub address: Multiaddr,
+    /// Time to establish the connection, or the reason the dial failed.
+    pub result: Result<Duration, String>,
+    /// Identify information received over the connection, if requested and sent in time.
+    pub identified: Option<Identified>,
+}
+
+/// Dial outcomes of all addresses of a peer.
+pub struct PeerReachability {
+    pub peer: PeerId,
+    pub addresses: Vec<AddressOutcome>,
+}
+
+impl PeerReachability {
+    /// Whether the peer accepted a connection on any address.
+    pub fn online(&self) -> bool {
+        self.addresses.iter().any(|outcome| outcome.result.is_ok())
+    }
+
+    pub fn json(&self) -> Json {
+        Json::object()
+            .field("peer_id", self.peer.to_string())
+            .field("online", self.online())
+            .field(
+                "addresses",
+                self.addresses
+                    .iter()
+                    .map(|outcome| {
+                        let document = Json::object().field("address", outcome.address.to_string());
+                        let document = match &outcome.result {
+                            Ok(elapsed) => document
+                                .field("reachable", true)
+                                .field("connect_ms", elapsed.as_millis() as u64),
+                            Err(error) => document
+                                .field("reachable", false)
+                                .field("error", error.as_str()),
+                        };
+                        match &outcome.identified {
+                            Some(identified) => document.field("identify", identified.json()),
+                            None => document,
+                        }
+                    })
+                    .collect::<Vec<_>>(),
+            )
+    }
+}
+
+/// Dial every address of every provider and report which ones accept a connection.
+///
+/// Each address is dialed from a fresh node, so connections of the query or to other addresses of
+/// the same peer don't hide unreachable addresses. All dials run concurrently.
+pub async fn dial_providers(
+    providers: &[ContentProvider],
+    settings: &Settings,
+    socket: &SocketOptions,
+) -> Vec<PeerReachability> {
+    join_all(
+        providers
+            .iter()
+            .map(|provider| dial_peer(provider.peer, &provider.addresses, settings, socket, false)),
+    )
+    .await
+}
+
+/// Dial every address of `peer` like [`dial_providers`] and also collect the identify information
+/// the peer sends over each connection.
+pub async fn verify_peer(
+    peer: PeerId,
+    addresses: &[Multiaddr],
+    settings: &Settings,
+    socket: &SocketOptions,
+) -> PeerReachability {
+    dial_peer(peer, addresses, settings, socket, true).await
+}
+
+/// Dial every address of `peer`, each from a fresh node, collecting the identify information
+/// with `identify` set.
+pub async fn dial_peer(
+    peer: PeerId,
+    addresses: &[Multiaddr],
+    settings: &Settings,
+    socket: &SocketOptions,
+    identify: bool,
+) -> PeerReachability {
+    let addresses = join_all(addresses.iter().map(|address| async move {
+        let mut dialed = address.clone();
+        if !matches!(dialed.iter().last(), Some(Protocol::P2p(_))) {
+            dialed.push(Protocol::P2p(peer.into()));
+        }
+        let (result, identified) = dial(dialed, settings, socket, identify).await;
+        AddressOutcome {
+            address: address.clone(),
+            result,
+            identified,
+        }
+    }))
+    .await;
+
+    PeerReachability { peer, addresses }
+}
+
+/// Dial `address` from a new node and return the time it took to establish the connection.
+///
+/// With `identify` set, the node also waits for the identify information of the peer.
+async fn dial(
+    address: Multiaddr,
+    settings: &Settings,
+    socket: &SocketOptions,
+    identify: bool,
+) -> (Result<Duration, String>, Option<Identified>) {
+    let mut config = transport_config(settings, socket);
+    let mut identify_events = None;
+    if identify {
+        let (identify_config, events) =
+            IdentifyConfig::new(IDENTIFY_PROTOCOL_VERSION.into(), Some(USER_AGENT.into()));
+        config = config.with_libp2p_identify(identify_config);
+        identify_events = Some(events);
+    }
+    let mut litep2p = match Litep2p::new(config.build()) {
+        Ok(litep2p) => litep2p,
+        Err(error) => return (Err(format!("litep2p initialization error: {error}")), None),
+    };
+    let start = Instant::now();
+    if let Err(error) = litep2p.dial_address(address).await {
+        return (Err(error.to_string()), None);
+    }
+
+    let deadline = tokio::time::Instant::now() + settings.connection_open_timeout + DIAL_GRACE;
+    let elapsed = loop {
+        tokio::select! {
+            _ = tokio::time::sleep_until(deadline) => return (Err("timeout".to_string()), None),
+            event = litep2p.next_event() => match event {
+                Some(Litep2pEvent::ConnectionEstablished { .. }) => break start.elapsed(),
+                Some(Litep2pEvent::DialFailure { error, .. }) => return (Err(dial_error(error)), None),
+                Some(Litep2pEvent::ListDialFailures { errors }) => {
+                    let error = errors
+                        .into_iter()
+                        .next()
+                        .map_or_else(|| "dial failed".to_string(), |(_, error)| dial_error(error));
+                    return (Err(error), None);
+                },
+                Some(_) => {},
+                None => return (Err("litep2p terminated".to_string()), None),
+            },
+        }
+    };
+    let Some(mut identify_events) = identify_events else {
+        return (Ok(elapsed), None);
+    };
+
+    // Keep driving the node so the identify exchange makes progress.
+    let deadline = tokio::time::Instant::now() + IDENTIFY_TIMEOUT;
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep_until(deadline) => return (Ok(elapsed), None),
+            _ = litep2p.next_event() => {},
+            event = identify_events.next() => match event {
+                Some(IdentifyEvent::PeerIdentified {
+                    protocol_version,
+                    user_agent,
+                    supported_protocols,
+                    observed_address,
+                    listen_addresses,
+                    ..
+                }) => {
+                    let mut protocols: Vec<_> =
+                        supported_protocols.iter().map(ToString::to_string).collect();
+                    protocols.sort();
+                    let identified = Identified {
+                        protocol_version,
+                        user_agent,
+                        observed_address,
+                        listen_addresses,
+                        protocols,
+                    };
+                    return (Ok(elapsed), Some(identified));
+                },
+                None => return (Ok(elapsed), None),
+            },
+        }
+    }
+}
+
+fn dial_error(error: DialError) -> String {
+    match error {
+        DialError::Timeout => "timeout".to_string(),
+        error => format!("{error:?}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use litep2p::{config::ConfigBuilder, transport::tcp::config::Config as TcpConfig};
+
+    use super::*;
+    use crate::preset::Preset;
+
+    #[tokio::test]
+    async fn verifies_addresses_and_identifies() {
+        let (identify_config, mut identify_events) =
+            IdentifyConfig::new("/test/1.0.0".into(), Some("test-node".into()));
+        let mut node = Litep2p::new(
+            ConfigBuilder::new()
+                .with_tcp(TcpConfig {
+                    listen_addresses: vec!["/ip4/127.0.0.1/tcp/0".parse().unwrap()],
+                    ..Default::default()
+                })
+                .with_libp2p_identify(identify_config)
+                .build(),
+        )
+        .unwrap();
+        let peer = *node.local_peer_id();
+        let address = node.listen_addresses().next().unwrap().clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = node.next_event() => {},
+                    _ = identify_events.next() => {},
+                }
+            }
+        });
+
+        let closed = "/ip4/127.0.0.1/tcp/1".parse().unwrap();
+        let verification = verify_peer(
+            peer,
+            &[address.clone(), closed],
+            &Preset::Substrate.settings(),
+            &SocketOptions::default(),
+        )
+        .await;
+
+        assert!(verification.online());
+        let [reachable, unreachable] = &verification.addresses[..] else {
+            panic!("two outcomes expected");
+        };
+        assert_eq!(reachable.address, address);
+        assert!(reachable.result.is_ok());
+        let identified = reachable.identified.as_ref().unwrap();
+        assert_eq!(identified.user_agent.as_deref(), Some("test-node"));
+        assert_eq!(identified.protocol_version.as_deref(), Some("/test/1.0.0"));
+        assert!(identified.listen_addresses.contains(&address));
+        assert!(unreachable.result.is_err());
+        assert!(unreachable.identified.is_none());
+
+        let json = verification.json();
+        assert_eq!(json.get("online"), Some(&Json::from(true)));
+        let addresses = json.get("addresses").unwrap().as_array().unwrap();
+        assert_eq!(
+            addresses[0].get("identify").unwrap().get("user_agent"),
+            Some(&Json::from("test-node"))
+        );
+        assert_eq!(addresses[1].get("reachable"), Some(&Json::from(false)));
+    }
+}
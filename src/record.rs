@@ -0,0 +1,576 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Context};
+use futures::StreamExt;
+use litep2p::{
+    protocol::libp2p::kademlia::{
+        KademliaEvent, PeerRecord, Quorum, Record, RecordKey as KademliaKey,
+    },
+    PeerId,
+};
+use sha2::{Digest, Sha256};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    cancelled,
+    holders::{Availability, HolderProbe},
+    json::Json,
+    json_output, kademlia_protocol, known_peers,
+    namespace::Namespace,
+    parse_key, print_document, print_protocol_hint, print_statistics, print_violations, query_dht,
+    refresh::Refresh,
+    retry::parse_duration,
+    run_json, NodeStalled, Partial, Query, QueryArgs, QueryRun, STALL_TIMEOUT,
+};
+
+/// Run a GET_VALUE query and print the found records.
+#[derive(clap::Args, Debug)]
+pub struct GetRecordArgs {
+    /// Key (hex or CID) of the record to query.
+    #[arg(long, value_name = "KEY", value_parser = parse_key)]
+    key: KademliaKey,
+    /// Write record values that don't decode as the key's namespace to `<key>-<peer>.bin` files in
+    /// this directory.
+    #[arg(long, value_name = "DIR")]
+    dump_raw: Option<PathBuf>,
+    /// Rerun the query every this many seconds on the same node and report every change of the
+    /// value under the key, with the peers serving the old and the new value, until interrupted.
+    #[arg(long, value_name = "SECS", conflicts_with = "dump_raw")]
+    watch: Option<u64>,
+    /// Instead of rerunning the lookup, send GET_VALUE straight to the peers that returned the
+    /// record last and report which of them still have it. The lookup only reruns once none of
+    /// them does.
+    #[arg(long, requires = "watch")]
+    probe_holders: bool,
+}
+
+/// Publish a record with PUT_VALUE and report which peers stored it.
+#[derive(clap::Args, Debug)]
+pub struct PutRecordArgs {
+    /// Key (hex or CID) of the record to publish.
+    #[arg(long, value_name = "KEY", value_parser = parse_key)]
+    key: KademliaKey,
+    /// Record value (hex).
+    #[arg(long, value_name = "VALUE", value_parser = parse_value, required_unless_present = "value_file", conflicts_with = "value_file")]
+    value: Option<Vec<u8>>,
+    /// File with the raw record value.
+    #[arg(long, value_name = "PATH")]
+    value_file: Option<PathBuf>,
+    /// How long to wait for the PUT_VALUE requests to be delivered before checking which peers
+    /// store the record, e.g. 10s.
+    #[arg(long, value_name = "DURATION", default_value = "10s", value_parser = parse_duration)]
+    settle: Duration,
+}
+
+/// Decode a record value from a hex string.
+fn parse_value(hex: &str) -> Result<Vec<u8>, hex::FromHexError> {
+    hex::decode(hex)
+}
+
+/// Run the GET_VALUE query.
+pub async fn run(args: GetRecordArgs, query: &QueryArgs) -> anyhow::Result<()> {
+    let known_peers = known_peers(query).await?;
+    let kad_proto = kademlia_protocol(query);
+    let settings = query.preset.settings();
+    let namespace = Namespace::detect(args.key.as_ref());
+    progress!("Key namespace: {namespace}");
+
+    let dht_query = Query::Record(args.key.clone());
+    let run = query_dht(query, &dht_query, &kad_proto, known_peers, &settings, None).await?;
+
+    if let Some(interval) = args.watch {
+        if !json_output() {
+            print_statistics(&run, &settings);
+        }
+        if let Err(error) = run.result {
+            print_protocol_hint(query, &run.fan_out, &kad_proto);
+            return Err(error);
+        }
+        let probe = args
+            .probe_holders
+            .then(|| HolderProbe::new(&kad_proto, query))
+            .transpose()?;
+        let interval = Duration::from_secs(interval);
+        return watch(
+            run, query, &kad_proto, args.key, interval, &namespace, probe,
+        )
+        .await;
+    }
+
+    let dumped = match &args.dump_raw {
+        Some(directory) => dump_raw(directory, &run.records, &namespace)?,
+        None => Vec::new(),
+    };
+
+    if json_output() {
+        let document = run_json(&dht_query, &kad_proto, &run)
+            .field("namespace", namespace.to_string())
+            .field("dumped", dumped)
+            .field("limit_violations", query.limits.check_records(&run.records));
+        print_document(document);
+        return run.result;
+    }
+
+    print_statistics(&run, &settings);
+    if let Err(error) = run.result {
+        print_protocol_hint(query, &run.fan_out, &kad_proto);
+        return Err(error);
+    }
+    print_records(&run.records, &namespace);
+    print_violations(query.limits.check_records(&run.records));
+
+    Ok(())
+}
+
+fn print_records(records: &[PeerRecord], namespace: &Namespace) {
+    let now = Instant::now();
+
+    for PeerRecord { peer, record } in records {
+        println!("Record from {peer}:");
+        println!("  value: {}", hex::encode(&record.value));
+        match record.publisher {
+            Some(publisher) => println!("  publisher: {publisher}"),
+            None => println!("  publisher: unknown"),
+        }
+        match record.expires {
+            Some(expires) => println!(
+                "  expires in: {} s",
+                expires.saturating_duration_since(now).as_secs()
+            ),
+            None => println!("  expires in: unknown"),
+        }
+        for line in namespace.decode(&record.value).unwrap_or_default() {
+            println!("  {line}");
+        }
+    }
+}
+
+/// Distinct value found under a watched key.
+struct Version {
+    value: Vec<u8>,
+    /// Peers that returned the value.
+    peers: Vec<PeerId>,
+}
+
+/// Group `records` by value, keyed by the SHA-256 hash of the value.
+fn versions(records: &[PeerRecord]) -> BTreeMap<[u8; 32], Version> {
+    let mut versions: BTreeMap<[u8; 32], Version> = BTreeMap::new();
+
+    for PeerRecord { peer, record } in records {
+        versions
+            .entry(Sha256::digest(&record.value).into())
+            .or_insert_with(|| Version {
+                value: record.value.clone(),
+                peers: Vec::new(),
+            })
+            .peers
+            .push(*peer);
+    }
+
+    versions
+}
+
+/// Rerun GET_VALUE for `key` every `interval` on the node of the finished `run` and report the
+/// values found, with a diff of the values whenever they change. A rerun that finds no records
+/// keeps the previous values, and a node that stalls during a rerun is restarted.
+///
+/// With a `probe`, the peers that returned the values last are asked directly instead, and the
+/// lookup only reruns when none of them has the record anymore.
+async fn watch(
+    mut run: QueryRun,
+    query: &QueryArgs,
+    kad_proto: &str,
+    key: KademliaKey,
+    interval: Duration,
+    namespace: &Namespace,
+    mut probe: Option<HolderProbe>,
+) -> anyhow::Result<()> {
+    let mut current = versions(&run.records);
+    let mut previous = BTreeMap::new();
+    let mut probed = Vec::new();
+    let mut refresh = Refresh::new();
+
+    for iteration in 1.. {
+        print_watch_iteration(iteration, namespace, &current, &previous, &probed);
+
+        run.maintain_until(tokio::time::Instant::now() + interval, &mut refresh)
+            .await?;
+        if let Some(probe) = &mut probe {
+            let holders: Vec<_> = current
+                .values()
+                .flat_map(|version| &version.peers)
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .map(|peer| {
+                    let addresses = run.identified.get(peer).cloned().unwrap_or_default();
+                    (*peer, addresses)
+                })
+                .collect();
+            probed = probe.probe(&key, &holders).await?;
+            let records: Vec<_> = probed
+                .iter()
+                .filter_map(|(peer, availability)| match availability {
+                    Availability::Holds(value) => Some(PeerRecord {
+                        peer: *peer,
+                        record: Record::new(key.clone(), value.clone()),
+                    }),
+                    _ => None,
+                })
+                .collect();
+            if !records.is_empty() {
+                previous = std::mem::replace(&mut current, versions(&records));
+                continue;
+            }
+            progress!(
+                "Iteration {}: no known holder has the record, rerunning the lookup",
+                iteration + 1
+            );
+        }
+
+        match collect_records(&mut run, &key, None)
+            .await
+            .map(Partial::into_inner)
+        {
+            Ok(records) if records.is_empty() => {
+                progress!(
+                    "Iteration {}: GET_VALUE query found no records",
+                    iteration + 1
+                );
+            }
+            Ok(records) => {
+                previous = std::mem::replace(&mut current, versions(&records));
+            }
+            Err(error) if error.is::<NodeStalled>() => {
+                progress!("Iteration {}: {error}", iteration + 1);
+                run.restart(query, kad_proto).await?;
+                refresh.restart();
+            }
+            Err(error) => return Err(error),
+        }
+    }
+
+    Ok(())
+}
+
+/// Print the values of a watch iteration with the peers serving them, and if they changed since
+/// the `previous` iteration, the lines of the old values missing from the new ones with `-` and
+/// the new lines with `+`. The `probed` holders that didn't return the record are listed too.
+fn print_watch_iteration(
+    iteration: usize,
+    namespace: &Namespace,
+    current: &BTreeMap<[u8; 32], Version>,
+    previous: &BTreeMap<[u8; 32], Version>,
+    probed: &[(PeerId, Availability)],
+) {
+    let lost: Vec<_> = probed
+        .iter()
+        .filter_map(|(peer, availability)| match availability {
+            Availability::Holds(_) => None,
+            Availability::Missing => Some((peer, "missing".to_string())),
+            Availability::Unreachable(error) => Some((peer, format!("unreachable: {error}"))),
+        })
+        .collect();
+    let changed = !previous.is_empty() && current.keys().ne(previous.keys());
+    let removed: Vec<_> = previous
+        .iter()
+        .filter(|(hash, _)| !current.contains_key(*hash))
+        .collect();
+
+    if json_output() {
+        let versions: Vec<_> = current
+            .iter()
+            .map(|(hash, version)| {
+                Json::object()
+                    .field("hash", hex::encode(hash))
+                    .field("value", hex::encode(&version.value))
+                    .field("new", changed && !previous.contains_key(hash))
+                    .field(
+                        "peers",
+                        version
+                            .peers
+                            .iter()
+                            .map(ToString::to_string)
+                            .collect::<Vec<_>>(),
+                    )
+            })
+            .collect();
+        let document = Json::object()
+            .field("iteration", iteration)
+            .field("changed", changed)
+            .field("versions", versions)
+            .field(
+                "removed",
+                removed
+                    .iter()
+                    .map(|(hash, _)| hex::encode(hash))
+                    .collect::<Vec<_>>(),
+            )
+            .field(
+                "lost_holders",
+                lost.iter()
+                    .map(|(peer, reason)| {
+                        Json::object()
+                            .field("peer_id", peer.to_string())
+                            .field("reason", reason.as_str())
+                    })
+                    .collect::<Vec<_>>(),
+            );
+        println!("{document}");
+        return;
+    }
+
+    println!();
+    println!("Iteration {iteration}: {} values", current.len());
+    for (hash, version) in current {
+        let marker = if changed && !previous.contains_key(hash) {
+            '+'
+        } else {
+            ' '
+        };
+        println!(
+            "{marker} {} from {} peers",
+            hex::encode(hash),
+            version.peers.len()
+        );
+        for peer in &version.peers {
+            println!("    {peer}");
+        }
+    }
+    for (hash, version) in &removed {
+        println!(
+            "- {} last served by {} peers",
+            hex::encode(hash),
+            version.peers.len()
+        );
+    }
+    if !probed.is_empty() {
+        println!(
+            "Probed {} holders, {} have the record",
+            probed.len(),
+            probed.len() - lost.len()
+        );
+        for (peer, reason) in &lost {
+            println!("  {peer}: {reason}");
+        }
+    }
+    if !changed {
+        return;
+    }
+
+    println!("Value changed:");
+    let lines = |versions: &mut dyn Iterator<Item = &Version>| -> Vec<String> {
+        versions
+            .flat_map(|version| {
+                namespace
+                    .decode(&version.value)
+                    .unwrap_or_else(|| vec![hex::encode(&version.value)])
+            })
+            .collect()
+    };
+    let old = lines(&mut removed.iter().map(|(_, version)| *version));
+    let new = lines(
+        &mut current
+            .iter()
+            .filter(|(hash, _)| !previous.contains_key(*hash))
+            .map(|(_, version)| version),
+    );
+    let (old_set, new_set): (HashSet<_>, HashSet<_>) = (old.iter().collect(), new.iter().collect());
+    for line in old.iter().filter(|line| !new_set.contains(line)) {
+        println!("  - {line}");
+    }
+    for line in new.iter().filter(|line| !old_set.contains(line)) {
+        println!("  + {line}");
+    }
+}
+
+/// Write the values of `records` that don't decode as `namespace` to `directory`, creating it if
+/// needed, and return the paths written.
+fn dump_raw(
+    directory: &Path,
+    records: &[PeerRecord],
+    namespace: &Namespace,
+) -> anyhow::Result<Vec<String>> {
+    let mut dumped = Vec::new();
+
+    for PeerRecord { peer, record } in records {
+        if namespace.decodes(&record.value) {
+            continue;
+        }
+
+        std::fs::create_dir_all(directory)
+            .with_context(|| format!("failed to create {}", directory.display()))?;
+        let path = directory.join(format!("{}-{peer}.bin", hex::encode(record.key.as_ref())));
+        std::fs::write(&path, &record.value)
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        progress!("Raw value from {peer} written to {}", path.display());
+        dumped.push(path.display().to_string());
+    }
+
+    Ok(dumped)
+}
+
+/// Publish the record and check which peers store it.
+///
+/// The record is put after a GET_VALUE lookup of the key that fills the routing table around it.
+/// litep2p doesn't report delivery of PUT_VALUE requests, so after the settle time the record is
+/// queried again and every remote peer returning the published value counts as having accepted it.
+pub async fn run_put(args: PutRecordArgs, query: &QueryArgs) -> anyhow::Result<()> {
+    let value = match (&args.value, &args.value_file) {
+        (Some(value), _) => value.clone(),
+        (None, Some(path)) => {
+            std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?
+        }
+        (None, None) => unreachable!("required by clap; qed"),
+    };
+    if let Some(violation) = query.limits.check_value(&value) {
+        progress!("Warning: {violation}");
+    }
+    let known_peers = known_peers(query).await?;
+    let kad_proto = kademlia_protocol(query);
+    let settings = query.preset.settings();
+
+    let dht_query = Query::Record(args.key.clone());
+    let mut run = query_dht(query, &dht_query, &kad_proto, known_peers, &settings, None).await?;
+    let document = run_json(&dht_query, &kad_proto, &run);
+    if run.result.is_err() && run.fan_out.responses() == 0 {
+        if json_output() {
+            print_document(document);
+        } else {
+            print_statistics(&run, &settings);
+            print_protocol_hint(query, &run.fan_out, &kad_proto);
+        }
+        return Err(anyhow!("no peer responded to the key lookup"));
+    }
+    if !json_output() {
+        print_statistics(&run, &settings);
+    }
+    progress!("Records found before publishing: {}", run.records.len());
+
+    let value_size = value.len();
+    let holders = publish(&mut run, args.key, value, args.settle, None)
+        .await?
+        .into_inner();
+    if json_output() {
+        let document = document.field("value_size", value_size).field(
+            "holders",
+            holders
+                .iter()
+                .map(|peer| peer.to_string())
+                .collect::<Vec<_>>(),
+        );
+        print_document(document);
+        return Ok(());
+    }
+    println!("Peers storing the record: {}", holders.len());
+    for peer in holders {
+        println!("{peer}");
+    }
+
+    Ok(())
+}
+
+/// Put `value` under `key` on the node of `run`, which looked the key up, and return the remote
+/// peers storing it after `settle`.
+///
+/// Cancelling `cancel` stops waiting and returns the peers found to store the record so far.
+pub(crate) async fn publish(
+    run: &mut QueryRun,
+    key: KademliaKey,
+    value: Vec<u8>,
+    settle: Duration,
+    cancel: Option<&CancellationToken>,
+) -> anyhow::Result<Partial<Vec<PeerId>>> {
+    progress!("Running PUT_VALUE with {} byte value...", value.len());
+    run.kademlia_handle
+        .put_record(Record::new(key.clone(), value.clone()))
+        .await;
+    tokio::select! {
+        result = run.drive_until(tokio::time::Instant::now() + settle) => result?,
+        _ = cancelled(cancel) => return Ok(Partial::Cancelled(Vec::new())),
+    }
+
+    progress!("Checking which peers store the record...");
+    record_holders(run, &key, &value, cancel).await
+}
+
+/// Query `key` from all peers close to it and return the remote ones storing `value`.
+async fn record_holders(
+    run: &mut QueryRun,
+    key: &KademliaKey,
+    value: &[u8],
+    cancel: Option<&CancellationToken>,
+) -> anyhow::Result<Partial<Vec<PeerId>>> {
+    let local_peer_id = *run.litep2p.local_peer_id();
+    let holders = collect_records(run, key, cancel).await?.map(|records| {
+        records
+            .into_iter()
+            .filter(|record| record.peer != local_peer_id && record.record.value == value)
+            .map(|record| record.peer)
+            .collect()
+    });
+
+    Ok(holders)
+}
+
+/// Query `key` from all peers close to it and return the records they returned, or
+/// [`NodeStalled`] if the node stops producing events.
+async fn collect_records(
+    run: &mut QueryRun,
+    key: &KademliaKey,
+    cancel: Option<&CancellationToken>,
+) -> anyhow::Result<Partial<Vec<PeerRecord>>> {
+    let query_id = run
+        .kademlia_handle
+        .get_record(key.clone(), Quorum::All)
+        .await;
+    let mut records = Vec::new();
+
+    loop {
+        tokio::select! {
+            _ = cancelled(cancel) => return Ok(Partial::Cancelled(records)),
+            _ = run.litep2p.next_event() => {},
+            _ = run.identify_events.next() => {},
+            event = run.kademlia_handle.next() => match event {
+                Some(KademliaEvent::GetRecordPartialResult { query_id: id, record }) if id == query_id => {
+                    records.push(record);
+                },
+                Some(KademliaEvent::GetRecordSuccess { query_id: id })
+                | Some(KademliaEvent::QueryFailed { query_id: id }) if id == query_id => {
+                    return Ok(Partial::Complete(records))
+                },
+                Some(_) => {},
+                None => return Err(anyhow!("libp2p Kademlia terminated")),
+            },
+            _ = tokio::time::sleep(STALL_TIMEOUT) => return Err(NodeStalled.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn groups_records_by_value() {
+        let key = KademliaKey::new(&b"key".to_vec());
+        let (old, new) = (PeerId::random(), PeerId::random());
+        let record = |peer, value: &[u8]| PeerRecord {
+            peer,
+            record: Record::new(key.clone(), value.to_vec()),
+        };
+
+        let versions = versions(&[
+            record(old, b"old"),
+            record(new, b"new"),
+            record(old, b"new"),
+        ]);
+        assert_eq!(versions.len(), 2);
+        let hash: [u8; 32] = Sha256::digest(b"new").into();
+        assert_eq!(versions[&hash].value, b"new");
+        assert_eq!(versions[&hash].peers, vec![new, old]);
+    }
+}
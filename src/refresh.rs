@@ -0,0 +1,129 @@
+use std::time::Duration;
+
+use litep2p::{
+    protocol::libp2p::kademlia::{KademliaEvent, KademliaHandle, QueryId},
+    PeerId,
+};
+use tokio::time::Instant;
+
+/// How often the long-running commands refresh the routing table.
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(600);
+
+/// Background refreshes of the routing table of a long-running node: one FIND_NODE query for a
+/// random peer every [`REFRESH_INTERVAL`], so the table stays fresh between the queries the node
+/// runs.
+pub struct Refresh {
+    /// Refresh query in progress.
+    query: Option<QueryId>,
+    /// When the next refresh is due.
+    due: Instant,
+    /// When the routing table was last refreshed.
+    refreshed: Instant,
+    started: u64,
+    failed: u64,
+}
+
+impl Refresh {
+    /// Refreshes of a node whose routing table was just populated.
+    pub fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            query: None,
+            due: now + REFRESH_INTERVAL,
+            refreshed: now,
+            started: 0,
+            failed: 0,
+        }
+    }
+
+    /// When the next refresh can start: when it is due and no other refresh is running.
+    pub fn due(&self) -> Option<Instant> {
+        self.query.is_none().then_some(self.due)
+    }
+
+    /// Start a refresh query on `handle` if one is due.
+    pub async fn start(&mut self, handle: &mut KademliaHandle) {
+        if self.due().is_some_and(|due| due <= Instant::now()) {
+            self.query = Some(handle.find_node(PeerId::random()).await);
+            self.due = Instant::now() + REFRESH_INTERVAL;
+            self.started += 1;
+        }
+    }
+
+    /// Account `event` if it ends the refresh query.
+    pub fn on_event(&mut self, event: &KademliaEvent) {
+        match event {
+            KademliaEvent::FindNodeSuccess { query_id, .. } if self.query == Some(*query_id) => {
+                progress!("Refreshed the routing table");
+                self.query = None;
+                self.refreshed = Instant::now();
+            }
+            KademliaEvent::QueryFailed { query_id } if self.query == Some(*query_id) => {
+                progress!("Routing table refresh failed");
+                self.query = None;
+                self.failed += 1;
+            }
+            _ => {}
+        }
+    }
+
+    /// Stop waiting for the refresh in progress, whose end would go unnoticed while the node runs
+    /// other queries. The query itself keeps refreshing the table in the background.
+    pub fn abandon(&mut self) {
+        self.query = None;
+    }
+
+    /// Forget the refresh in progress of a node that was replaced by a freshly populated one.
+    pub fn restart(&mut self) {
+        self.query = None;
+        self.refreshed = Instant::now();
+    }
+
+    /// Refresh queries started.
+    pub fn started(&self) -> u64 {
+        self.started
+    }
+
+    /// Refresh queries failed.
+    pub fn failed(&self) -> u64 {
+        self.failed
+    }
+
+    /// When the routing table was last refreshed.
+    pub fn refreshed(&self) -> Instant {
+        self.refreshed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn tracks_refresh_queries() {
+        let mut refresh = Refresh::new();
+        assert_eq!(refresh.due(), Some(Instant::now() + REFRESH_INTERVAL));
+
+        tokio::time::advance(REFRESH_INTERVAL).await;
+        refresh.query = Some(QueryId(1));
+        assert_eq!(refresh.due(), None);
+        refresh.on_event(&KademliaEvent::QueryFailed {
+            query_id: QueryId(2),
+        });
+        assert_eq!((refresh.query, refresh.failed()), (Some(QueryId(1)), 0));
+        refresh.on_event(&KademliaEvent::QueryFailed {
+            query_id: QueryId(1),
+        });
+        assert_eq!((refresh.query, refresh.failed()), (None, 1));
+        assert_eq!(refresh.refreshed().elapsed(), REFRESH_INTERVAL);
+
+        refresh.query = Some(QueryId(3));
+        refresh.on_event(&KademliaEvent::FindNodeSuccess {
+            query_id: QueryId(3),
+            target: PeerId::random(),
+            peers: Vec::new(),
+        });
+        assert_eq!(refresh.query, None);
+        assert_eq!(refresh.refreshed(), Instant::now());
+    }
+}
@@ -0,0 +1,74 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use litep2p::BandwidthSink;
+
+use crate::json::Json;
+
+/// DNS-over-HTTPS requests sent, including retries.
+static DNS_QUERIES: AtomicUsize = AtomicUsize::new(0);
+
+/// Count a DNS-over-HTTPS request.
+pub fn on_dns_query() {
+    DNS_QUERIES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Resources used by the tool during a run.
+pub struct Usage {
+    /// Peak resident set size in bytes, only known on Linux.
+    peak_memory: Option<u64>,
+    /// Most connections open at the same time.
+    peak_connections: usize,
+    dns_queries: usize,
+    inbound_bytes: usize,
+    outbound_bytes: usize,
+}
+
+impl Usage {
+    /// Measure the usage so far of a node that had at most `peak_connections` open at once.
+    pub fn measure(peak_connections: usize, bandwidth: &BandwidthSink) -> Self {
+        Self {
+            peak_memory: peak_memory(),
+            peak_connections,
+            dns_queries: DNS_QUERIES.load(Ordering::Relaxed),
+            inbound_bytes: bandwidth.inbound(),
+            outbound_bytes: bandwidth.outbound(),
+        }
+    }
+
+    pub fn print(&self) {
+        match self.peak_memory {
+            Some(bytes) => println!("Peak memory: {:.1} MiB", bytes as f64 / (1 << 20) as f64),
+            None => println!("Peak memory: unknown"),
+        }
+        println!("Peak open connections: {}", self.peak_connections);
+        println!("DNS-over-HTTPS queries: {}", self.dns_queries);
+        println!(
+            "Bytes on the wire: {} received, {} sent",
+            self.inbound_bytes, self.outbound_bytes
+        );
+    }
+
+    pub fn json(&self) -> Json {
+        Json::object()
+            .field("peak_memory_bytes", self.peak_memory)
+            .field("peak_connections", self.peak_connections)
+            .field("dns_queries", self.dns_queries)
+            .field("inbound_bytes", self.inbound_bytes)
+            .field("outbound_bytes", self.outbound_bytes)
+    }
+}
+
+/// Peak resident set size of the process (`VmHWM`) in bytes.
+fn peak_memory() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kib: u64 = line
+        .trim_start_matches("VmHWM:")
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()?;
+
+    Some(kib * 1024)
+}
@@ -0,0 +1,201 @@
+use std::{future::Future, str::FromStr, time::Duration};
+
+use anyhow::{anyhow, Context};
+use rand::Rng;
+
+/// Retry policy applied to dials, DNS lookups and queries.
+///
+/// Parsed from a policy string like `3x, backoff=2s..30s, jitter`: up to 3 retries with
+/// exponential backoff starting at 2 s and capped at 30 s, each delay randomized to 50-100%.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    retries: usize,
+    min_backoff: Duration,
+    max_backoff: Duration,
+    jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 0,
+            min_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(30),
+            jitter: false,
+        }
+    }
+}
+
+/// Parse a duration like `500ms`, `2s` or `1m`.
+pub fn parse_duration(s: &str) -> anyhow::Result<Duration> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+    let value: u64 = value
+        .parse()
+        .with_context(|| format!("invalid duration `{s}`"))?;
+
+    let seconds = |multiplier: u64| {
+        value
+            .checked_mul(multiplier)
+            .map(Duration::from_secs)
+            .ok_or_else(|| anyhow!("duration `{s}` is too long"))
+    };
+    match unit {
+        "ms" => Ok(Duration::from_millis(value)),
+        "s" | "" => Ok(Duration::from_secs(value)),
+        "m" => seconds(60),
+        "h" => seconds(60 * 60),
+        _ => Err(anyhow!("invalid duration unit in `{s}`")),
+    }
+}
+
+impl FromStr for RetryPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut policy = RetryPolicy::default();
+
+        for item in s.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+            if item == "none" {
+                policy.retries = 0;
+            } else if item == "jitter" {
+                policy.jitter = true;
+            } else if let Some(retries) = item.strip_suffix('x') {
+                policy.retries = retries
+                    .parse()
+                    .with_context(|| format!("invalid retry count `{item}`"))?;
+            } else if let Some(backoff) = item.strip_prefix("backoff=") {
+                let (min, max) = backoff.split_once("..").unwrap_or((backoff, backoff));
+                policy.min_backoff = parse_duration(min)?;
+                policy.max_backoff = parse_duration(max)?;
+                if policy.min_backoff > policy.max_backoff {
+                    return Err(anyhow!("minimum backoff exceeds maximum in `{item}`"));
+                }
+            } else {
+                return Err(anyhow!("unknown retry policy item `{item}`"));
+            }
+        }
+
+        Ok(policy)
+    }
+}
+
+impl RetryPolicy {
+    /// Number of retries after the first attempt.
+    pub fn retries(&self) -> usize {
+        self.retries
+    }
+
+    /// Delay before retry number `retry` (starting from 0).
+    pub fn backoff(&self, retry: usize) -> Duration {
+        let factor = 1u32.checked_shl(retry.min(31) as u32).unwrap_or(u32::MAX);
+        let delay = self
+            .min_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff);
+
+        if self.jitter {
+            delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+        } else {
+            delay
+        }
+    }
+
+    /// Run `operation` until it succeeds or the retries are exhausted.
+    pub async fn run<T, E, F, Fut>(&self, mut operation: F) -> Result<T, E>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let mut retry = 0;
+        loop {
+            match operation().await {
+                Ok(value) => return Ok(value),
+                Err(error) if retry >= self.retries => return Err(error),
+                Err(_) => {
+                    tokio::time::sleep(self.backoff(retry)).await;
+                    retry += 1;
+                }
+            }
+        }
+    }
+}
+
+/// Sleep until the deadline. Never resolves if there is no deadline.
+pub async fn sleep_until(deadline: Option<tokio::time::Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("500ms").unwrap(), Duration::from_millis(500));
+        assert_eq!(parse_duration("2s").unwrap(), Duration::from_secs(2));
+        assert_eq!(parse_duration(" 7 ").unwrap(), Duration::from_secs(7));
+        assert_eq!(parse_duration("1m").unwrap(), Duration::from_secs(60));
+        assert_eq!(parse_duration("2h").unwrap(), Duration::from_secs(7200));
+        assert!(parse_duration("1d").is_err());
+        assert!(parse_duration("s").is_err());
+        assert!(parse_duration(&format!("{}h", u64::MAX)).is_err());
+        assert!(parse_duration(&format!("{}m", u64::MAX / 59)).is_err());
+    }
+
+    #[test]
+    fn parses_no_retries() {
+        let policy = RetryPolicy::from_str("none").unwrap();
+        assert_eq!(policy.retries(), 0);
+        assert!(!policy.jitter);
+        assert_eq!(RetryPolicy::from_str("").unwrap().retries(), 0);
+        assert_eq!(RetryPolicy::from_str("3x, none").unwrap().retries(), 0);
+    }
+
+    #[test]
+    fn parses_full_policy() {
+        let policy = RetryPolicy::from_str("3x, backoff=2s..30s, jitter").unwrap();
+        assert_eq!(policy.retries(), 3);
+        assert_eq!(policy.min_backoff, Duration::from_secs(2));
+        assert_eq!(policy.max_backoff, Duration::from_secs(30));
+        assert!(policy.jitter);
+
+        let policy = RetryPolicy::from_str("2x,backoff=500ms").unwrap();
+        assert_eq!(policy.min_backoff, Duration::from_millis(500));
+        assert_eq!(policy.max_backoff, Duration::from_millis(500));
+    }
+
+    #[test]
+    fn rejects_invalid_policies() {
+        let error = RetryPolicy::from_str("backoff=30s..2s").unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("minimum backoff exceeds maximum"));
+        let error = RetryPolicy::from_str("3x, forever").unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("unknown retry policy item `forever`"));
+        assert!(RetryPolicy::from_str("manyx").is_err());
+        assert!(RetryPolicy::from_str("backoff=1y").is_err());
+    }
+
+    #[test]
+    fn caps_backoff() {
+        let policy = RetryPolicy::from_str("10x, backoff=2s..30s").unwrap();
+        let delays: Vec<_> = (0..6)
+            .map(|retry| policy.backoff(retry).as_secs())
+            .collect();
+        assert_eq!(delays, [2, 4, 8, 16, 30, 30]);
+        assert_eq!(policy.backoff(usize::MAX), Duration::from_secs(30));
+
+        let policy = RetryPolicy::from_str("backoff=2s..30s, jitter").unwrap();
+        for retry in 0..10 {
+            let delay = policy.backoff(retry);
+            assert!(delay >= Duration::from_secs(1) && delay <= Duration::from_secs(30));
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::Write,
+    path::Path,
+};
+
+use anyhow::Context;
+use litep2p::PeerId;
+use multiaddr::{Multiaddr, Protocol};
+
+use crate::{address::is_private, csv::Sighting};
+
+/// Write the peers with known addresses to `path` as `<multiaddr>/p2p/<peer id>` lines, the
+/// format of `--known-peers-file`, and return the number of peers written.
+///
+/// Addresses are the ones the contacted peers were dialed at, their identify listen addresses and
+/// the addresses of the closest peers found. Private addresses are skipped unless
+/// `allow_private` is set.
+pub fn write_routing_table(
+    path: &Path,
+    contacted: &HashMap<PeerId, Sighting>,
+    identified: &HashMap<PeerId, Vec<Multiaddr>>,
+    closest_peers: &[(PeerId, Vec<Multiaddr>)],
+    allow_private: bool,
+) -> anyhow::Result<usize> {
+    let mut peers: HashMap<PeerId, BTreeSet<Multiaddr>> = HashMap::new();
+    let addresses = contacted
+        .iter()
+        .map(|(peer, sighting)| (peer, &sighting.addresses))
+        .chain(identified)
+        .chain(
+            closest_peers
+                .iter()
+                .map(|(peer, addresses)| (peer, addresses)),
+        );
+    for (peer, addresses) in addresses {
+        for address in addresses {
+            if !allow_private && is_private(address) {
+                continue;
+            }
+            let mut address = address.clone();
+            if !matches!(address.iter().last(), Some(Protocol::P2p(_))) {
+                address.push(Protocol::P2p((*peer).into()));
+            }
+            peers.entry(*peer).or_default().insert(address);
+        }
+    }
+
+    let mut lines: Vec<_> = peers.values().flatten().map(ToString::to_string).collect();
+    lines.sort();
+    let mut cache = format!("# dht-inspect routing table cache, {} peers\n", peers.len());
+    for line in lines {
+        let _ = writeln!(cache, "{line}");
+    }
+    std::fs::write(path, cache).with_context(|| format!("failed to write {}", path.display()))?;
+
+    Ok(peers.len())
+}
@@ -0,0 +1,198 @@
+use std::{net::IpAddr, time::Duration};
+
+use anyhow::{anyhow, Context};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+};
+use tokio_rustls::{rustls::ServerName, TlsConnector};
+use url::{Host, Url};
+
+use crate::{
+    doh::{parse_http_response, read_http_response, tls_connector},
+    json::Json,
+};
+
+/// Time allowed for one JSON-RPC request, from connecting to reading the whole response.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Storage key of `Session::CurrentIndex`: `twox128("Session") ++ twox128("CurrentIndex")`.
+const SESSION_INDEX_KEY: &str =
+    "0xcec5070d609dd3497f72bde07fc96ba072763800a36a99fdfc7c10f6415f6ee6";
+
+/// Storage key of `Staking::ActiveEra`: `twox128("Staking") ++ twox128("ActiveEra")`.
+const ACTIVE_ERA_KEY: &str = "0x5f3e4907f716ac89b6347d15ececedca487df464e44a534ba6b0cbb32407b587";
+
+/// Session and era of the relay chain at the best block.
+pub struct ChainEpoch {
+    pub session: u32,
+    /// `None` if the chain has no staking pallet, e.g. after staking moved to Asset Hub.
+    pub era: Option<u32>,
+}
+
+/// Substrate JSON-RPC client over HTTP(S).
+pub struct RelayChainRpc {
+    url: Url,
+    /// Connector of `https://` endpoints.
+    connector: Option<TlsConnector>,
+}
+
+impl RelayChainRpc {
+    /// Create new [`RelayChainRpc`] sending requests to `url`.
+    pub fn new(url: &str) -> anyhow::Result<Self> {
+        let url = Url::parse(url).context("invalid RPC URL")?;
+        let connector = match url.scheme() {
+            "http" => None,
+            "https" => Some(tls_connector()?),
+            _ => {
+                return Err(anyhow!(
+                    "RPC URL must be http:// or https://, WebSocket is not supported"
+                ))
+            }
+        };
+        if url.host_str().is_none() {
+            return Err(anyhow!("RPC URL has no host"));
+        }
+
+        Ok(Self { url, connector })
+    }
+
+    /// Query the current session and era.
+    pub async fn epoch(&self) -> anyhow::Result<ChainEpoch> {
+        let session = self
+            .storage_u32(SESSION_INDEX_KEY)
+            .await?
+            .ok_or_else(|| anyhow!("chain has no session pallet"))?;
+        let era = self.storage_u32(ACTIVE_ERA_KEY).await?;
+
+        Ok(ChainEpoch { session, era })
+    }
+
+    /// Read the SCALE-encoded `u32` at the start of the storage value under `key`.
+    async fn storage_u32(&self, key: &str) -> anyhow::Result<Option<u32>> {
+        let request =
+            format!(r#"{{"jsonrpc":"2.0","id":1,"method":"state_getStorage","params":["{key}"]}}"#);
+        let response = self.post(request).await?;
+        let response = Json::parse(&String::from_utf8_lossy(&response))?;
+        if let Some(error) = response.get("error") {
+            return Err(anyhow!("RPC request failed: {error}"));
+        }
+
+        match response.get("result") {
+            Some(Json::Null) => Ok(None),
+            Some(Json::String(value)) => {
+                let value =
+                    hex::decode(value.trim_start_matches("0x")).context("invalid storage value")?;
+                let bytes = value
+                    .get(..4)
+                    .ok_or_else(|| anyhow!("storage value too short"))?;
+                Ok(Some(u32::from_le_bytes(
+                    bytes.try_into().expect("4 bytes; qed"),
+                )))
+            }
+            _ => Err(anyhow!("invalid RPC response")),
+        }
+    }
+
+    /// POST a JSON-RPC request and return the response body.
+    ///
+    /// Fails if the request takes longer than [`REQUEST_TIMEOUT`].
+    async fn post(&self, body: String) -> anyhow::Result<Vec<u8>> {
+        tokio::time::timeout(REQUEST_TIMEOUT, self.request(body))
+            .await
+            .map_err(|_| {
+                anyhow!(
+                    "RPC request timed out after {} s",
+                    REQUEST_TIMEOUT.as_secs()
+                )
+            })?
+    }
+
+    async fn request(&self, body: String) -> anyhow::Result<Vec<u8>> {
+        let port = self.url.port_or_known_default().unwrap_or(80);
+        let (tcp, server_name) = match self.url.host().expect("checked in constructor; qed") {
+            Host::Domain(domain) => (
+                TcpStream::connect((domain, port)).await,
+                ServerName::try_from(domain),
+            ),
+            Host::Ipv4(ip) => (
+                TcpStream::connect((ip, port)).await,
+                Ok(ServerName::IpAddress(IpAddr::V4(ip))),
+            ),
+            Host::Ipv6(ip) => (
+                TcpStream::connect((ip, port)).await,
+                Ok(ServerName::IpAddress(IpAddr::V6(ip))),
+            ),
+        };
+        let tcp = tcp.context("failed to connect to RPC endpoint")?;
+        let request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            &self.url[url::Position::BeforePath..],
+            &self.url[url::Position::BeforeHost..url::Position::AfterPort],
+            body.len(),
+        );
+
+        let response = match &self.connector {
+            Some(connector) => {
+                let server_name = server_name.context("invalid RPC server name")?;
+                exchange(connector.connect(server_name, tcp).await?, &request).await?
+            }
+            None => exchange(tcp, &request).await?,
+        };
+
+        parse_http_response(&response)
+    }
+}
+
+/// Send `request` and read the response until the server closes the connection.
+async fn exchange(
+    mut stream: impl AsyncRead + AsyncWrite + Unpin,
+    request: &str,
+) -> anyhow::Result<Vec<u8>> {
+    stream.write_all(request.as_bytes()).await?;
+
+    read_http_response(&mut stream).await
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{io::AsyncReadExt, net::TcpListener};
+
+    use super::*;
+
+    /// Answer one request on `listener` with `result` as the JSON-RPC result.
+    async fn answer(listener: TcpListener, result: &'static str) {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = vec![0; 4096];
+        let _ = stream.read(&mut request).await.unwrap();
+        let body = format!(r#"{{"jsonrpc":"2.0","id":1,"result":{result}}}"#);
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        );
+        stream.write_all(response.as_bytes()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn connects_to_ipv6_endpoints() {
+        let listener = TcpListener::bind("[::1]:0").await.unwrap();
+        let url = format!("http://[::1]:{}", listener.local_addr().unwrap().port());
+        let server = tokio::spawn(answer(listener, r#""0x07000000""#));
+
+        let rpc = RelayChainRpc::new(&url).unwrap();
+        assert_eq!(rpc.storage_u32(SESSION_INDEX_KEY).await.unwrap(), Some(7));
+        server.await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn times_out_silent_endpoints() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        let rpc = RelayChainRpc::new(&url).unwrap();
+        let error = rpc.epoch().await.err().unwrap();
+        assert!(error.to_string().contains("timed out"), "{error}");
+        drop(listener);
+    }
+}
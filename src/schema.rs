@@ -0,0 +1,499 @@
+#[cfg(test)]
+use crate::json::Json;
+
+/// Version of [`SCHEMA`], stored in the `schema_version` field of the documents it describes.
+///
+/// Bumped whenever a field is removed, renamed or changes its type. Adding a field is a compatible
+/// change, so consumers should ignore fields they don't know.
+pub const SCHEMA_VERSION: usize = 1;
+
+/// JSON Schema (draft 2020-12) of the query, crawl, sharded crawl, audit and inspect result
+/// documents.
+pub const SCHEMA: &str = r##"{
+  "$schema": "https://json-schema.org/draft/2020-12/schema",
+  "title": "dht-inspect output",
+  "description": "Result documents printed with --output json, version 1.",
+  "anyOf": [
+    { "$ref": "#/$defs/query_result" },
+    { "$ref": "#/$defs/crawl_result" },
+    { "$ref": "#/$defs/sharded_crawl_result" },
+    { "$ref": "#/$defs/audit_result" },
+    { "$ref": "#/$defs/inspect_result" }
+  ],
+  "$defs": {
+    "schema_version": { "const": 1 },
+    "count": { "type": "integer", "minimum": 0 },
+    "peer": {
+      "type": "object",
+      "required": ["peer_id", "addresses"],
+      "properties": {
+        "peer_id": { "type": "string" },
+        "addresses": { "type": "array", "items": { "type": "string" } }
+      }
+    },
+    "resources": {
+      "type": "object",
+      "required": ["peak_memory_bytes", "peak_connections", "dns_queries", "inbound_bytes", "outbound_bytes"],
+      "properties": {
+        "peak_memory_bytes": { "type": ["integer", "null"], "minimum": 0 },
+        "peak_connections": { "$ref": "#/$defs/count" },
+        "dns_queries": { "$ref": "#/$defs/count" },
+        "inbound_bytes": { "$ref": "#/$defs/count" },
+        "outbound_bytes": { "$ref": "#/$defs/count" }
+      }
+    },
+    "reachability": {
+      "description": "Outcome of dialing every address of a peer from a fresh node.",
+      "type": "object",
+      "required": ["peer_id", "online", "addresses"],
+      "properties": {
+        "peer_id": { "type": "string" },
+        "online": { "type": "boolean" },
+        "addresses": {
+          "type": "array",
+          "items": {
+            "type": "object",
+            "required": ["address", "reachable"],
+            "properties": {
+              "address": { "type": "string" },
+              "reachable": { "type": "boolean" },
+              "connect_ms": { "$ref": "#/$defs/count" },
+              "error": { "type": "string" }
+            }
+          }
+        }
+      }
+    },
+    "query_result": {
+      "description": "Outcome of a GET_PROVIDERS, GET_VALUE or FIND_NODE query.",
+      "type": "object",
+      "required": ["schema_version", "query", "key", "protocol", "statistics", "error"],
+      "properties": {
+        "schema_version": { "$ref": "#/$defs/schema_version" },
+        "query": { "enum": ["GET_PROVIDERS", "GET_VALUE", "FIND_NODE"] },
+        "key": { "type": "string" },
+        "protocol": { "type": "string" },
+        "statistics": {
+          "type": "object",
+          "required": ["discovered_peers", "contacted_peers", "elapsed_ms", "resources"],
+          "properties": {
+            "discovered_peers": { "$ref": "#/$defs/count" },
+            "contacted_peers": { "$ref": "#/$defs/count" },
+            "elapsed_ms": { "$ref": "#/$defs/count" },
+            "resources": { "$ref": "#/$defs/resources" }
+          }
+        },
+        "providers": { "type": "array", "items": { "$ref": "#/$defs/peer" } },
+        "records": {
+          "type": "array",
+          "items": {
+            "type": "object",
+            "required": ["peer_id", "value", "publisher", "expires_in_s"],
+            "properties": {
+              "peer_id": { "type": "string" },
+              "value": { "type": "string" },
+              "publisher": { "type": ["string", "null"] },
+              "expires_in_s": { "type": ["integer", "null"], "minimum": 0 }
+            }
+          }
+        },
+        "closest_peers": { "type": "array", "items": { "$ref": "#/$defs/peer" } },
+        "error": { "type": ["string", "null"] }
+      }
+    },
+    "crawl_result": {
+      "description": "Summary of a crawl or exploration of the keyspace.",
+      "type": "object",
+      "required": [
+        "schema_version", "query", "protocol", "queries", "failed_queries", "dial_timeouts",
+        "stalling_peers", "elapsed_ms", "peers", "peers_with_addresses", "spilled_peers",
+        "contacted_peers", "identified_peers", "network_size_estimate", "coverage", "addresses",
+        "ips", "resources"
+      ],
+      "properties": {
+        "schema_version": { "$ref": "#/$defs/schema_version" },
+        "query": { "enum": ["CRAWL", "EXPLORE"] },
+        "protocol": { "type": "string" },
+        "queries": { "$ref": "#/$defs/count" },
+        "failed_queries": { "$ref": "#/$defs/count" },
+        "dial_timeouts": { "$ref": "#/$defs/count" },
+        "stalling_peers": { "$ref": "#/$defs/count" },
+        "elapsed_ms": { "$ref": "#/$defs/count" },
+        "peers": { "$ref": "#/$defs/count" },
+        "peers_with_addresses": { "$ref": "#/$defs/count" },
+        "spilled_peers": { "$ref": "#/$defs/count" },
+        "contacted_peers": { "$ref": "#/$defs/count" },
+        "identified_peers": { "$ref": "#/$defs/count" },
+        "network_size_estimate": { "type": ["number", "null"], "minimum": 0 },
+        "coverage": {
+          "type": "object",
+          "required": ["buckets", "queried", "inferred", "not_covered", "missed_peers"],
+          "properties": {
+            "buckets": { "$ref": "#/$defs/count" },
+            "queried": { "type": "number", "minimum": 0 },
+            "inferred": { "type": "number", "minimum": 0 },
+            "not_covered": { "type": "number", "minimum": 0 },
+            "missed_peers": { "type": ["number", "null"] }
+          }
+        },
+        "addresses": {
+          "type": "object",
+          "required": ["total", "private", "networks", "transports", "top_ports"],
+          "properties": {
+            "total": { "$ref": "#/$defs/count" },
+            "private": { "$ref": "#/$defs/count" },
+            "networks": { "type": "object" },
+            "transports": { "type": "object" },
+            "top_ports": {
+              "type": "array",
+              "items": {
+                "type": "object",
+                "required": ["port", "addresses"],
+                "properties": {
+                  "port": { "$ref": "#/$defs/count" },
+                  "addresses": { "$ref": "#/$defs/count" }
+                }
+              }
+            }
+          }
+        },
+        "ips": {
+          "type": "object",
+          "required": ["total", "shared", "peers_per_ip", "top_ips"],
+          "properties": {
+            "total": { "$ref": "#/$defs/count" },
+            "shared": { "$ref": "#/$defs/count" },
+            "peers_per_ip": { "type": "object" },
+            "top_ips": {
+              "type": "array",
+              "items": {
+                "type": "object",
+                "required": ["ip", "peers"],
+                "properties": {
+                  "ip": { "type": "string" },
+                  "peers": { "$ref": "#/$defs/count" }
+                }
+              }
+            }
+          }
+        },
+        "resources": { "$ref": "#/$defs/resources" },
+        "audit": {
+          "description": "Dials of the audit stage of crawl --pipeline, null without it.",
+          "type": ["object", "null"],
+          "required": ["dialed_peers", "reachable_peers"],
+          "properties": {
+            "dialed_peers": { "$ref": "#/$defs/count" },
+            "reachable_peers": { "$ref": "#/$defs/count" }
+          }
+        },
+        "discovered": {
+          "type": "array",
+          "items": {
+            "allOf": [
+              { "$ref": "#/$defs/peer" },
+              {
+                "type": "object",
+                "required": ["contacted"],
+                "properties": {
+                  "contacted": { "type": "boolean" },
+                  "reachable": {
+                    "description": "Whether the audit stage connected to the peer, absent if it didn't dial it.",
+                    "type": "boolean"
+                  }
+                }
+              }
+            ]
+          }
+        }
+      }
+    },
+    "sharded_crawl_result": {
+      "description": "Outcome of crawl --shards. Every shard crawled writes a crawl_result listing its peers to shard_dir.",
+      "type": "object",
+      "required": [
+        "schema_version", "query", "protocol", "shards", "crawled_shards", "skipped_shards",
+        "failed_shards", "shard_dir", "elapsed_ms"
+      ],
+      "properties": {
+        "schema_version": { "$ref": "#/$defs/schema_version" },
+        "query": { "const": "CRAWL" },
+        "protocol": { "type": "string" },
+        "shards": { "$ref": "#/$defs/count" },
+        "crawled_shards": { "$ref": "#/$defs/count" },
+        "skipped_shards": { "$ref": "#/$defs/count" },
+        "failed_shards": { "$ref": "#/$defs/count" },
+        "shard_dir": { "type": "string" },
+        "elapsed_ms": { "$ref": "#/$defs/count" }
+      }
+    },
+    "audit_result": {
+      "description": "Publication of a provider record by add-provider and, with --audit-expiry, whether it expired.",
+      "allOf": [
+        { "$ref": "#/$defs/query_result" },
+        {
+          "type": "object",
+          "required": ["published", "expired"],
+          "properties": {
+            "published": {
+              "type": ["object", "null"],
+              "required": ["provider_record_found", "record_addresses", "providers"],
+              "properties": {
+                "provider_record_found": { "type": "boolean" },
+                "record_addresses": { "type": ["integer", "null"], "minimum": 0 },
+                "providers": { "$ref": "#/$defs/count" }
+              }
+            },
+            "expired": { "type": ["boolean", "null"] }
+          }
+        }
+      ]
+    },
+    "inspect_result": {
+      "description": "GET_PROVIDERS query of inspect, with the audit of the peers closest to the key, the provider dials and the verdict.",
+      "allOf": [
+        { "$ref": "#/$defs/query_result" },
+        {
+          "type": "object",
+          "required": ["closest_peer_audit", "reachability", "health"],
+          "properties": {
+            "closest_peer_audit": {
+              "type": "array",
+              "items": {
+                "type": "object",
+                "required": ["peer_id", "contacted", "reachability"],
+                "properties": {
+                  "peer_id": { "type": "string" },
+                  "contacted": { "type": "boolean" },
+                  "reachability": { "$ref": "#/$defs/reachability" }
+                }
+              }
+            },
+            "reachability": { "type": "array", "items": { "$ref": "#/$defs/reachability" } },
+            "health": {
+              "type": "object",
+              "required": ["status", "problems"],
+              "properties": {
+                "status": { "enum": ["healthy", "degraded", "unavailable"] },
+                "problems": { "type": "array", "items": { "type": "string" } }
+              }
+            }
+          }
+        }
+      ]
+    }
+  }
+}
+"##;
+
+/// Check `document` against the schema definition `name`.
+///
+/// Supports the keywords [`SCHEMA`] uses: `type`, `const`, `enum`, `minimum`, `required`,
+/// `properties`, `items`, `allOf`, `anyOf` and local `$ref`s.
+#[cfg(test)]
+pub fn validate(document: &Json, name: &str) -> Result<(), String> {
+    let schema = Json::parse(SCHEMA).expect("the schema is valid JSON; qed");
+    let definition = resolve(&schema, &format!("#/$defs/{name}"))?;
+
+    check(&schema, definition, document, "$")
+}
+
+#[cfg(test)]
+fn resolve<'a>(schema: &'a Json, reference: &str) -> Result<&'a Json, String> {
+    let path = reference
+        .strip_prefix("#/")
+        .ok_or_else(|| format!("unsupported reference {reference}"))?;
+    path.split('/').try_fold(schema, |node, key| {
+        node.get(key)
+            .ok_or_else(|| format!("unresolved reference {reference}"))
+    })
+}
+
+#[cfg(test)]
+fn check(schema: &Json, rule: &Json, value: &Json, path: &str) -> Result<(), String> {
+    if let Some(reference) = rule.get("$ref").and_then(Json::as_str) {
+        check(schema, resolve(schema, reference)?, value, path)?;
+    }
+    if let Some(types) = rule.get("type") {
+        let matches = |name: &Json| match (name.as_str(), value) {
+            (Some("null"), Json::Null)
+            | (Some("boolean"), Json::Bool(_))
+            | (Some("number"), Json::Number(_))
+            | (Some("string"), Json::String(_))
+            | (Some("array"), Json::Array(_))
+            | (Some("object"), Json::Object(_)) => true,
+            (Some("integer"), Json::Number(number)) => number.fract() == 0.0,
+            _ => false,
+        };
+        let allowed = match types {
+            Json::Array(types) => types.iter().any(matches),
+            name => matches(name),
+        };
+        if !allowed {
+            return Err(format!("{path}: {value} is not of type {types}"));
+        }
+    }
+    if let Some(expected) = rule.get("const") {
+        if expected != value {
+            return Err(format!("{path}: {value} is not {expected}"));
+        }
+    }
+    if let Some(values) = rule.get("enum").and_then(Json::as_array) {
+        if !values.contains(value) {
+            return Err(format!(
+                "{path}: {value} is not one of {}",
+                Json::from(values.to_vec())
+            ));
+        }
+    }
+    if let (Some(Json::Number(minimum)), Json::Number(number)) = (rule.get("minimum"), value) {
+        if number < minimum {
+            return Err(format!("{path}: {number} is below {minimum}"));
+        }
+    }
+    if let Json::Object(_) = value {
+        for field in rule
+            .get("required")
+            .and_then(Json::as_array)
+            .unwrap_or_default()
+        {
+            let field = field.as_str().unwrap_or_default();
+            if value.get(field).is_none() {
+                return Err(format!("{path}: missing field {field}"));
+            }
+        }
+        if let Some(Json::Object(properties)) = rule.get("properties") {
+            for (field, rule) in properties {
+                if let Some(value) = value.get(field) {
+                    check(schema, rule, value, &format!("{path}.{field}"))?;
+                }
+            }
+        }
+    }
+    if let (Some(rule), Json::Array(values)) = (rule.get("items"), value) {
+        for (i, value) in values.iter().enumerate() {
+            check(schema, rule, value, &format!("{path}[{i}]"))?;
+        }
+    }
+    for rule in rule
+        .get("allOf")
+        .and_then(Json::as_array)
+        .unwrap_or_default()
+    {
+        check(schema, rule, value, path)?;
+    }
+    if let Some(rules) = rule.get("anyOf").and_then(Json::as_array) {
+        if !rules
+            .iter()
+            .any(|rule| check(schema, rule, value, path).is_ok())
+        {
+            return Err(format!("{path}: {value} matches none of the alternatives"));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Minimal valid query result.
+    fn query_result() -> Json {
+        let resources = Json::object()
+            .field("peak_memory_bytes", None::<u64>)
+            .field("peak_connections", 3usize)
+            .field("dns_queries", 0usize)
+            .field("inbound_bytes", 1024usize)
+            .field("outbound_bytes", 512usize);
+        Json::object()
+            .field("schema_version", SCHEMA_VERSION)
+            .field("query", "FIND_NODE")
+            .field("key", "00")
+            .field("protocol", "/ipfs/kad/1.0.0")
+            .field(
+                "statistics",
+                Json::object()
+                    .field("discovered_peers", 10usize)
+                    .field("contacted_peers", 4usize)
+                    .field("elapsed_ms", 1500usize)
+                    .field("resources", resources),
+            )
+            .field("error", None::<String>)
+    }
+
+    #[test]
+    fn schema_is_valid_json() {
+        let schema = Json::parse(SCHEMA).unwrap();
+        assert_eq!(
+            schema
+                .get("$defs")
+                .and_then(|defs| defs.get("schema_version"))
+                .and_then(|version| version.get("const")),
+            Some(&Json::from(SCHEMA_VERSION))
+        );
+        for alternative in schema.get("anyOf").and_then(Json::as_array).unwrap() {
+            let reference = alternative.get("$ref").and_then(Json::as_str).unwrap();
+            resolve(&schema, reference).unwrap();
+        }
+    }
+
+    #[test]
+    fn validates_documents() {
+        validate(&query_result(), "query_result").unwrap();
+        let peer = Json::object()
+            .field("peer_id", "12D3KooW")
+            .field("addresses", vec!["/ip4/1.2.3.4/tcp/30333"]);
+        validate(
+            &query_result().field("closest_peers", vec![peer]),
+            "query_result",
+        )
+        .unwrap();
+
+        let audit = query_result()
+            .field("published", None::<Json>)
+            .field("expired", None::<bool>);
+        validate(&audit, "audit_result").unwrap();
+        assert!(validate(&query_result(), "audit_result").is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_documents() {
+        let Json::Object(fields) = query_result() else {
+            unreachable!()
+        };
+        let without = |name: &str| {
+            Json::Object(
+                fields
+                    .iter()
+                    .filter(|(field, _)| field != name)
+                    .cloned()
+                    .collect(),
+            )
+        };
+
+        let error = validate(&without("protocol"), "query_result").unwrap_err();
+        assert_eq!(error, "$: missing field protocol");
+        let document = without("query").field("query", "PUT_VALUE");
+        assert!(validate(&document, "query_result").is_err());
+        let document = without("schema_version").field("schema_version", 2usize);
+        assert!(validate(&document, "query_result").is_err());
+        let document = without("error").field("error", 1usize);
+        assert!(validate(&document, "query_result").is_err());
+        let document = query_result().field(
+            "closest_peers",
+            vec![Json::object().field("peer_id", "12D3KooW")],
+        );
+        let error = validate(&document, "query_result").unwrap_err();
+        assert_eq!(error, "$.closest_peers[0]: missing field addresses");
+        let document = without("statistics").field(
+            "statistics",
+            Json::object()
+                .field("discovered_peers", 1.5)
+                .field("contacted_peers", 0usize)
+                .field("elapsed_ms", 0usize)
+                .field("resources", Json::object()),
+        );
+        assert!(validate(&document, "query_result").is_err());
+    }
+}
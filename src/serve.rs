@@ -0,0 +1,342 @@
+use std::{collections::HashMap, net::SocketAddr, str::FromStr};
+
+use anyhow::{anyhow, Context};
+use futures::{
+    channel::{mpsc, oneshot},
+    StreamExt,
+};
+use litep2p::{
+    protocol::libp2p::kademlia::{ContentProvider, KademliaEvent, QueryId},
+    Litep2pEvent, PeerId,
+};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    time::Instant,
+};
+
+use crate::{
+    http::{self, read_request, write_response},
+    json::Json,
+    json_output, kademlia_protocol, known_peers, parse_key, peer_json, print_protocol_hint,
+    print_statistics, query_dht,
+    refresh::Refresh,
+    verify::record_identified,
+    NodeStalled, Query, QueryArgs, STALL_TIMEOUT,
+};
+
+/// Serve DHT queries over an HTTP API from a long-lived node.
+#[derive(clap::Args, Debug)]
+pub struct ServeArgs {
+    /// Address to serve the API at.
+    #[arg(long, value_name = "IP:PORT", default_value = "127.0.0.1:8080")]
+    listen: SocketAddr,
+}
+
+/// Page served at `/` to run queries from a browser and list their results.
+const PAGE: &str = include_str!("serve.html");
+
+/// Query received over the API with the channel to send its JSON result or error to.
+type ApiRequest = (Query, oneshot::Sender<Result<Json, String>>);
+
+/// API query in progress.
+struct PendingQuery {
+    query: Query,
+    responder: oneshot::Sender<Result<Json, String>>,
+    /// Responses with closer peers the node had received when the query started.
+    responses: usize,
+}
+
+/// Serve `GET /providers/<key>` and `GET /find-node/<peer id>` until interrupted, and at `GET /` a
+/// page to run these queries from a browser.
+///
+/// The node populates its routing table with a FIND_NODE query for a random peer first, then runs
+/// the queries of all requests concurrently, keeping the routing table and connections between
+/// them.
+///
+/// litep2p fails GET_PROVIDERS queries that find no providers. Such a query is answered with an
+/// empty provider list if the node received responses with closer peers while it ran, and with
+/// `502 Bad Gateway` otherwise.
+///
+/// The routing table is refreshed in the background every ten minutes. If the node stops producing
+/// events while queries are outstanding, it is restarted and the outstanding queries are answered
+/// with `502 Bad Gateway`.
+pub async fn run(args: ServeArgs, query: &QueryArgs) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(args.listen)
+        .await
+        .with_context(|| format!("failed to listen on {}", args.listen))?;
+
+    serve(listener, query).await
+}
+
+async fn serve(listener: TcpListener, query: &QueryArgs) -> anyhow::Result<()> {
+    let address = listener.local_addr()?;
+    let known_peers = known_peers(query).await?;
+    let kad_proto = kademlia_protocol(query);
+    let settings = query.preset.settings();
+    let warmup = Query::Peer(PeerId::random());
+    let mut run = query_dht(query, &warmup, &kad_proto, known_peers, &settings, None).await?;
+    if run.result.is_err() && run.fan_out.responses() == 0 {
+        if !json_output() {
+            print_statistics(&run, &settings);
+            print_protocol_hint(query, &run.fan_out, &kad_proto);
+        }
+        return Err(anyhow!("no peer responded to the warm-up FIND_NODE query"));
+    }
+
+    let (sender, mut requests) = mpsc::unbounded();
+    tokio::spawn(accept(listener, sender));
+    progress!(
+        "Serving the API at http://{address}, {} peers in the routing table",
+        run.discovered_peers.len()
+    );
+
+    let mut pending: HashMap<QueryId, PendingQuery> = HashMap::new();
+    // Last event of the node, or start of the first outstanding query if later.
+    let mut last_event = Instant::now();
+    let mut refresh = Refresh::new();
+    loop {
+        tokio::select! {
+            request = requests.next() => {
+                let Some((api_query, responder)) = request else {
+                    return Err(anyhow!("API server terminated"));
+                };
+                if pending.is_empty() {
+                    last_event = Instant::now();
+                }
+                let query_id = api_query.start(&mut run.kademlia_handle).await;
+                pending.insert(query_id, PendingQuery {
+                    query: api_query,
+                    responder,
+                    responses: run.fan_out.responses(),
+                });
+            },
+            _ = tokio::time::sleep_until(last_event + STALL_TIMEOUT), if !pending.is_empty() => {
+                progress!("{NodeStalled}");
+                for (_, PendingQuery { responder, .. }) in pending.drain() {
+                    let _ = responder.send(Err("node stalled, restarting it".to_string()));
+                }
+                run.restart(query, &kad_proto).await?;
+                refresh.restart();
+            },
+            _ = tokio::time::sleep_until(refresh.due().unwrap_or(last_event)), if refresh.due().is_some() => {
+                refresh.start(&mut run.kademlia_handle).await;
+            },
+            event = run.litep2p.next_event() => {
+                last_event = Instant::now();
+                if let Some(Litep2pEvent::ConnectionEstablished { peer, .. }) = event {
+                    run.contacted_peers.insert(peer);
+                }
+            },
+            event = run.identify_events.next() => {
+                last_event = Instant::now();
+                record_identified(&mut run.identified, event)
+            },
+            event = run.kademlia_handle.next() => {
+                last_event = Instant::now();
+                if let Some(event) = &event {
+                    refresh.on_event(event);
+                }
+                match event {
+                    Some(KademliaEvent::GetProvidersSuccess { query_id, provided_key, providers }) => {
+                        if let Some(PendingQuery { responder, .. }) = pending.remove(&query_id) {
+                            let providers: Vec<_> = providers
+                                .into_iter()
+                                .map(|provider| {
+                                    let ContentProvider { peer, addresses } = provider;
+                                    peer_json(&peer, &run.address_filter.filter(addresses))
+                                })
+                                .collect();
+                            let document = Json::object()
+                                .field("key", hex::encode(provided_key.as_ref()))
+                                .field("providers", providers);
+                            let _ = responder.send(Ok(document));
+                        }
+                    },
+                    Some(KademliaEvent::FindNodeSuccess { query_id, target, peers }) => {
+                        if let Some(PendingQuery { responder, .. }) = pending.remove(&query_id) {
+                            let peers: Vec<_> = peers
+                                .into_iter()
+                                .map(|(peer, addresses)| {
+                                    peer_json(&peer, &run.address_filter.filter(addresses))
+                                })
+                                .collect();
+                            let document = Json::object()
+                                .field("peer_id", target.to_string())
+                                .field("closest_peers", peers);
+                            let _ = responder.send(Ok(document));
+                        }
+                    },
+                    Some(KademliaEvent::QueryFailed { query_id }) => {
+                        if let Some(PendingQuery { query, responder, responses }) = pending.remove(&query_id) {
+                            let result = match query {
+                                Query::Providers(key) if run.fan_out.responses() > responses => {
+                                    Ok(Json::object()
+                                        .field("key", hex::encode(key.as_ref()))
+                                        .field("providers", Vec::<Json>::new()))
+                                },
+                                _ => Err("Kademlia query failed".to_string()),
+                            };
+                            let _ = responder.send(result);
+                        }
+                    },
+                    Some(KademliaEvent::RoutingTableUpdate { peers }) => {
+                        let (returned, known) = (peers.len(), run.discovered_peers.len());
+                        run.discovered_peers.extend(peers);
+                        run.fan_out.on_response(returned, run.discovered_peers.len() - known);
+                    },
+                    Some(_) => {},
+                    None => return Err(anyhow!("libp2p Kademlia terminated")),
+                }
+            },
+        }
+    }
+}
+
+/// Accept API connections and forward their queries to the node.
+async fn accept(listener: TcpListener, requests: mpsc::UnboundedSender<ApiRequest>) {
+    loop {
+        let stream = http::accept(&listener).await;
+        let requests = requests.clone();
+        tokio::spawn(async move {
+            let _ = respond(stream, requests).await;
+        });
+    }
+}
+
+async fn respond(
+    mut stream: TcpStream,
+    requests: mpsc::UnboundedSender<ApiRequest>,
+) -> std::io::Result<()> {
+    let Some((method, path)) = read_request(&mut stream).await? else {
+        return Ok(());
+    };
+
+    let segments: Vec<_> = path.split('/').collect();
+    let query = match (method.as_str(), segments.as_slice()) {
+        ("GET", ["", ""]) => {
+            return write_response(&mut stream, "200 OK", "text/html; charset=utf-8", PAGE).await
+        }
+        ("GET", ["", "providers", key]) => parse_key(key)
+            .map(Query::Providers)
+            .map_err(|error| error.to_string()),
+        ("GET", ["", "find-node", peer]) => PeerId::from_str(peer)
+            .map(Query::Peer)
+            .map_err(|error| format!("invalid peer ID: {error}")),
+        _ => return write_json(&mut stream, "404 Not Found", error_json("not found")).await,
+    };
+    let query = match query {
+        Ok(query) => query,
+        Err(error) => return write_json(&mut stream, "400 Bad Request", error_json(&error)).await,
+    };
+
+    let (responder, result) = oneshot::channel();
+    if requests.unbounded_send((query, responder)).is_err() {
+        return write_json(
+            &mut stream,
+            "503 Service Unavailable",
+            error_json("node stopped"),
+        )
+        .await;
+    }
+    match result.await {
+        Ok(Ok(document)) => write_json(&mut stream, "200 OK", document).await,
+        Ok(Err(error)) => write_json(&mut stream, "502 Bad Gateway", error_json(&error)).await,
+        Err(_) => {
+            write_json(
+                &mut stream,
+                "503 Service Unavailable",
+                error_json("node stopped"),
+            )
+            .await
+        }
+    }
+}
+
+fn error_json(error: &str) -> Json {
+    Json::object().field("error", error)
+}
+
+async fn write_json(stream: &mut TcpStream, status: &str, document: Json) -> std::io::Result<()> {
+    write_response(stream, status, "application/json", &document.to_string()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::test_utils::{spawn_node, KAD_PROTO};
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        query: QueryArgs,
+    }
+
+    /// Send a GET request for `path` and return the status line and the body.
+    async fn fetch(address: SocketAddr, path: &str) -> (String, String) {
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        stream
+            .write_all(format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").as_bytes())
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+
+        (head.lines().next().unwrap().to_string(), body.to_string())
+    }
+
+    /// [`fetch`] a JSON document.
+    async fn get(address: SocketAddr, path: &str) -> (String, Json) {
+        let (status, body) = fetch(address, path).await;
+        (status, Json::parse(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn serves_the_page() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let (sender, _requests) = mpsc::unbounded();
+        tokio::spawn(accept(listener, sender));
+
+        let (status, body) = fetch(address, "/").await;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert_eq!(body, PAGE);
+        let (status, _) = get(address, "/index.html").await;
+        assert_eq!(status, "HTTP/1.1 404 Not Found");
+    }
+
+    #[tokio::test]
+    async fn answers_missing_providers_with_empty_list() {
+        let peers: HashMap<_, _> = (0..2)
+            .map(|_| {
+                let (peer, address) = spawn_node(HashMap::new());
+                (peer, vec![address])
+            })
+            .collect();
+        let (bootnode, address) = spawn_node(peers);
+        let query = Cli::parse_from([
+            "dht-inspect",
+            "--bootnode",
+            &format!("{address}/p2p/{bootnode}"),
+            "--kad-proto",
+            KAD_PROTO,
+            "--allow-private-addresses",
+        ])
+        .query;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { serve(listener, &query).await });
+
+        // Connections wait in the listen backlog until the warm-up query finishes.
+        let (status, document) = get(address, "/providers/010203").await;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert_eq!(document.get("key"), Some(&Json::from("010203")));
+        assert_eq!(document.get("providers"), Some(&Json::Array(Vec::new())));
+
+        let (status, _) = get(address, "/find-node/not-a-peer").await;
+        assert_eq!(status, "HTTP/1.1 400 Bad Request");
+    }
+}
@@ -0,0 +1,80 @@
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+/// Interval at which [`ctrl_c`] checks whether SIGINT arrived.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Set by the SIGINT handler.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Wait for Ctrl-C.
+///
+/// While the future is alive, the first SIGINT resolves it instead of terminating the process.
+/// The default handler is restored right after, so a second Ctrl-C terminates the process as
+/// usual. Never resolves on platforms other than Unix.
+pub async fn ctrl_c() {
+    #[cfg(unix)]
+    {
+        let _handler = SigintHandler::install();
+        while !INTERRUPTED.load(Ordering::SeqCst) {
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+    #[cfg(not(unix))]
+    std::future::pending::<()>().await
+}
+
+/// SIGINT handler installed until dropped.
+#[cfg(unix)]
+struct SigintHandler;
+
+#[cfg(unix)]
+impl SigintHandler {
+    fn install() -> Self {
+        extern "C" fn on_sigint(_: libc::c_int) {
+            INTERRUPTED.store(true, Ordering::SeqCst);
+            // SAFETY: `signal` is async-signal-safe.
+            unsafe { libc::signal(libc::SIGINT, libc::SIG_DFL) };
+        }
+
+        INTERRUPTED.store(false, Ordering::SeqCst);
+        // SAFETY: the handler only touches an atomic and calls async-signal-safe functions.
+        unsafe {
+            libc::signal(
+                libc::SIGINT,
+                on_sigint as extern "C" fn(libc::c_int) as libc::sighandler_t,
+            )
+        };
+
+        Self
+    }
+}
+
+#[cfg(unix)]
+impl Drop for SigintHandler {
+    fn drop(&mut self) {
+        // SAFETY: restoring the default disposition has no preconditions.
+        unsafe { libc::signal(libc::SIGINT, libc::SIG_DFL) };
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn resolves_on_sigint() {
+        let interrupted = tokio::spawn(ctrl_c());
+        // Let the task install the handler before raising the signal.
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        // SAFETY: the handler is installed, so the signal doesn't terminate the test process.
+        unsafe { libc::raise(libc::SIGINT) };
+
+        tokio::time::timeout(Duration::from_secs(5), interrupted)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+}
@@ -0,0 +1,73 @@
+use std::{
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+
+use crate::json::Json;
+
+/// How often the `--progress-file` is rewritten.
+pub const PROGRESS_FILE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// State of a running query, shown by the `--tui` dashboard and written to the `--progress-file`.
+pub struct Status<'a> {
+    pub query: &'a str,
+    pub key: String,
+    pub protocol: &'a str,
+    pub phase: String,
+    pub attempt: usize,
+    /// FIND_NODE queries of the current prepopulation started and planned.
+    pub prepopulation: (usize, usize),
+    pub elapsed: Duration,
+    /// Estimated time until the prepopulation is finished.
+    pub eta: Option<Duration>,
+    pub discovered_peers: usize,
+    pub contacted_peers: usize,
+    pub open_connections: usize,
+    pub responses: usize,
+    /// Providers, records or closest peers found so far.
+    pub found: Vec<String>,
+}
+
+impl Status<'_> {
+    fn json(&self) -> Json {
+        let updated = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+
+        Json::object()
+            .field("query", self.query)
+            .field("key", self.key.as_str())
+            .field("protocol", self.protocol)
+            .field("phase", self.phase.as_str())
+            .field("attempt", self.attempt)
+            .field(
+                "prepopulation",
+                Json::object()
+                    .field("started", self.prepopulation.0)
+                    .field("planned", self.prepopulation.1),
+            )
+            .field("elapsed_ms", self.elapsed.as_millis() as u64)
+            .field("eta_ms", self.eta.map(|eta| eta.as_millis() as u64))
+            .field("discovered_peers", self.discovered_peers)
+            .field("contacted_peers", self.contacted_peers)
+            .field("open_connections", self.open_connections)
+            .field("responses", self.responses)
+            .field("found", self.found.len())
+            .field("updated_ms", updated.as_millis() as u64)
+    }
+
+    /// Replace the file at `path` with the status in JSON.
+    ///
+    /// The status is written to `<path>.tmp` first and renamed over `path`, so readers never see a
+    /// partially written file.
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        std::fs::write(&temporary, format!("{}\n", self.json()))
+            .with_context(|| format!("failed to write {}", Path::new(&temporary).display()))?;
+        std::fs::rename(&temporary, path)
+            .with_context(|| format!("failed to replace {}", path.display()))
+    }
+}
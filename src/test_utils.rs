@@ -0,0 +1,41 @@
+use std::collections::HashMap;
+
+use futures::StreamExt;
+use litep2p::{
+    config::ConfigBuilder, protocol::libp2p::kademlia::ConfigBuilder as KademliaConfigBuilder,
+    transport::tcp::config::Config as TcpConfig, Litep2p, PeerId,
+};
+use multiaddr::Multiaddr;
+
+/// Kademlia protocol of the nodes started by [`spawn_node`].
+pub const KAD_PROTO: &str = "/test/kad";
+
+/// Start a local Kademlia node knowing `known_peers` and return its ID and address.
+pub fn spawn_node(known_peers: HashMap<PeerId, Vec<Multiaddr>>) -> (PeerId, Multiaddr) {
+    let (kademlia_config, mut kademlia_handle) = KademliaConfigBuilder::new()
+        .with_protocol_names(vec![KAD_PROTO.to_string().into()])
+        .with_known_peers(known_peers)
+        .build();
+    let mut node = Litep2p::new(
+        ConfigBuilder::new()
+            .with_tcp(TcpConfig {
+                listen_addresses: vec!["/ip4/127.0.0.1/tcp/0".parse().unwrap()],
+                ..Default::default()
+            })
+            .with_libp2p_kademlia(kademlia_config)
+            .build(),
+    )
+    .unwrap();
+    let peer = *node.local_peer_id();
+    let address = node.listen_addresses().next().unwrap().clone();
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = node.next_event() => {},
+                _ = kademlia_handle.next() => {},
+            }
+        }
+    });
+
+    (peer, address)
+}
@@ -0,0 +1,219 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fmt::Write,
+    path::Path,
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context};
+use futures::StreamExt;
+use litep2p::{
+    protocol::request_response::{
+        ConfigBuilder as RequestResponseConfigBuilder, DialOptions, RequestResponseEvent,
+        RequestResponseHandle,
+    },
+    Litep2p, PeerId,
+};
+use multiaddr::Multiaddr;
+use prost::Message;
+
+use crate::{transport_config, QueryArgs};
+
+/// Maximum size of a FIND_NODE response.
+const MAX_RESPONSE_SIZE: usize = 1024 * 1024;
+
+/// Time a peer has to answer once the substream is open.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Number of FIND_NODE requests in flight.
+const PARALLEL_REQUESTS: usize = 32;
+
+/// Kademlia message type of GET_VALUE.
+pub const GET_VALUE: i32 = 1;
+
+/// Kademlia message type of FIND_NODE.
+const FIND_NODE: i32 = 4;
+
+/// Kademlia message, only the fields of FIND_NODE and GET_VALUE requests and responses.
+#[derive(Clone, PartialEq, Message)]
+pub struct KademliaMessage {
+    #[prost(int32, tag = "1")]
+    pub kind: i32,
+    #[prost(bytes = "vec", tag = "2")]
+    pub key: Vec<u8>,
+    #[prost(message, optional, tag = "3")]
+    pub record: Option<KademliaRecord>,
+    #[prost(message, repeated, tag = "8")]
+    pub closer_peers: Vec<KademliaPeer>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct KademliaRecord {
+    #[prost(bytes = "vec", tag = "1")]
+    pub key: Vec<u8>,
+    #[prost(bytes = "vec", tag = "2")]
+    pub value: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct KademliaPeer {
+    #[prost(bytes = "vec", tag = "1")]
+    id: Vec<u8>,
+}
+
+/// Peers each peer reported as closest to its own ID, an approximation of its routing table.
+#[derive(Default)]
+struct Topology {
+    edges: HashMap<PeerId, BTreeSet<PeerId>>,
+    failed: usize,
+}
+
+impl Topology {
+    /// Render the topology as a GraphViz digraph. Peers that didn't answer are drawn dashed.
+    fn dot(&self) -> String {
+        let label = |peer: &PeerId| {
+            let peer = peer.to_string();
+            peer[peer.len().saturating_sub(8)..].to_string()
+        };
+        let nodes: BTreeSet<_> = self
+            .edges
+            .iter()
+            .flat_map(|(peer, reported)| std::iter::once(peer).chain(reported))
+            .collect();
+
+        let mut dot = String::from("digraph dht {\n");
+        for node in nodes {
+            let style = if self.edges.contains_key(node) {
+                ""
+            } else {
+                ", style=dashed"
+            };
+            let _ = writeln!(dot, "  \"{node}\" [label=\"{}\"{style}];", label(node));
+        }
+        let mut edges: Vec<_> = self.edges.iter().collect();
+        edges.sort();
+        for (peer, reported) in edges {
+            for other in reported {
+                let _ = writeln!(dot, "  \"{peer}\" -> \"{other}\";");
+            }
+        }
+        dot.push_str("}\n");
+
+        dot
+    }
+}
+
+/// Start a node speaking only `kad_proto`, over request-response, to send Kademlia requests to
+/// single peers.
+pub fn request_node(
+    kad_proto: &str,
+    query: &QueryArgs,
+) -> anyhow::Result<(Litep2p, RequestResponseHandle)> {
+    let settings = query.preset.settings();
+    let (config, handle) = RequestResponseConfigBuilder::new(kad_proto.to_string().into())
+        .with_max_size(MAX_RESPONSE_SIZE)
+        .with_timeout(
+            settings.connection_open_timeout + settings.substream_open_timeout + RESPONSE_TIMEOUT,
+        )
+        .build();
+    let litep2p = Litep2p::new(
+        transport_config(&settings, &query.socket)
+            .with_request_response_protocol(config)
+            .build(),
+    )
+    .context("litep2p initialization error")?;
+
+    Ok((litep2p, handle))
+}
+
+/// Ask every peer in `peers` for the peers closest to its own ID and write the resulting
+/// "who reported whom" graph to `path` in the DOT format.
+///
+/// The requests are sent from a separate node speaking only `kad_proto`, so the peers answer from
+/// their routing tables and the edges are not mixed up by litep2p's iterative lookups.
+pub async fn export(
+    path: &Path,
+    peers: &HashMap<PeerId, HashSet<Multiaddr>>,
+    kad_proto: &str,
+    query: &QueryArgs,
+) -> anyhow::Result<()> {
+    let (mut litep2p, mut handle) = request_node(kad_proto, query)?;
+
+    progress!("Mapping the topology of {} peers...", peers.len());
+    let mut queue: Vec<_> = peers
+        .iter()
+        .filter(|(_, addresses)| !addresses.is_empty())
+        .collect();
+    let mut topology = Topology::default();
+    let mut pending = HashMap::new();
+    loop {
+        while pending.len() < PARALLEL_REQUESTS {
+            let Some((peer, addresses)) = queue.pop() else {
+                break;
+            };
+            litep2p.add_known_address(*peer, addresses.iter().cloned());
+            let request = KademliaMessage {
+                kind: FIND_NODE,
+                key: peer.to_bytes(),
+                record: None,
+                closer_peers: Vec::new(),
+            };
+            match handle
+                .send_request(*peer, request.encode_to_vec(), DialOptions::Dial)
+                .await
+            {
+                Ok(request_id) => {
+                    pending.insert(request_id, *peer);
+                }
+                Err(_) => topology.failed += 1,
+            }
+        }
+        if pending.is_empty() {
+            break;
+        }
+
+        tokio::select! {
+            _ = litep2p.next_event() => {},
+            event = handle.next() => match event {
+                Some(RequestResponseEvent::ResponseReceived { request_id, response, .. }) => {
+                    let Some(peer) = pending.remove(&request_id) else {
+                        continue
+                    };
+                    match KademliaMessage::decode(response.as_slice()) {
+                        Ok(message) => {
+                            let reported = message
+                                .closer_peers
+                                .iter()
+                                .filter_map(|closer| PeerId::from_bytes(&closer.id).ok())
+                                .filter(|closer| *closer != peer)
+                                .collect();
+                            topology.edges.insert(peer, reported);
+                        },
+                        Err(_) => topology.failed += 1,
+                    }
+                },
+                Some(RequestResponseEvent::RequestFailed { request_id, .. }) => {
+                    if pending.remove(&request_id).is_some() {
+                        topology.failed += 1;
+                    }
+                },
+                Some(RequestResponseEvent::RequestReceived { request_id, .. }) => {
+                    handle.reject_request(request_id);
+                },
+                None => return Err(anyhow!("request-response protocol terminated")),
+            },
+        }
+    }
+
+    std::fs::write(path, topology.dot())
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    progress!(
+        "Topology written to {}: {} peers answered, {} failed, {} edges",
+        path.display(),
+        topology.edges.len(),
+        topology.failed,
+        topology.edges.values().map(BTreeSet::len).sum::<usize>()
+    );
+
+    Ok(())
+}
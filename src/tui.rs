@@ -0,0 +1,117 @@
+use std::{
+    collections::VecDeque,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use crate::status::Status;
+
+/// How often the dashboard is redrawn.
+pub const REDRAW_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Number of progress messages shown in the log panel.
+const LOG_LINES: usize = 10;
+
+/// Number of found peers listed in the results panel.
+const FOUND_LINES: usize = 10;
+
+/// Whether the dashboard is shown and progress messages go to its log panel.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Latest progress messages.
+static LOG: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+/// Whether the dashboard is active.
+pub fn active() -> bool {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+/// Show the dashboard instead of the progress messages until the returned guard is dropped.
+pub fn start() -> Dashboard {
+    ACTIVE.store(true, Ordering::Relaxed);
+    eprint!("\x1b[?25l");
+    Dashboard
+}
+
+/// Active dashboard. Dropping it stops redrawing, leaving the last frame on the terminal, shows the
+/// cursor again and prints progress messages again, however the query ends.
+#[must_use = "the dashboard stops when dropped"]
+pub struct Dashboard;
+
+impl Drop for Dashboard {
+    fn drop(&mut self) {
+        ACTIVE.store(false, Ordering::Relaxed);
+        LOG.lock().expect("log lock poisoned").clear();
+        eprint!("\x1b[?25h");
+    }
+}
+
+/// Add a progress message to the log panel.
+pub fn log(line: String) {
+    let mut log = LOG.lock().expect("log lock poisoned");
+    if log.len() == LOG_LINES {
+        log.pop_front();
+    }
+    log.push_back(line);
+}
+
+/// Redraw the dashboard on stderr, keeping stdout for the results.
+pub fn draw(dashboard: &Status) {
+    let mut frame = String::from("\x1b[H\x1b[2J");
+    let _ = writeln!(frame, "\x1b[1m{} {}\x1b[0m", dashboard.query, dashboard.key);
+    let _ = writeln!(frame, "Protocol: {}", dashboard.protocol);
+    let _ = writeln!(
+        frame,
+        "Phase: {} ({} s)",
+        dashboard.phase,
+        dashboard.elapsed.as_secs()
+    );
+    let _ = writeln!(frame);
+    let _ = writeln!(frame, "\x1b[1mNetwork\x1b[0m");
+    let _ = writeln!(
+        frame,
+        "  Routing table: {} peers discovered",
+        dashboard.discovered_peers
+    );
+    let _ = writeln!(
+        frame,
+        "  Connections: {} open, {} peers contacted",
+        dashboard.open_connections, dashboard.contacted_peers
+    );
+    let _ = writeln!(frame, "  Kademlia responses: {}", dashboard.responses);
+    let _ = writeln!(frame);
+    let _ = writeln!(frame, "\x1b[1mFound: {}\x1b[0m", dashboard.found.len());
+    for found in dashboard.found.iter().take(FOUND_LINES) {
+        let _ = writeln!(frame, "  {found}");
+    }
+    if dashboard.found.len() > FOUND_LINES {
+        let _ = writeln!(frame, "  ... {} more", dashboard.found.len() - FOUND_LINES);
+    }
+    let _ = writeln!(frame);
+    let _ = writeln!(frame, "\x1b[1mLog\x1b[0m");
+    for line in LOG.lock().expect("log lock poisoned").iter() {
+        let _ = writeln!(frame, "  {line}");
+    }
+
+    eprint!("{frame}");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stops_when_dropped() {
+        let dashboard = start();
+        assert!(active());
+        log("message".to_string());
+
+        drop(dashboard);
+        assert!(!active());
+        assert!(LOG.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,185 @@
+use std::{collections::HashMap, fmt, time::Duration};
+
+use crate::retry::{sleep_until, RetryPolicy};
+
+use futures::{Stream, StreamExt};
+use litep2p::{
+    protocol::{
+        libp2p::{identify::IdentifyEvent, kademlia::ContentProvider},
+        request_response::{
+            DialOptions, RejectReason, RequestResponseError, RequestResponseEvent,
+            RequestResponseHandle,
+        },
+    },
+    types::RequestId,
+    Litep2p, PeerId,
+};
+use multiaddr::Multiaddr;
+
+/// How long to wait for identify of providers that answered after all requests completed.
+const IDENTIFY_GRACE: Duration = Duration::from_secs(3);
+
+/// Whether a provider serves the application protocol.
+pub enum ProtocolSupport {
+    /// The provider answered the request.
+    Serving,
+    /// The protocol was negotiated, but the provider closed the stream without answering.
+    Accepted,
+    /// The provider doesn't support the protocol.
+    NotServing,
+    /// The provider couldn't be dialed.
+    Unreachable,
+    /// The request timed out.
+    Timeout,
+    /// Other error.
+    Failed(String),
+}
+
+impl fmt::Display for ProtocolSupport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Serving => write!(f, "serving"),
+            Self::Accepted => write!(f, "accepted stream, no response"),
+            Self::NotServing => write!(f, "protocol not supported"),
+            Self::Unreachable => write!(f, "unreachable"),
+            Self::Timeout => write!(f, "timeout"),
+            Self::Failed(error) => write!(f, "failed: {error}"),
+        }
+    }
+}
+
+impl From<RequestResponseError> for ProtocolSupport {
+    fn from(error: RequestResponseError) -> Self {
+        match error {
+            RequestResponseError::UnsupportedProtocol => Self::NotServing,
+            RequestResponseError::Timeout => Self::Timeout,
+            RequestResponseError::NotConnected
+            | RequestResponseError::Rejected(RejectReason::DialFailed(_)) => Self::Unreachable,
+            RequestResponseError::Rejected(
+                RejectReason::SubstreamClosed | RejectReason::ConnectionClosed,
+            ) => Self::Accepted,
+            error => Self::Failed(format!("{error:?}")),
+        }
+    }
+}
+
+/// Open the application protocol to every provider and report whether they accept the stream.
+///
+/// Failed dials are retried according to `retry`. Listen addresses advertised by the providers via
+/// identify are recorded into `identified`.
+pub async fn verify_providers(
+    litep2p: &mut Litep2p,
+    handle: &mut RequestResponseHandle,
+    identify_events: &mut (dyn Stream<Item = IdentifyEvent> + Send + Unpin),
+    identified: &mut HashMap<PeerId, Vec<Multiaddr>>,
+    providers: &[ContentProvider],
+    retry: &RetryPolicy,
+) -> Vec<(PeerId, ProtocolSupport)> {
+    let mut pending: HashMap<RequestId, PeerId> = HashMap::new();
+    let mut results = Vec::new();
+    let mut retries: HashMap<PeerId, usize> = HashMap::new();
+    // Requests to resend once their backoff elapses.
+    let mut delayed: Vec<(tokio::time::Instant, PeerId)> = Vec::new();
+
+    for provider in providers {
+        if provider.addresses.is_empty() {
+            results.push((provider.peer, ProtocolSupport::Unreachable));
+            continue;
+        }
+
+        litep2p.add_known_address(provider.peer, provider.addresses.iter().cloned());
+        match handle
+            .send_request(provider.peer, Vec::new(), DialOptions::Dial)
+            .await
+        {
+            Ok(request_id) => {
+                pending.insert(request_id, provider.peer);
+            }
+            Err(error) => {
+                results.push((provider.peer, ProtocolSupport::Failed(error.to_string())));
+            }
+        }
+    }
+
+    while !pending.is_empty() || !delayed.is_empty() {
+        let next_retry = delayed.iter().map(|(deadline, _)| *deadline).min();
+
+        tokio::select! {
+            _ = litep2p.next_event() => {},
+            event = identify_events.next() => record_identified(identified, event),
+            _ = sleep_until(next_retry) => {
+                let now = tokio::time::Instant::now();
+                let (due, rest) = delayed.into_iter().partition(|(deadline, _)| *deadline <= now);
+                delayed = rest;
+
+                for (_, peer) in due {
+                    match handle.send_request(peer, Vec::new(), DialOptions::Dial).await {
+                        Ok(request_id) => {
+                            pending.insert(request_id, peer);
+                        },
+                        Err(error) => {
+                            results.push((peer, ProtocolSupport::Failed(error.to_string())));
+                        },
+                    }
+                }
+            },
+            event = handle.next() => match event {
+                Some(RequestResponseEvent::ResponseReceived { request_id, .. }) => {
+                    if let Some(peer) = pending.remove(&request_id) {
+                        results.push((peer, ProtocolSupport::Serving));
+                    }
+                },
+                Some(RequestResponseEvent::RequestFailed { request_id, error, .. }) => {
+                    let Some(peer) = pending.remove(&request_id) else {
+                        continue
+                    };
+
+                    let support = ProtocolSupport::from(error);
+                    let attempt = retries.entry(peer).or_default();
+                    if matches!(support, ProtocolSupport::Unreachable) && *attempt < retry.retries() {
+                        delayed.push((tokio::time::Instant::now() + retry.backoff(*attempt), peer));
+                        *attempt += 1;
+                    } else {
+                        results.push((peer, support));
+                    }
+                },
+                Some(RequestResponseEvent::RequestReceived { request_id, .. }) => {
+                    handle.reject_request(request_id);
+                },
+                None => break,
+            },
+        }
+    }
+
+    // Identify may complete after the request, give connected providers a moment to finish it.
+    let grace = tokio::time::Instant::now() + IDENTIFY_GRACE;
+    while results.iter().any(|(peer, support)| {
+        matches!(
+            support,
+            ProtocolSupport::Serving | ProtocolSupport::Accepted
+        ) && !identified.contains_key(peer)
+    }) {
+        tokio::select! {
+            _ = litep2p.next_event() => {},
+            event = identify_events.next() => record_identified(identified, event),
+            _ = tokio::time::sleep_until(grace) => break,
+        }
+    }
+
+    results
+}
+
+/// Record listen addresses of an identified peer.
+pub fn record_identified(
+    identified: &mut HashMap<PeerId, Vec<Multiaddr>>,
+    event: Option<IdentifyEvent>,
+) {
+    if let Some(IdentifyEvent::PeerIdentified {
+        peer,
+        listen_addresses,
+        ..
+    }) = event
+    {
+        identified.insert(peer, listen_addresses);
+    }
+}